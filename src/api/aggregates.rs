@@ -1,21 +1,51 @@
 // Copyright (C) 2020-2022 Daniel Mueller <deso@posteo.net>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
 use chrono::serde::ts_milliseconds::deserialize as datetime_from_timestamp;
+use chrono::serde::ts_milliseconds::serialize as datetime_to_timestamp;
 use chrono::Date;
 use chrono::DateTime;
+use chrono::Datelike as _;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::Timelike as _;
 use chrono::Utc;
 
+use http_endpoint::Endpoint as _;
+
 use num_decimal::Num;
 
+use serde::de::Error as _;
 use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+use thiserror::Error as ThisError;
 
 use crate::api::response::Response;
+use crate::api::response::ResponseError;
+use crate::api::response::Status;
+use crate::api_info::split_next_url;
+use crate::de::num_from_json;
+use crate::de::opt_bool_from_json;
+use crate::endpoint::Version;
+use crate::error::Error;
+use crate::error::RequestError;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::events::Aggregate as EventAggregate;
+use crate::tz::new_york_midnight;
+use crate::Client;
 use crate::Str;
 
 
 /// An enumeration of the various supported time span values.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum TimeSpan {
   /// A minutely aggregate.
   Minute,
@@ -47,33 +77,326 @@ impl AsRef<str> for TimeSpan {
   }
 }
 
+impl FromStr for TimeSpan {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let span = match s {
+      "minute" => TimeSpan::Minute,
+      "hour" => TimeSpan::Hour,
+      "day" => TimeSpan::Day,
+      "week" => TimeSpan::Week,
+      "month" => TimeSpan::Month,
+      "quarter" => TimeSpan::Quarter,
+      "year" => TimeSpan::Year,
+      _ => return Err(Error::Str(format!("invalid time span: {}", s).into())),
+    };
+    Ok(span)
+  }
+}
+
+impl<'de> Deserialize<'de> for TimeSpan {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let span = String::deserialize(deserializer)?;
+    TimeSpan::from_str(&span).map_err(D::Error::custom)
+  }
+}
+
+impl Serialize for TimeSpan {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.serialize_str(self.as_ref())
+  }
+}
+
+impl TimeSpan {
+  /// Round the provided point in time down to the start of the unit of
+  /// this time span containing it.
+  ///
+  /// Weeks are considered to start on Sundays, quarters in January,
+  /// April, July, and October. Note that only boundaries of a single
+  /// unit are considered, irrespective of any multiplier.
+  pub fn floor(self, time: DateTime<Utc>) -> DateTime<Utc> {
+    use chrono::TimeZone as _;
+
+    let naive = time.naive_utc();
+    let date = naive.date();
+    let (date, hour, minute) = match self {
+      TimeSpan::Minute => (date, naive.hour(), naive.minute()),
+      TimeSpan::Hour => (date, naive.hour(), 0),
+      TimeSpan::Day => (date, 0, 0),
+      TimeSpan::Week => {
+        let days = i64::from(date.weekday().num_days_from_sunday());
+        (date - Duration::days(days), 0, 0)
+      },
+      TimeSpan::Month => (date.with_day(1).unwrap(), 0, 0),
+      TimeSpan::Quarter => {
+        let month = date.month0() / 3 * 3 + 1;
+        (NaiveDate::from_ymd_opt(date.year(), month, 1).unwrap(), 0, 0)
+      },
+      TimeSpan::Year => (NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(), 0, 0),
+    };
+
+    Utc.from_utc_datetime(&date.and_hms_opt(hour, minute, 0).unwrap())
+  }
+
+  /// Round the provided point in time up to the start of the next unit
+  /// of this time span, unless it already is at such a boundary.
+  ///
+  /// See `floor` for the definition of the boundaries.
+  pub fn ceil(self, time: DateTime<Utc>) -> DateTime<Utc> {
+    let floor = self.floor(time);
+    if floor == time {
+      time
+    } else {
+      self.add_to(floor)
+    }
+  }
+
+  /// Retrieve the nominal length of `multiplier` units of this time
+  /// span.
+  ///
+  /// Calendar based spans (months, quarters, and years) vary in
+  /// length. For them, the average length in the Gregorian calendar is
+  /// reported, making the result approximate.
+  pub fn approx_duration(self, multiplier: u32) -> Duration {
+    let seconds = match self {
+      TimeSpan::Minute => 60,
+      TimeSpan::Hour => 60 * 60,
+      TimeSpan::Day => 24 * 60 * 60,
+      TimeSpan::Week => 7 * 24 * 60 * 60,
+      // 365.2425 days / 12
+      TimeSpan::Month => 2_629_746,
+      TimeSpan::Quarter => 3 * 2_629_746,
+      TimeSpan::Year => 12 * 2_629_746,
+    };
+    Duration::seconds(seconds * i64::from(multiplier))
+  }
+
+  /// Advance the provided point in time by one unit of this time span.
+  ///
+  /// Calendar based spans (months, quarters, and years) clamp the day
+  /// of the month to the length of the resulting month.
+  fn add_to(self, time: DateTime<Utc>) -> DateTime<Utc> {
+    use chrono::TimeZone as _;
+
+    let months = match self {
+      TimeSpan::Minute => return time + Duration::minutes(1),
+      TimeSpan::Hour => return time + Duration::hours(1),
+      TimeSpan::Day => return time + Duration::days(1),
+      TimeSpan::Week => return time + Duration::weeks(1),
+      TimeSpan::Month => 1,
+      TimeSpan::Quarter => 3,
+      TimeSpan::Year => 12,
+    };
+
+    let naive = time.naive_utc();
+    let date = naive.date();
+    let month0 = date.year() * 12 + date.month0() as i32 + months;
+    let year = month0.div_euclid(12);
+    let month = month0.rem_euclid(12) as u32 + 1;
+    let date = (1..=date.day())
+      .rev()
+      .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+      .unwrap_or(date);
+
+    Utc.from_utc_datetime(&date.and_time(naive.time()))
+  }
+}
+
+
+/// Serialize a date in `YYYY-MM-DD` format.
+fn date_to_str<S>(date: &Date<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  serializer.collect_str(&date.format("%Y-%m-%d"))
+}
+
+
+/// Deserialize a date in `YYYY-MM-DD` format.
+fn date_from_str<'de, D>(deserializer: D) -> Result<Date<Utc>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  use chrono::TimeZone as _;
+
+  let date = String::deserialize(deserializer)?;
+  let date = NaiveDate::from_str(&date).map_err(D::Error::custom)?;
+  Ok(Utc.from_utc_date(&date))
+}
+
 
 /// A GET request to be made to the
 /// `/v2/aggs/ticker/<symbol>/range/1/<span>/<start>/<end>` endpoint.
-#[derive(Clone, Debug, PartialEq)]
+///
+/// Requests can be serialized, e.g., for deriving keys of cached
+/// responses from them.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct AggregateReq {
   /// The ticker symbol to request aggregated data for.
+  #[serde(rename = "symbol")]
   pub symbol: String,
   /// The aggregated time span.
+  #[serde(rename = "time_span")]
   pub time_span: TimeSpan,
   /// The time span multiplier to use.
-  pub multiplier: u8,
+  #[serde(rename = "multiplier")]
+  pub multiplier: u32,
   /// The start date to request aggregates for.
+  #[serde(
+    rename = "start_date",
+    serialize_with = "date_to_str",
+    deserialize_with = "date_from_str"
+  )]
   pub start_date: Date<Utc>,
   /// The end date to request aggregates for.
   ///
   /// Note that the reported the reported aggregates will include
   /// this date, i.e., the range is inclusive of this end date.
+  #[serde(
+    rename = "end_date",
+    serialize_with = "date_to_str",
+    deserialize_with = "date_from_str"
+  )]
   pub end_date: Date<Utc>,
 }
 
 
+impl AggregateReq {
+  /// Snap the request's start and end date to the boundaries of its
+  /// time span, i.e., round the start date down to the first day of
+  /// the unit containing it and the end date up to the last day of the
+  /// unit containing it.
+  ///
+  /// Polygon reports all aggregates overlapping the requested range,
+  /// aligned to its own boundaries. Snapping the range makes explicit
+  /// which aggregates will be reported. The dates of minute, hourly,
+  /// and daily requests are already aligned and are left unchanged. See
+  /// `TimeSpan::floor` for the definition of the boundaries.
+  pub fn snap(self) -> Self {
+    use chrono::TimeZone as _;
+
+    let span = self.time_span;
+    let (start_date, end_date) = match span {
+      TimeSpan::Minute | TimeSpan::Hour | TimeSpan::Day => (self.start_date, self.end_date),
+      TimeSpan::Week | TimeSpan::Month | TimeSpan::Quarter | TimeSpan::Year => {
+        let start = span.floor(self.start_date.and_hms_opt(0, 0, 0).unwrap());
+        let end = span.floor(self.end_date.and_hms_opt(0, 0, 0).unwrap());
+        let end = span.add_to(end) - Duration::days(1);
+        (
+          Utc.from_utc_date(&start.naive_utc().date()),
+          Utc.from_utc_date(&end.naive_utc().date()),
+        )
+      },
+    };
+
+    Self {
+      start_date,
+      end_date,
+      ..self
+    }
+  }
+}
+
+
+/// Parse a date in `YYYY-MM-DD` format.
+fn parse_date(s: &str, spec: &str) -> Result<Date<Utc>, Error> {
+  use chrono::TimeZone as _;
+
+  let date = NaiveDate::from_str(s).map_err(|err| {
+    Error::Str(format!("invalid date '{}' in aggregate spec '{}': {}", s, spec, err).into())
+  })?;
+  Ok(Utc.from_utc_date(&date))
+}
+
+impl FromStr for AggregateReq {
+  type Err = Error;
+
+  /// Parse an `AggregateReq` from a compact specification of the form
+  /// `<symbol>:<multiplier>:<span>:<start>:<end>`, e.g.,
+  /// `AAPL:1:day:2018-02-01:2018-03-01`.
+  fn from_str(spec: &str) -> Result<Self, Self::Err> {
+    let parts = spec.split(':').collect::<Vec<_>>();
+    let (symbol, multiplier, span, start, end) = match parts.as_slice() {
+      [symbol, multiplier, span, start, end] => (*symbol, *multiplier, *span, *start, *end),
+      _ => {
+        return Err(Error::Str(
+          format!(
+            "invalid aggregate spec '{}': expected <symbol>:<multiplier>:<span>:<start>:<end>",
+            spec
+          )
+          .into(),
+        ))
+      },
+    };
+
+    if symbol.is_empty() {
+      return Err(Error::Str(format!("invalid aggregate spec '{}': empty symbol", spec).into()))
+    }
+
+    let multiplier = multiplier.parse::<u32>().map_err(|err| {
+      Error::Str(
+        format!(
+          "invalid multiplier '{}' in aggregate spec '{}': {}",
+          multiplier, spec, err
+        )
+        .into(),
+      )
+    })?;
+    let time_span = TimeSpan::from_str(span).map_err(|err| {
+      Error::Str(format!("invalid aggregate spec '{}': {}", spec, err).into())
+    })?;
+
+    Ok(Self {
+      symbol: symbol.to_string(),
+      time_span,
+      multiplier,
+      start_date: parse_date(start, spec)?,
+      end_date: parse_date(end, spec)?,
+    })
+  }
+}
+
+impl TryFrom<&str> for AggregateReq {
+  type Error = Error;
+
+  fn try_from(spec: &str) -> Result<Self, Self::Error> {
+    Self::from_str(spec)
+  }
+}
+
+
 /// A ticker as returned by the
 /// `/v2/aggs/ticker/<symbol>/range/1/<span>/<start>/<end>` endpoint.
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+///
+/// Aggregates are ordered by their timestamp. Volumes are compared by
+/// their bit pattern, i.e., a `NaN` volume is considered equal to
+/// itself, whereas `0.0` and `-0.0` are considered different.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Aggregate {
+  /// The symbol the aggregate is for.
+  ///
+  /// Polygon reports the symbol for some endpoints only, such as the
+  /// previous close, but not for the aggregates of a single ticker.
+  #[serde(
+    rename = "T",
+    alias = "sym",
+    default,
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub symbol: Option<String>,
   /// The aggregate's timestamp.
-  #[serde(rename = "t", deserialize_with = "datetime_from_timestamp")]
+  #[serde(
+    rename = "t",
+    deserialize_with = "datetime_from_timestamp",
+    serialize_with = "datetime_to_timestamp"
+  )]
   pub timestamp: DateTime<Utc>,
   /// The trade volume during the aggregated time frame.
   ///
@@ -82,20 +405,398 @@ pub struct Aggregate {
   #[serde(rename = "v")]
   pub volume: f64,
   /// The open price.
-  #[serde(rename = "o")]
+  #[serde(rename = "o", deserialize_with = "num_from_json")]
   pub open_price: Num,
   /// The tick's close price.
-  #[serde(rename = "c")]
+  #[serde(rename = "c", deserialize_with = "num_from_json")]
   pub close_price: Num,
   /// The tick's high price.
-  #[serde(rename = "h")]
+  #[serde(rename = "h", deserialize_with = "num_from_json")]
   pub high_price: Num,
   /// The tick's low price.
-  #[serde(rename = "l")]
+  #[serde(rename = "l", deserialize_with = "num_from_json")]
   pub low_price: Num,
+  /// Whether the aggregate is for an OTC ticker.
+  ///
+  /// Polygon only reports this flag for OTC bars, meaning that it is
+  /// absent for exchange-listed ones.
+  #[serde(
+    rename = "otc",
+    default,
+    deserialize_with = "opt_bool_from_json",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub otc: Option<bool>,
+}
+
+/// Map a volume onto an integer, such that the integers' order is a
+/// total order of the volumes consistent with their bit patterns.
+fn volume_key(volume: f64) -> i64 {
+  // This is the mapping used by `f64::total_cmp`, which is not
+  // available on our minimum supported Rust version.
+  let bits = volume.to_bits() as i64;
+  bits ^ ((((bits >> 63) as u64) >> 1) as i64)
+}
+
+impl PartialEq for Aggregate {
+  fn eq(&self, other: &Self) -> bool {
+    self.symbol == other.symbol
+      && self.timestamp == other.timestamp
+      && self.volume.to_bits() == other.volume.to_bits()
+      && self.open_price == other.open_price
+      && self.close_price == other.close_price
+      && self.high_price == other.high_price
+      && self.low_price == other.low_price
+      && self.otc == other.otc
+  }
+}
+
+impl Eq for Aggregate {}
+
+impl PartialOrd for Aggregate {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Aggregate {
+  fn cmp(&self, other: &Self) -> Ordering {
+    // Ties are broken by the remaining fields, to stay consistent with
+    // `PartialEq`.
+    self
+      .timestamp
+      .cmp(&other.timestamp)
+      .then_with(|| self.open_price.cmp(&other.open_price))
+      .then_with(|| self.close_price.cmp(&other.close_price))
+      .then_with(|| self.high_price.cmp(&other.high_price))
+      .then_with(|| self.low_price.cmp(&other.low_price))
+      .then_with(|| self.otc.cmp(&other.otc))
+      .then_with(|| self.symbol.cmp(&other.symbol))
+      .then_with(|| volume_key(self.volume).cmp(&volume_key(other.volume)))
+  }
+}
+
+impl Aggregate {
+  /// Retrieve the aggregate's timestamp as milliseconds since the Unix
+  /// epoch, as originally reported by Polygon.
+  pub fn timestamp_millis(&self) -> i64 {
+    self.timestamp.timestamp_millis()
+  }
+
+  /// Convert this aggregate into its streaming counterpart, as
+  /// contained in `events::Event::SecondAggregate` and
+  /// `events::Event::MinuteAggregate`.
+  ///
+  /// The aggregate's timestamp marks the start of the bar and the end
+  /// is inferred by advancing it by one unit of `span`. The volume is
+  /// rounded to the nearest integer and fields that have no REST
+  /// equivalent are left empty.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn into_event_aggregate(self, symbol: String, span: TimeSpan) -> EventAggregate {
+    EventAggregate {
+      symbol,
+      volume: self.volume.round() as u64,
+      accumulated_volume: None,
+      official_open_price: None,
+      tick_vwap: None,
+      day_vwap: None,
+      open_price: self.open_price,
+      close_price: self.close_price,
+      high_price: self.high_price,
+      low_price: self.low_price,
+      start_timestamp: self.timestamp,
+      end_timestamp: span.add_to(self.timestamp),
+    }
+  }
+}
+
+
+/// An error encountered while retrieving the latest aggregate of a
+/// symbol.
+#[derive(Debug, ThisError)]
+pub enum LatestBarError {
+  /// The request failed.
+  #[error("failed to request the latest aggregate")]
+  Request(
+    #[from]
+    #[source]
+    RequestError<GetLatestError>,
+  ),
+  /// The response did not indicate success.
+  #[error("failed to retrieve the latest aggregate")]
+  Response(
+    #[from]
+    #[source]
+    ResponseError,
+  ),
+  /// No aggregate was reported for the symbol in the requested range.
+  #[error("no recent aggregate found for {0}")]
+  NotFound(String),
 }
 
-type GetResponse = Response<Option<Vec<Aggregate>>>;
+
+impl AggregateReq {
+  /// Create a request for the most recent aggregates of the given
+  /// symbol and time span, as of `now`.
+  ///
+  /// The request is meant to be issued via `GetLatest`, which only
+  /// asks for the newest aggregate in the range. The range covers the
+  /// unit of the time span containing `now` as well as the previous
+  /// one, but at least the last four days, such that weekends and
+  /// holidays are bridged.
+  pub fn latest(symbol: &str, time_span: TimeSpan, now: DateTime<Utc>) -> Self {
+    use chrono::TimeZone as _;
+
+    let previous = time_span.floor(time_span.floor(now) - Duration::milliseconds(1));
+    let start = previous.min(now - Duration::days(4));
+
+    Self {
+      symbol: symbol.to_string(),
+      time_span,
+      multiplier: 1,
+      start_date: Utc.from_utc_date(&start.naive_utc().date()),
+      end_date: Utc.from_utc_date(&now.naive_utc().date()),
+    }
+  }
+}
+
+
+/// The maximum number of aggregates Polygon reports for a single
+/// request.
+const MAX_AGGREGATES: usize = 50000;
+
+
+impl AggregateReq {
+  /// Split the request into consecutive requests, each covering a
+  /// range of dates small enough for the reported aggregates to stay
+  /// below Polygon's limit of aggregates per response.
+  ///
+  /// The split is based on the maximum number of aggregates possible
+  /// per day, i.e., it assumes trading around the clock. Ranges that
+  /// fit into a single request result in a single request.
+  pub fn split(&self) -> Vec<AggregateReq> {
+    let per_day = match self.time_span {
+      TimeSpan::Minute => 24 * 60,
+      TimeSpan::Hour => 24,
+      _ => return vec![self.clone()],
+    };
+    let multiplier = usize::try_from(self.multiplier.max(1)).unwrap_or(usize::MAX);
+    let days = (MAX_AGGREGATES.saturating_mul(multiplier) / per_day).max(1);
+    let days = Duration::days(i64::try_from(days).unwrap_or(i64::MAX));
+
+    let mut requests = Vec::new();
+    let mut start = self.start_date;
+    while start <= self.end_date {
+      let end = start
+        .checked_add_signed(days - Duration::days(1))
+        .filter(|end| *end < self.end_date)
+        .unwrap_or(self.end_date);
+
+      requests.push(AggregateReq {
+        start_date: start,
+        end_date: end,
+        ..self.clone()
+      });
+
+      match end.succ_opt() {
+        Some(next) => start = next,
+        None => break,
+      }
+    }
+    requests
+  }
+}
+
+
+/// Issue the provided request, split into as many requests as
+/// necessary to not have any aggregates truncated by Polygon's limit
+/// of aggregates per response.
+///
+/// Requests are issued sequentially and the aggregates of all of them
+/// are reported in time order, with duplicates removed. The combined
+/// response is only `Ok` if all individual ones were, and `Delayed` if
+/// any of them was. It carries the request ID of the last request
+/// issued, but no result count.
+pub async fn issue_split(
+  client: &Client,
+  request: AggregateReq,
+) -> Result<Response<Vec<Aggregate>>, RequestError<GetError>> {
+  let mut aggregates = Vec::new();
+  let mut delayed = false;
+  let mut request_id = None;
+
+  for request in request.split() {
+    let response = client.issue::<Get>(request).await?;
+    request_id = response.request_id;
+
+    match response.status {
+      Status::Ok(result) => aggregates.extend(result),
+      Status::Delayed(result) => {
+        delayed = true;
+        aggregates.extend(result)
+      },
+      Status::Err => {
+        return Ok(Response {
+          status: Status::Err,
+          request_id,
+          results_count: None,
+          next_url: None,
+        })
+      },
+    }
+  }
+
+  aggregates.sort_by_key(|aggregate| aggregate.timestamp);
+  aggregates.dedup_by_key(|aggregate| aggregate.timestamp);
+
+  let status = if delayed {
+    Status::Delayed(aggregates)
+  } else {
+    Status::Ok(aggregates)
+  };
+  Ok(Response {
+    status,
+    request_id,
+    results_count: None,
+    next_url: None,
+  })
+}
+
+
+/// An error encountered while retrieving all pages of aggregates.
+#[derive(Debug, ThisError)]
+pub enum PagedError {
+  /// The request for a page failed.
+  #[error("failed to request a page of aggregates")]
+  Request(
+    #[from]
+    #[source]
+    RequestError<GetPageError>,
+  ),
+  /// Polygon referenced a page that was already retrieved, which
+  /// would cause pagination to never conclude.
+  #[error("page {0} was referenced more than once")]
+  RepeatedPage(String),
+}
+
+
+/// Issue the provided request, following the `next_url` reported by
+/// Polygon until all pages of aggregates have been retrieved.
+///
+/// Polygon paginates aggregates once a response would exceed the
+/// requested limit. In contrast to `issue_split`, which avoids
+/// reaching the limit in the first place, this function handles
+/// Polygon-side pagination. The combined response is only `Ok` if all
+/// pages were, and `Delayed` if any of them was. It carries the
+/// request ID of the last request issued, but no result count.
+///
+/// `PagedError::RepeatedPage` is reported if Polygon references a page
+/// already retrieved.
+pub async fn issue_paged(
+  client: &Client,
+  request: AggregateReq,
+) -> Result<Response<Vec<Aggregate>>, PagedError> {
+  let path = Get::path(&request);
+  let query = Get::query(&request).map_err(|err| RequestError::Endpoint(err.into()))?;
+  let mut page = match query {
+    Some(query) => format!("{}?{}", path, query),
+    None => path.into_owned(),
+  };
+  // Pages are identified by path and query only, as the first one is
+  // not a full URL like those reported by Polygon.
+  let key = |page: &str| split_next_url(page, None);
+  let mut visited = HashSet::new();
+  let mut aggregates = Vec::new();
+  let mut delayed = false;
+
+  loop {
+    let _ = visited.insert(key(&page));
+    let response = client.issue::<GetPage>(page).await?;
+    let request_id = response.request_id;
+
+    match response.status {
+      Status::Ok(result) => aggregates.extend(result),
+      Status::Delayed(result) => {
+        delayed = true;
+        aggregates.extend(result)
+      },
+      Status::Err => {
+        break Ok(Response {
+          status: Status::Err,
+          request_id,
+          results_count: None,
+          next_url: None,
+        })
+      },
+    }
+
+    match response.next_url {
+      Some(next_url) if visited.contains(&key(&next_url)) => {
+        break Err(PagedError::RepeatedPage(next_url))
+      },
+      Some(next_url) => page = next_url,
+      None => {
+        let status = if delayed {
+          Status::Delayed(aggregates)
+        } else {
+          Status::Ok(aggregates)
+        };
+        break Ok(Response {
+          status,
+          request_id,
+          results_count: None,
+          next_url: None,
+        })
+      },
+    }
+  }
+}
+
+
+/// Format the start or end of the range of dates to request aggregates
+/// for, as part of the request path.
+///
+/// Polygon accepts either `YYYY-MM-DD` dates or millisecond timestamps.
+/// Minute and hourly aggregates are requested by timestamp, to not be
+/// subject to Polygon's interpretation of a date's boundaries, while
+/// daily and coarser ones use the more readable date form. In both
+/// cases the range includes all of the end date. Days are considered
+/// to start at midnight in New York, so that pre-market and after-hours
+/// aggregates are attributed to the trading day they belong to.
+fn format_bound(span: TimeSpan, date: &Date<Utc>, end: bool) -> String {
+  match span {
+    TimeSpan::Minute | TimeSpan::Hour => {
+      let date = date.naive_utc();
+      let time = if end {
+        // Only the maximum date has no successor, which is of no
+        // practical relevance.
+        let next = date.succ_opt().unwrap_or(date);
+        new_york_midnight(next) - Duration::milliseconds(1)
+      } else {
+        new_york_midnight(date)
+      };
+      time.timestamp_millis().to_string()
+    },
+    TimeSpan::Day | TimeSpan::Week | TimeSpan::Month | TimeSpan::Quarter | TimeSpan::Year => {
+      date.format("%Y-%m-%d").to_string()
+    },
+  }
+}
+
+
+type GetResponse = Response<Vec<Aggregate>>;
+
+/// An aggregates response as reported by Polygon.
+///
+/// Polygon reports `null` or no results at all for ranges without any
+/// aggregates, which we treat as empty.
+type RawGetResponse = Response<Option<Vec<Aggregate>>>;
+
+impl From<RawGetResponse> for GetResponse {
+  fn from(other: RawGetResponse) -> Self {
+    other.map(Option::unwrap_or_default)
+  }
+}
 
 Endpoint! {
   /// The representation of a GET request to the
@@ -105,17 +806,75 @@ Endpoint! {
     /// The ticker information was retrieved successfully.
     /* 200 */ OK,
   ],
-  Err => GetError, []
+  Err => GetError, [],
+  Raw => RawGetResponse,
 
   fn path(input: &Self::Input) -> Str {
-    format!(
-      "/v2/aggs/ticker/{sym}/range/{mult}/{span}/{start}/{end}",
+    let resource = format!(
+      "aggs/ticker/{sym}/range/{mult}/{span}/{start}/{end}",
       sym = input.symbol,
       mult = input.multiplier,
       span = input.time_span.as_ref(),
-      start = input.start_date.format("%Y-%m-%d"),
-      end = input.end_date.format("%Y-%m-%d"),
-    ).into()
+      start = format_bound(input.time_span, &input.start_date, false),
+      end = format_bound(input.time_span, &input.end_date, true),
+    );
+    Version::V2.path(&resource)
+  }
+
+  fn query(_input: &Self::Input) -> Result<Option<Str>, Self::ConversionError> {
+    // Polygon reports only 5000 aggregates by default. Always ask for
+    // the maximum instead.
+    Ok(Some(format!("limit={}", MAX_AGGREGATES).into()))
+  }
+}
+
+
+Endpoint! {
+  /// The representation of a GET request for the newest aggregate in
+  /// the range covered by the provided request.
+  ///
+  /// Polygon is asked to sort aggregates in descending order and to
+  /// report only the first one, so that only a single aggregate is
+  /// transferred irrespective of the size of the range.
+  pub GetLatest(AggregateReq),
+  Ok => GetResponse, [
+    /// The aggregate was retrieved successfully.
+    /* 200 */ OK,
+  ],
+  Err => GetLatestError, [],
+  Raw => RawGetResponse,
+
+  fn path(input: &Self::Input) -> Str {
+    Get::path(input)
+  }
+
+  fn query(_input: &Self::Input) -> Result<Option<Str>, Self::ConversionError> {
+    Ok(Some("sort=desc&limit=1".into()))
+  }
+}
+
+
+Endpoint! {
+  /// The representation of a GET request for a page of aggregates, as
+  /// referenced by the `next_url` of an aggregates response.
+  ///
+  /// The input is the page's URL, or its absolute path including the
+  /// query, optionally prefixed with the API host. Any API key
+  /// contained in the query is replaced with that of the client.
+  pub GetPage(String),
+  Ok => GetResponse, [
+    /// The page was retrieved successfully.
+    /* 200 */ OK,
+  ],
+  Err => GetPageError, [],
+  Raw => RawGetResponse,
+
+  fn path(input: &Self::Input) -> Str {
+    split_next_url(input, None).0.into()
+  }
+
+  fn query(input: &Self::Input) -> Result<Option<Str>, Self::ConversionError> {
+    Ok(split_next_url(input, None).1.map(Str::from))
   }
 }
 
@@ -125,21 +884,445 @@ mod tests {
   use super::*;
 
   use std::f64::EPSILON;
-  use std::str::FromStr as _;
 
   use chrono::Duration;
   use chrono::NaiveDate;
   use chrono::TimeZone as _;
 
   use serde_json::from_str as from_json;
+  use serde_json::to_string as to_json;
 
   #[cfg(not(target_arch = "wasm32"))]
   use test_log::test;
 
+  #[cfg(not(target_arch = "wasm32"))]
+  use std::sync::Arc;
+  #[cfg(not(target_arch = "wasm32"))]
+  use std::sync::Mutex;
+
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::api_info;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::mock_server;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::Response as MockResponse;
   #[cfg(not(target_arch = "wasm32"))]
   use crate::Client;
 
 
+  /// Check that we split requests into chunks not exceeding the
+  /// maximum number of aggregates per response.
+  #[test]
+  fn split_aggregate_req() {
+    let date = |month, day| Utc.from_utc_date(&NaiveDate::from_ymd_opt(2021, month, day).unwrap());
+    let request = AggregateReq {
+      symbol: "AAPL".into(),
+      time_span: TimeSpan::Minute,
+      multiplier: 1,
+      start_date: date(1, 1),
+      end_date: date(3, 31),
+    };
+
+    let ranges = request
+      .split()
+      .into_iter()
+      .map(|request| (request.start_date, request.end_date))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      ranges,
+      vec![
+        (date(1, 1), date(2, 3)),
+        (date(2, 4), date(3, 9)),
+        (date(3, 10), date(3, 31)),
+      ]
+    );
+
+    let request = AggregateReq {
+      multiplier: 5,
+      ..request
+    };
+    assert_eq!(request.split().len(), 1);
+
+    let request = AggregateReq {
+      time_span: TimeSpan::Day,
+      multiplier: 1,
+      start_date: date(1, 1),
+      end_date: date(12, 31),
+      ..request
+    };
+    assert_eq!(request.split(), vec![request]);
+  }
+
+  /// Check that `issue_split` combines the aggregates of all
+  /// sub-requests.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn issue_split_request() {
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let paths_ = paths.clone();
+    let addr = mock_server(move |request| {
+      let paths = paths_.clone();
+      async move {
+        assert!(request.path.contains("limit=50000"), "{}", request.path);
+        // The path ends in `<start>/<end>?<query>`.
+        let start = request.path.split('/').nth_back(1).unwrap().to_string();
+        paths.lock().unwrap().push(request.path.clone());
+
+        // Minute aggregates are requested by millisecond timestamp.
+        let timestamp = start.parse::<i64>().unwrap() + 14 * 3_600_000 + 30 * 60_000;
+        // Every response contains an aggregate for the start of the
+        // range as well as a duplicate one.
+        let body = format!(
+          r#"{{"status":"OK","results":[
+            {{"t":{},"v":1,"o":1,"c":1,"h":1,"l":1}},
+            {{"t":1610980200000,"v":1,"o":1,"c":1,"h":1,"l":1}}
+          ]}}"#,
+          timestamp
+        );
+        MockResponse::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let request = AggregateReq {
+      symbol: "AAPL".into(),
+      time_span: TimeSpan::Minute,
+      multiplier: 1,
+      start_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2021, 1, 1).unwrap()),
+      end_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2021, 3, 31).unwrap()),
+    };
+
+    let aggregates = issue_split(&client, request)
+      .await
+      .unwrap()
+      .into_result()
+      .unwrap();
+    assert_eq!(paths.lock().unwrap().len(), 3);
+
+    let timestamps = aggregates
+      .iter()
+      .map(Aggregate::timestamp_millis)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      timestamps,
+      vec![1609529400000, 1610980200000, 1612467000000, 1615404600000]
+    );
+  }
+
+
+  /// Check that requests for the latest aggregate cover the current
+  /// and the previous unit of the time span.
+  #[test]
+  fn latest_aggregate_req() {
+    let date =
+      |year, month, day| Utc.from_utc_date(&NaiveDate::from_ymd_opt(year, month, day).unwrap());
+    // A Monday.
+    let now = Utc.with_ymd_and_hms(2021, 11, 8, 15, 30, 0).unwrap();
+
+    let request = AggregateReq::latest("AAPL", TimeSpan::Minute, now);
+    assert_eq!(request.symbol, "AAPL");
+    assert_eq!(request.multiplier, 1);
+    assert_eq!(request.start_date, date(2021, 11, 4));
+    assert_eq!(request.end_date, date(2021, 11, 8));
+
+    let request = AggregateReq::latest("AAPL", TimeSpan::Month, now);
+    assert_eq!(request.start_date, date(2021, 10, 1));
+    assert_eq!(request.end_date, date(2021, 11, 8));
+
+    let request = AggregateReq::latest("AAPL", TimeSpan::Year, now);
+    assert_eq!(request.start_date, date(2020, 1, 1));
+  }
+
+  /// Create the request used for exercising `issue_paged`.
+  #[cfg(not(target_arch = "wasm32"))]
+  fn paged_request() -> AggregateReq {
+    AggregateReq {
+      symbol: "AAPL".into(),
+      time_span: TimeSpan::Day,
+      multiplier: 1,
+      start_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2020, 1, 2).unwrap()),
+      end_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2020, 1, 10).unwrap()),
+    }
+  }
+
+  /// Check that `issue_paged` follows the `next_url` reported by
+  /// Polygon, combining the aggregates of all pages.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn issue_paged_request() {
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let paths_ = paths.clone();
+    let addr = mock_server(move |request| {
+      let paths = paths_.clone();
+      async move {
+        paths.lock().unwrap().push(request.path.clone());
+
+        let body = if request.path.contains("cursor=YXA9MiZhcz0mbGltaXQ9MQ") {
+          r#"{"status":"OK","request_id":"2","resultsCount":1,"results":[
+            {"t":1578090000000,"v":1,"o":1,"c":1,"h":1,"l":1}
+          ]}"#
+        } else {
+          r#"{"status":"OK","request_id":"1","resultsCount":1,"results":[
+            {"t":1578003600000,"v":1,"o":1,"c":1,"h":1,"l":1}
+          ],"next_url":"https://api.polygon.io/v2/aggs/ticker/AAPL/range/1/day/1578090000000/1578700800000?cursor=YXA9MiZhcz0mbGltaXQ9MQ"}"#
+        };
+        MockResponse::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let response = issue_paged(&client, paged_request()).await.unwrap();
+    assert_eq!(response.request_id(), Some("2"));
+    assert_eq!(response.next_url(), None);
+
+    let timestamps = response
+      .into_result()
+      .unwrap()
+      .iter()
+      .map(Aggregate::timestamp_millis)
+      .collect::<Vec<_>>();
+    assert_eq!(timestamps, vec![1578003600000, 1578090000000]);
+
+    let paths = paths.lock().unwrap();
+    assert_eq!(paths.len(), 2);
+    assert!(
+      paths[0].starts_with("/v2/aggs/ticker/AAPL/range/1/day/2020-01-02/2020-01-10?limit=50000"),
+      "{}",
+      paths[0]
+    );
+    assert!(
+      paths[1].starts_with(
+        "/v2/aggs/ticker/AAPL/range/1/day/1578090000000/1578700800000?cursor=YXA9MiZhcz0mbGltaXQ9MQ"
+      ),
+      "{}",
+      paths[1]
+    );
+  }
+
+  /// Check that `issue_paged` reports an error instead of following a
+  /// `next_url` referencing a page already retrieved.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn issue_paged_request_cycle() {
+    let requests = Arc::new(Mutex::new(0));
+    let requests_ = requests.clone();
+    let addr = mock_server(move |_request| {
+      let requests = requests_.clone();
+      async move {
+        *requests.lock().unwrap() += 1;
+
+        let body = r#"{"status":"OK","request_id":"1","resultsCount":1,"results":[
+            {"t":1578003600000,"v":1,"o":1,"c":1,"h":1,"l":1}
+          ],"next_url":"https://api.polygon.io/v2/aggs/ticker/AAPL/range/1/day/1578090000000/1578700800000?cursor=YXA9MiZhcz0mbGltaXQ9MQ"}"#;
+        MockResponse::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let err = issue_paged(&client, paged_request()).await.unwrap_err();
+    match err {
+      PagedError::RepeatedPage(page) => assert!(page.ends_with("?cursor=YXA9MiZhcz0mbGltaXQ9MQ")),
+      _ => panic!("unexpected error: {:?}", err),
+    }
+    assert_eq!(*requests.lock().unwrap(), 2);
+  }
+
+  /// Check that `issue_paged` replaces an API key contained in a
+  /// host-prefixed `next_url` instead of sending two of them.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn issue_paged_request_with_api_key() {
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let paths_ = paths.clone();
+    let addr = mock_server(move |request| {
+      let paths = paths_.clone();
+      async move {
+        paths.lock().unwrap().push(request.path.clone());
+
+        let body = if request.path.contains("cursor=YXA9MiZhcz0mbGltaXQ9MQ") {
+          r#"{"status":"OK","request_id":"2","resultsCount":0,"results":[]}"#
+        } else {
+          r#"{"status":"OK","request_id":"1","resultsCount":0,"results":[],"next_url":"api.polygon.io/v2/aggs/ticker/AAPL/range/1/day/1578090000000/1578700800000?cursor=YXA9MiZhcz0mbGltaXQ9MQ&apiKey=YYYYYYYYYYYY"}"#
+        };
+        MockResponse::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let response = issue_paged(&client, paged_request()).await.unwrap();
+    assert_eq!(response.request_id(), Some("2"));
+
+    let paths = paths.lock().unwrap();
+    assert_eq!(paths.len(), 2);
+    assert!(
+      paths[1].starts_with(
+        "/v2/aggs/ticker/AAPL/range/1/day/1578090000000/1578700800000?cursor=YXA9MiZhcz0mbGltaXQ9MQ&apiKey="
+      ),
+      "{}",
+      paths[1]
+    );
+    assert_eq!(paths[1].matches("apiKey=").count(), 1, "{}", paths[1]);
+    assert!(!paths[1].contains("YYYYYYYYYYYY"), "{}", paths[1]);
+  }
+
+  /// Check that multipliers not representable by a single byte make it
+  /// into the request path.
+  #[test]
+  fn large_multiplier_path() {
+    let request = AggregateReq {
+      symbol: "AAPL".into(),
+      time_span: TimeSpan::Minute,
+      multiplier: 300,
+      start_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2021, 11, 1).unwrap()),
+      end_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2021, 11, 30).unwrap()),
+    };
+
+    assert_eq!(
+      Get::path(&request),
+      "/v2/aggs/ticker/AAPL/range/300/minute/1635739200000/1638334799999"
+    );
+  }
+
+  /// Check that minute and hourly aggregates are requested by
+  /// millisecond timestamp, covering all of the end date in New York.
+  #[test]
+  fn intraday_path_uses_millis() {
+    let request = AggregateReq {
+      symbol: "SPY".into(),
+      time_span: TimeSpan::Minute,
+      multiplier: 1,
+      start_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2020, 1, 2).unwrap()),
+      end_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2020, 1, 2).unwrap()),
+    };
+    assert_eq!(
+      Get::path(&request),
+      "/v2/aggs/ticker/SPY/range/1/minute/1577941200000/1578027599999"
+    );
+
+    let request = AggregateReq {
+      time_span: TimeSpan::Hour,
+      ..request
+    };
+    assert_eq!(
+      Get::path(&request),
+      "/v2/aggs/ticker/SPY/range/1/hour/1577941200000/1578027599999"
+    );
+  }
+
+  /// Check that an after-hours aggregate on the end date is covered by
+  /// the requested range, while one on the day before the start date
+  /// is not.
+  #[test]
+  fn intraday_range_covers_after_hours() {
+    let request = AggregateReq {
+      symbol: "SPY".into(),
+      time_span: TimeSpan::Minute,
+      multiplier: 1,
+      start_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2021, 12, 3).unwrap()),
+      end_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2021, 12, 3).unwrap()),
+    };
+
+    let path = Get::path(&request);
+    let mut bounds = path.rsplit('/').map(|bound| bound.parse::<i64>().unwrap());
+    let end = bounds.next().unwrap();
+    let start = bounds.next().unwrap();
+
+    // 2021-12-03 19:59 EST, i.e., 2021-12-04 00:59 UTC.
+    let after_hours = Utc.with_ymd_and_hms(2021, 12, 4, 0, 59, 0).unwrap();
+    assert!(start <= after_hours.timestamp_millis());
+    assert!(after_hours.timestamp_millis() <= end);
+
+    // 2021-12-02 19:59 EST, i.e., 2021-12-03 00:59 UTC.
+    let previous = Utc.with_ymd_and_hms(2021, 12, 3, 0, 59, 0).unwrap();
+    assert!(previous.timestamp_millis() < start);
+  }
+
+  /// Check that daily and coarser aggregates are requested by date.
+  #[test]
+  fn daily_path_uses_dates() {
+    let request = AggregateReq {
+      symbol: "SPY".into(),
+      time_span: TimeSpan::Day,
+      multiplier: 1,
+      start_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2020, 1, 2).unwrap()),
+      end_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2020, 3, 31).unwrap()),
+    };
+    assert_eq!(
+      Get::path(&request),
+      "/v2/aggs/ticker/SPY/range/1/day/2020-01-02/2020-03-31"
+    );
+
+    let request = AggregateReq {
+      time_span: TimeSpan::Month,
+      ..request
+    };
+    assert_eq!(
+      Get::path(&request),
+      "/v2/aggs/ticker/SPY/range/1/month/2020-01-02/2020-03-31"
+    );
+  }
+
+  /// Check that we can parse an `AggregateReq` from a compact
+  /// specification.
+  #[test]
+  fn parse_aggregate_req() {
+    let request = AggregateReq::try_from("AAPL:1:day:2018-02-01:2018-03-01").unwrap();
+    let expected = AggregateReq {
+      symbol: "AAPL".into(),
+      time_span: TimeSpan::Day,
+      multiplier: 1,
+      start_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2018, 2, 1).unwrap()),
+      end_date: Utc.from_utc_date(&NaiveDate::from_ymd_opt(2018, 3, 1).unwrap()),
+    };
+    assert_eq!(request, expected);
+
+    let request = AggregateReq::from_str("SPY:15:minute:2021-11-01:2021-11-30").unwrap();
+    assert_eq!(request.time_span, TimeSpan::Minute);
+    assert_eq!(request.multiplier, 15);
+  }
+
+  /// Check that we reject malformed compact `AggregateReq`
+  /// specifications with a descriptive error.
+  #[test]
+  fn parse_malformed_aggregate_req() {
+    let specs = [
+      ("", "expected <symbol>:<multiplier>:<span>:<start>:<end>"),
+      ("AAPL:1:day:2018-02-01", "expected <symbol>"),
+      ("AAPL:1:day:2018-02-01:2018-03-01:foo", "expected <symbol>"),
+      (":1:day:2018-02-01:2018-03-01", "empty symbol"),
+      ("AAPL:one:day:2018-02-01:2018-03-01", "invalid multiplier 'one'"),
+      ("AAPL:-1:day:2018-02-01:2018-03-01", "invalid multiplier '-1'"),
+      ("AAPL:1:days:2018-02-01:2018-03-01", "invalid time span: days"),
+      ("AAPL:1:day:2018-02-30:2018-03-01", "invalid date '2018-02-30'"),
+      ("AAPL:1:day:2018-02-01:03/01/2018", "invalid date '03/01/2018'"),
+    ];
+
+    for (spec, expected) in &specs {
+      let err = AggregateReq::from_str(spec).unwrap_err().to_string();
+      assert!(err.contains(expected), "{}: {}", spec, err);
+    }
+  }
+
+  /// Check that an `AggregateReq` survives a serialization round trip.
+  #[test]
+  fn serialize_deserialize_aggregate_req() {
+    let request = AggregateReq::from_str("AAPL:5:minute:2018-02-01:2018-03-01").unwrap();
+    let json = to_json(&request).unwrap();
+    assert_eq!(
+      json,
+      r#"{"symbol":"AAPL","time_span":"minute","multiplier":5,"start_date":"2018-02-01","end_date":"2018-03-01"}"#
+    );
+    assert_eq!(from_json::<AggregateReq>(&json).unwrap(), request);
+
+    let json = json.replace("minute", "minutes");
+    let err = from_json::<AggregateReq>(&json).unwrap_err();
+    assert!(err.to_string().contains("invalid time span"), "{}", err);
+  }
+
   /// Make sure that we can deserialize an `Aggregate`.
   #[test]
   fn deserialize_aggregate() {
@@ -167,6 +1350,162 @@ mod tests {
     assert_eq!(aggregate.close_price, Num::new(10374, 100));
     assert_eq!(aggregate.high_price, Num::new(10382, 100));
     assert_eq!(aggregate.low_price, Num::new(10265, 100));
+    assert_eq!(aggregate.otc, None);
+    assert_eq!(aggregate.timestamp_millis(), 1549314000000);
+  }
+
+  /// Check that the `otc` flag is picked up when present and survives
+  /// a serialization round trip.
+  #[test]
+  fn deserialize_serialize_otc_aggregates() {
+    let response = r#"[
+  {"v":8800,"o":0.0415,"c":0.0404,"h":0.0415,"l":0.0404,"t":1637038800000,"otc":true},
+  {"v":31315282,"o":102.87,"c":103.74,"h":103.82,"l":102.65,"t":1549314000000}
+]"#;
+
+    let aggregates = from_json::<Vec<Aggregate>>(response).unwrap();
+    assert_eq!(aggregates.len(), 2);
+    assert_eq!(aggregates[0].otc, Some(true));
+    assert_eq!(aggregates[1].otc, None);
+
+    let json = to_json(&aggregates).unwrap();
+    let parsed = from_json::<Vec<Aggregate>>(&json).unwrap();
+    assert_eq!(parsed, aggregates);
+  }
+
+  /// Check that the symbol is picked up when keyed as either `T` or
+  /// `sym`.
+  #[test]
+  fn deserialize_aggregate_symbol() {
+    let bars = r#"[
+  {"T":"AAPL","v":8800,"o":102.87,"c":103.74,"h":103.82,"l":102.65,"t":1549314000000},
+  {"sym":"AAPL","v":8800,"o":102.87,"c":103.74,"h":103.82,"l":102.65,"t":1549314000000}
+]"#;
+
+    let aggregates = from_json::<Vec<Aggregate>>(bars).unwrap();
+    assert_eq!(aggregates[0].symbol.as_deref(), Some("AAPL"));
+    assert_eq!(aggregates[0], aggregates[1]);
+
+    let json = to_json(&aggregates[0]).unwrap();
+    assert!(json.contains(r#""T":"AAPL""#), "{}", json);
+  }
+
+  /// Check that we can convert a daily REST aggregate into a streaming
+  /// one.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test]
+  fn convert_day_aggregate_into_event_aggregate() {
+    let response = r#"{"v":3.1315282e+07,"o":102.87,"c":103.74,"h":103.82,"l":102.65,"t":1549256400000}"#;
+    let aggregate = from_json::<Aggregate>(response).unwrap();
+    let aggregate = aggregate.into_event_aggregate("AAPL".to_string(), TimeSpan::Day);
+
+    assert_eq!(aggregate.symbol, "AAPL");
+    assert_eq!(aggregate.volume, 31_315_282);
+    assert_eq!(aggregate.accumulated_volume, None);
+    assert_eq!(aggregate.official_open_price, None);
+    assert_eq!(aggregate.tick_vwap, None);
+    assert_eq!(aggregate.day_vwap, None);
+    assert_eq!(aggregate.open_price, Num::new(10287, 100));
+    assert_eq!(aggregate.close_price, Num::new(10374, 100));
+    assert_eq!(aggregate.high_price, Num::new(10382, 100));
+    assert_eq!(aggregate.low_price, Num::new(10265, 100));
+    assert_eq!(
+      aggregate.start_timestamp,
+      DateTime::parse_from_rfc3339("2019-02-04T00:00:00-05:00").unwrap()
+    );
+    assert_eq!(
+      aggregate.end_timestamp,
+      DateTime::parse_from_rfc3339("2019-02-05T00:00:00-05:00").unwrap()
+    );
+  }
+
+  /// Check that advancing by calendar based time spans clamps the day
+  /// of the month.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test]
+  fn advance_by_time_span() {
+    let time = DateTime::parse_from_rfc3339("2020-01-31T05:00:00Z")
+      .unwrap()
+      .with_timezone(&Utc);
+    let expected = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+    assert_eq!(TimeSpan::Month.add_to(time), expected("2020-02-29T05:00:00Z"));
+    assert_eq!(TimeSpan::Quarter.add_to(time), expected("2020-04-30T05:00:00Z"));
+    assert_eq!(TimeSpan::Year.add_to(time), expected("2021-01-31T05:00:00Z"));
+    assert_eq!(TimeSpan::Week.add_to(time), expected("2020-02-07T05:00:00Z"));
+  }
+
+  /// Check that we report the approximate duration of time spans.
+  #[test]
+  fn approx_time_span_duration() {
+    assert_eq!(TimeSpan::Hour.approx_duration(2), Duration::hours(2));
+    assert_eq!(TimeSpan::Minute.approx_duration(1), Duration::minutes(1));
+    assert_eq!(TimeSpan::Week.approx_duration(3), Duration::weeks(3));
+    assert_eq!(TimeSpan::Day.approx_duration(0), Duration::zero());
+    assert_eq!(
+      TimeSpan::Year.approx_duration(1),
+      Duration::seconds(31_556_952)
+    );
+    assert_eq!(
+      TimeSpan::Quarter.approx_duration(4),
+      TimeSpan::Year.approx_duration(1)
+    );
+  }
+
+  /// Check that we can snap points in time to time span boundaries.
+  #[test]
+  fn snap_time_to_span() {
+    let time = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+    let mid_minute = time("2021-11-03T14:37:21.500Z");
+
+    assert_eq!(TimeSpan::Minute.floor(mid_minute), time("2021-11-03T14:37:00Z"));
+    assert_eq!(TimeSpan::Minute.ceil(mid_minute), time("2021-11-03T14:38:00Z"));
+    assert_eq!(TimeSpan::Hour.floor(mid_minute), time("2021-11-03T14:00:00Z"));
+    assert_eq!(TimeSpan::Hour.ceil(mid_minute), time("2021-11-03T15:00:00Z"));
+    assert_eq!(TimeSpan::Day.ceil(mid_minute), time("2021-11-04T00:00:00Z"));
+    // 2021-11-03 was a Wednesday.
+    assert_eq!(TimeSpan::Week.floor(mid_minute), time("2021-10-31T00:00:00Z"));
+    assert_eq!(TimeSpan::Month.floor(mid_minute), time("2021-11-01T00:00:00Z"));
+    assert_eq!(TimeSpan::Quarter.floor(mid_minute), time("2021-10-01T00:00:00Z"));
+    assert_eq!(TimeSpan::Quarter.ceil(mid_minute), time("2022-01-01T00:00:00Z"));
+    assert_eq!(TimeSpan::Year.floor(mid_minute), time("2021-01-01T00:00:00Z"));
+
+    // Points in time already on a boundary stay where they are.
+    let aligned = time("2021-11-03T14:37:00Z");
+    assert_eq!(TimeSpan::Minute.floor(aligned), aligned);
+    assert_eq!(TimeSpan::Minute.ceil(aligned), aligned);
+  }
+
+  /// Check that we can snap the dates of an `AggregateReq` to its time
+  /// span's boundaries.
+  #[test]
+  fn snap_aggregate_req() {
+    let date = |y, m, d| Utc.from_utc_date(&NaiveDate::from_ymd_opt(y, m, d).unwrap());
+    let request = AggregateReq {
+      symbol: "AAPL".into(),
+      time_span: TimeSpan::Month,
+      multiplier: 1,
+      start_date: date(2021, 11, 15),
+      end_date: date(2022, 2, 10),
+    };
+
+    let snapped = request.clone().snap();
+    assert_eq!(snapped.start_date, date(2021, 11, 1));
+    assert_eq!(snapped.end_date, date(2022, 2, 28));
+
+    let request = AggregateReq {
+      time_span: TimeSpan::Week,
+      ..request
+    };
+    let snapped = request.clone().snap();
+    assert_eq!(snapped.start_date, date(2021, 11, 14));
+    assert_eq!(snapped.end_date, date(2022, 2, 12));
+
+    let request = AggregateReq {
+      time_span: TimeSpan::Minute,
+      ..request
+    };
+    assert_eq!(request.clone().snap(), request);
   }
 
   #[test]
@@ -193,7 +1532,6 @@ mod tests {
     let mut aggregates = from_json::<GetResponse>(response)
       .unwrap()
       .into_result()
-      .unwrap()
       .unwrap();
 
     assert_eq!(aggregates.len(), 1);
@@ -206,6 +1544,82 @@ mod tests {
     );
   }
 
+  /// Check that aggregates are sorted by their timestamp.
+  #[test]
+  fn sort_aggregates() {
+    let response = r#"{
+  "status": "OK",
+  "results": [
+    {"v":300,"o":3,"c":3,"h":3,"l":3,"t":1549314180000},
+    {"v":100,"o":1,"c":1,"h":1,"l":1,"t":1549314060000},
+    {"v":200,"o":2,"c":2,"h":2,"l":2,"t":1549314120000}
+  ]
+}"#;
+
+    let mut aggregates = from_json::<GetResponse>(response)
+      .unwrap()
+      .into_result()
+      .unwrap();
+    aggregates.sort();
+
+    let timestamps = aggregates
+      .iter()
+      .map(Aggregate::timestamp_millis)
+      .collect::<Vec<_>>();
+    assert_eq!(timestamps, vec![1549314060000, 1549314120000, 1549314180000]);
+    assert_eq!(aggregates[0].open_price, Num::from(1));
+  }
+
+  /// Check that equality and ordering of aggregates agree, even for
+  /// volumes that are not a number.
+  #[test]
+  fn compare_aggregates_with_nan_volume() {
+    let aggregate = from_json::<Aggregate>(r#"{"v":1,"o":1,"c":1,"h":1,"l":1,"t":1549314060000}"#)
+      .unwrap();
+    let nan = Aggregate {
+      volume: f64::NAN,
+      ..aggregate.clone()
+    };
+    assert_eq!(nan, nan);
+    assert_eq!(nan.cmp(&nan), Ordering::Equal);
+    assert_ne!(nan, aggregate);
+    assert_ne!(nan.cmp(&aggregate), Ordering::Equal);
+
+    let zero = Aggregate {
+      volume: 0.0,
+      ..aggregate.clone()
+    };
+    let negative_zero = Aggregate {
+      volume: -0.0,
+      ..aggregate
+    };
+    assert_ne!(zero, negative_zero);
+    assert_eq!(negative_zero.cmp(&zero), Ordering::Less);
+  }
+
+  /// Check that we treat `null` or missing results as empty.
+  #[test]
+  fn deserialize_null_results() {
+    let response = r#"{
+  "ticker": "AAPL",
+  "status": "OK",
+  "adjusted": true,
+  "queryCount": 0,
+  "resultsCount": 0,
+  "results": null
+}"#;
+    let result = Get::parse(response.as_bytes()).unwrap().into_result();
+    assert_eq!(result, Ok(Vec::new()));
+
+    let response = r#"{"ticker":"AAPL","status":"OK","queryCount":0,"resultsCount":0}"#;
+    let result = Get::parse(response.as_bytes()).unwrap().into_result();
+    assert_eq!(result, Ok(Vec::new()));
+
+    // Other responses still require results to be present.
+    let result = from_json::<Response<Vec<Aggregate>>>(response);
+    assert!(result.is_err());
+  }
+
   #[cfg(not(target_arch = "wasm32"))]
   #[test(tokio::test)]
   async fn request_empty_aggregates() {
@@ -226,8 +1640,7 @@ mod tests {
       .await
       .unwrap()
       .into_result()
-      .unwrap()
-      .unwrap_or_default();
+      .unwrap();
 
     assert_eq!(result, Vec::new());
   }
@@ -252,7 +1665,6 @@ mod tests {
       .await
       .unwrap()
       .into_result()
-      .unwrap()
       .unwrap();
 
     // The number of trading days was inferred to be 21.
@@ -289,7 +1701,7 @@ mod tests {
       .into_result()
       .unwrap();
 
-    assert_eq!(aggregates, None);
+    assert_eq!(aggregates, Vec::new());
   }
 
   #[cfg(not(target_arch = "wasm32"))]
@@ -312,7 +1724,6 @@ mod tests {
       .await
       .unwrap()
       .into_result()
-      .unwrap()
       .unwrap();
 
     assert_eq!(aggregates.len(), 384);
@@ -341,7 +1752,6 @@ mod tests {
       .await
       .unwrap()
       .into_result()
-      .unwrap()
       .unwrap();
 
     // We expect 15 aggregates for the hours 4:00 to 19:00 (both inclusive).
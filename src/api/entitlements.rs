@@ -0,0 +1,134 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use serde_json::Value;
+
+use crate::api::Response;
+use crate::api::Status;
+use crate::endpoint::Version;
+use crate::error::RequestError;
+use crate::Str;
+
+
+/// The symbol used for probing entitlements.
+pub(crate) const PROBE_SYMBOL: &str = "AAPL";
+
+
+/// The entitlement of an API key, as determined by probing a real-time
+/// data endpoint.
+///
+/// Polygon does not expose an account's entitlements directly. The
+/// classification is inferred from the response to a last trade
+/// request instead.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Entitlement {
+  /// The API key is entitled to real-time data.
+  RealTime,
+  /// The API key is entitled to delayed data only.
+  Delayed,
+  /// The API key is not entitled to the data.
+  Unauthorized,
+}
+
+impl Entitlement {
+  /// Classify the result of a probe request.
+  pub(crate) fn classify(
+    result: Result<Response<Value>, RequestError<GetError>>,
+  ) -> Result<Self, RequestError<GetError>> {
+    match result {
+      Ok(response) => match response.status {
+        Status::Ok(..) => Ok(Self::RealTime),
+        Status::Delayed(..) => Ok(Self::Delayed),
+        // Polygon reports a status of "NOT_AUTHORIZED" in case the
+        // plan does not cover the data.
+        Status::Err => Ok(Self::Unauthorized),
+      },
+      Err(RequestError::Endpoint(GetError::AuthenticationFailed(..)))
+      | Err(RequestError::Endpoint(GetError::NotAuthorized(..))) => Ok(Self::Unauthorized),
+      Err(err) => Err(err),
+    }
+  }
+}
+
+
+Endpoint! {
+  /// The representation of a GET request to the
+  /// `/v2/last/trade/<symbol>` endpoint, used for probing the
+  /// entitlements of an API key.
+  pub Get(String),
+  Ok => Response<Value>, [
+    /// The last trade was retrieved successfully.
+    /* 200 */ OK,
+  ],
+  Err => GetError, [
+    /// The API key is not entitled to the requested data.
+    /* 403 */ FORBIDDEN => NotAuthorized,
+  ]
+
+  fn path(input: &Self::Input) -> Str {
+    Version::V2.path(&format!("last/trade/{}", input))
+  }
+}
+
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use test_log::test;
+
+  use crate::mock::api_info;
+  use crate::mock::mock_server;
+  use crate::mock::Response as MockResponse;
+  use crate::Client;
+
+
+  /// Probe the entitlements of a client talking to a mock server that
+  /// responds with the given status and body.
+  async fn probe(status: u16, body: &'static str) -> Entitlement {
+    let addr = mock_server(move |request| async move {
+      assert!(request.path.starts_with("/v2/last/trade/AAPL?"));
+      MockResponse::json(status, body)
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    client.entitlement().await.unwrap()
+  }
+
+
+  /// Check that we classify a successful response as real-time
+  /// entitlement.
+  #[test(tokio::test)]
+  async fn classify_real_time() {
+    let body = r#"{"status":"OK","request_id":"f05562305bd26ced64b98ed68b3c5d96","results":{"T":"AAPL","p":129.8473,"s":25,"t":1617901342969834000}}"#;
+    assert_eq!(probe(200, body).await, Entitlement::RealTime);
+  }
+
+  /// Check that we classify a delayed response as delayed entitlement.
+  #[test(tokio::test)]
+  async fn classify_delayed() {
+    let body = r#"{"status":"DELAYED","request_id":"f05562305bd26ced64b98ed68b3c5d96","results":{"T":"AAPL","p":129.8473,"s":25,"t":1617901342969834000}}"#;
+    assert_eq!(probe(200, body).await, Entitlement::Delayed);
+  }
+
+  /// Check that we classify a rejected request as missing entitlement.
+  #[test(tokio::test)]
+  async fn classify_unauthorized() {
+    let body = r#"{"status":"NOT_AUTHORIZED","request_id":"f05562305bd26ced64b98ed68b3c5d96","message":"You are not entitled to this data. Please upgrade your plan at https://polygon.io/pricing"}"#;
+    assert_eq!(probe(403, body).await, Entitlement::Unauthorized);
+
+    let body = r#"{"status":"ERROR","request_id":"f05562305bd26ced64b98ed68b3c5d96","error":"Unknown API Key"}"#;
+    assert_eq!(probe(401, body).await, Entitlement::Unauthorized);
+  }
+
+  /// Check that other errors are reported as such.
+  #[test(tokio::test)]
+  async fn classify_error() {
+    let addr = mock_server(|_request| async { MockResponse::json(500, "{}") }).await;
+    let client = Client::new(api_info(addr));
+    let result = client.entitlement().await;
+    assert!(result.is_err(), "{:?}", result);
+  }
+}
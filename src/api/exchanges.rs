@@ -3,6 +3,7 @@
 
 use serde::Deserialize;
 
+use crate::endpoint::Version;
 use crate::Str;
 
 
@@ -41,7 +42,7 @@ Endpoint! {
   Err => GetError, []
 
   fn path(_input: &Self::Input) -> Str {
-    "/v1/meta/exchanges".into()
+    Version::V1.path("meta/exchanges")
   }
 }
 
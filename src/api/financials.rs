@@ -0,0 +1,240 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use chrono::NaiveDate;
+
+use num_decimal::Num;
+
+use serde::Deserialize;
+use serde_json::Error as JsonError;
+
+use url::form_urlencoded::Serializer;
+
+use crate::api::response::Response;
+use crate::de::opt_num_from_json;
+use crate::endpoint::Version;
+use crate::Str;
+
+
+/// The type of period a financial report covers.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub enum Period {
+  /// An annual report.
+  #[serde(rename = "Y")]
+  Year,
+  /// A quarterly report.
+  #[serde(rename = "Q")]
+  Quarter,
+  /// A report covering the trailing twelve months.
+  #[serde(rename = "T")]
+  TrailingTwelveMonths,
+}
+
+impl AsRef<str> for Period {
+  fn as_ref(&self) -> &'static str {
+    match *self {
+      Period::Year => "Y",
+      Period::Quarter => "Q",
+      Period::TrailingTwelveMonths => "T",
+    }
+  }
+}
+
+
+/// A request to the `/v2/reference/financials/<ticker>` endpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FinancialsReq {
+  /// The ticker to retrieve financials for.
+  pub ticker: String,
+  /// The maximum number of reports to retrieve.
+  pub limit: Option<usize>,
+  /// The type of period of the reports to retrieve.
+  pub period: Option<Period>,
+}
+
+
+/// A financial report as returned by the
+/// `/v2/reference/financials/<ticker>` endpoint.
+///
+/// Please note that only the key line items of income statement and
+/// balance sheet are represented here.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Financials {
+  /// The ticker the report is for.
+  #[serde(rename = "ticker")]
+  pub ticker: String,
+  /// The type of period the report covers.
+  #[serde(rename = "period")]
+  pub period: Period,
+  /// The calendar date the report is associated with.
+  #[serde(rename = "calendarDate")]
+  pub calendar_date: NaiveDate,
+  /// The end of the reporting period.
+  #[serde(rename = "reportPeriod")]
+  pub report_period: NaiveDate,
+  /// The date the report was last updated.
+  #[serde(rename = "updated")]
+  pub updated: NaiveDate,
+  /// The revenues in the period.
+  #[serde(rename = "revenues", default, deserialize_with = "opt_num_from_json")]
+  pub revenues: Option<Num>,
+  /// The gross profit in the period.
+  #[serde(rename = "grossProfit", default, deserialize_with = "opt_num_from_json")]
+  pub gross_profit: Option<Num>,
+  /// The operating income in the period.
+  #[serde(rename = "operatingIncome", default, deserialize_with = "opt_num_from_json")]
+  pub operating_income: Option<Num>,
+  /// The net income in the period.
+  #[serde(rename = "netIncome", default, deserialize_with = "opt_num_from_json")]
+  pub net_income: Option<Num>,
+  /// The basic earnings per share.
+  #[serde(
+    rename = "earningsPerBasicShare",
+    default,
+    deserialize_with = "opt_num_from_json"
+  )]
+  pub earnings_per_basic_share: Option<Num>,
+  /// The total assets at the end of the period.
+  #[serde(rename = "assets", default, deserialize_with = "opt_num_from_json")]
+  pub assets: Option<Num>,
+  /// The total liabilities at the end of the period.
+  #[serde(rename = "liabilities", default, deserialize_with = "opt_num_from_json")]
+  pub liabilities: Option<Num>,
+  /// The shareholders' equity at the end of the period.
+  #[serde(
+    rename = "shareholdersEquity",
+    default,
+    deserialize_with = "opt_num_from_json"
+  )]
+  pub shareholders_equity: Option<Num>,
+  /// The cash and equivalents at the end of the period.
+  #[serde(
+    rename = "cashAndEquivalents",
+    default,
+    deserialize_with = "opt_num_from_json"
+  )]
+  pub cash_and_equivalents: Option<Num>,
+}
+
+
+Endpoint! {
+  /// The representation of a GET request to the
+  /// `/v2/reference/financials/<ticker>` endpoint.
+  pub Get(FinancialsReq),
+  Ok => Response<Vec<Financials>>, [
+    /// The financials were retrieved successfully.
+    /* 200 */ OK,
+  ],
+  Err => GetError, [
+    /// The specified resource was not found.
+    /* 404 */ NOT_FOUND => NotFound,
+  ]
+
+  fn path(input: &Self::Input) -> Str {
+    Version::V2.path(&format!("reference/financials/{}", input.ticker))
+  }
+
+  fn query(input: &Self::Input) -> Result<Option<Str>, JsonError> {
+    let mut query = Serializer::new(String::new());
+    if let Some(limit) = &input.limit {
+      let _ = query.append_pair("limit", &limit.to_string());
+    }
+    if let Some(period) = &input.period {
+      let _ = query.append_pair("type", period.as_ref());
+    }
+
+    let query = query.finish();
+    if query.is_empty() {
+      Ok(None)
+    } else {
+      Ok(Some(query.into()))
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use http_endpoint::Endpoint as _;
+
+  use serde_json::from_str as from_json;
+
+
+  /// Check that we can decode a response containing financials.
+  #[test]
+  fn parse_financials() {
+    let response = r#"{
+  "status": "OK",
+  "results": [
+    {
+      "ticker": "AAPL",
+      "period": "Q",
+      "calendarDate": "2020-03-31",
+      "reportPeriod": "2020-03-28",
+      "updated": "2020-05-01",
+      "dateKey": "2020-05-01",
+      "revenues": 58313000000,
+      "grossProfit": 22370000000,
+      "operatingIncome": 12853000000,
+      "netIncome": 11249000000,
+      "earningsPerBasicShare": 2.58,
+      "earningsPerDilutedShare": 2.55,
+      "assets": 320400000000,
+      "liabilities": 241975000000,
+      "shareholdersEquity": 78425000000,
+      "cashAndEquivalents": 40174000000
+    },
+    {
+      "ticker": "AAPL",
+      "period": "Q",
+      "calendarDate": "2019-12-31",
+      "reportPeriod": "2019-12-28",
+      "updated": "2020-01-29",
+      "revenues": 91819000000
+    }
+  ]
+}"#;
+
+    let mut financials = from_json::<Response<Vec<Financials>>>(response)
+      .unwrap()
+      .into_result()
+      .unwrap();
+    assert_eq!(financials.len(), 2);
+
+    let report = financials.remove(0);
+    assert_eq!(report.ticker, "AAPL");
+    assert_eq!(report.period, Period::Quarter);
+    assert_eq!(report.calendar_date, NaiveDate::from_ymd_opt(2020, 3, 31).unwrap());
+    assert_eq!(report.report_period, NaiveDate::from_ymd_opt(2020, 3, 28).unwrap());
+    assert_eq!(report.updated, NaiveDate::from_ymd_opt(2020, 5, 1).unwrap());
+    assert_eq!(report.revenues, Some(Num::from(58_313_000_000u64)));
+    assert_eq!(report.net_income, Some(Num::from(11_249_000_000u64)));
+    assert_eq!(report.earnings_per_basic_share, Some(Num::new(258, 100)));
+    assert_eq!(report.shareholders_equity, Some(Num::from(78_425_000_000u64)));
+
+    let report = financials.remove(0);
+    assert_eq!(report.revenues, Some(Num::from(91_819_000_000u64)));
+    assert_eq!(report.assets, None);
+  }
+
+  /// Check that we pass the limit and period as query parameters.
+  #[test]
+  fn financials_query() {
+    let request = FinancialsReq {
+      ticker: "AAPL".into(),
+      limit: Some(4),
+      period: Some(Period::TrailingTwelveMonths),
+    };
+    assert_eq!(Get::path(&request), "/v2/reference/financials/AAPL");
+    assert_eq!(Get::query(&request).unwrap().unwrap(), "limit=4&type=T");
+
+    let request = FinancialsReq {
+      ticker: "AAPL".into(),
+      limit: None,
+      period: None,
+    };
+    assert_eq!(Get::query(&request).unwrap(), None);
+  }
+}
@@ -0,0 +1,236 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use chrono::serde::ts_milliseconds::deserialize as datetime_from_timestamp;
+use chrono::DateTime;
+use chrono::Utc;
+
+use num_decimal::Num;
+
+use serde::Deserialize;
+use serde_json::Error as JsonError;
+
+use url::form_urlencoded::Serializer;
+
+use crate::api::aggregates::Aggregate;
+use crate::api::aggregates::TimeSpan;
+use crate::api::response::Response;
+use crate::de::num_from_json;
+use crate::endpoint::Version;
+use crate::Str;
+
+
+/// A technical indicator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Indicator {
+  /// The simple moving average.
+  SimpleMovingAverage,
+  /// The exponential moving average.
+  ExponentialMovingAverage,
+  /// The relative strength index.
+  RelativeStrengthIndex,
+}
+
+impl AsRef<str> for Indicator {
+  fn as_ref(&self) -> &'static str {
+    match *self {
+      Indicator::SimpleMovingAverage => "sma",
+      Indicator::ExponentialMovingAverage => "ema",
+      Indicator::RelativeStrengthIndex => "rsi",
+    }
+  }
+}
+
+
+/// A GET request to be made to the
+/// `/v1/indicators/<indicator>/<symbol>` endpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndicatorReq {
+  /// The indicator to retrieve.
+  pub indicator: Indicator,
+  /// The ticker symbol to retrieve the indicator for.
+  pub symbol: String,
+  /// The time span of the aggregates the indicator is calculated
+  /// from.
+  pub time_span: TimeSpan,
+  /// The number of aggregates the indicator is calculated over.
+  ///
+  /// Polygon uses a window of 50 if none is provided.
+  pub window: Option<u32>,
+  /// Whether the underlying aggregates are adjusted for splits.
+  ///
+  /// Polygon adjusts aggregates if not specified otherwise.
+  pub adjusted: Option<bool>,
+  /// Whether to include the aggregates the indicator was calculated
+  /// from in the response.
+  pub expand_underlying: bool,
+  /// The maximum number of values to retrieve.
+  pub limit: Option<usize>,
+}
+
+
+/// A single value of an indicator.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Value {
+  /// The timestamp of the aggregate the value corresponds to.
+  #[serde(rename = "timestamp", deserialize_with = "datetime_from_timestamp")]
+  pub timestamp: DateTime<Utc>,
+  /// The indicator's value.
+  #[serde(rename = "value", deserialize_with = "num_from_json")]
+  pub value: Num,
+}
+
+
+/// The aggregates an indicator was calculated from.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Underlying {
+  /// The aggregates, if they were requested.
+  #[serde(rename = "aggregates", default)]
+  pub aggregates: Vec<Aggregate>,
+}
+
+
+/// The values of an indicator as returned by the
+/// `/v1/indicators/<indicator>/<symbol>` endpoint.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Indicators {
+  /// The indicator's values, most recent first.
+  #[serde(rename = "values", default)]
+  pub values: Vec<Value>,
+  /// The underlying aggregates, if requested by means of
+  /// `IndicatorReq::expand_underlying`.
+  #[serde(rename = "underlying", default)]
+  pub underlying: Option<Underlying>,
+}
+
+
+Endpoint! {
+  /// The representation of a GET request to the
+  /// `/v1/indicators/<indicator>/<symbol>` endpoint.
+  pub Get(IndicatorReq),
+  Ok => Response<Indicators>, [
+    /// The indicator was retrieved successfully.
+    /* 200 */ OK,
+  ],
+  Err => GetError, [
+    /// The specified resource was not found.
+    /* 404 */ NOT_FOUND => NotFound,
+  ]
+
+  fn path(input: &Self::Input) -> Str {
+    Version::V1.path(&format!("indicators/{}/{}", input.indicator.as_ref(), input.symbol))
+  }
+
+  fn query(input: &Self::Input) -> Result<Option<Str>, JsonError> {
+    let mut query = Serializer::new(String::new());
+    let _ = query.append_pair("timespan", input.time_span.as_ref());
+    if let Some(window) = &input.window {
+      let _ = query.append_pair("window", &window.to_string());
+    }
+    if let Some(adjusted) = &input.adjusted {
+      let _ = query.append_pair("adjusted", &adjusted.to_string());
+    }
+    if input.expand_underlying {
+      let _ = query.append_pair("expand_underlying", "true");
+    }
+    if let Some(limit) = &input.limit {
+      let _ = query.append_pair("limit", &limit.to_string());
+    }
+    Ok(Some(query.finish().into()))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use http_endpoint::Endpoint as _;
+
+  use serde_json::from_str as from_json;
+
+
+  /// Check that we can decode a response including the underlying
+  /// aggregates.
+  #[test]
+  fn parse_expanded_underlying() {
+    let response = r#"{
+  "results": {
+    "underlying": {
+      "aggregates": [
+        {"v":53623,"vw":4.2336,"o":4.22,"c":4.19,"h":4.28,"l":4.18,"t":1664812800000,"n":288},
+        {"v":51930,"vw":4.173,"o":4.19,"c":4.18,"h":4.2,"l":4.14,"t":1664899200000,"n":319}
+      ],
+      "url": "https://api.polygon.io/v2/aggs/ticker/UFO/range/1/day/1063281600000/1664985600000"
+    },
+    "values": [
+      {"timestamp":1664899200000,"value":4.185},
+      {"timestamp":1664812800000,"value":4.205}
+    ]
+  },
+  "status": "OK",
+  "request_id": "b9d3a8c8cdff5ee0c45cdad0dc9e75b2"
+}"#;
+
+    let indicators = from_json::<Response<Indicators>>(response)
+      .unwrap()
+      .into_result()
+      .unwrap();
+    assert_eq!(indicators.values.len(), 2);
+    assert_eq!(indicators.values[0].value, Num::new(4185, 1000));
+    assert_eq!(indicators.values[0].timestamp.timestamp_millis(), 1664899200000);
+
+    let aggregates = indicators.underlying.unwrap().aggregates;
+    assert_eq!(aggregates.len(), 2);
+    assert_eq!(aggregates[1].close_price, Num::new(418, 100));
+    assert_eq!(aggregates[1].timestamp_millis(), 1664899200000);
+  }
+
+  /// Check that we can decode a response without underlying aggregates.
+  #[test]
+  fn parse_values_only() {
+    let response = r#"{
+  "results": {
+    "underlying": {
+      "url": "https://api.polygon.io/v2/aggs/ticker/UFO/range/1/day/1063281600000/1664985600000"
+    },
+    "values": [{"timestamp":1664899200000,"value":4.185}]
+  },
+  "status": "OK"
+}"#;
+
+    let indicators = from_json::<Response<Indicators>>(response)
+      .unwrap()
+      .into_result()
+      .unwrap();
+    assert_eq!(indicators.values.len(), 1);
+    assert_eq!(indicators.underlying.unwrap().aggregates, Vec::new());
+  }
+
+  /// Check that we pass the provided options as query parameters.
+  #[test]
+  fn indicator_query() {
+    let request = IndicatorReq {
+      indicator: Indicator::SimpleMovingAverage,
+      symbol: "UFO".into(),
+      time_span: TimeSpan::Day,
+      window: Some(20),
+      adjusted: Some(false),
+      expand_underlying: true,
+      limit: None,
+    };
+    assert_eq!(Get::path(&request), "/v1/indicators/sma/UFO");
+    assert_eq!(
+      Get::query(&request).unwrap().unwrap(),
+      "timespan=day&window=20&adjusted=false&expand_underlying=true"
+    );
+
+    let request = IndicatorReq {
+      window: None,
+      adjusted: None,
+      expand_underlying: false,
+      ..request
+    };
+    assert_eq!(Get::query(&request).unwrap().unwrap(), "timespan=day");
+  }
+}
@@ -9,6 +9,7 @@ use serde::de::Error;
 use serde::de::Unexpected;
 use serde::Deserialize;
 
+use crate::endpoint::Version;
 use crate::Str;
 
 
@@ -68,7 +69,7 @@ Endpoint! {
   Err => GetError, []
 
   fn path(_input: &Self::Input) -> Str {
-    "/v1/marketstatus/now".into()
+    Version::V1.path("marketstatus/now")
   }
 }
 
@@ -4,6 +4,7 @@
 use serde::Deserialize;
 
 use crate::api::response::Response;
+use crate::endpoint::Version;
 use crate::Str;
 
 
@@ -30,7 +31,7 @@ Endpoint! {
   Err => GetError, []
 
   fn path(_input: &Self::Input) -> Str {
-    "/v2/reference/markets".into()
+    Version::V2.path("reference/markets")
   }
 }
 
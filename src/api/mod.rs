@@ -5,22 +5,39 @@ mod response;
 
 /// Definitions surrounding aggregate prices of stocks.
 pub mod aggregates;
+/// Definitions for probing the entitlements of an API key.
+pub mod entitlements;
 /// Definitions pertaining the available exchanges.
 pub mod exchanges;
+/// Definitions for retrieving the financials of a company.
+pub mod financials;
+/// Definitions for retrieving technical indicators.
+pub mod indicators;
 /// Definitions pertaining the available locales.
 pub mod locales;
 /// Definitions for retrieving the current market status.
 pub mod market_status;
 /// Definitions pertaining the available markets.
 pub mod markets;
+/// Definitions for retrieving the previous close of a stock.
+pub mod previous_close;
+/// Definitions for retrieving companies related to another one.
+pub mod related_companies;
 /// Definitions pertaining a ticker.
 pub mod ticker;
 /// Definitions for retrieving the available ticker types.
 pub mod ticker_types;
-/// Definitions pertaining a ticker.
+/// Definitions for retrieving the available tickers.
 pub mod tickers;
+/// Definitions for retrieving historic trades.
+pub mod trades;
 
+/// The treatment of a mismatch between the number of results reported
+/// and received.
+pub use response::CountCheck;
 /// A response type used in certain API calls.
 pub use response::Response;
 /// An error type for responses indicating failures.
 pub use response::ResponseError;
+/// The status of a `Response`, along with the results it carries.
+pub use response::Status;
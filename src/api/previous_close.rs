@@ -0,0 +1,96 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use thiserror::Error as ThisError;
+
+use crate::api::aggregates::Aggregate;
+use crate::api::response::Response;
+use crate::api::response::ResponseError;
+use crate::endpoint::Version;
+use crate::error::RequestError;
+use crate::Str;
+
+
+/// An error encountered while retrieving the previous close of a
+/// symbol.
+#[derive(Debug, ThisError)]
+pub enum PreviousCloseError {
+  /// The request failed.
+  #[error("failed to request the previous close")]
+  Request(
+    #[from]
+    #[source]
+    RequestError<GetError>,
+  ),
+  /// The response did not contain the previous close.
+  #[error("failed to retrieve the previous close")]
+  Response(
+    #[from]
+    #[source]
+    ResponseError,
+  ),
+}
+
+
+Endpoint! {
+  /// The representation of a GET request to the
+  /// `/v2/aggs/ticker/<symbol>/prev` endpoint, retrieving the daily
+  /// aggregate of the previous trading day.
+  pub Get(String),
+  Ok => Response<Vec<Aggregate>>, [
+    /// The previous close was retrieved successfully.
+    /* 200 */ OK,
+  ],
+  Err => GetError, [
+    /// The specified symbol was not found.
+    /* 404 */ NOT_FOUND => NotFound,
+  ]
+
+  fn path(input: &Self::Input) -> Str {
+    Version::V2.path(&format!("aggs/ticker/{}/prev", input))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use num_decimal::Num;
+
+  use serde_json::from_str as from_json;
+
+
+  /// Check that we can decode a previous close response.
+  #[test]
+  fn parse_previous_close() {
+    let response = r#"{
+  "ticker": "AAPL",
+  "queryCount": 1,
+  "resultsCount": 1,
+  "adjusted": true,
+  "results": [
+    {
+      "T": "AAPL",
+      "v": 131704427,
+      "vw": 116.3058,
+      "o": 115.55,
+      "c": 115.97,
+      "h": 117.59,
+      "l": 114.13,
+      "t": 1605042000000
+    }
+  ],
+  "status": "OK",
+  "request_id": "6a7e466379af0a71039d60cc78e72282"
+}"#;
+
+    let aggregate = from_json::<Response<Vec<Aggregate>>>(response)
+      .unwrap()
+      .single_result()
+      .unwrap();
+    assert_eq!(aggregate.symbol.as_deref(), Some("AAPL"));
+    assert_eq!(aggregate.close_price, Num::new(11597, 100));
+    assert_eq!(aggregate.timestamp_millis(), 1605042000000);
+  }
+}
@@ -0,0 +1,118 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use serde::Deserialize;
+
+use crate::api::response::Response;
+use crate::endpoint::Version;
+use crate::Str;
+
+
+/// A company related to another one, as returned by the
+/// `/v1/related-companies/<ticker>` endpoint.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RelatedCompany {
+  /// The related company's ticker.
+  pub ticker: String,
+}
+
+
+Endpoint! {
+  /// The representation of a GET request to the
+  /// `/v1/related-companies/<ticker>` endpoint.
+  pub Get(String),
+  Ok => Response<Vec<RelatedCompany>>, [
+    /// The related companies were retrieved successfully.
+    /* 200 */ OK,
+  ],
+  Err => GetError, [
+    /// The specified ticker was not found.
+    /* 404 */ NOT_FOUND => NotFound,
+  ]
+
+  fn path(input: &Self::Input) -> Str {
+    Version::V1.path(&format!("related-companies/{}", input))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use serde_json::from_str as from_json;
+
+  #[cfg(not(target_arch = "wasm32"))]
+  use test_log::test;
+
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::error::RequestError;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::api_info;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::mock_server;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::Response as MockResponse;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::Client;
+
+
+  /// Check that we can decode a related companies response.
+  #[test]
+  fn parse_related_companies() {
+    let response = r#"{
+  "request_id": "31d59dda-80e5-4721-8496-d0d32a654afe",
+  "results": [
+    {"ticker": "MSFT"},
+    {"ticker": "GOOGL"},
+    {"ticker": "AMZN"}
+  ],
+  "status": "OK",
+  "stock_symbol": "AAPL"
+}"#;
+
+    let companies = from_json::<Response<Vec<RelatedCompany>>>(response)
+      .unwrap()
+      .into_result()
+      .unwrap();
+    let tickers = companies
+      .into_iter()
+      .map(|company| company.ticker)
+      .collect::<Vec<_>>();
+    assert_eq!(tickers, vec!["MSFT", "GOOGL", "AMZN"]);
+  }
+
+  /// Check that an unknown ticker is reported as `GetError::NotFound`.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn request_unknown_related_companies() {
+    let addr = mock_server(|request| async move {
+      assert!(request.path.starts_with("/v1/related-companies/ZZZZ?"));
+      let body = r#"{"status":"NOT_FOUND","request_id":"31d59dda-80e5-4721-8496-d0d32a654afe","message":"Ticker not found."}"#;
+      MockResponse::json(404, body)
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let err = client.issue::<Get>("ZZZZ".into()).await.unwrap_err();
+    match err {
+      RequestError::Endpoint(GetError::NotFound(..)) => (),
+      _ => panic!("unexpected error: {:?}", err),
+    }
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn request_aapl_related_companies() {
+    let client = Client::from_env().unwrap();
+    let companies = client
+      .issue::<Get>("AAPL".into())
+      .await
+      .unwrap()
+      .into_result()
+      .unwrap();
+
+    assert!(!companies.is_empty());
+    assert!(companies.iter().all(|company| company.ticker != "AAPL"));
+  }
+}
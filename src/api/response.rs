@@ -5,6 +5,8 @@ use serde::Deserialize;
 
 use thiserror::Error;
 
+use tracing::warn;
+
 
 /// A response error as reported by Polygon.
 #[derive(Clone, Debug, PartialEq, Error)]
@@ -12,10 +14,10 @@ use thiserror::Error;
 pub struct ResponseError(pub String);
 
 
-/// The response as returned by various endpoints.
+/// The status of a response, along with the results it carries.
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 #[serde(tag = "status", content = "results")]
-pub enum Response<T> {
+pub enum Status<T> {
   /// The request was successful and all results were retrieved.
   #[serde(rename = "OK")]
   Ok(T),
@@ -28,14 +30,149 @@ pub enum Response<T> {
   Err,
 }
 
+
+/// The treatment of a mismatch between the number of results a
+/// response reports and the number it actually contains.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CountCheck {
+  /// Emit a warning.
+  Warn,
+  /// Report an error.
+  Strict,
+}
+
+// Deriving `Default` for enums requires a more recent compiler than
+// we support.
+#[allow(clippy::derivable_impls)]
+impl Default for CountCheck {
+  fn default() -> Self {
+    Self::Warn
+  }
+}
+
+
+/// The response as returned by various endpoints.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Response<T> {
+  /// The status of the response, along with the results.
+  #[serde(flatten)]
+  pub status: Status<T>,
+  /// The ID Polygon assigned to the request.
+  ///
+  /// Polygon's support asks for this ID when investigating issues.
+  #[serde(rename = "request_id", default)]
+  pub(crate) request_id: Option<String>,
+  /// The number of results Polygon reported, if any.
+  ///
+  /// Only some endpoints report this number.
+  #[serde(rename = "resultsCount", default)]
+  pub(crate) results_count: Option<usize>,
+  /// The URL for retrieving the next page of results, if any.
+  ///
+  /// Only some endpoints paginate their results.
+  #[serde(rename = "next_url", default)]
+  pub(crate) next_url: Option<String>,
+}
+
 impl<T> Response<T> {
+  /// Retrieve the ID Polygon assigned to the request, if it was
+  /// reported.
+  pub fn request_id(&self) -> Option<&str> {
+    self.request_id.as_deref()
+  }
+
+  /// Retrieve the number of results Polygon reported, if any.
+  ///
+  /// Only some endpoints report this number.
+  pub fn results_count(&self) -> Option<usize> {
+    self.results_count
+  }
+
+  /// Retrieve the URL for retrieving the next page of results, if any.
+  ///
+  /// Only some endpoints paginate their results.
+  pub fn next_url(&self) -> Option<&str> {
+    self.next_url.as_deref()
+  }
+
+  /// Convert the results of a `Response`, if any, using the provided
+  /// function.
+  pub(crate) fn map<F, U>(self, f: F) -> Response<U>
+  where
+    F: FnOnce(T) -> U,
+  {
+    let status = match self.status {
+      Status::Ok(data) => Status::Ok(f(data)),
+      Status::Delayed(data) => Status::Delayed(f(data)),
+      Status::Err => Status::Err,
+    };
+
+    Response {
+      status,
+      request_id: self.request_id,
+      results_count: self.results_count,
+      next_url: self.next_url,
+    }
+  }
+
   /// Convert a `Response` into a `Result`.
   ///
-  /// Both `Ok` and `Delayed` variants are treated as success.
+  /// Both `Ok` and `Delayed` statuses are treated as success.
   pub fn into_result(self) -> Result<T, ResponseError> {
-    match self {
-      Self::Ok(data) | Self::Delayed(data) => Ok(data),
-      Self::Err => Err(ResponseError("an unexpected status was reported".into())),
+    match self.status {
+      Status::Ok(data) | Status::Delayed(data) => Ok(data),
+      Status::Err => Err(ResponseError("an unexpected status was reported".into())),
+    }
+  }
+}
+
+impl<T> Response<Vec<T>> {
+  /// Convert a `Response` into a `Result`, checking that the number of
+  /// results matches the one reported by Polygon.
+  ///
+  /// A mismatch may indicate that results got truncated. It is
+  /// treated according to `check`. If Polygon did not report a count,
+  /// this method behaves like `into_result`.
+  pub fn into_checked_result(self, check: CountCheck) -> Result<Vec<T>, ResponseError> {
+    let count = self.results_count;
+    let request_id = self.request_id.clone();
+    let results = self.into_result()?;
+
+    match count {
+      Some(count) if count != results.len() => match check {
+        CountCheck::Warn => {
+          warn!(
+            reported = count,
+            received = results.len(),
+            request_id = debug(&request_id),
+            "number of results does not match the reported count"
+          );
+          Ok(results)
+        },
+        CountCheck::Strict => Err(ResponseError(format!(
+          "expected {} results as reported but got {}",
+          count,
+          results.len()
+        ))),
+      },
+      _ => Ok(results),
+    }
+  }
+
+  /// Convert a `Response` wrapping a single result in an array into a
+  /// `Result` of this very element.
+  ///
+  /// Some endpoints report a single object as a one-element array.
+  /// An error is reported if the response did not indicate success or
+  /// if the array contains no or more than one element.
+  pub fn single_result(self) -> Result<T, ResponseError> {
+    let mut results = self.into_result()?;
+    match results.len() {
+      1 => Ok(results.remove(0)),
+      count => Err(ResponseError(format!(
+        "expected exactly one result but got {}",
+        count
+      ))),
     }
   }
 }
@@ -53,8 +190,8 @@ mod tests {
   fn decode_ok() {
     let json = r#"{"status":"OK","results":["abc"]}"#;
     let response = from_json::<Response<Vec<String>>>(json).unwrap();
-    match response {
-      Response::Ok(data) if data.as_slice() == ["abc"] => (),
+    match response.status {
+      Status::Ok(data) if data.as_slice() == ["abc"] => (),
       _ => panic!("unexpected result"),
     }
   }
@@ -64,9 +201,83 @@ mod tests {
   fn decode_delayed() {
     let json = r#"{"status":"DELAYED","results":["abc"]}"#;
     let response = from_json::<Response<Vec<String>>>(json).unwrap();
-    match response {
-      Response::Delayed(data) if data.as_slice() == ["abc"] => (),
+    match response.status {
+      Status::Delayed(data) if data.as_slice() == ["abc"] => (),
       _ => panic!("unexpected result"),
     }
   }
+
+  /// Check that we can retrieve the request ID of both successful and
+  /// unsuccessful responses.
+  #[test]
+  fn decode_request_id() {
+    let json = r#"{"status":"OK","request_id":"6a7e466379af0a71039d60cc78e72282","results":[]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    assert_eq!(response.request_id(), Some("6a7e466379af0a71039d60cc78e72282"));
+    assert_eq!(response.into_result().unwrap(), Vec::<String>::new());
+
+    let json = r#"{"status":"ERROR","request_id":"d9ebd3b6c7a3d3ab9d1c0f7ee6487a26"}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    assert_eq!(response.status, Status::Err);
+    assert_eq!(response.request_id(), Some("d9ebd3b6c7a3d3ab9d1c0f7ee6487a26"));
+
+    let json = r#"{"status":"OK","results":[]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    assert_eq!(response.request_id(), None);
+  }
+
+  /// Check that we can extract the single result of a response.
+  #[test]
+  fn single_result() {
+    let json = r#"{"status":"OK","results":["abc"]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    assert_eq!(response.single_result().unwrap(), "abc");
+
+    let json = r#"{"status":"DELAYED","results":["abc"]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    assert_eq!(response.single_result().unwrap(), "abc");
+  }
+
+  /// Check that a mismatch between the reported and the actual number
+  /// of results is treated as requested.
+  #[test]
+  fn checked_result_count() {
+    let json = r#"{"status":"OK","resultsCount":2,"results":["abc"]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    assert_eq!(response.results_count(), Some(2));
+
+    let result = response.clone().into_checked_result(CountCheck::default());
+    assert_eq!(result.unwrap(), vec!["abc".to_string()]);
+
+    let err = response.into_checked_result(CountCheck::Strict).unwrap_err();
+    assert_eq!(err.0, "expected 2 results as reported but got 1");
+
+    let json = r#"{"status":"OK","resultsCount":1,"results":["abc"]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    assert!(response.into_checked_result(CountCheck::Strict).is_ok());
+
+    let json = r#"{"status":"OK","results":["abc"]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    assert!(response.into_checked_result(CountCheck::Strict).is_ok());
+  }
+
+  /// Check that extracting the single result fails for a response
+  /// without results.
+  #[test]
+  fn single_result_none() {
+    let json = r#"{"status":"OK","results":[]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    let err = response.single_result().unwrap_err();
+    assert_eq!(err.0, "expected exactly one result but got 0");
+  }
+
+  /// Check that extracting the single result fails for a response
+  /// with multiple results.
+  #[test]
+  fn single_result_many() {
+    let json = r#"{"status":"OK","results":["abc","def"]}"#;
+    let response = from_json::<Response<Vec<String>>>(json).unwrap();
+    let err = response.single_result().unwrap_err();
+    assert_eq!(err.0, "expected exactly one result but got 2");
+  }
 }
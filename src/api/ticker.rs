@@ -4,6 +4,8 @@
 use serde::Deserialize;
 
 use crate::api::response::Response;
+use crate::de::bool_from_json;
+use crate::endpoint::Version;
 use crate::Str;
 
 
@@ -70,7 +72,7 @@ pub struct Ticker {
   #[serde(rename = "currency")]
   pub currency: String,
   /// Whether the ticker is still active.
-  #[serde(rename = "active")]
+  #[serde(rename = "active", deserialize_with = "bool_from_json")]
   pub active: bool,
   /// The ticker's type.
   #[serde(rename = "type")]
@@ -108,7 +110,7 @@ Endpoint! {
   ]
 
   fn path(input: &Self::Input) -> Str {
-    format!("/v2/reference/tickers/{}", input).into()
+    Version::V2.path(&format!("reference/tickers/{}", input))
   }
 }
 
@@ -5,54 +5,108 @@ use std::collections::BTreeMap;
 
 use serde::Deserialize;
 
-use crate::api::response::Response;
+use crate::endpoint::Version;
 use crate::Str;
 
 
+/// A ticker type as returned by the `/v3/reference/tickers/types`
+/// endpoint.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TickerType {
+  /// The code identifying the type, e.g., "CS".
+  #[serde(rename = "code")]
+  pub code: String,
+  /// A description of the type, e.g., "Common Stock".
+  #[serde(rename = "description")]
+  pub description: String,
+  /// The asset class the type belongs to, e.g., "stocks".
+  #[serde(rename = "asset_class")]
+  pub asset_class: String,
+  /// The locale of the type.
+  #[serde(rename = "locale")]
+  pub locale: String,
+}
+
+
 /// A struct representing the ticker types.
-///
-/// Please note that not all fields available in a request are
-/// represented here.
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct TickerTypes {
-  /// A mapping from ticker types to descriptions.
-  #[serde(rename = "types")]
-  pub types: BTreeMap<String, String>,
-  /// A mapping from index types to descriptions.
-  #[serde(rename = "indexTypes")]
-  pub index_types: BTreeMap<String, String>,
+  /// The available ticker types.
+  #[serde(rename = "results", default)]
+  pub types: Vec<TickerType>,
 }
 
+impl TickerTypes {
+  /// Create a mapping from ticker type codes to their descriptions.
+  pub fn descriptions(&self) -> BTreeMap<String, String> {
+    self
+      .types
+      .iter()
+      .map(|type_| (type_.code.clone(), type_.description.clone()))
+      .collect()
+  }
+}
+
+
 Endpoint! {
-  /// The representation of a GET request to the `/v2/reference/types`
-  /// endpoint.
+  /// The representation of a GET request to the
+  /// `/v3/reference/tickers/types` endpoint.
   pub Get(()),
-  Ok => Response<TickerTypes>, [
+  Ok => TickerTypes, [
     /// The ticker types were retrieved successfully.
     /* 200 */ OK,
   ],
   Err => GetError, []
 
   fn path(_input: &Self::Input) -> Str {
-    "/v2/reference/types".into()
+    Version::V3.path("reference/tickers/types")
   }
 }
 
 
-#[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  use serde_json::from_str as from_json;
+
+  #[cfg(not(target_arch = "wasm32"))]
   use test_log::test;
 
+  #[cfg(not(target_arch = "wasm32"))]
   use crate::Client;
 
+
+  /// Check that we can decode the ticker types reference.
+  #[test]
+  fn parse_ticker_types() {
+    let response = r#"{
+  "results": [
+    {"code": "CS", "description": "Common Stock", "asset_class": "stocks", "locale": "us"},
+    {"code": "ETF", "description": "Exchange Traded Fund", "asset_class": "stocks", "locale": "us"},
+    {"code": "ADRC", "description": "American Depository Receipt Common", "asset_class": "stocks", "locale": "us"}
+  ],
+  "status": "OK",
+  "request_id": "efbfc7c2304bba6c2f19a2567f568134",
+  "count": 3
+}"#;
+
+    let types = from_json::<TickerTypes>(response).unwrap();
+    assert_eq!(types.types.len(), 3);
+    assert_eq!(types.types[0].asset_class, "stocks");
+
+    let descriptions = types.descriptions();
+    assert_eq!(descriptions["CS"], "Common Stock");
+    assert_eq!(descriptions["ETF"], "Exchange Traded Fund");
+    assert_eq!(descriptions["ADRC"], "American Depository Receipt Common");
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
   #[test(tokio::test)]
   async fn request_ticker_types() {
     let client = Client::from_env().unwrap();
     let types = client.issue::<Get>(()).await.unwrap();
 
-    println!("{:?}", types);
+    assert!(types.descriptions().contains_key("CS"));
   }
 }
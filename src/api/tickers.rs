@@ -1,38 +1,236 @@
-use crate::api::ticker::Ticker;
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::Utc;
+
 use serde::Deserialize;
+use serde_json::Error as JsonError;
+
+use thiserror::Error as ThisError;
 
-use crate::api::response::Response;
+use url::form_urlencoded::parse as parse_query;
+use url::form_urlencoded::Serializer;
+
+use crate::api::ticker::Type;
+use crate::de::bool_from_json;
+use crate::endpoint::Version;
+use crate::error::RequestError;
 use crate::Str;
 
-/// All tickers as returned by the `/v2/reference/tickers/`
-/// endpoint.
+
+/// A request to the `/v3/reference/tickers` endpoint.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TickersReq {
+  /// Retrieve the tickers as they were available on the given date,
+  /// including ones that have since been delisted.
+  pub date: Option<NaiveDate>,
+  /// The maximum number of tickers to retrieve with a single request.
+  pub limit: Option<usize>,
+  /// The cursor to continue from, as reported by a previous response.
+  pub cursor: Option<String>,
+}
+
+
+/// A ticker as returned by the `/v3/reference/tickers` endpoint.
 ///
 /// Please note that not all fields available in a request are
 /// represented here.
 #[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Ticker {
+  /// The ticker.
+  #[serde(rename = "ticker")]
+  pub ticker: String,
+  /// The ticker's name.
+  #[serde(rename = "name")]
+  pub name: String,
+  /// The ticker's market, e.g., "stocks".
+  #[serde(rename = "market")]
+  pub market: String,
+  /// The locale.
+  #[serde(rename = "locale")]
+  pub locale: String,
+  /// The ticker's currency.
+  #[serde(rename = "currency_name", default)]
+  pub currency: Option<String>,
+  /// Whether the ticker is still active.
+  #[serde(rename = "active", deserialize_with = "bool_from_json")]
+  pub active: bool,
+  /// The ticker's type.
+  #[serde(rename = "type", default)]
+  pub type_: Option<Type>,
+  /// The time at which the ticker got delisted, if it was.
+  #[serde(rename = "delisted_utc", default)]
+  pub delisted: Option<DateTime<Utc>>,
+}
+
+
+/// A page of tickers as returned by the `/v3/reference/tickers`
+/// endpoint.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct TickersResp {
-    /// Vector of ticker information.
-    #[serde(rename = "ticker")]
-    pub tickers: Vec<Ticker>,
+  /// Vector of ticker information.
+  #[serde(rename = "results", default)]
+  pub tickers: Vec<Ticker>,
+  /// The URL for retrieving the next page, if any.
+  #[serde(rename = "next_url", default)]
+  pub next_url: Option<String>,
+}
+
+impl TickersResp {
+  /// Retrieve the cursor for requesting the next page, if any.
+  pub fn next_cursor(&self) -> Option<String> {
+    let next_url = self.next_url.as_ref()?;
+    let (_, query) = next_url.split_once('?')?;
+    parse_query(query.as_bytes())
+      .find(|(key, _)| key == "cursor")
+      .map(|(_, value)| value.into_owned())
+  }
 }
 
+
+/// An error encountered while retrieving all tickers as of a date.
+#[derive(Debug, ThisError)]
+pub enum TickersAsOfError {
+  /// The request for a page failed.
+  #[error("failed to request a page of tickers")]
+  Request(
+    #[from]
+    #[source]
+    RequestError<GetError>,
+  ),
+  /// Polygon reported a cursor that was already followed, which would
+  /// cause pagination to never conclude.
+  #[error("cursor {0} was reported more than once")]
+  RepeatedCursor(String),
+}
+
+
 Endpoint! {
   /// The representation of a GET request to the
-  /// `/v2/reference/tickers/` endpoint.
-  pub Get(()),
-  Ok => Response<TickersResp>, [
+  /// `/v3/reference/tickers` endpoint.
+  pub Get(TickersReq),
+  Ok => TickersResp, [
     /// The ticker information was retrieved successfully.
     /* 200 */ OK,
   ],
   Err => GetError, [
     /// The specified resource was not found.
-    ///
-    /// This error will also occur on valid tickers when the market is
-    /// closed.
     /* 404 */ NOT_FOUND => NotFound,
   ]
 
   fn path(_input: &Self::Input) -> Str {
-    "/v3/reference/tickers/".to_string().into()
+    Version::V3.path("reference/tickers")
+  }
+
+  fn query(input: &Self::Input) -> Result<Option<Str>, JsonError> {
+    let mut query = Serializer::new(String::new());
+    if let Some(date) = &input.date {
+      let _ = query.append_pair("date", &date.format("%Y-%m-%d").to_string());
+    }
+    if let Some(limit) = &input.limit {
+      let _ = query.append_pair("limit", &limit.to_string());
+    }
+    if let Some(cursor) = &input.cursor {
+      let _ = query.append_pair("cursor", cursor);
+    }
+
+    let query = query.finish();
+    if query.is_empty() {
+      Ok(None)
+    } else {
+      Ok(Some(query.into()))
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use http_endpoint::Endpoint as _;
+
+  use serde_json::from_str as from_json;
+
+
+  /// Check that we emit the expected query parameters.
+  #[test]
+  fn encode_query() {
+    assert_eq!(Get::query(&TickersReq::default()).unwrap(), None);
+
+    let request = TickersReq {
+      date: NaiveDate::from_ymd_opt(2008, 9, 12),
+      limit: Some(1000),
+      cursor: None,
+    };
+    assert_eq!(
+      Get::query(&request).unwrap().as_deref(),
+      Some("date=2008-09-12&limit=1000")
+    );
+  }
+
+  /// Check that we can decode a page of tickers and extract the cursor
+  /// to the next one.
+  #[test]
+  fn decode_tickers() {
+    let response = r#"{
+  "results": [
+    {
+      "ticker": "LEHMQ",
+      "name": "Lehman Brothers Holdings Inc.",
+      "market": "stocks",
+      "locale": "us",
+      "type": "CS",
+      "active": false,
+      "currency_name": "usd",
+      "delisted_utc": "2012-03-06T00:00:00Z"
+    }
+  ],
+  "status": "OK",
+  "request_id": "e70013d92930de90e089dc8fa098888e",
+  "count": 1,
+  "next_url": "https://api.polygon.io/v3/reference/tickers?cursor=YWN0aXZlPXRydWU%3D"
+}"#;
+
+    let tickers = from_json::<TickersResp>(response).unwrap();
+    assert_eq!(tickers.tickers.len(), 1);
+
+    let ticker = &tickers.tickers[0];
+    assert_eq!(ticker.ticker, "LEHMQ");
+    assert_eq!(ticker.market, "stocks");
+    assert_eq!(ticker.type_, Some(Type::CommonStock));
+    assert!(!ticker.active);
+    assert_eq!(
+      ticker.delisted,
+      Some(DateTime::parse_from_rfc3339("2012-03-06T00:00:00Z").unwrap().into())
+    );
+    assert_eq!(tickers.next_cursor().as_deref(), Some("YWN0aXZlPXRydWU="));
+
+    let tickers = from_json::<TickersResp>(r#"{"status":"OK","count":0}"#).unwrap();
+    assert!(tickers.tickers.is_empty());
+    assert_eq!(tickers.next_cursor(), None);
+  }
+
+  /// Check that we accept the `active` flag in integer form.
+  #[test]
+  fn decode_integer_active_flag() {
+    let response = r#"{
+  "results": [
+    {
+      "ticker": "AAPL",
+      "name": "Apple Inc.",
+      "market": "stocks",
+      "locale": "us",
+      "active": 1
+    }
+  ],
+  "status": "OK",
+  "count": 1
+}"#;
+
+    let tickers = from_json::<TickersResp>(response).unwrap();
+    assert!(tickers.tickers[0].active);
   }
 }
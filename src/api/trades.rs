@@ -0,0 +1,257 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use num_decimal::Num;
+
+use serde::Deserialize;
+use serde::Deserializer;
+use serde_json::Error as JsonError;
+
+use url::form_urlencoded::Serializer;
+
+use crate::de::num_from_json;
+use crate::endpoint::Version;
+use crate::Str;
+
+
+/// Deserialize a time stamp given as nanoseconds since the Unix epoch.
+fn system_time_from_nanos<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let nanos = u64::deserialize(deserializer)?;
+  Ok(UNIX_EPOCH + Duration::from_nanos(nanos))
+}
+
+/// Deserialize an optional time stamp given as nanoseconds since the
+/// Unix epoch.
+fn opt_system_time_from_nanos<'de, D>(deserializer: D) -> Result<Option<SystemTime>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let nanos = Option::<u64>::deserialize(deserializer)?;
+  Ok(nanos.map(|nanos| UNIX_EPOCH + Duration::from_nanos(nanos)))
+}
+
+
+/// The kinds of time stamps reported for a trade.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TimestampKind {
+  /// The time at which the trade was processed by the Securities
+  /// Information Processor (SIP).
+  Sip,
+  /// The time at which the trade was generated at the exchange.
+  Participant,
+  /// The time at which the trade was reported to a Trade Reporting
+  /// Facility (TRF).
+  Trf,
+}
+
+
+/// A request to the `/v3/trades/<symbol>` endpoint.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TradesReq {
+  /// The ticker symbol to retrieve trades for.
+  pub symbol: String,
+  /// The maximum number of trades to retrieve with a single request.
+  pub limit: Option<usize>,
+  /// The cursor to continue from, as reported by a previous response.
+  pub cursor: Option<String>,
+}
+
+
+/// A historic trade as returned by the `/v3/trades/<symbol>` endpoint.
+///
+/// Please note that not all fields available in a request are
+/// represented here.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Trade {
+  /// The trade's ID.
+  #[serde(rename = "id")]
+  pub id: String,
+  /// The exchange the trade occurred on.
+  #[serde(rename = "exchange")]
+  pub exchange: u64,
+  /// The price.
+  #[serde(rename = "price", deserialize_with = "num_from_json")]
+  pub price: Num,
+  /// The number of shares traded.
+  #[serde(rename = "size")]
+  pub quantity: u64,
+  /// The trade's condition codes.
+  #[serde(rename = "conditions", default)]
+  pub conditions: Vec<u64>,
+  /// The sequence number of the trade, unique per symbol and day.
+  #[serde(rename = "sequence_number")]
+  pub sequence_number: u64,
+  /// The time at which the trade was processed by the SIP.
+  #[serde(rename = "sip_timestamp", deserialize_with = "system_time_from_nanos")]
+  pub sip_timestamp: SystemTime,
+  /// The time at which the trade was generated at the exchange, if
+  /// reported.
+  #[serde(
+    rename = "participant_timestamp",
+    default,
+    deserialize_with = "opt_system_time_from_nanos"
+  )]
+  pub participant_timestamp: Option<SystemTime>,
+  /// The time at which the trade was reported to the TRF, for trades
+  /// reported to one.
+  #[serde(
+    rename = "trf_timestamp",
+    default,
+    deserialize_with = "opt_system_time_from_nanos"
+  )]
+  pub trf_timestamp: Option<SystemTime>,
+}
+
+impl Trade {
+  /// Retrieve the time stamp of the given kind, if it was reported.
+  pub fn timestamp(&self, kind: TimestampKind) -> Option<SystemTime> {
+    match kind {
+      TimestampKind::Sip => Some(self.sip_timestamp),
+      TimestampKind::Participant => self.participant_timestamp,
+      TimestampKind::Trf => self.trf_timestamp,
+    }
+  }
+}
+
+
+/// A page of trades as returned by the `/v3/trades/<symbol>` endpoint.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TradesResp {
+  /// The trades.
+  #[serde(rename = "results", default)]
+  pub trades: Vec<Trade>,
+  /// The URL for retrieving the next page, if any.
+  #[serde(rename = "next_url", default)]
+  pub next_url: Option<String>,
+}
+
+
+Endpoint! {
+  /// The representation of a GET request to the `/v3/trades/<symbol>`
+  /// endpoint.
+  pub Get(TradesReq),
+  Ok => TradesResp, [
+    /// The trades were retrieved successfully.
+    /* 200 */ OK,
+  ],
+  Err => GetError, [
+    /// The specified resource was not found.
+    /* 404 */ NOT_FOUND => NotFound,
+  ]
+
+  fn path(input: &Self::Input) -> Str {
+    Version::V3.path(&format!("trades/{}", input.symbol))
+  }
+
+  fn query(input: &Self::Input) -> Result<Option<Str>, JsonError> {
+    let mut query = Serializer::new(String::new());
+    if let Some(limit) = &input.limit {
+      let _ = query.append_pair("limit", &limit.to_string());
+    }
+    if let Some(cursor) = &input.cursor {
+      let _ = query.append_pair("cursor", cursor);
+    }
+
+    let query = query.finish();
+    if query.is_empty() {
+      Ok(None)
+    } else {
+      Ok(Some(query.into()))
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use http_endpoint::Endpoint as _;
+
+  use serde_json::from_str as from_json;
+
+
+  /// Check that we can decode a page of trades, including all of their
+  /// time stamps.
+  #[test]
+  fn parse_trades() {
+    let response = r#"{
+  "results": [
+    {
+      "conditions": [12, 41],
+      "exchange": 4,
+      "id": "1",
+      "participant_timestamp": 1517562000015577000,
+      "price": 171.55,
+      "sequence_number": 1063,
+      "sip_timestamp": 1517562000016036600,
+      "size": 100,
+      "tape": 3,
+      "trf_id": 201,
+      "trf_timestamp": 1517562000015900000
+    },
+    {
+      "exchange": 11,
+      "id": "2",
+      "price": 171.56,
+      "sequence_number": 1064,
+      "sip_timestamp": 1517562000016042000,
+      "size": 5,
+      "tape": 3
+    }
+  ],
+  "status": "OK",
+  "request_id": "a47d1beb8c11b6ae897ab76cdbbf35a3",
+  "next_url": "https://api.polygon.io/v3/trades/AAPL?cursor=YWN0aXZlPXRydWU"
+}"#;
+
+    let resp = from_json::<TradesResp>(response).unwrap();
+    assert_eq!(resp.trades.len(), 2);
+    assert!(resp.next_url.is_some());
+
+    let at = |nanos| Some(UNIX_EPOCH + Duration::from_nanos(nanos));
+    let trade = &resp.trades[0];
+    assert_eq!(trade.price, Num::new(17155, 100));
+    assert_eq!(trade.quantity, 100);
+    assert_eq!(trade.conditions, vec![12, 41]);
+    assert_eq!(trade.timestamp(TimestampKind::Sip), at(1517562000016036600));
+    assert_eq!(
+      trade.timestamp(TimestampKind::Participant),
+      at(1517562000015577000)
+    );
+    assert_eq!(trade.timestamp(TimestampKind::Trf), at(1517562000015900000));
+
+    let trade = &resp.trades[1];
+    assert_eq!(trade.timestamp(TimestampKind::Sip), at(1517562000016042000));
+    assert_eq!(trade.timestamp(TimestampKind::Participant), None);
+    assert_eq!(trade.timestamp(TimestampKind::Trf), None);
+  }
+
+  /// Check that we pass the provided options as query parameters.
+  #[test]
+  fn trades_query() {
+    let request = TradesReq {
+      symbol: "AAPL".into(),
+      limit: Some(50),
+      cursor: Some("YWN0aXZlPXRydWU".into()),
+    };
+    assert_eq!(Get::path(&request), "/v3/trades/AAPL");
+    assert_eq!(
+      Get::query(&request).unwrap().unwrap(),
+      "limit=50&cursor=YWN0aXZlPXRydWU"
+    );
+
+    let request = TradesReq {
+      symbol: "AAPL".into(),
+      ..Default::default()
+    };
+    assert_eq!(Get::query(&request).unwrap(), None);
+  }
+}
@@ -3,7 +3,14 @@
 
 use std::env::var_os;
 use std::ffi::OsString;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::fs::read_to_string;
+use std::path::Path;
 
+use url::form_urlencoded::parse as parse_query;
+use url::form_urlencoded::Serializer as QuerySerializer;
 use url::Url;
 
 use crate::Error;
@@ -15,15 +22,91 @@ const ENV_STREAM_URL: &str = "POLYGON_STREAM_URL";
 /// The environment variable representing the API key.
 const ENV_API_KEY: &str = "POLYGON_API_KEY";
 
-/// The default stream URL.
+/// The query parameter used for communicating the API key to Polygon.
+pub(crate) const API_KEY_PARAM: &str = "apiKey";
+
+/// The default API URL.
 const DEFAULT_API_URL: &str = "https://api.polygon.io";
+/// The host of the default API URL.
+const DEFAULT_API_HOST: &str = "api.polygon.io";
 /// The default stream URL.
 const DEFAULT_STREAM_URL: &str = "wss://socket.polygon.io";
 
 
+/// Mask the given API key for inclusion in diagnostics, leaving only
+/// its first and last four characters intact for keys long enough to
+/// not be given away by doing so.
+pub(crate) fn redact(api_key: &str) -> String {
+  let chars = api_key.chars().collect::<Vec<_>>();
+  if chars.len() < 12 {
+    "****".to_string()
+  } else {
+    let head = chars[..4].iter().collect::<String>();
+    let tail = chars[chars.len() - 4..].iter().collect::<String>();
+    format!("{}****{}", head, tail)
+  }
+}
+
+
+/// Mask the API key contained in the query of the given URL, for
+/// inclusion in diagnostics.
+pub(crate) fn redact_url(url: &Url) -> Url {
+  let mut redacted = url.clone();
+  let _ = redacted.query_pairs_mut().clear().extend_pairs(url.query_pairs().map(
+    |(name, value)| {
+      if name == API_KEY_PARAM {
+        let value = redact(&value);
+        (name, value.into())
+      } else {
+        (name, value)
+      }
+    },
+  ));
+  redacted
+}
+
+
+/// Split a `next_url` cursor, as reported by Polygon's paginated
+/// endpoints, into its path and query.
+///
+/// Polygon reports the cursor either as absolute URL or as a path,
+/// possibly prefixed with the API host. The latter is recognized for
+/// Polygon's API host as well as `host`, if provided. Scheme and host
+/// are ignored in all cases and any API key contained in the query is
+/// removed.
+pub(crate) fn split_next_url(next_url: &str, host: Option<&str>) -> (String, Option<String>) {
+  let (path, query) = match Url::parse(next_url) {
+    Ok(url) => (url.path().to_string(), url.query().map(str::to_string)),
+    Err(..) => {
+      let rest = host
+        .into_iter()
+        .chain(Some(DEFAULT_API_HOST))
+        .find_map(|host| next_url.strip_prefix(host))
+        .unwrap_or(next_url);
+      let (path, query) = match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (rest, None),
+      };
+      (format!("/{}", path.trim_start_matches('/')), query)
+    },
+  };
+
+  let query = query.and_then(|query| {
+    let pairs = parse_query(query.as_bytes()).filter(|(key, _)| key != API_KEY_PARAM);
+    let query = QuerySerializer::new(String::new())
+      .extend_pairs(pairs)
+      .finish();
+    Some(query).filter(|query| !query.is_empty())
+  });
+  (path, query)
+}
+
+
 /// An object encapsulating the information used for working with the
 /// Alpaca API.
-#[derive(Clone, Debug, PartialEq)]
+///
+/// The API key is masked in `Debug` output.
+#[derive(Clone, PartialEq)]
 pub struct ApiInfo {
   /// The base URL for API requests.
   pub(crate) api_url: Url,
@@ -33,6 +116,17 @@ pub struct ApiInfo {
   pub(crate) api_key: String,
 }
 
+impl Debug for ApiInfo {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    fmt
+      .debug_struct("ApiInfo")
+      .field("api_url", &self.api_url)
+      .field("stream_url", &self.stream_url)
+      .field("api_key", &redact(&self.api_key))
+      .finish()
+  }
+}
+
 impl ApiInfo {
   /// Create an `ApiInfo` object using the given API key and assuming
   /// default API and Stream endpoint URLs.
@@ -47,6 +141,39 @@ impl ApiInfo {
     }
   }
 
+  /// Create an `ApiInfo` object from its individual parts.
+  ///
+  /// The API URL has to use the `http` or `https` scheme and the stream
+  /// URL the `ws` or `wss` one, otherwise an error is reported.
+  pub fn from_parts<S>(api_url: Url, stream_url: Url, api_key: S) -> Result<Self, Error>
+  where
+    S: Into<String>,
+  {
+    fn check_scheme(url: &Url, what: &str, schemes: &[&str]) -> Result<(), Error> {
+      if schemes.contains(&url.scheme()) {
+        Ok(())
+      } else {
+        let err = format!(
+          "{} {} uses unsupported scheme '{}'; expected one of: {}",
+          what,
+          url,
+          url.scheme(),
+          schemes.join(", "),
+        );
+        Err(Error::Str(err.into()))
+      }
+    }
+
+    check_scheme(&api_url, "API URL", &["http", "https"])?;
+    check_scheme(&stream_url, "stream URL", &["ws", "wss"])?;
+
+    Ok(Self {
+      api_url,
+      stream_url,
+      api_key: api_key.into(),
+    })
+  }
+
   /// Create an `ApiInfo` object with information from the environment.
   ///
   /// This constructor retrieves API related information from the
@@ -100,6 +227,50 @@ impl ApiInfo {
       api_key,
     })
   }
+
+  /// Create an `ApiInfo` object using the API key stored in the file at
+  /// the given path and assuming default API and Stream endpoint URLs.
+  ///
+  /// Leading and trailing whitespace, such as a terminating newline,
+  /// is removed from the key. This constructor is meant for cases where
+  /// secrets are mounted as files, e.g., in container deployments.
+  pub fn from_key_file<P>(path: P) -> Result<Self, Error>
+  where
+    P: AsRef<Path>,
+  {
+    let path = path.as_ref();
+    let api_key = read_to_string(path).map_err(|err| {
+      Error::Str(format!("failed to read API key from {}: {}", path.display(), err).into())
+    })?;
+    let api_key = api_key.trim();
+    if api_key.is_empty() {
+      return Err(Error::Str(
+        format!("API key file {} is empty", path.display()).into(),
+      ))
+    }
+
+    Ok(Self::new(api_key))
+  }
+
+  /// Resolve a `next_url` cursor, as reported by Polygon's paginated
+  /// endpoints, into a URL usable for retrieving the next page.
+  ///
+  /// Polygon reports the cursor either as absolute URL or as a path
+  /// (possibly prefixed with the API host). In all cases only path and
+  /// query are honored and resolved against the configured API URL.
+  /// The API key is set to the one of this object, replacing any key
+  /// that may already be present.
+  pub fn resolve_next_url(&self, next_url: &str) -> Result<Url, Error> {
+    let (path, query) = split_next_url(next_url, self.api_url.host_str());
+    let mut url = self.api_url.clone();
+    url.set_path(&path);
+    url.set_query(query.as_deref());
+    let _ = url
+      .query_pairs_mut()
+      .append_pair(API_KEY_PARAM, &self.api_key);
+
+    Ok(url)
+  }
 }
 
 
@@ -107,6 +278,11 @@ impl ApiInfo {
 mod tests {
   use super::*;
 
+  use std::env::temp_dir;
+  use std::fs::remove_file;
+  use std::fs::write;
+  use std::process;
+
 
   /// Verify that we can create an `ApiInfo` object.
   #[test]
@@ -115,4 +291,129 @@ mod tests {
     // error.
     let _ = ApiInfo::new("XXXXXXXXXXXXXXXXXXXX");
   }
+
+  /// Check that the API key is masked in `Debug` output.
+  #[test]
+  fn debug_redacts_api_key() {
+    let api_info = ApiInfo::new("USER12345678");
+    let debug = format!("{:?}", api_info);
+    assert!(!debug.contains("USER12345678"), "{}", debug);
+    assert!(debug.contains("USER****5678"), "{}", debug);
+
+    let debug = format!("{:?}", ApiInfo::new("short"));
+    assert!(!debug.contains("short"), "{}", debug);
+  }
+
+  /// Check that the API key is masked in URLs, while all other query
+  /// parameters are retained.
+  #[test]
+  fn redact_api_key_in_url() {
+    let url = Url::parse("https://api.polygon.io/v2/aggs?limit=10&apiKey=USER12345678").unwrap();
+    let redacted = redact_url(&url);
+    assert_eq!(
+      redacted.as_str(),
+      "https://api.polygon.io/v2/aggs?limit=10&apiKey=USER****5678"
+    );
+  }
+
+  /// Check that we can read the API key from a file.
+  #[test]
+  fn from_key_file() {
+    let path = temp_dir().join(format!("polyio-api-key-{}", process::id()));
+    let () = write(&path, "XXXXXXXXXXXXXXXXXXXX\n").unwrap();
+    let result = ApiInfo::from_key_file(&path);
+
+    let () = write(&path, " \n").unwrap();
+    let empty = ApiInfo::from_key_file(&path);
+    let () = remove_file(&path).unwrap();
+
+    assert_eq!(result.unwrap(), ApiInfo::new("XXXXXXXXXXXXXXXXXXXX"));
+    let err = empty.unwrap_err();
+    assert!(err.to_string().ends_with("is empty"), "{}", err);
+
+    let err = ApiInfo::from_key_file(&path).unwrap_err();
+    assert!(
+      err.to_string().starts_with("failed to read API key from"),
+      "{}",
+      err
+    );
+  }
+
+  /// Check that we can create an `ApiInfo` object from valid parts.
+  #[test]
+  fn from_valid_parts() {
+    let api_url = Url::parse("http://127.0.0.1:8080").unwrap();
+    let stream_url = Url::parse("ws://127.0.0.1:8081").unwrap();
+    let api_info = ApiInfo::from_parts(api_url.clone(), stream_url.clone(), "XXXX").unwrap();
+    assert_eq!(api_info.api_url, api_url);
+    assert_eq!(api_info.stream_url, stream_url);
+    assert_eq!(api_info.api_key, "XXXX");
+
+    let api_url = Url::parse(DEFAULT_API_URL).unwrap();
+    let stream_url = Url::parse(DEFAULT_STREAM_URL).unwrap();
+    let api_info = ApiInfo::from_parts(api_url, stream_url, "XXXXXXXXXXXXXXXXXXXX").unwrap();
+    assert_eq!(api_info, ApiInfo::new("XXXXXXXXXXXXXXXXXXXX"));
+  }
+
+  /// Check that we reject parts using the wrong URL scheme.
+  #[test]
+  fn from_invalid_parts() {
+    let api_url = Url::parse("https://api.polygon.io").unwrap();
+    let stream_url = Url::parse("https://socket.polygon.io").unwrap();
+    let err = ApiInfo::from_parts(api_url.clone(), stream_url, "XXXX").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "stream URL https://socket.polygon.io/ uses unsupported scheme 'https'; expected one of: ws, wss"
+    );
+
+    let stream_url = Url::parse("wss://socket.polygon.io").unwrap();
+    let err = ApiInfo::from_parts(stream_url.clone(), stream_url, "XXXX").unwrap_err();
+    assert!(err.to_string().starts_with("API URL wss://"), "{}", err);
+  }
+
+  /// Check that we can resolve an absolute `next_url` against the
+  /// configured API URL.
+  #[test]
+  fn resolve_absolute_next_url() {
+    let mut api_info = ApiInfo::new("XXXXXXXXXXXXXXXXXXXX");
+    api_info.api_url = Url::parse("http://127.0.0.1:8080").unwrap();
+
+    let next = "https://api.polygon.io/v3/reference/tickers?cursor=YWN0aXZlPXRydWU";
+    let url = api_info.resolve_next_url(next).unwrap();
+    assert_eq!(
+      url.as_str(),
+      "http://127.0.0.1:8080/v3/reference/tickers?cursor=YWN0aXZlPXRydWU&apiKey=XXXXXXXXXXXXXXXXXXXX"
+    );
+  }
+
+  /// Check that we can resolve a root-relative `next_url`, with and
+  /// without a duplicated host.
+  #[test]
+  fn resolve_relative_next_url() {
+    let api_info = ApiInfo::new("XXXXXXXXXXXXXXXXXXXX");
+    let expected =
+      "https://api.polygon.io/v3/reference/tickers?cursor=YWN0aXZlPXRydWU&apiKey=XXXXXXXXXXXXXXXXXXXX";
+
+    let next = "/v3/reference/tickers?cursor=YWN0aXZlPXRydWU";
+    let url = api_info.resolve_next_url(next).unwrap();
+    assert_eq!(url.as_str(), expected);
+
+    let next = "api.polygon.io/v3/reference/tickers?cursor=YWN0aXZlPXRydWU";
+    let url = api_info.resolve_next_url(next).unwrap();
+    assert_eq!(url.as_str(), expected);
+  }
+
+  /// Check that an API key already present in a `next_url` is replaced
+  /// and not duplicated.
+  #[test]
+  fn resolve_next_url_with_api_key() {
+    let api_info = ApiInfo::new("XXXXXXXXXXXXXXXXXXXX");
+
+    let next = "https://api.polygon.io/v3/trades/AAPL?cursor=YXA9MSZhcz0&apiKey=YYYYYYYYYYYY";
+    let url = api_info.resolve_next_url(next).unwrap();
+    assert_eq!(
+      url.as_str(),
+      "https://api.polygon.io/v3/trades/AAPL?cursor=YXA9MSZhcz0&apiKey=XXXXXXXXXXXXXXXXXXXX"
+    );
+  }
 }
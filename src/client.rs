@@ -1,15 +1,50 @@
 // Copyright (C) 2019-2022 Daniel Mueller <deso@posteo.net>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::any::type_name;
+#[cfg(not(target_arch = "wasm32"))]
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashMap;
 use std::collections::HashSet;
+#[cfg(not(target_arch = "wasm32"))]
 use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::SystemTime;
+
+use chrono::NaiveDate;
+#[cfg(not(target_arch = "wasm32"))]
+use chrono::DateTime;
+#[cfg(not(target_arch = "wasm32"))]
+use chrono::Utc;
+
+#[cfg(not(target_arch = "wasm32"))]
+use futures::future::join_all;
 
 #[cfg(not(target_arch = "wasm32"))]
 use futures::Stream;
 
+#[cfg(not(target_arch = "wasm32"))]
+use hyper::body::Bytes;
+
 use http_endpoint::Endpoint;
 
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::sync::Semaphore;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::time::sleep;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::time::timeout;
+
 use tracing::debug;
 use tracing::instrument;
 use tracing::span;
@@ -25,71 +60,99 @@ use url::Url;
 #[cfg(not(target_arch = "wasm32"))]
 use websocket_util::tungstenite::Error as WebSocketError;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::api::aggregates;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::api::aggregates::Aggregate;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::api::aggregates::AggregateReq;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::api::aggregates::LatestBarError;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::api::aggregates::TimeSpan;
+use crate::api::entitlements;
+use crate::api::entitlements::Entitlement;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::api::previous_close;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::api::previous_close::PreviousCloseError;
+use crate::api::ticker_types;
+use crate::api::tickers;
+use crate::api::tickers::TickersAsOfError;
+use crate::api::tickers::TickersReq;
+use crate::api_info::redact_url;
 use crate::api_info::ApiInfo;
+use crate::api_info::API_KEY_PARAM;
+#[cfg(not(target_arch = "wasm32"))]
 use crate::error::Error;
 use crate::error::RequestError;
-use crate::events::Stock;
+#[cfg(not(target_arch = "wasm32"))]
 use crate::events::Subscription;
 #[cfg(not(target_arch = "wasm32"))]
+use crate::metrics::MetricsSink;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::metrics::NoopMetrics;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::rate_limit::RateLimiter;
+#[cfg(not(target_arch = "wasm32"))]
 use crate::events::{
-  stream,
+  stream_with_config,
   Event,
+  StreamConfig,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use crate::Str;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::USER_AGENT;
 
-/// The query parameter used for communicating the API key to Polygon.
-const API_KEY_PARAM: &str = "apiKey";
 
+/// The number of tickers to request per page when retrieving all of
+/// them.
+const TICKERS_PAGE_LIMIT: usize = 1000;
 
-/// Normalize a list of subscriptions, removing duplicates and overlaps.
-///
-/// If a subscription applies to all stocks of a certain type (e.g.,
-/// `Subscription::Trades(Stock::All)`) then more specific subscriptions
-/// are removed (e.g., `Subscription::Trades(Stock::Symbol("SPY"))`).
-fn normalize<S>(subscriptions: S) -> HashSet<Subscription>
-where
-  S: IntoIterator<Item = Subscription>,
-{
-  let mut subs = subscriptions.into_iter().collect::<HashSet<_>>();
 
-  if subs.contains(&Subscription::SecondAggregates(Stock::All)) {
-    subs.retain(|sub| match sub {
-      Subscription::SecondAggregates(stock) => *stock == Stock::All,
-      _ => true,
-    })
-  }
+/// A shared mapping from ticker type codes to their descriptions.
+type TypeDescriptions = Arc<BTreeMap<String, String>>;
 
-  if subs.contains(&Subscription::MinuteAggregates(Stock::All)) {
-    subs.retain(|sub| match sub {
-      Subscription::MinuteAggregates(stock) => *stock == Stock::All,
-      _ => true,
-    })
-  }
+/// A shared mapping from request URLs to the `ETag` and body of the
+/// most recent successful response.
+#[cfg(not(target_arch = "wasm32"))]
+type ETagCache = Arc<Mutex<HashMap<String, (String, Bytes)>>>;
 
-  if subs.contains(&Subscription::Trades(Stock::All)) {
-    subs.retain(|sub| match sub {
-      Subscription::Trades(stock) => *stock == Stock::All,
-      _ => true,
-    })
-  }
 
-  if subs.contains(&Subscription::Quotes(Stock::All)) {
-    subs.retain(|sub| match sub {
-      Subscription::Quotes(stock) => *stock == Stock::All,
-      _ => true,
-    })
-  }
+/// A policy for retrying requests that failed transiently.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy, Debug)]
+struct RetryPolicy {
+  /// The maximum number of times to retry a request.
+  count: usize,
+  /// The time to wait before the first retry. It doubles with every
+  /// subsequent one.
+  backoff: Duration,
+}
+
 
-  subs
+/// Check whether a response with the given status indicates a
+/// transient failure for which a retry may succeed.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_retryable(status: http::StatusCode) -> bool {
+  matches!(
+    status,
+    http::StatusCode::BAD_GATEWAY
+      | http::StatusCode::SERVICE_UNAVAILABLE
+      | http::StatusCode::GATEWAY_TIMEOUT
+  )
 }
 
 
-/// Build the URL for a request to the provided endpoint.
-fn url<E>(api_info: &ApiInfo, input: &E::Input) -> Result<Url, E::Error>
+/// Build the URL for a request to the provided endpoint, with the
+/// endpoint's path prefixed by `base_path`.
+fn url<E>(api_info: &ApiInfo, base_path: &str, input: &E::Input) -> Result<Url, E::Error>
 where
   E: Endpoint,
 {
   let mut url = api_info.api_url.clone();
-  url.set_path(&E::path(input));
+  url.set_path(&format!("{}{}", base_path, E::path(input)));
   url.set_query(E::query(input)?.as_ref().map(AsRef::as_ref));
   url
     .query_pairs_mut()
@@ -105,31 +168,61 @@ mod hype {
 
   use std::str::from_utf8;
 
+  use http::header::ETAG;
+  use http::header::IF_NONE_MATCH;
+  use http::header::USER_AGENT as USER_AGENT_HEADER;
   use http::request::Builder as HttpRequestBuilder;
   use http::Request;
+  use http::StatusCode;
 
   use hyper::body::to_bytes;
+  use hyper::body::Bytes;
   use hyper::client::HttpConnector;
   use hyper::Body;
   use hyper::Client as HttpClient;
   use hyper_tls::HttpsConnector;
 
+  use native_tls::Error as TlsError;
+  use native_tls::TlsConnector;
+
   pub type Backend = HttpClient<HttpsConnector<HttpConnector>, Body>;
 
   pub fn new() -> Backend {
     HttpClient::builder().build(HttpsConnector::new())
   }
 
-  /// Create a `Request` to the endpoint.
-  fn request<E>(api_info: &ApiInfo, input: &E::Input) -> Result<Request<Body>, E::Error>
+  /// Create a `Backend` that accepts any server certificate.
+  pub fn new_accepting_invalid_certs() -> Result<Backend, TlsError> {
+    let tls = TlsConnector::builder()
+      .danger_accept_invalid_certs(true)
+      .build()?;
+    let mut http = HttpConnector::new();
+    // Let the `HttpsConnector` handle `https` URLs.
+    http.enforce_http(false);
+    let https = HttpsConnector::from((http, tls.into()));
+    Ok(HttpClient::builder().build(https))
+  }
+
+  /// Create a `Request` to the endpoint at the given URL, conditional
+  /// on the resource no longer matching the given `ETag`, if any.
+  fn request<E>(
+    url: &Url,
+    user_agent: &str,
+    etag: Option<&str>,
+    input: &E::Input,
+  ) -> Result<Request<Body>, E::Error>
   where
     E: Endpoint,
   {
-    let url = url::<E>(api_info, input)?;
-    let request = HttpRequestBuilder::new()
+    let mut builder = HttpRequestBuilder::new()
       .method(E::method())
       .uri(url.as_str())
-      .body(Body::from(
+      .header(USER_AGENT_HEADER, user_agent);
+    if let Some(etag) = etag {
+      builder = builder.header(IF_NONE_MATCH, etag);
+    }
+
+    let request = builder.body(Body::from(
         E::body(input)?.unwrap_or_else(|| Cow::Borrowed(&[0; 0])),
       ))?;
 
@@ -137,32 +230,45 @@ mod hype {
     Ok(request)
   }
 
-  #[allow(clippy::cognitive_complexity)]
-  pub async fn issue<E>(
+  /// Issue a request to the endpoint, reporting the response's status,
+  /// `ETag` (if any), and body.
+  #[allow(clippy::cognitive_complexity, clippy::type_complexity)]
+  pub async fn fetch<E>(
     client: &Backend,
     api_info: &ApiInfo,
-    input: E::Input,
-  ) -> Result<E::Output, RequestError<E::Error>>
+    base_path: &str,
+    user_agent: &str,
+    etag: Option<&str>,
+    input: &E::Input,
+  ) -> Result<(StatusCode, Option<String>, Bytes), RequestError<E::Error>>
   where
     E: Endpoint,
   {
-    let req = request::<E>(api_info, &input).map_err(RequestError::Endpoint)?;
+    let url = url::<E>(api_info, base_path, input).map_err(RequestError::Endpoint)?;
+    let req = request::<E>(&url, user_agent, etag, input).map_err(RequestError::Endpoint)?;
     let span = span!(
       Level::DEBUG,
       "request",
       method = display(&req.method()),
-      url = display(&req.uri()),
+      url = display(&redact_url(&url)),
     );
 
     async move {
       debug!("requesting");
-      trace!(request = debug(&req));
+      // The request's URI contains the API key. It is part of the span
+      // in redacted form already.
+      trace!(headers = debug(req.headers()));
 
       let result = client.request(req).await?;
       let status = result.status();
       debug!(status = debug(&status));
       trace!(response = debug(&result));
 
+      let etag = result
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(ToString::to_string);
       let bytes = to_bytes(result.into_body()).await?;
       let body = bytes.as_ref();
 
@@ -171,7 +277,7 @@ mod hype {
         Err(b) => trace!(body = display(&b)),
       }
 
-      E::evaluate(status, body).map_err(RequestError::Endpoint)
+      Ok((status, etag, bytes))
     }
     .instrument(span)
     .await
@@ -204,12 +310,11 @@ mod wasm {
     window().expect("no window found; not running inside a browser?")
   }
 
-  /// Create a `Request` to the endpoint.
-  fn request<E>(api_info: &ApiInfo, input: &E::Input) -> Result<Request, RequestError<E::Error>>
+  /// Create a `Request` to the endpoint at the given URL.
+  fn request<E>(url: &Url, input: &E::Input) -> Result<Request, RequestError<E::Error>>
   where
     E: Endpoint,
   {
-    let url = url::<E>(api_info, input).map_err(RequestError::Endpoint)?;
     let body = E::body(input)
       .map_err(E::Error::from)
       .map_err(RequestError::Endpoint)?;
@@ -233,22 +338,23 @@ mod wasm {
   pub async fn issue<E>(
     client: &Backend,
     api_info: &ApiInfo,
+    base_path: &str,
     input: E::Input,
   ) -> Result<E::Output, RequestError<E::Error>>
   where
     E: Endpoint,
   {
-    let req = request::<E>(api_info, &input)?;
+    let url = url::<E>(api_info, base_path, &input).map_err(RequestError::Endpoint)?;
+    let req = request::<E>(&url, &input)?;
     let span = span!(
       Level::DEBUG,
       "request",
       method = display(&req.method()),
-      url = display(&req.url()),
+      url = display(&redact_url(&url)),
     );
 
     async move {
       debug!("requesting");
-      trace!(request = debug(&req));
 
       let response = JsFuture::from(client.fetch_with_request(&req)).await?;
       let response = response.dyn_into::<Response>()?;
@@ -274,19 +380,304 @@ use hype::*;
 #[cfg(target_arch = "wasm32")]
 use wasm::*;
 
+/// A type helping with the construction of `Client` objects.
+///
+/// Unless overwritten, the default Polygon API and stream URLs are
+/// used. An API key always has to be provided.
+#[derive(Debug, Default)]
+pub struct ClientBuilder {
+  /// The base URL for API requests.
+  api_url: Option<Url>,
+  /// The base URL for market data streaming.
+  stream_url: Option<Url>,
+  /// The API key to use for authentication.
+  api_key: Option<String>,
+  /// The prefix to apply to the path of each endpoint.
+  base_path: Option<String>,
+  /// The maximum time a request may take to complete.
+  #[cfg(not(target_arch = "wasm32"))]
+  timeout: Option<Duration>,
+  /// The maximum number of requests to issue per period of time.
+  #[cfg(not(target_arch = "wasm32"))]
+  rate_limit: Option<(usize, Duration)>,
+  /// The maximum number of requests in flight at any time.
+  #[cfg(not(target_arch = "wasm32"))]
+  max_concurrency: Option<usize>,
+  /// The user agent to report to Polygon.
+  #[cfg(not(target_arch = "wasm32"))]
+  user_agent: Option<Str>,
+  /// The policy for retrying failed requests, if any.
+  #[cfg(not(target_arch = "wasm32"))]
+  retry: Option<RetryPolicy>,
+  /// The sink to report request metrics to.
+  #[cfg(not(target_arch = "wasm32"))]
+  metrics: Option<Arc<dyn MetricsSink>>,
+  /// Whether to accept invalid TLS certificates.
+  #[cfg(not(target_arch = "wasm32"))]
+  danger_accept_invalid_certs: bool,
+  /// Whether to cache responses based on their `ETag`.
+  #[cfg(not(target_arch = "wasm32"))]
+  etag_cache: bool,
+}
+
+impl ClientBuilder {
+  /// Set the base URL for API requests, e.g., to point the client to a
+  /// mock server.
+  pub fn api_url(mut self, url: Url) -> Self {
+    self.api_url = Some(url);
+    self
+  }
+
+  /// Set the base URL for market data streaming.
+  pub fn stream_url(mut self, url: Url) -> Self {
+    self.stream_url = Some(url);
+    self
+  }
+
+  /// Set the API key to use for authentication.
+  pub fn api_key<S>(mut self, api_key: S) -> Self
+  where
+    S: Into<String>,
+  {
+    self.api_key = Some(api_key.into());
+    self
+  }
+
+  /// Set a prefix to apply to the path of each endpoint, e.g.,
+  /// `/polygon` for accessing Polygon through a gateway that exposes
+  /// `/v2/...` as `/polygon/v2/...`.
+  ///
+  /// A trailing slash is ignored. By default, no prefix is applied.
+  pub fn base_path<S>(mut self, base_path: S) -> Self
+  where
+    S: Into<String>,
+  {
+    self.base_path = Some(base_path.into());
+    self
+  }
+
+  /// Set the maximum time a request may take to complete before
+  /// failing with `RequestError::Timeout`.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Limit the number of requests issued to `count` per `period`.
+  ///
+  /// Requests exceeding the limit are delayed until they can be issued
+  /// without violating it.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn rate_limit(mut self, count: usize, period: Duration) -> Self {
+    self.rate_limit = Some((count, period));
+    self
+  }
+
+  /// Limit the number of requests in flight at any time to `count`
+  /// (but at least one).
+  ///
+  /// Requests exceeding the limit are delayed until a previously
+  /// issued one completed. By default, the number of concurrent
+  /// requests is unlimited.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn max_concurrency(mut self, count: usize) -> Self {
+    self.max_concurrency = Some(count.max(1));
+    self
+  }
+
+  /// Set the user agent to report for API requests and market data
+  /// streaming, overwriting the default of `polyio/<version>`.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn user_agent<S>(mut self, user_agent: S) -> Self
+  where
+    S: Into<Str>,
+  {
+    self.user_agent = Some(user_agent.into());
+    self
+  }
+
+  /// Retry requests failing with a transient server error (HTTP status
+  /// 502, 503, or 504) up to `count` times.
+  ///
+  /// The first retry happens after `backoff`, with the delay doubling
+  /// for each subsequent one. Only requests to endpoints using an
+  /// idempotent HTTP method are retried. By default, requests are not
+  /// retried.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn retries(mut self, count: usize, backoff: Duration) -> Self {
+    self.retry = Some(RetryPolicy { count, backoff });
+    self
+  }
+
+  /// Set the sink to report metrics about each completed request to.
+  ///
+  /// By default, metrics are discarded.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn metrics<M>(mut self, metrics: M) -> Self
+  where
+    M: MetricsSink + 'static,
+  {
+    self.metrics = Some(Arc::new(metrics));
+    self
+  }
+
+  /// Accept any TLS certificate presented by the server, including
+  /// self-signed and expired ones, for API requests and market data
+  /// streaming.
+  ///
+  /// # Warning
+  /// Certificate validation is what protects against
+  /// man-in-the-middle attacks. Disabling it is only meant for
+  /// debugging, e.g., for inspecting traffic through a local
+  /// intercepting proxy, and must never be used in production. By
+  /// default, invalid certificates are rejected.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+    self.danger_accept_invalid_certs = accept;
+    self
+  }
+
+  /// Cache the bodies of successful responses to `GET` requests that
+  /// carry an `ETag`, and make subsequent identical requests
+  /// conditional on it. Once Polygon reports `304 Not Modified`, the
+  /// cached body is used instead.
+  ///
+  /// This is meant for polling rarely changing reference data, such as
+  /// tickers or exchanges. One body is retained per distinct request
+  /// URL for the lifetime of the client and its clones. By default,
+  /// no responses are cached.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn etag_cache(mut self, enable: bool) -> Self {
+    self.etag_cache = enable;
+    self
+  }
+
+  /// Build the final `Client`.
+  pub fn build(self) -> Result<Client, Error> {
+    let api_key = self
+      .api_key
+      .ok_or_else(|| Error::Str("no API key provided".into()))?;
+
+    let mut api_info = ApiInfo::new(api_key);
+    if let Some(api_url) = self.api_url {
+      api_info.api_url = api_url;
+    }
+    if let Some(stream_url) = self.stream_url {
+      api_info.stream_url = stream_url;
+    }
+
+    let mut client = Client::new(api_info);
+    if let Some(base_path) = self.base_path {
+      let base_path = base_path.trim_end_matches('/');
+      client.base_path = if base_path.is_empty() || base_path.starts_with('/') {
+        base_path.to_string()
+      } else {
+        format!("/{}", base_path)
+      };
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let client = Client {
+      client: if self.danger_accept_invalid_certs {
+        new_accepting_invalid_certs().map_err(|err| {
+          Error::Str(format!("failed to create TLS connector: {}", err).into())
+        })?
+      } else {
+        client.client
+      },
+      danger_accept_invalid_certs: self.danger_accept_invalid_certs,
+      timeout: self.timeout,
+      rate_limiter: self
+        .rate_limit
+        .map(|(count, period)| Arc::new(RateLimiter::new(count, period))),
+      concurrency: self
+        .max_concurrency
+        .map(|count| Arc::new(Semaphore::new(count))),
+      user_agent: self.user_agent.unwrap_or(client.user_agent),
+      retry: self.retry,
+      metrics: self.metrics.unwrap_or(client.metrics),
+      etags: self
+        .etag_cache
+        .then(|| Arc::new(Mutex::new(HashMap::new()))),
+      ..client
+    };
+    Ok(client)
+  }
+}
+
+
 /// A `Client` is the entity used by clients of this module for
 /// interacting with the Polygon API.
-#[derive(Debug)]
+///
+/// Cloning a `Client` is cheap. Clones share the underlying connection
+/// pool as well as the rate limit, if one is configured.
+#[derive(Clone, Debug)]
 pub struct Client {
   api_info: ApiInfo,
   client: Backend,
+  /// The prefix to apply to the path of each endpoint, without a
+  /// trailing slash.
+  base_path: String,
+  /// The maximum time a request may take to complete.
+  #[cfg(not(target_arch = "wasm32"))]
+  timeout: Option<Duration>,
+  /// The rate limiter to use for requests, if any.
+  #[cfg(not(target_arch = "wasm32"))]
+  rate_limiter: Option<Arc<RateLimiter>>,
+  /// The semaphore bounding the number of requests in flight, if any.
+  #[cfg(not(target_arch = "wasm32"))]
+  concurrency: Option<Arc<Semaphore>>,
+  /// The user agent to report to Polygon.
+  #[cfg(not(target_arch = "wasm32"))]
+  user_agent: Str,
+  /// The policy for retrying failed requests, if any.
+  #[cfg(not(target_arch = "wasm32"))]
+  retry: Option<RetryPolicy>,
+  /// The sink to report request metrics to.
+  #[cfg(not(target_arch = "wasm32"))]
+  metrics: Arc<dyn MetricsSink>,
+  /// Whether invalid TLS certificates are accepted.
+  #[cfg(not(target_arch = "wasm32"))]
+  danger_accept_invalid_certs: bool,
+  /// The cache of responses by `ETag`, if enabled.
+  #[cfg(not(target_arch = "wasm32"))]
+  etags: Option<ETagCache>,
+  /// The ticker type descriptions, once retrieved.
+  ticker_types: Arc<Mutex<Option<TypeDescriptions>>>,
 }
 
 impl Client {
   /// Create a new `Client` using the given API information.
   pub fn new(api_info: ApiInfo) -> Self {
     let client = new();
-    Self { api_info, client }
+    Self {
+      api_info,
+      client,
+      base_path: String::new(),
+      #[cfg(not(target_arch = "wasm32"))]
+      timeout: None,
+      #[cfg(not(target_arch = "wasm32"))]
+      rate_limiter: None,
+      #[cfg(not(target_arch = "wasm32"))]
+      concurrency: None,
+      #[cfg(not(target_arch = "wasm32"))]
+      user_agent: USER_AGENT.into(),
+      #[cfg(not(target_arch = "wasm32"))]
+      retry: None,
+      #[cfg(not(target_arch = "wasm32"))]
+      metrics: Arc::new(NoopMetrics),
+      #[cfg(not(target_arch = "wasm32"))]
+      danger_accept_invalid_certs: false,
+      #[cfg(not(target_arch = "wasm32"))]
+      etags: None,
+      ticker_types: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  /// Create a `ClientBuilder` for creating a customized `Client`.
+  pub fn builder() -> ClientBuilder {
+    ClientBuilder::default()
   }
 
   /// Create a new `Client` with information from the environment.
@@ -295,13 +686,359 @@ impl Client {
     Ok(Self::new(api_info))
   }
 
+  /// Create a new `Client` using the API key stored in the file at the
+  /// given path.
+  ///
+  /// See `ApiInfo::from_key_file` for details.
+  pub fn from_key_file<P>(path: P) -> Result<Self, Error>
+  where
+    P: AsRef<Path>,
+  {
+    let api_info = ApiInfo::from_key_file(path)?;
+    Ok(Self::new(api_info))
+  }
+
   /// Create and issue a request and decode the response.
+  ///
+  /// The returned future is cancel-safe: dropping it aborts an
+  /// in-flight request, closing the underlying connection, and
+  /// releases any concurrency slot held. Cancelled requests are not
+  /// reported to the configured `MetricsSink`.
+  #[cfg(not(target_arch = "wasm32"))]
   #[instrument(level = "debug", skip(self, input))]
   pub async fn issue<E>(&self, input: E::Input) -> Result<E::Output, RequestError<E::Error>>
   where
     E: Endpoint,
   {
-    issue::<E>(&self.client, &self.api_info, input).await
+    let (output, _body) = self.issue_::<E>(&self.api_info, input).await?;
+    Ok(output)
+  }
+
+  /// Create and issue a request on behalf of the owner of the given API
+  /// key and decode the response.
+  ///
+  /// Contrary to `issue`, the request is authenticated using `api_key`
+  /// instead of the client's own key. All other settings, including
+  /// the connection pool, are shared.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[instrument(level = "debug", skip(self, input, api_key))]
+  pub async fn issue_as<E>(
+    &self,
+    input: E::Input,
+    api_key: &str,
+  ) -> Result<E::Output, RequestError<E::Error>>
+  where
+    E: Endpoint,
+  {
+    let api_info = self.api_info_for(api_key);
+    let (output, _body) = self.issue_::<E>(&api_info, input).await?;
+    Ok(output)
+  }
+
+  /// Create and issue a request and decode the response, additionally
+  /// reporting the raw response body as it was received.
+  ///
+  /// This method is meant as a debugging aid, e.g., for comparing the
+  /// decoded output against the data Polygon actually reported.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[allow(clippy::type_complexity)]
+  #[instrument(level = "debug", skip(self, input))]
+  pub async fn issue_with_raw<E>(
+    &self,
+    input: E::Input,
+  ) -> Result<(E::Output, Vec<u8>), RequestError<E::Error>>
+  where
+    E: Endpoint,
+  {
+    let (output, body) = self.issue_::<E>(&self.api_info, input).await?;
+    Ok((output, body.to_vec()))
+  }
+
+  /// Implementation of `issue` also reporting the raw response body.
+  #[cfg(not(target_arch = "wasm32"))]
+  async fn issue_<E>(
+    &self,
+    api_info: &ApiInfo,
+    input: E::Input,
+  ) -> Result<(E::Output, Bytes), RequestError<E::Error>>
+  where
+    E: Endpoint,
+  {
+    let start = Instant::now();
+    let mut final_status = None;
+    // The cache key along with the cached `ETag` and body, if any, in
+    // case the response is eligible for caching.
+    let cache = match &self.etags {
+      Some(etags) if E::method() == http::Method::GET => {
+        url::<E>(api_info, &self.base_path, &input).ok().map(|url| {
+          let key = String::from(url);
+          let cached = etags.lock().unwrap().get(&key).cloned();
+          (key, cached)
+        })
+      },
+      _ => None,
+    };
+
+    let future = async {
+      // Only requests that can safely be repeated are ever retried.
+      let retries = if E::method().is_idempotent() {
+        self.retry.map(|retry| retry.count).unwrap_or(0)
+      } else {
+        0
+      };
+      let mut attempt = 0;
+
+      loop {
+        // The permit is held for the duration of the request and
+        // released before a potential retry backs off.
+        let permit = match &self.concurrency {
+          // We never close the semaphore so acquisition cannot fail.
+          Some(concurrency) => Some(concurrency.acquire().await.unwrap()),
+          None => None,
+        };
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+          rate_limiter.acquire().await;
+        }
+
+        let etag = cache
+          .as_ref()
+          .and_then(|(_, cached)| cached.as_ref())
+          .map(|(etag, _)| etag.as_str());
+        let (status, etag, body) = fetch::<E>(
+          &self.client,
+          api_info,
+          &self.base_path,
+          &self.user_agent,
+          etag,
+          &input,
+        )
+        .await?;
+        drop(permit);
+        final_status = Some(status);
+
+        let (status, body) = match (&cache, etag) {
+          (Some((_, Some((_, cached)))), _) if status == http::StatusCode::NOT_MODIFIED => {
+            debug!("using cached response");
+            (http::StatusCode::OK, cached.clone())
+          },
+          (Some((key, _)), Some(etag)) if status.is_success() => {
+            // We only ever get here if the cache is enabled.
+            let etags = self.etags.as_ref().unwrap();
+            let _ = etags
+              .lock()
+              .unwrap()
+              .insert(key.clone(), (etag, body.clone()));
+            (status, body)
+          },
+          _ => (status, body),
+        };
+
+        if attempt < retries && is_retryable(status) {
+          // We only ever get here if a retry policy is set.
+          let backoff = self.retry.unwrap().backoff * 2u32.saturating_pow(attempt as u32);
+          debug!(
+            status = debug(&status),
+            attempt,
+            backoff = debug(&backoff),
+            "retrying request"
+          );
+          let () = sleep(backoff).await;
+          attempt += 1;
+          continue
+        }
+
+        let output = E::evaluate(status, &body).map_err(RequestError::Endpoint)?;
+        break Ok((output, body))
+      }
+    };
+
+    let result = match self.timeout {
+      Some(duration) => match timeout(duration, future).await {
+        Ok(result) => result,
+        Err(..) => {
+          // Any response received for an earlier attempt is not the
+          // final one.
+          final_status = None;
+          Err(RequestError::Timeout)
+        },
+      },
+      None => future.await,
+    };
+
+    self.metrics.observe(type_name::<E>(), final_status, start.elapsed());
+    result
+  }
+
+  /// Create and issue a request and decode the response.
+  #[cfg(target_arch = "wasm32")]
+  #[instrument(level = "debug", skip(self, input))]
+  pub async fn issue<E>(&self, input: E::Input) -> Result<E::Output, RequestError<E::Error>>
+  where
+    E: Endpoint,
+  {
+    issue::<E>(&self.client, &self.api_info, &self.base_path, input).await
+  }
+
+  /// Create and issue a request on behalf of the owner of the given API
+  /// key and decode the response.
+  #[cfg(target_arch = "wasm32")]
+  #[instrument(level = "debug", skip(self, input, api_key))]
+  pub async fn issue_as<E>(
+    &self,
+    input: E::Input,
+    api_key: &str,
+  ) -> Result<E::Output, RequestError<E::Error>>
+  where
+    E: Endpoint,
+  {
+    let api_info = self.api_info_for(api_key);
+    issue::<E>(&self.client, &api_info, &self.base_path, input).await
+  }
+
+  /// Create the API information for authenticating with the given API
+  /// key, retaining the client's URLs.
+  fn api_info_for(&self, api_key: &str) -> ApiInfo {
+    ApiInfo {
+      api_key: api_key.to_string(),
+      ..self.api_info.clone()
+    }
+  }
+
+  /// Create and issue a request and decode the response, additionally
+  /// reporting the time it took for the request to complete.
+  ///
+  /// The reported duration covers everything from sending the request
+  /// until the response body has been received and decoded.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub async fn issue_timed<E>(
+    &self,
+    input: E::Input,
+  ) -> Result<(E::Output, Duration), RequestError<E::Error>>
+  where
+    E: Endpoint,
+  {
+    let start = Instant::now();
+    let output = self.issue::<E>(input).await?;
+    Ok((output, start.elapsed()))
+  }
+
+  /// Retrieve all tickers as they were available on the given date.
+  ///
+  /// Contrary to a query of the currently available tickers, the
+  /// result includes tickers that have since been delisted, making it
+  /// suitable for point-in-time analyses free of survivorship bias.
+  /// All pages of the result are retrieved by following the cursor
+  /// reported by Polygon.
+  ///
+  /// `TickersAsOfError::RepeatedCursor` is reported if Polygon reports
+  /// a cursor already followed.
+  pub async fn tickers_as_of(
+    &self,
+    date: NaiveDate,
+  ) -> Result<Vec<tickers::Ticker>, TickersAsOfError> {
+    let mut request = TickersReq {
+      date: Some(date),
+      limit: Some(TICKERS_PAGE_LIMIT),
+      cursor: None,
+    };
+    let mut visited = HashSet::new();
+    let mut result = Vec::new();
+
+    loop {
+      let mut response = self.issue::<tickers::Get>(request.clone()).await?;
+      result.append(&mut response.tickers);
+
+      match response.next_cursor() {
+        Some(cursor) if !visited.insert(cursor.clone()) => {
+          break Err(TickersAsOfError::RepeatedCursor(cursor))
+        },
+        Some(cursor) => request.cursor = Some(cursor),
+        None => break Ok(result),
+      }
+    }
+  }
+
+  /// Retrieve the previous day's close for each of the given symbols.
+  ///
+  /// Requests are issued concurrently, subject to the client's rate
+  /// limit and concurrency limit, if configured. The retrieval for each
+  /// symbol succeeds or fails individually.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub async fn previous_closes<S>(
+    &self,
+    symbols: &[S],
+  ) -> HashMap<String, Result<Aggregate, PreviousCloseError>>
+  where
+    S: AsRef<str>,
+  {
+    let requests = symbols.iter().map(|symbol| async move {
+      let symbol = symbol.as_ref().to_string();
+      let result = self
+        .issue::<previous_close::Get>(symbol.clone())
+        .await
+        .map_err(PreviousCloseError::from)
+        .and_then(|response| response.single_result().map_err(PreviousCloseError::from));
+      (symbol, result)
+    });
+
+    join_all(requests).await.into_iter().collect()
+  }
+
+  /// Retrieve the most recent aggregate of the given symbol for the
+  /// provided time span, e.g., the latest minute bar.
+  ///
+  /// Only the newest aggregate within the current and the previous
+  /// unit of the time span (but at least the last four days) is
+  /// requested. Note that it may cover a unit that has not yet
+  /// concluded. `LatestBarError::NotFound` is reported if no aggregate
+  /// exists in this range.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub async fn latest_bar(
+    &self,
+    symbol: &str,
+    time_span: TimeSpan,
+  ) -> Result<Aggregate, LatestBarError> {
+    let now = DateTime::<Utc>::from(SystemTime::now());
+    let request = AggregateReq::latest(symbol, time_span, now);
+    let aggregates = self
+      .issue::<aggregates::GetLatest>(request)
+      .await?
+      .into_result()?;
+
+    aggregates
+      .into_iter()
+      .max_by_key(|aggregate| aggregate.timestamp)
+      .ok_or_else(|| LatestBarError::NotFound(symbol.to_string()))
+  }
+
+  /// Retrieve a mapping from ticker type codes (e.g., "CS") to their
+  /// descriptions (e.g., "Common Stock").
+  ///
+  /// The mapping is only retrieved once and cached afterwards. The
+  /// cache is shared between clones of the `Client`.
+  pub async fn ticker_types(
+    &self,
+  ) -> Result<Arc<BTreeMap<String, String>>, RequestError<ticker_types::GetError>> {
+    if let Some(types) = self.ticker_types.lock().unwrap().as_ref() {
+      return Ok(types.clone())
+    }
+
+    let types = self.issue::<ticker_types::Get>(()).await?;
+    let types = Arc::new(types.descriptions());
+    *self.ticker_types.lock().unwrap() = Some(types.clone());
+    Ok(types)
+  }
+
+  /// Determine what the API key in use is entitled to.
+  ///
+  /// Polygon does not report entitlements directly. Instead, a last
+  /// trade request is issued and its response classified.
+  pub async fn entitlement(&self) -> Result<Entitlement, RequestError<entitlements::GetError>> {
+    let result = self
+      .issue::<entitlements::Get>(entitlements::PROBE_SYMBOL.to_string())
+      .await;
+    Entitlement::classify(result)
   }
 
   /// Subscribe to the given stream in order to receive updates.
@@ -313,7 +1050,7 @@ impl Client {
   where
     S: IntoIterator<Item = Subscription>,
   {
-    let subscriptions = normalize(subscriptions);
+    let subscriptions = subscriptions.into_iter().collect::<Vec<_>>();
     self.subscribe_(subscriptions).await
   }
 
@@ -339,7 +1076,12 @@ impl Client {
       api_key: self.api_info.api_key.clone(),
     };
 
-    stream(api_info, subscriptions).await
+    let config = StreamConfig {
+      user_agent: self.user_agent.clone(),
+      danger_accept_invalid_certs: self.danger_accept_invalid_certs,
+      ..Default::default()
+    };
+    stream_with_config(api_info, subscriptions, config).await
   }
 }
 
@@ -348,47 +1090,859 @@ impl Client {
 mod tests {
   use super::*;
 
-  use maplit::hashset;
+  use std::env::temp_dir;
+  use std::fs::remove_file;
+  use std::fs::write;
+  use std::process::id;
+  #[cfg(not(target_arch = "wasm32"))]
+  use std::sync::atomic::AtomicUsize;
+  #[cfg(not(target_arch = "wasm32"))]
+  use std::sync::atomic::Ordering;
+
+  #[cfg(not(target_arch = "wasm32"))]
+  use http::Method;
+
+  #[cfg(not(target_arch = "wasm32"))]
+  use num_decimal::Num;
 
   #[cfg(not(target_arch = "wasm32"))]
   use test_log::test;
 
+  #[cfg(not(target_arch = "wasm32"))]
+  use tokio::spawn;
+  #[cfg(not(target_arch = "wasm32"))]
+  use tokio::time::sleep;
+  #[cfg(not(target_arch = "wasm32"))]
+  use tokio::time::timeout;
+  #[cfg(not(target_arch = "wasm32"))]
+  use tokio::time::Instant as TokioInstant;
 
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::api::exchanges;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::api_info;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::mock_server;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::mock_tls_server;
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::mock::Response;
+
+
+  /// Check that the URL for a versioned endpoint is composed from the
+  /// configured API URL, the endpoint's version, and its resource.
   #[test]
-  fn normalize_subscriptions() {
-    let subscriptions = vec![
-      Subscription::Quotes(Stock::Symbol("SPY".into())),
-      Subscription::Trades(Stock::Symbol("MSFT".into())),
-      Subscription::Quotes(Stock::All),
-    ];
-    let expected = hashset! {
-      Subscription::Trades(Stock::Symbol("MSFT".into())),
-      Subscription::Quotes(Stock::All),
+  fn compose_versioned_url() {
+    let api_url = Url::parse("https://api.example.com").unwrap();
+    let stream_url = Url::parse("wss://socket.example.com").unwrap();
+    let api_info = ApiInfo::from_parts(api_url, stream_url, "XXXX").unwrap();
+    let request = TickersReq {
+      limit: Some(10),
+      ..Default::default()
     };
-    assert_eq!(normalize(subscriptions), expected);
-
-    let subscriptions = vec![
-      Subscription::SecondAggregates(Stock::All),
-      Subscription::SecondAggregates(Stock::Symbol("SPY".into())),
-      Subscription::MinuteAggregates(Stock::Symbol("AAPL".into())),
-      Subscription::MinuteAggregates(Stock::Symbol("VMW".into())),
-      Subscription::MinuteAggregates(Stock::All),
-    ];
-    let expected = hashset! {
-      Subscription::SecondAggregates(Stock::All),
-      Subscription::MinuteAggregates(Stock::All),
+
+    let url = url::<tickers::Get>(&api_info, "", &request).unwrap();
+    assert_eq!(
+      url.as_str(),
+      "https://api.example.com/v3/reference/tickers?limit=10&apiKey=XXXX"
+    );
+  }
+
+  /// Check that a configured base path is prepended to the endpoint's
+  /// path.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn base_path() {
+    let addr = mock_server(|request| async move {
+      if request.path.starts_with("/polygon/v1/meta/exchanges?") {
+        Response::json(200, "[]")
+      } else {
+        Response::json(404, "{}")
+      }
+    })
+    .await;
+
+    for base_path in ["/polygon", "polygon/"] {
+      let client = Client::builder()
+        .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+        .api_key("XXXXXXXXXXXXXXXXXXXX")
+        .base_path(base_path)
+        .build()
+        .unwrap();
+
+      let exchgs = client.issue::<exchanges::Get>(()).await.unwrap();
+      assert!(exchgs.is_empty());
+    }
+  }
+
+  /// Check that the API key does not show up in the log output of a
+  /// request.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[tokio::test]
+  async fn log_redacts_api_key() {
+    use std::io::Result as IoResult;
+    use std::io::Write;
+
+    use tracing::subscriber::set_default;
+    use tracing_subscriber::fmt;
+
+    use crate::api_info::redact;
+
+    /// A writer appending to a shared buffer.
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+      fn write(&mut self, data: &[u8]) -> IoResult<usize> {
+        self.0.lock().unwrap().write(data)
+      }
+
+      fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+      }
+    }
+
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = fmt()
+      .with_ansi(false)
+      .with_max_level(Level::TRACE)
+      .with_writer({
+        let output = output.clone();
+        move || Buffer(output.clone())
+      })
+      .finish();
+    let _guard = set_default(subscriber);
+
+    let addr = mock_server(|_request| async move { Response::json(200, "[]") }).await;
+    let api_info = api_info(addr);
+    let api_key = api_info.api_key.clone();
+    let client = Client::new(api_info);
+    let _ = client.issue::<exchanges::Get>(()).await.unwrap();
+
+    let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    assert!(output.contains("requesting"), "{}", output);
+    assert!(output.contains(&redact(&api_key)), "{}", output);
+    assert!(!output.contains(&api_key), "{}", output);
+  }
+
+  /// Check that the duration reported by `Client::issue_timed` covers
+  /// the time it took the server to respond.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn issue_timed() {
+    let delay = Duration::from_millis(100);
+    let addr = mock_server(move |request| async move {
+      assert!(request.path.starts_with("/v1/meta/exchanges?"));
+      sleep(delay).await;
+      Response::json(200, "[]")
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let (exchgs, duration) = client.issue_timed::<exchanges::Get>(()).await.unwrap();
+    assert!(exchgs.is_empty());
+    assert!(duration >= delay, "{:?}", duration);
+  }
+
+  /// Check that we can point a `Client` built by a `ClientBuilder` to
+  /// a mock server.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn build_mock_client() {
+    let addr = mock_server(|request| async move {
+      assert!(request.path.ends_with("apiKey=XXXXXXXXXXXXXXXXXXXX"));
+      Response::json(200, r#"[{"id":1,"type":"exchange","market":"equities","name":"NYSE"}]"#)
+    })
+    .await;
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .rate_limit(5, Duration::from_secs(60))
+      .build()
+      .unwrap();
+
+    let exchgs = client.issue::<exchanges::Get>(()).await.unwrap();
+    assert_eq!(exchgs.len(), 1);
+    assert_eq!(exchgs[0].name, "NYSE");
+  }
+
+  /// Check that the configured `MetricsSink` observes the outcome of
+  /// each request.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn observe_metrics() {
+    type Observations = Vec<(String, Option<u16>)>;
+
+    #[derive(Debug, Default)]
+    struct Recorder(Arc<Mutex<Observations>>);
+
+    impl MetricsSink for Recorder {
+      fn observe(&self, endpoint: &str, status: Option<http::StatusCode>, _duration: Duration) {
+        let status = status.map(|status| status.as_u16());
+        self.0.lock().unwrap().push((endpoint.to_string(), status));
+      }
+    }
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let addr = mock_server(move |_request| {
+      let count = count.clone();
+      async move {
+        if count.fetch_add(1, Ordering::SeqCst) == 0 {
+          Response::json(200, r#"[{"id":1,"type":"exchange","market":"equities","name":"NYSE"}]"#)
+        } else {
+          Response::json(401, r#"{"message":"unauthorized"}"#)
+        }
+      }
+    })
+    .await;
+
+    let recorder = Recorder::default();
+    let observed = recorder.0.clone();
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .metrics(recorder)
+      .build()
+      .unwrap();
+
+    let _exchgs = client.issue::<exchanges::Get>(()).await.unwrap();
+    let _err = client.issue::<exchanges::Get>(()).await.unwrap_err();
+
+    let endpoint = "polyio::api::exchanges::Get".to_string();
+    let observed = observed.lock().unwrap().clone();
+    assert_eq!(
+      observed,
+      vec![(endpoint.clone(), Some(200)), (endpoint, Some(401))]
+    );
+  }
+
+  /// Check that `Client::issue_as` authenticates with the provided API
+  /// key.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn issue_as() {
+    let addr = mock_server(|request| async move {
+      assert!(request.path.ends_with("apiKey=YYYYYYYYYYYYYYYYYYYY"));
+      Response::json(200, r#"[{"id":1,"type":"exchange","market":"equities","name":"NYSE"}]"#)
+    })
+    .await;
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .build()
+      .unwrap();
+
+    let exchgs = client
+      .issue_as::<exchanges::Get>((), "YYYYYYYYYYYYYYYYYYYY")
+      .await
+      .unwrap();
+    assert_eq!(exchgs.len(), 1);
+  }
+
+  /// Check that we retrieve the previous closes of multiple symbols,
+  /// reporting failures individually.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn previous_closes() {
+    use crate::api::previous_close::GetError;
+
+    let addr = mock_server(|request| async move {
+      let symbol = request
+        .path
+        .strip_prefix("/v2/aggs/ticker/")
+        .and_then(|path| path.split('/').next())
+        .unwrap()
+        .to_string();
+      if symbol == "ZZZZ" {
+        let body = r#"{"status":"NOT_FOUND","request_id":"1","message":"Ticker not found."}"#;
+        Response::json(404, body)
+      } else {
+        let body = format!(
+          r#"{{"ticker":"{}","status":"OK","results":[{{"T":"{}","v":100,"o":1,"c":2,"h":3,"l":1,"t":1605042000000}}]}}"#,
+          symbol, symbol
+        );
+        Response::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let mut closes = client.previous_closes(&["AAPL", "MSFT", "ZZZZ"]).await;
+    assert_eq!(closes.len(), 3);
+
+    let close = closes.remove("AAPL").unwrap().unwrap();
+    assert_eq!(close.close_price, Num::from(2));
+    assert!(closes.remove("MSFT").unwrap().is_ok());
+
+    let err = closes.remove("ZZZZ").unwrap().unwrap_err();
+    match err {
+      PreviousCloseError::Request(RequestError::Endpoint(GetError::NotFound(..))) => (),
+      err => panic!("unexpected error: {:?}", err),
+    }
+  }
+
+  /// Check that we report the most recent aggregate of a symbol.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn latest_bar() {
+    let addr = mock_server(|request| async move {
+      assert!(
+        request.path.contains("?sort=desc&limit=1&"),
+        "{}",
+        request.path
+      );
+
+      if request.path.starts_with("/v2/aggs/ticker/AAPL/range/1/minute/") {
+        let body = r#"{"ticker":"AAPL","status":"OK","resultsCount":3,"results":[
+          {"v":100,"o":1,"c":1,"h":3,"l":1,"t":1605041880000},
+          {"v":100,"o":1,"c":2,"h":3,"l":1,"t":1605042000000},
+          {"v":100,"o":1,"c":3,"h":3,"l":1,"t":1605041940000}
+        ]}"#;
+        Response::json(200, body)
+      } else {
+        let body = r#"{"ticker":"ZZZZ","status":"OK","resultsCount":0}"#;
+        Response::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let bar = client.latest_bar("AAPL", TimeSpan::Minute).await.unwrap();
+    assert_eq!(bar.timestamp_millis(), 1605042000000);
+    assert_eq!(bar.close_price, Num::from(2));
+
+    let err = client
+      .latest_bar("ZZZZ", TimeSpan::Minute)
+      .await
+      .unwrap_err();
+    match err {
+      LatestBarError::NotFound(symbol) => assert_eq!(symbol, "ZZZZ"),
+      err => panic!("unexpected error: {:?}", err),
+    }
+  }
+
+  /// Check that dropping an `issue` future aborts the in-flight
+  /// request and releases its concurrency slot.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn cancel_in_flight_request() {
+    use tokio::io::AsyncReadExt as _;
+    use tokio::io::AsyncWriteExt as _;
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot::channel;
+    use tokio::time::timeout;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (received_send, received_recv) = channel();
+    let (closed_send, closed_recv) = channel();
+
+    spawn(async move {
+      // The first request never gets answered. We only observe the
+      // client closing the connection.
+      let (mut stream, _addr) = listener.accept().await.unwrap();
+      let mut buffer = [0; 4096];
+      let mut data = Vec::new();
+      while !data.windows(4).any(|window| window == b"\r\n\r\n") {
+        let count = stream.read(&mut buffer).await.unwrap();
+        data.extend_from_slice(&buffer[..count]);
+      }
+      let () = received_send.send(()).unwrap();
+
+      let count = stream.read(&mut buffer).await.unwrap_or(0);
+      let () = closed_send.send(count).unwrap();
+
+      // Subsequent requests are answered right away.
+      let (mut stream, _addr) = listener.accept().await.unwrap();
+      let _count = stream.read(&mut buffer).await.unwrap();
+      let body = "[]";
+      let response = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        body.len(),
+        body
+      );
+      stream.write_all(response.as_bytes()).await.unwrap();
+    });
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .max_concurrency(1)
+      .build()
+      .unwrap();
+
+    let request = {
+      let client = client.clone();
+      spawn(async move { client.issue::<exchanges::Get>(()).await })
     };
-    assert_eq!(normalize(subscriptions), expected);
-
-    let subscriptions = vec![
-      Subscription::Trades(Stock::All),
-      Subscription::Trades(Stock::Symbol("VMW".into())),
-      Subscription::Trades(Stock::All),
-    ];
-    let expected = hashset! {
-      Subscription::Trades(Stock::All),
+    let () = received_recv.await.unwrap();
+    request.abort();
+    assert!(request.await.unwrap_err().is_cancelled());
+
+    // The server sees the connection closing without having sent a
+    // response.
+    let count = timeout(Duration::from_secs(5), closed_recv)
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(count, 0);
+
+    // The concurrency slot of the cancelled request got released.
+    let exchgs = timeout(Duration::from_secs(5), client.issue::<exchanges::Get>(()))
+      .await
+      .unwrap()
+      .unwrap();
+    assert!(exchgs.is_empty());
+  }
+
+  /// Check that invalid TLS certificates are only accepted if
+  /// explicitly requested.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn accept_invalid_certs() {
+    let addr = mock_tls_server(|_request| async move { Response::json(200, "[]") }).await;
+    let builder = || {
+      Client::builder()
+        .api_url(Url::parse(&format!("https://localhost:{}", addr.port())).unwrap())
+        .api_key("XXXXXXXXXXXXXXXXXXXX")
     };
-    assert_eq!(normalize(subscriptions), expected);
+
+    let client = builder().build().unwrap();
+    let err = client.issue::<exchanges::Get>(()).await.unwrap_err();
+    assert!(matches!(err, RequestError::Hyper(..)), "{:?}", err);
+
+    let client = builder().danger_accept_invalid_certs(true).build().unwrap();
+    let exchgs = client.issue::<exchanges::Get>(()).await.unwrap();
+    assert!(exchgs.is_empty());
+  }
+
+  /// Check that requests report the default user agent.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn default_user_agent() {
+    let addr = mock_server(|request| async move {
+      let expected = concat!("polyio/", env!("CARGO_PKG_VERSION"));
+      assert_eq!(request.header("user-agent"), Some(expected));
+      Response::json(200, "[]")
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let exchgs = client.issue::<exchanges::Get>(()).await.unwrap();
+    assert!(exchgs.is_empty());
+  }
+
+  /// Check that we can overwrite the user agent reported by a
+  /// `Client`.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn custom_user_agent() {
+    let addr = mock_server(|request| async move {
+      assert_eq!(request.header("user-agent"), Some("my-app/1.0"));
+      Response::json(200, "[]")
+    })
+    .await;
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .user_agent("my-app/1.0")
+      .build()
+      .unwrap();
+
+    let exchgs = client.issue::<exchanges::Get>(()).await.unwrap();
+    assert!(exchgs.is_empty());
+  }
+
+  /// Check that `Client::tickers_as_of` requests tickers for the given
+  /// date and follows the reported cursor.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn tickers_as_of() {
+    let addr = mock_server(|request| async move {
+      assert!(
+        request.path.starts_with("/v3/reference/tickers?date=2008-09-12&"),
+        "{}",
+        request.path
+      );
+
+      if request.path.contains("cursor=YWN0aXZlPXRydWU") {
+        let body = r#"{"status":"OK","results":[
+          {"ticker":"MSFT","name":"Microsoft Corp","market":"stocks","locale":"us","active":true}
+        ]}"#;
+        Response::json(200, body)
+      } else {
+        let body = r#"{"status":"OK","results":[
+          {"ticker":"LEHMQ","name":"Lehman Brothers","market":"stocks","locale":"us","active":false}
+        ],"next_url":"https://api.polygon.io/v3/reference/tickers?cursor=YWN0aXZlPXRydWU"}"#;
+        Response::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let date = NaiveDate::from_ymd_opt(2008, 9, 12).unwrap();
+    let tickers = client.tickers_as_of(date).await.unwrap();
+    let tickers = tickers
+      .iter()
+      .map(|ticker| ticker.ticker.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(tickers, ["LEHMQ", "MSFT"]);
+  }
+
+  /// Check that `Client::tickers_as_of` reports an error instead of
+  /// looping forever if Polygon reports a cursor more than once.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn tickers_as_of_repeated_cursor() {
+    let addr = mock_server(|request| async move {
+      let next_url = if request.path.contains("cursor=YWN0aXZlPXRydWU") {
+        "https://api.polygon.io/v3/reference/tickers?cursor=YWN0aXZlPWZhbHNl"
+      } else {
+        "https://api.polygon.io/v3/reference/tickers?cursor=YWN0aXZlPXRydWU"
+      };
+      let body = format!(
+        r#"{{"status":"OK","results":[
+          {{"ticker":"MSFT","name":"Microsoft Corp","market":"stocks","locale":"us","active":true}}
+        ],"next_url":"{}"}}"#,
+        next_url
+      );
+      Response::json(200, body)
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let date = NaiveDate::from_ymd_opt(2008, 9, 12).unwrap();
+    let err = client.tickers_as_of(date).await.unwrap_err();
+    match err {
+      TickersAsOfError::RepeatedCursor(cursor) => assert_eq!(cursor, "YWN0aXZlPXRydWU"),
+      err => panic!("unexpected error: {:?}", err),
+    }
+  }
+
+  /// Check that `Client::ticker_types` retrieves the ticker types only
+  /// once.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn cache_ticker_types() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_ = count.clone();
+    let addr = mock_server(move |request| {
+      let count = count_.clone();
+      async move {
+        assert!(request.path.starts_with("/v3/reference/tickers/types?"));
+        let _ = count.fetch_add(1, Ordering::Relaxed);
+
+        let body = r#"{"status":"OK","results":[
+          {"code":"CS","description":"Common Stock","asset_class":"stocks","locale":"us"}
+        ]}"#;
+        Response::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let types = client.ticker_types().await.unwrap();
+    assert_eq!(types["CS"], "Common Stock");
+
+    let types = client.clone().ticker_types().await.unwrap();
+    assert_eq!(types.len(), 1);
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+  }
+
+  /// Check that `Client::issue_with_raw` reports the raw response body
+  /// along with the decoded output of a single request.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn issue_with_raw() {
+    let body = r#"{"status":"OK","results":[
+      {"ticker":"MSFT","name":"Microsoft Corp","market":"stocks","locale":"us","active":true}
+    ]}"#;
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_ = count.clone();
+    let addr = mock_server(move |_request| {
+      let count = count_.clone();
+      async move {
+        let _ = count.fetch_add(1, Ordering::Relaxed);
+        Response::json(200, body)
+      }
+    })
+    .await;
+
+    let client = Client::new(api_info(addr));
+    let (tickers, raw) = client
+      .issue_with_raw::<tickers::Get>(TickersReq::default())
+      .await
+      .unwrap();
+    assert_eq!(tickers.tickers.len(), 1);
+    assert_eq!(tickers.tickers[0].ticker, "MSFT");
+    assert_eq!(raw, body.as_bytes());
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+  }
+
+  /// Check that the cached body is reported once the server indicates
+  /// that the resource has not changed since the `ETag` was issued.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn etag_cache() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let addr = mock_server({
+      let requests = requests.clone();
+      move |request| {
+        let if_none_match = request.header("if-none-match").map(ToString::to_string);
+        requests.lock().unwrap().push(if_none_match.clone());
+        async move {
+          match if_none_match.as_deref() {
+            Some(r#""v1""#) => Response {
+              status: 304,
+              headers: Vec::new(),
+              body: Vec::new(),
+            },
+            _ => {
+              let body = r#"[{"id":1,"type":"exchange","market":"equities","name":"NYSE"}]"#;
+              let mut response = Response::json(200, body);
+              response.headers.push(("etag".into(), r#""v1""#.into()));
+              response
+            },
+          }
+        }
+      }
+    })
+    .await;
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .etag_cache(true)
+      .build()
+      .unwrap();
+
+    for _ in 0..2 {
+      let exchgs = client.issue::<exchanges::Get>(()).await.unwrap();
+      assert_eq!(exchgs.len(), 1);
+      assert_eq!(exchgs[0].name, "NYSE");
+    }
+
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests, vec![None, Some(r#""v1""#.to_string())]);
+  }
+
+  /// Check that clones of a `Client` share the rate limit.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test(start_paused = true))]
+  async fn clone_shares_rate_limit() {
+    let addr = mock_server(|_request| async move { Response::json(200, "[]") }).await;
+    let period = Duration::from_secs(60);
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .rate_limit(1, period)
+      .build()
+      .unwrap();
+
+    let start = TokioInstant::now();
+    let handles = (0..2)
+      .map(|_| {
+        let client = client.clone();
+        spawn(async move { client.issue::<exchanges::Get>(()).await.unwrap() })
+      })
+      .collect::<Vec<_>>();
+
+    for handle in handles {
+      assert!(handle.await.unwrap().is_empty());
+    }
+    // Only one request fits into each period, so the second one had
+    // to wait for the first period to end.
+    assert!(start.elapsed() >= period, "{:?}", start.elapsed());
+  }
+
+  /// Check that no more than the configured maximum number of requests
+  /// is in flight at any time.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn limit_concurrency() {
+    let current = Arc::new(AtomicUsize::new(0));
+    let max = Arc::new(AtomicUsize::new(0));
+    let addr = mock_server({
+      let current = current.clone();
+      let max = max.clone();
+      move |_request| {
+        let current = current.clone();
+        let max = max.clone();
+        async move {
+          let count = current.fetch_add(1, Ordering::SeqCst) + 1;
+          let _ = max.fetch_max(count, Ordering::SeqCst);
+          sleep(Duration::from_millis(50)).await;
+          let _ = current.fetch_sub(1, Ordering::SeqCst);
+          Response::json(200, "[]")
+        }
+      }
+    })
+    .await;
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .max_concurrency(2)
+      .build()
+      .unwrap();
+
+    let handles = (0..8)
+      .map(|_| {
+        let client = client.clone();
+        spawn(async move { client.issue::<exchanges::Get>(()).await.unwrap() })
+      })
+      .collect::<Vec<_>>();
+
+    for handle in handles {
+      assert!(handle.await.unwrap().is_empty());
+    }
+    assert_eq!(max.load(Ordering::SeqCst), 2);
+  }
+
+  /// Check that a concurrency limit of zero still lets requests
+  /// through, one at a time.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn zero_concurrency() {
+    let addr = mock_server(|_request| async move { Response::json(200, "[]") }).await;
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .max_concurrency(0)
+      .build()
+      .unwrap();
+
+    let result = timeout(Duration::from_secs(5), client.issue::<exchanges::Get>(())).await;
+    assert!(result.unwrap().unwrap().is_empty());
+  }
+
+  /// Check that requests to idempotent endpoints are retried on
+  /// transient server errors.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn retry_idempotent_request() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let addr = mock_server({
+      let count = count.clone();
+      move |_request| {
+        let attempt = count.fetch_add(1, Ordering::SeqCst);
+        async move {
+          if attempt < 2 {
+            Response::json(503, "{}")
+          } else {
+            Response::json(200, "[]")
+          }
+        }
+      }
+    })
+    .await;
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .retries(3, Duration::from_millis(1))
+      .build()
+      .unwrap();
+
+    let exchgs = client.issue::<exchanges::Get>(()).await.unwrap();
+    assert!(exchgs.is_empty());
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+  }
+
+  /// Check that requests to non-idempotent endpoints are not retried.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn no_retry_non_idempotent_request() {
+    Endpoint! {
+      Post(()),
+      Ok => Vec<String>, [
+        /* 200 */ OK,
+      ],
+      Err => PostError, []
+
+      fn method() -> Method {
+        Method::POST
+      }
+
+      fn path(_input: &Self::Input) -> Str {
+        "/v1/mock".into()
+      }
+    }
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let addr = mock_server({
+      let count = count.clone();
+      move |_request| {
+        let _ = count.fetch_add(1, Ordering::SeqCst);
+        async move { Response::json(503, "{}") }
+      }
+    })
+    .await;
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .retries(3, Duration::from_millis(1))
+      .build()
+      .unwrap();
+
+    let result = client.issue::<Post>(()).await;
+    match result {
+      Err(RequestError::Endpoint(PostError::UnexpectedStatus(status, _))) => {
+        assert_eq!(status, http::StatusCode::SERVICE_UNAVAILABLE)
+      },
+      _ => panic!("unexpected result: {:?}", result),
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+  }
+
+  /// Check that building a `Client` without an API key fails.
+  #[test]
+  fn build_client_without_api_key() {
+    let result = Client::builder().build();
+    match result {
+      Err(Error::Str(err)) if err == "no API key provided" => (),
+      _ => panic!("unexpected result: {:?}", result),
+    }
+  }
+
+  /// Check that we can create a `Client` using an API key stored in a
+  /// file.
+  #[test]
+  fn client_from_key_file() {
+    let path = temp_dir().join(format!("polyio-client-key-{}", id()));
+    let () = write(&path, "XXXXXXXXXXXXXXXXXXXX\n").unwrap();
+    let result = Client::from_key_file(&path);
+    let () = remove_file(&path).unwrap();
+
+    let client = result.unwrap();
+    assert_eq!(client.api_info.api_key, "XXXXXXXXXXXXXXXXXXXX");
+  }
+
+  /// Check that requests exceeding the configured timeout fail.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test(tokio::test)]
+  async fn request_timeout() {
+    let addr = mock_server(|_request| async move {
+      sleep(Duration::from_secs(10)).await;
+      Response::json(200, "[]")
+    })
+    .await;
+
+    let client = Client::builder()
+      .api_url(Url::parse(&format!("http://{}", addr)).unwrap())
+      .api_key("XXXXXXXXXXXXXXXXXXXX")
+      .timeout(Duration::from_millis(50))
+      .build()
+      .unwrap();
+
+    let result = client.issue::<exchanges::Get>(()).await;
+    match result {
+      Err(RequestError::Timeout) => (),
+      _ => panic!("unexpected result: {:?}", result),
+    }
   }
 
   #[cfg(not(target_arch = "wasm32"))]
@@ -399,7 +1953,7 @@ mod tests {
 
     let result = client.subscribe(vec![]).await;
     match result {
-      Err(Error::Str(err)) if err.starts_with("authentication not successful") => (),
+      Err(Error::AuthenticationFailed(..)) => (),
       _ => panic!("unexpected result"),
     }
   }
@@ -0,0 +1,210 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The rules follow the consolidated processing guidelines of the
+//! securities information processors (SIPs). A trade may carry
+//! multiple conditions, in which case it only affects a metric if all
+//! of them permit it. Codes not covered by the lookup table are
+//! assumed to not restrict a trade in any way.
+
+/// A regular trade.
+pub const REGULAR: u64 = 0;
+/// An average price trade.
+pub const AVERAGE_PRICE: u64 = 2;
+/// A cash sale.
+pub const CASH_SALE: u64 = 7;
+/// A trade reported on a derivatively priced basis.
+pub const DERIVATIVELY_PRICED: u64 = 10;
+/// A trade executed during extended hours, i.e., before the market
+/// opens or after it closes ("Form T").
+pub const FORM_T: u64 = 12;
+/// A trade executed during extended hours and reported out of
+/// sequence.
+pub const EXTENDED_HOURS_OUT_OF_SEQUENCE: u64 = 13;
+/// An intermarket sweep order.
+pub const INTERMARKET_SWEEP: u64 = 14;
+/// The official closing price of a market center.
+pub const OFFICIAL_CLOSE: u64 = 15;
+/// The official opening price of a market center.
+pub const OFFICIAL_OPEN: u64 = 16;
+/// A trade settling on the next day.
+pub const NEXT_DAY: u64 = 20;
+/// A trade with a price variation.
+pub const PRICE_VARIATION: u64 = 21;
+/// A trade referencing a prior price.
+pub const PRIOR_REFERENCE_PRICE: u64 = 22;
+/// A trade settling at the seller's option.
+pub const SELLER: u64 = 29;
+/// A trade reported out of sequence.
+pub const SOLD_OUT_OF_SEQUENCE: u64 = 32;
+/// A trade for less than a round lot.
+pub const ODD_LOT: u64 = 37;
+/// A contingent trade.
+pub const CONTINGENT: u64 = 52;
+/// A qualified contingent trade.
+pub const QUALIFIED_CONTINGENT: u64 = 53;
+
+
+/// The metrics a trade condition permits a trade to update.
+#[derive(Clone, Copy, Debug)]
+struct Rules {
+  /// Whether the trade updates the consolidated high and low.
+  high_low: bool,
+  /// Whether the trade is eligible for the consolidated last price.
+  last: bool,
+  /// Whether the trade updates the consolidated volume.
+  volume: bool,
+}
+
+impl Rules {
+  const fn new(high_low: bool, last: bool, volume: bool) -> Self {
+    Self {
+      high_low,
+      last,
+      volume,
+    }
+  }
+}
+
+
+/// Look up the rules for the given condition code.
+fn rules(code: u64) -> Option<Rules> {
+  let rules = match code {
+    // Regular, acquisition, automatic execution, bunched, closing
+    // prints, cross, and distribution trades.
+    0 | 1 | 3 | 4 | 8 | 9 | 11 => Rules::new(true, true, true),
+    AVERAGE_PRICE => Rules::new(false, false, true),
+    // Bunched sold trade.
+    5 => Rules::new(true, false, true),
+    CASH_SALE => Rules::new(false, false, true),
+    DERIVATIVELY_PRICED => Rules::new(true, false, true),
+    FORM_T | EXTENDED_HOURS_OUT_OF_SEQUENCE => Rules::new(false, false, true),
+    INTERMARKET_SWEEP => Rules::new(true, true, true),
+    OFFICIAL_CLOSE | OFFICIAL_OPEN => Rules::new(false, false, false),
+    // Market center opening, reopening, and closing trades.
+    17..=19 => Rules::new(true, true, true),
+    NEXT_DAY | PRICE_VARIATION => Rules::new(false, false, true),
+    PRIOR_REFERENCE_PRICE => Rules::new(true, false, true),
+    // Rule 155, opening prints, stopped stock, and reopening prints.
+    23 | 25 | 27 | 28 => Rules::new(true, true, true),
+    SELLER => Rules::new(false, false, true),
+    // Sold last.
+    30 => Rules::new(true, true, true),
+    SOLD_OUT_OF_SEQUENCE => Rules::new(true, false, true),
+    // Split trade, stock option, and yellow flag regular trade.
+    34..=36 => Rules::new(true, true, true),
+    ODD_LOT => Rules::new(false, false, true),
+    // Trade through exempt.
+    41 => Rules::new(true, true, true),
+    CONTINGENT | QUALIFIED_CONTINGENT => Rules::new(false, false, true),
+    _ => return None,
+  };
+  Some(rules)
+}
+
+
+/// Check whether all of the given conditions permit a trade to update
+/// the metric selected by `f`.
+fn permits<F>(conditions: &[u64], f: F) -> bool
+where
+  F: Fn(&Rules) -> bool,
+{
+  conditions
+    .iter()
+    .filter_map(|code| rules(*code))
+    .all(|rules| f(&rules))
+}
+
+
+/// Check whether a trade with the given conditions is an odd lot
+/// trade, i.e., one for less than a round lot.
+pub fn is_odd_lot(conditions: &[u64]) -> bool {
+  conditions.contains(&ODD_LOT)
+}
+
+/// Check whether a trade with the given conditions was executed during
+/// extended hours.
+pub fn is_extended_hours(conditions: &[u64]) -> bool {
+  conditions
+    .iter()
+    .any(|code| matches!(*code, FORM_T | EXTENDED_HOURS_OUT_OF_SEQUENCE))
+}
+
+/// Check whether a trade with the given conditions updates the
+/// consolidated high and low prices.
+pub fn updates_high_low(conditions: &[u64]) -> bool {
+  permits(conditions, |rules| rules.high_low)
+}
+
+/// Check whether a trade with the given conditions updates the
+/// consolidated volume.
+pub fn updates_volume(conditions: &[u64]) -> bool {
+  permits(conditions, |rules| rules.volume)
+}
+
+/// Check whether a trade with the given conditions is eligible to
+/// update the consolidated last price.
+pub fn eligible_for_consolidated_last(conditions: &[u64]) -> bool {
+  permits(conditions, |rules| rules.last)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that regular trades update all metrics.
+  #[test]
+  fn regular_trade() {
+    for conditions in &[&[][..], &[REGULAR], &[INTERMARKET_SWEEP]] {
+      assert!(!is_odd_lot(conditions));
+      assert!(!is_extended_hours(conditions));
+      assert!(updates_high_low(conditions));
+      assert!(updates_volume(conditions));
+      assert!(eligible_for_consolidated_last(conditions));
+    }
+  }
+
+  /// Check that we correctly classify odd lot trades.
+  #[test]
+  fn odd_lot_trade() {
+    let conditions = [INTERMARKET_SWEEP, ODD_LOT];
+    assert!(is_odd_lot(&conditions));
+    assert!(!is_extended_hours(&conditions));
+    assert!(!updates_high_low(&conditions));
+    assert!(updates_volume(&conditions));
+    assert!(!eligible_for_consolidated_last(&conditions));
+  }
+
+  /// Check that we correctly classify extended hours trades.
+  #[test]
+  fn extended_hours_trade() {
+    for conditions in &[[FORM_T], [EXTENDED_HOURS_OUT_OF_SEQUENCE]] {
+      assert!(is_extended_hours(conditions));
+      assert!(!updates_high_low(conditions));
+      assert!(updates_volume(conditions));
+      assert!(!eligible_for_consolidated_last(conditions));
+    }
+  }
+
+  /// Check that official open and close prices do not count as trades
+  /// for any metric.
+  #[test]
+  fn official_prices() {
+    for conditions in &[[OFFICIAL_OPEN], [OFFICIAL_CLOSE]] {
+      assert!(!updates_high_low(conditions));
+      assert!(!updates_volume(conditions));
+      assert!(!eligible_for_consolidated_last(conditions));
+    }
+  }
+
+  /// Check that unknown codes do not restrict a trade.
+  #[test]
+  fn unknown_condition() {
+    let conditions = [9999];
+    assert!(updates_high_low(&conditions));
+    assert!(updates_volume(&conditions));
+    assert!(eligible_for_consolidated_last(&conditions));
+  }
+}
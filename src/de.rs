@@ -0,0 +1,376 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Deserialization helpers shared by the API and streaming types.
+//!
+//! Polygon keys symbols as `sym` in streamed events but as `T` in REST
+//! results. Types carrying a symbol hence accept either, by means of
+//! `#[serde(rename = "...", alias = "...")]` with the native key as
+//! the rename, such that data from one API can be decoded into types
+//! of the other.
+
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::str::FromStr as _;
+
+use num_decimal::Num;
+
+use serde::de::Deserializer;
+use serde::de::Error;
+use serde::de::Unexpected;
+use serde::de::Visitor;
+
+
+/// The largest (absolute) decimal exponent we accept when parsing
+/// numbers in exponential notation.
+const MAX_EXPONENT: u32 = 38;
+
+
+/// Parse a `Num` from a string in either decimal (`156.9799`) or
+/// exponential (`1.569799e2`) notation.
+fn parse_num(s: &str) -> Option<Num> {
+  let (mantissa, exponent) = match s.find(['e', 'E']) {
+    Some(idx) => (&s[..idx], s[idx + 1..].parse::<i32>().ok()?),
+    None => (s, 0),
+  };
+
+  let mantissa = Num::from_str(mantissa).ok()?;
+  let abs = exponent.unsigned_abs();
+  if abs > MAX_EXPONENT {
+    return None
+  }
+
+  let power = 10u128.pow(abs);
+  if exponent < 0 {
+    Some(mantissa / power)
+  } else {
+    Some(mantissa * power)
+  }
+}
+
+
+/// A visitor for deserializing a `Num` from a JSON number or string.
+struct NumVisitor;
+
+impl<'de> Visitor<'de> for NumVisitor {
+  type Value = Num;
+
+  fn expecting(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    fmt.write_str("a number in decimal or exponential notation")
+  }
+
+  fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    parse_num(s).ok_or_else(|| Error::invalid_value(Unexpected::Str(s), &self))
+  }
+
+  fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(Num::from(v))
+  }
+
+  fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(Num::from(v))
+  }
+
+  fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    // The `Display` representation of a float is the shortest one that
+    // round trips, which is exactly the decimal number Polygon sent.
+    let s = v.to_string();
+    parse_num(&s).ok_or_else(|| Error::invalid_value(Unexpected::Float(v), &self))
+  }
+}
+
+
+/// Deserialize a `Num` from a JSON number or string, accepting both
+/// decimal and exponential notation.
+pub(crate) fn num_from_json<'de, D>(deserializer: D) -> Result<Num, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  deserializer.deserialize_any(NumVisitor)
+}
+
+
+/// Deserialize an optional `Num` from a JSON number or string,
+/// accepting both decimal and exponential notation.
+pub(crate) fn opt_num_from_json<'de, D>(deserializer: D) -> Result<Option<Num>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  use serde::Deserialize;
+
+  #[derive(Deserialize)]
+  struct Wrapper(#[serde(deserialize_with = "num_from_json")] Num);
+
+  let value = Option::<Wrapper>::deserialize(deserializer)?;
+  Ok(value.map(|Wrapper(num)| num))
+}
+
+
+/// A visitor for deserializing a `bool` from a JSON boolean, string,
+/// or integer.
+struct BoolVisitor;
+
+impl<'de> Visitor<'de> for BoolVisitor {
+  type Value = bool;
+
+  fn expecting(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    fmt.write_str("a boolean, \"true\", \"false\", 0, or 1")
+  }
+
+  fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(v)
+  }
+
+  fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    match s {
+      "true" => Ok(true),
+      "false" => Ok(false),
+      _ => Err(Error::invalid_value(Unexpected::Str(s), &self)),
+    }
+  }
+
+  fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    match v {
+      0 => Ok(false),
+      1 => Ok(true),
+      _ => Err(Error::invalid_value(Unexpected::Signed(v), &self)),
+    }
+  }
+
+  fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    match v {
+      0 => Ok(false),
+      1 => Ok(true),
+      _ => Err(Error::invalid_value(Unexpected::Unsigned(v), &self)),
+    }
+  }
+}
+
+
+/// Deserialize a `bool` from a JSON boolean, string, or integer.
+///
+/// Depending on the endpoint, Polygon reports flags as `true`/`false`,
+/// as `"true"`/`"false"`, or as `1`/`0`.
+pub(crate) fn bool_from_json<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  deserializer.deserialize_any(BoolVisitor)
+}
+
+
+/// Deserialize an optional `bool` from a JSON boolean, string, or
+/// integer.
+pub(crate) fn opt_bool_from_json<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  use serde::Deserialize;
+
+  #[derive(Deserialize)]
+  struct Wrapper(#[serde(deserialize_with = "bool_from_json")] bool);
+
+  let value = Option::<Wrapper>::deserialize(deserializer)?;
+  Ok(value.map(|Wrapper(flag)| flag))
+}
+
+
+/// A visitor for deserializing a `String` from a JSON string or
+/// integer.
+#[cfg(not(target_arch = "wasm32"))]
+struct StringVisitor;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de> Visitor<'de> for StringVisitor {
+  type Value = String;
+
+  fn expecting(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    fmt.write_str("a string or an integer")
+  }
+
+  fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(s.to_string())
+  }
+
+  fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(v.to_string())
+  }
+
+  fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(v.to_string())
+  }
+}
+
+
+/// Deserialize an optional `String` from a JSON string or integer.
+///
+/// Polygon is not consistent in the representation of identifiers,
+/// reporting some as strings and others as numbers.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn opt_string_from_json<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  use serde::Deserialize;
+
+  #[derive(Deserialize)]
+  struct Wrapper(#[serde(deserialize_with = "string_from_json")] String);
+
+  fn string_from_json<'de, D>(deserializer: D) -> Result<String, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_any(StringVisitor)
+  }
+
+  let value = Option::<Wrapper>::deserialize(deserializer)?;
+  Ok(value.map(|Wrapper(string)| string))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use serde::Deserialize;
+  use serde_json::from_str as from_json;
+
+
+  #[derive(Debug, Deserialize)]
+  struct Price {
+    #[serde(deserialize_with = "num_from_json")]
+    p: Num,
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  #[derive(Debug, Deserialize)]
+  struct OptPrice {
+    #[serde(default, deserialize_with = "opt_num_from_json")]
+    o: Option<Num>,
+  }
+
+
+  #[derive(Debug, Deserialize)]
+  struct Flag {
+    #[serde(deserialize_with = "bool_from_json")]
+    f: bool,
+  }
+
+  #[derive(Debug, Deserialize)]
+  struct OptFlag {
+    #[serde(default, deserialize_with = "opt_bool_from_json")]
+    f: Option<bool>,
+  }
+
+
+  /// Check that we can parse prices in decimal notation.
+  #[test]
+  fn deserialize_decimal() {
+    let price = from_json::<Price>(r#"{"p":156.9799}"#).unwrap();
+    assert_eq!(price.p, Num::new(1_569_799, 10000));
+
+    let price = from_json::<Price>(r#"{"p":"156.9799"}"#).unwrap();
+    assert_eq!(price.p, Num::new(1_569_799, 10000));
+  }
+
+  /// Check that we can parse prices in exponential notation.
+  #[test]
+  fn deserialize_exponential() {
+    let price = from_json::<Price>(r#"{"p":1.0e2}"#).unwrap();
+    assert_eq!(price.p, Num::from(100));
+
+    let price = from_json::<Price>(r#"{"p":"1.0e2"}"#).unwrap();
+    assert_eq!(price.p, Num::from(100));
+
+    let price = from_json::<Price>(r#"{"p":"1.569799E2"}"#).unwrap();
+    assert_eq!(price.p, Num::new(1_569_799, 10000));
+
+    let price = from_json::<Price>(r#"{"p":"25e-3"}"#).unwrap();
+    assert_eq!(price.p, Num::new(25, 1000));
+  }
+
+  /// Check that we can parse optional prices.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test]
+  fn deserialize_optional() {
+    let price = from_json::<OptPrice>(r#"{}"#).unwrap();
+    assert_eq!(price.o, None);
+
+    let price = from_json::<OptPrice>(r#"{"o":null}"#).unwrap();
+    assert_eq!(price.o, None);
+
+    let price = from_json::<OptPrice>(r#"{"o":1.0e2}"#).unwrap();
+    assert_eq!(price.o, Some(Num::from(100)));
+  }
+
+  /// Check that we reject malformed prices.
+  #[test]
+  fn deserialize_invalid() {
+    assert!(from_json::<Price>(r#"{"p":"1.0e"}"#).is_err());
+    assert!(from_json::<Price>(r#"{"p":"abc"}"#).is_err());
+    assert!(from_json::<Price>(r#"{"p":"1e99999"}"#).is_err());
+    assert!(from_json::<Price>(r#"{"p":true}"#).is_err());
+  }
+
+  /// Check that we can parse flags in all the representations Polygon
+  /// uses.
+  #[test]
+  fn deserialize_bool() {
+    for (json, expected) in [
+      (r#"{"f":true}"#, true),
+      (r#"{"f":false}"#, false),
+      (r#"{"f":"true"}"#, true),
+      (r#"{"f":"false"}"#, false),
+      (r#"{"f":1}"#, true),
+      (r#"{"f":0}"#, false),
+    ] {
+      assert_eq!(from_json::<Flag>(json).unwrap().f, expected, "{}", json);
+    }
+
+    assert_eq!(from_json::<OptFlag>(r#"{}"#).unwrap().f, None);
+    assert_eq!(from_json::<OptFlag>(r#"{"f":null}"#).unwrap().f, None);
+    assert_eq!(from_json::<OptFlag>(r#"{"f":1}"#).unwrap().f, Some(true));
+  }
+
+  /// Check that we reject malformed flags.
+  #[test]
+  fn deserialize_invalid_bool() {
+    assert!(from_json::<Flag>(r#"{"f":2}"#).is_err());
+    assert!(from_json::<Flag>(r#"{"f":-1}"#).is_err());
+    assert!(from_json::<Flag>(r#"{"f":"yes"}"#).is_err());
+    assert!(from_json::<Flag>(r#"{"f":1.0}"#).is_err());
+  }
+}
@@ -8,6 +8,8 @@ use std::fmt::Result as FmtResult;
 
 use serde::Deserialize;
 
+use crate::Str;
+
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct ErrorMessage {
@@ -25,12 +27,45 @@ impl Display for ErrorMessage {
 impl Error for ErrorMessage {}
 
 
+/// A version of the Polygon REST API.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Version {
+  /// Version 1 of the API, rooted at `/v1`.
+  V1,
+  /// Version 2 of the API, rooted at `/v2`.
+  V2,
+  /// Version 3 of the API, rooted at `/v3`.
+  V3,
+}
+
+impl Version {
+  /// Compose the absolute path to the given resource of this API
+  /// version.
+  ///
+  /// The resource is expected to not contain a leading slash.
+  pub(crate) fn path(self, resource: &str) -> Str {
+    let version = match self {
+      Version::V1 => "v1",
+      Version::V2 => "v2",
+      Version::V3 => "v3",
+    };
+    format!("/{}/{}", version, resource).into()
+  }
+}
+
+
 /// A macro used for defining the properties for a request to a
 /// particular HTTP endpoint.
+///
+/// By default, responses are decoded into the endpoint's output type
+/// directly. An endpoint may specify a `Raw` type to decode into
+/// instead, which is then converted into the output type by means of
+/// `From`.
 macro_rules! Endpoint {
   ( $(#[$docs:meta])* $pub:vis $name:ident($in:ty),
     Ok => $out:ty, [$($(#[$ok_docs:meta])* $ok_status:ident,)*],
-    Err => $err:ident, [$($(#[$err_docs:meta])* $err_status:ident => $variant:ident,)*]
+    Err => $err:ident, [$($(#[$err_docs:meta])* $err_status:ident => $variant:ident,)*],
+    Raw => $raw:ty,
     $($defs:tt)* ) => {
 
     EndpointDef! {
@@ -48,7 +83,7 @@ macro_rules! Endpoint {
       ApiErr => crate::endpoint::ErrorMessage,
 
       fn parse(body: &[u8]) -> Result<Self::Output, Self::ConversionError> {
-        ::serde_json::from_slice::<Self::Output>(body)
+        ::serde_json::from_slice::<$raw>(body).map(Self::Output::from)
       }
 
       fn parse_err(body: &[u8]) -> Result<Self::ApiError, Vec<u8>> {
@@ -58,4 +93,17 @@ macro_rules! Endpoint {
       $($defs)*
     }
   };
+  ( $(#[$docs:meta])* $pub:vis $name:ident($in:ty),
+    Ok => $out:ty, [$($(#[$ok_docs:meta])* $ok_status:ident,)*],
+    Err => $err:ident, [$($(#[$err_docs:meta])* $err_status:ident => $variant:ident,)*]
+    $($defs:tt)* ) => {
+
+    Endpoint! {
+      $(#[$docs])* $pub $name($in),
+      Ok => $out, [$($(#[$ok_docs])* $ok_status,)*],
+      Err => $err, [$($(#[$err_docs])* $err_status => $variant,)*],
+      Raw => $out,
+      $($defs)*
+    }
+  };
 }
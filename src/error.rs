@@ -24,6 +24,10 @@ use wasm_bindgen::JsValue;
 #[cfg(not(target_arch = "wasm32"))]
 use websocket_util::tungstenite::Error as WebSocketError;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::events::is_retryable;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::events::DisconnectReason;
 use crate::Str;
 
 
@@ -41,6 +45,10 @@ pub enum RequestError<E> {
     #[source]
     HyperError,
   ),
+  /// The request did not complete within the configured timeout.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[error("the request timed out")]
+  Timeout,
   /// A UTF-8 error that may occur when converting bytes to a string.
   #[cfg(target_arch = "wasm32")]
   #[error("a UTF-8 conversion failed")]
@@ -119,6 +127,18 @@ pub enum Error {
     #[source]
     ParseError,
   ),
+  /// Polygon rejected the API key while setting up a stream.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[error("authentication not successful: {0}")]
+  AuthenticationFailed(String),
+  /// Polygon did not confirm an operation on a stream in time.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[error("{0}")]
+  Timeout(Str),
+  /// Polygon closed the connection while it was being set up.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[error("connection closed by Polygon: {0}")]
+  Disconnected(DisconnectReason),
   /// A websocket error.
   #[cfg(not(target_arch = "wasm32"))]
   #[error("encountered a websocket related error")]
@@ -129,6 +149,35 @@ pub enum Error {
   ),
 }
 
+impl Error {
+  /// Check whether the error is transient, i.e., whether retrying the
+  /// failed operation may succeed.
+  ///
+  /// Timeouts, connection losses and transport level failures, as well
+  /// as server side HTTP failures and rate limiting are considered
+  /// retryable. Authentication failures, Polygon refusing a connection
+  /// because the maximum number of connections was reached, protocol
+  /// violations such as malformed data, and errors originating in this
+  /// crate are considered fatal. Errors reported by a stream of events
+  /// are classified by `events::is_retryable`.
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      Error::Http(..) | Error::Json(..) | Error::Str(..) | Error::Url(..) => false,
+      Error::HttpStatus(status, _) => {
+        status.is_server_error() || *status == HttpStatusCode::TOO_MANY_REQUESTS
+      },
+      #[cfg(not(target_arch = "wasm32"))]
+      Error::AuthenticationFailed(..) => false,
+      #[cfg(not(target_arch = "wasm32"))]
+      Error::Timeout(..) => true,
+      #[cfg(not(target_arch = "wasm32"))]
+      Error::Disconnected(reason) => *reason != DisconnectReason::MaxConnections,
+      #[cfg(not(target_arch = "wasm32"))]
+      Error::WebSocket(err) => is_retryable(err),
+    }
+  }
+}
+
 impl From<EndpointError<JsonError>> for Error {
   fn from(src: EndpointError<JsonError>) -> Self {
     match src {
@@ -171,6 +220,53 @@ mod tests {
     assert_eq!(err.source().unwrap().to_string(), "entity not available");
   }
 
+  /// Check that we classify errors as retryable or fatal as expected.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test]
+  fn classify_errors() {
+    use std::io::Error as IoError;
+    use std::io::ErrorKind;
+
+    let err = Error::AuthenticationFailed("authentication failed".into());
+    assert!(!err.is_retryable());
+
+    let err = Error::Disconnected(DisconnectReason::MaxConnections);
+    assert!(!err.is_retryable());
+
+    let err = Error::Json(serde_json::from_str::<u64>("foo").unwrap_err());
+    assert!(!err.is_retryable());
+
+    let reset = || WebSocketError::Io(IoError::from(ErrorKind::ConnectionReset));
+    assert!(is_retryable(&reset()));
+    assert!(Error::WebSocket(reset()).is_retryable());
+
+    let err = Error::Timeout("subscription not confirmed in time".into());
+    assert!(err.is_retryable());
+
+    let status = HttpStatusCode::from_u16(503).unwrap();
+    let err = Error::HttpStatus(status, HttpBody(Vec::new()));
+    assert!(err.is_retryable());
+
+    let status = HttpStatusCode::from_u16(401).unwrap();
+    let err = Error::HttpStatus(status, HttpBody(Vec::new()));
+    assert!(!err.is_retryable());
+  }
+
+  /// Check that a Polygon initiated disconnect is classified based on
+  /// the reason reported.
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test]
+  fn classify_disconnects() {
+    use std::io::Error as IoError;
+    use std::io::ErrorKind;
+
+    let disconnect =
+      |reason| WebSocketError::Io(IoError::new(ErrorKind::ConnectionAborted, reason));
+    assert!(!is_retryable(&disconnect(DisconnectReason::MaxConnections)));
+    assert!(is_retryable(&disconnect(DisconnectReason::Other("idle".into()))));
+    assert!(!is_retryable(&WebSocketError::Utf8));
+  }
+
   /// Ensure that our `RequestError` type fulfills all the requirements
   /// we deem necessary.
   #[test]
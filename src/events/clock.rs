@@ -0,0 +1,107 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+
+/// A source of the current wall-clock time.
+///
+/// Combinators depending on the current time retrieve it from a
+/// `Clock`, so that tests can control it. Timers are only used for
+/// waking up once a deadline may have passed; whether it actually did
+/// is decided based on the clock.
+pub trait Clock: Debug {
+  /// Retrieve the current time.
+  fn now(&self) -> DateTime<Utc>;
+}
+
+
+/// A `Clock` reporting the system's time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  #[inline]
+  fn now(&self) -> DateTime<Utc> {
+    DateTime::from(SystemTime::now())
+  }
+}
+
+
+/// A `Clock` reporting a time that is advanced explicitly.
+///
+/// Clones share the time, meaning that a clone can be used to advance
+/// the time seen by a combinator the original was passed to.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+  now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+  /// Create a `MockClock` reporting the given time.
+  pub fn new(now: DateTime<Utc>) -> Self {
+    Self {
+      now: Arc::new(Mutex::new(now)),
+    }
+  }
+
+  /// Set the reported time.
+  pub fn set(&self, now: DateTime<Utc>) {
+    *self.now.lock().unwrap() = now;
+  }
+
+  /// Advance the reported time by the given duration.
+  pub fn advance(&self, duration: Duration) {
+    let mut now = self.now.lock().unwrap();
+    *now += duration;
+  }
+}
+
+impl Clock for MockClock {
+  /// Retrieve the time last set or advanced to.
+  fn now(&self) -> DateTime<Utc> {
+    *self.now.lock().unwrap()
+  }
+}
+
+
+/// Reset `sleep` to fire once a clock currently reporting `now` reaches
+/// `deadline`.
+pub(crate) fn reset_sleep(sleep: Pin<&mut Sleep>, now: DateTime<Utc>, deadline: DateTime<Utc>) {
+  let remaining = (deadline - now).to_std().unwrap_or_default();
+  sleep.reset(Instant::now() + remaining)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone as _;
+
+
+  /// Check that clones of a `MockClock` share the time.
+  #[test]
+  fn advance_mock_clock() {
+    let start = Utc.timestamp_millis_opt(1577818283019).unwrap();
+    let clock = MockClock::new(start);
+    let clone = clock.clone();
+
+    clone.advance(Duration::seconds(5));
+    assert_eq!(clock.now(), start + Duration::seconds(5));
+
+    clock.set(start);
+    assert_eq!(clone.now(), start);
+  }
+}
@@ -0,0 +1,86 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::VecDeque;
+
+use futures::stream::unfold;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use crate::events::Event;
+use crate::events::StreamItem;
+
+
+/// A type accumulating events into outputs that each span multiple of
+/// them.
+pub(crate) trait Accumulator {
+  /// The type of output produced.
+  type Output;
+
+  /// Account for the given event, pushing any outputs it finished to
+  /// `pending`.
+  fn track(&mut self, event: Event, pending: &mut VecDeque<Self::Output>);
+
+  /// Finish all partial outputs, pushing them to `pending`.
+  fn flush(&mut self, pending: &mut VecDeque<Self::Output>);
+}
+
+
+/// The state of an accumulating stream.
+struct State<S, A>
+where
+  A: Accumulator,
+{
+  /// The underlying stream.
+  stream: S,
+  /// The accumulator in use.
+  accumulator: A,
+  /// Outputs finished but not yet emitted.
+  pending: VecDeque<A::Output>,
+  /// Whether the underlying stream is exhausted.
+  done: bool,
+}
+
+
+/// Accumulate the events of a stream as created by `stream_items`
+/// using the provided `Accumulator`.
+///
+/// Partial outputs are flushed once the connection got lost or was
+/// re-established, or the underlying stream ends, such that an output
+/// never spans a gap. Other items are discarded.
+pub(crate) fn accumulate<S, A>(stream: S, accumulator: A) -> impl Stream<Item = A::Output>
+where
+  S: Stream<Item = StreamItem>,
+  A: Accumulator,
+{
+  let state = State {
+    stream: Box::pin(stream),
+    accumulator,
+    pending: VecDeque::new(),
+    done: false,
+  };
+
+  unfold(state, |mut state| async move {
+    loop {
+      if let Some(output) = state.pending.pop_front() {
+        break Some((output, state))
+      }
+
+      if state.done {
+        break None
+      }
+
+      match state.stream.next().await {
+        Some(StreamItem::Event(event)) => state.accumulator.track(event, &mut state.pending),
+        Some(StreamItem::Disconnected(..))
+        | Some(StreamItem::Reconnected)
+        | Some(StreamItem::Gap { .. }) => state.accumulator.flush(&mut state.pending),
+        Some(_) => (),
+        None => {
+          state.accumulator.flush(&mut state.pending);
+          state.done = true;
+        },
+      }
+    }
+  })
+}
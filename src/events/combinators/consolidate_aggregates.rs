@@ -0,0 +1,360 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use futures::Stream;
+
+use num_decimal::Num;
+
+use serde_json::Error as JsonError;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::Aggregate;
+use crate::events::Event;
+
+
+/// The length of a minute, in milliseconds.
+const MINUTE_MS: i64 = 60_000;
+
+
+/// Determine the start of the minute the given time falls into.
+fn minute_start(time: &DateTime<Utc>) -> DateTime<Utc> {
+  let millis = time.timestamp_millis().rem_euclid(MINUTE_MS);
+  *time - Duration::milliseconds(millis)
+}
+
+
+/// A minute aggregate in the making.
+#[derive(Debug)]
+struct Bar {
+  /// The aggregate, with prices and volume accounting for all second
+  /// aggregates seen so far.
+  aggregate: Aggregate,
+  /// The sum of the products of volume weighted average price and
+  /// volume of all second aggregates reporting a price.
+  weighted_price: Num,
+  /// The volume of all second aggregates reporting a volume weighted
+  /// average price.
+  weighted_volume: u64,
+}
+
+impl Bar {
+  /// Start a new bar for the minute starting at `start`.
+  fn new(start: DateTime<Utc>, second: &Aggregate) -> Self {
+    let mut bar = Self {
+      aggregate: Aggregate {
+        volume: 0,
+        start_timestamp: start,
+        end_timestamp: start + Duration::milliseconds(MINUTE_MS),
+        ..second.clone()
+      },
+      weighted_price: Num::from(0),
+      weighted_volume: 0,
+    };
+    bar.account(second, true);
+    bar
+  }
+
+  /// Account for the given second aggregate.
+  fn account(&mut self, second: &Aggregate, first: bool) {
+    let aggregate = &mut self.aggregate;
+    if !first {
+      if second.high_price > aggregate.high_price {
+        aggregate.high_price = second.high_price.clone();
+      }
+      if second.low_price < aggregate.low_price {
+        aggregate.low_price = second.low_price.clone();
+      }
+      aggregate.close_price = second.close_price.clone();
+      aggregate.accumulated_volume = second.accumulated_volume.or(aggregate.accumulated_volume);
+      aggregate.official_open_price = second
+        .official_open_price
+        .clone()
+        .or_else(|| aggregate.official_open_price.take());
+      aggregate.day_vwap = second
+        .day_vwap
+        .clone()
+        .or_else(|| aggregate.day_vwap.take());
+    }
+    aggregate.volume = aggregate.volume.saturating_add(second.volume);
+
+    if let Some(price) = &second.tick_vwap {
+      self.weighted_price += price * second.volume_num();
+      self.weighted_volume = self.weighted_volume.saturating_add(second.volume);
+    }
+  }
+
+  /// Finish the bar, creating the minute aggregate.
+  fn finish(self) -> Event {
+    let mut aggregate = self.aggregate;
+    aggregate.tick_vwap = if self.weighted_volume > 0 {
+      Some(self.weighted_price / Num::from(self.weighted_volume))
+    } else {
+      None
+    };
+    Event::MinuteAggregate(aggregate)
+  }
+}
+
+
+/// A stream consolidating second aggregates into minute ones.
+#[derive(Debug)]
+struct ConsolidateAggregates<S> {
+  /// The underlying stream.
+  stream: Pin<Box<S>>,
+  /// The bars in the making, per symbol.
+  bars: HashMap<String, Bar>,
+  /// The start of the most recent minute we have seen a second
+  /// aggregate for.
+  current: Option<DateTime<Utc>>,
+  /// Items ready to be emitted.
+  pending: VecDeque<Result<Result<Event, JsonError>, WebSocketError>>,
+  /// Whether the underlying stream is exhausted.
+  done: bool,
+}
+
+impl<S> ConsolidateAggregates<S> {
+  /// Finish all bars for minutes before `minute`, or all of them if
+  /// `minute` is `None`.
+  fn finish(&mut self, minute: Option<DateTime<Utc>>) {
+    let mut finished = self
+      .bars
+      .iter()
+      .filter(|(_, bar)| match minute {
+        Some(minute) => bar.aggregate.start_timestamp < minute,
+        None => true,
+      })
+      .map(|(symbol, _)| symbol.clone())
+      .collect::<Vec<_>>();
+    // Report bars in a deterministic order.
+    finished.sort();
+
+    for symbol in finished {
+      let bar = self.bars.remove(&symbol).unwrap();
+      self.pending.push_back(Ok(Ok(bar.finish())));
+    }
+  }
+
+  /// Account for a second aggregate we received from the underlying
+  /// stream.
+  fn track(&mut self, second: &Aggregate) {
+    let minute = minute_start(&second.start_timestamp);
+    match self.current {
+      // Aggregates for minutes already reported are late and can no
+      // longer be accounted for.
+      Some(current) if minute < current => return,
+      Some(current) if minute == current => (),
+      _ => {
+        self.finish(Some(minute));
+        self.current = Some(minute);
+      },
+    }
+
+    match self.bars.get_mut(&second.symbol) {
+      Some(bar) => bar.account(second, false),
+      None => {
+        let bar = Bar::new(minute, second);
+        let _ = self.bars.insert(second.symbol.clone(), bar);
+      },
+    }
+  }
+}
+
+impl<S> Stream for ConsolidateAggregates<S>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  type Item = S::Item;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+
+    loop {
+      if let Some(item) = this.pending.pop_front() {
+        break Poll::Ready(Some(item))
+      }
+
+      if this.done {
+        break Poll::Ready(None)
+      }
+
+      match this.stream.as_mut().poll_next(cx) {
+        Poll::Ready(Some(item)) => {
+          if let Ok(Ok(Event::SecondAggregate(aggregate))) = &item {
+            this.track(aggregate);
+          }
+          this.pending.push_back(item);
+        },
+        Poll::Ready(None) => {
+          this.finish(None);
+          this.done = true;
+        },
+        Poll::Pending => break Poll::Pending,
+      }
+    }
+  }
+}
+
+
+/// Consolidate second aggregates into minute aggregates.
+///
+/// This function wraps a stream of events and folds the second
+/// aggregates for each symbol into minute aggregates, which are
+/// emitted in addition to all events of the underlying stream. A
+/// minute aggregate uses the open price of the first and the close
+/// price of the last second aggregate, the highest high and lowest low
+/// price, as well as the total volume. The volume weighted average
+/// price is recomputed from those of the individual second aggregates.
+///
+/// The minute a second aggregate belongs to is determined based on its
+/// start timestamp. Minute aggregates are emitted once the first
+/// second aggregate for a later minute is received, for any symbol.
+/// Hence, symbols with sparse seconds (or none at all for a minute)
+/// are handled, as long as the overall stream is active. Aggregates
+/// for a minute that was already reported are not accounted for. Any
+/// bars in the making are emitted once the underlying stream ends.
+pub fn consolidate_aggregates<S>(
+  stream: S,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  ConsolidateAggregates {
+    stream: Box::pin(stream),
+    bars: HashMap::new(),
+    current: None,
+    pending: VecDeque::new(),
+    done: false,
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+  use futures::StreamExt as _;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+
+
+  /// The start of the minute used throughout the tests.
+  const START: i64 = 1536036780000;
+
+
+  /// Create a second aggregate event.
+  fn second(symbol: &str, offset: i64, volume: u64, prices: [u32; 5]) -> Event {
+    let [open, close, high, low, vwap] = prices;
+    let start = START + offset * 1000;
+    let prices = [open, close, high, low];
+    let aggregate = test_util::aggregate(symbol, start, start + 1000, volume, prices);
+    Event::SecondAggregate(Aggregate {
+      accumulated_volume: Some(1000 + offset as u64),
+      tick_vwap: Some(Num::from(vwap)),
+      ..aggregate
+    })
+  }
+
+  /// Consolidate the given events, reporting only minute aggregates.
+  async fn consolidate(events: Vec<Event>) -> Vec<Aggregate> {
+    let events = events.into_iter().map(|event| Ok(Ok(event)));
+    consolidate_aggregates(iter(events))
+      .filter_map(|event| async move {
+        match event.unwrap().unwrap() {
+          Event::MinuteAggregate(aggregate) => Some(aggregate),
+          _ => None,
+        }
+      })
+      .collect()
+      .await
+  }
+
+
+  /// Check that we consolidate the second aggregates of a minute.
+  #[test(tokio::test)]
+  async fn consolidate_minute() {
+    let events = vec![
+      second("MSFT", 0, 100, [10, 11, 12, 9, 10]),
+      second("MSFT", 3, 300, [11, 14, 15, 11, 14]),
+      second("MSFT", 59, 100, [14, 13, 14, 8, 10]),
+      // The first second of the next minute finishes the previous one.
+      second("MSFT", 60, 1, [13, 13, 13, 13, 13]),
+    ];
+    let events = events.into_iter().map(|event| Ok(Ok(event)));
+    let events = consolidate_aggregates(iter(events))
+      .map(|event| event.unwrap().unwrap())
+      .collect::<Vec<_>>()
+      .await;
+    // All original events are passed through, with the consolidated
+    // one emitted before the event finishing it.
+    assert_eq!(events.len(), 6);
+
+    let aggregate = match &events[3] {
+      Event::MinuteAggregate(aggregate) => aggregate,
+      event => panic!("unexpected event: {:?}", event),
+    };
+    assert_eq!(aggregate.symbol, "MSFT");
+    assert_eq!(aggregate.volume, 500);
+    assert_eq!(aggregate.accumulated_volume, Some(1059));
+    assert_eq!(aggregate.open_price, Num::from(10));
+    assert_eq!(aggregate.close_price, Num::from(13));
+    assert_eq!(aggregate.high_price, Num::from(15));
+    assert_eq!(aggregate.low_price, Num::from(8));
+    // (10 * 100 + 14 * 300 + 10 * 100) / 500
+    assert_eq!(aggregate.tick_vwap, Some(Num::new(62, 5)));
+    assert_eq!(aggregate.start_timestamp_millis(), START);
+    assert_eq!(aggregate.end_timestamp_millis(), START + 60000);
+
+    // The bar in the making gets flushed at the end.
+    match &events[5] {
+      Event::MinuteAggregate(aggregate) => {
+        assert_eq!(aggregate.volume, 1);
+        assert_eq!(aggregate.start_timestamp_millis(), START + 60000);
+      },
+      event => panic!("unexpected event: {:?}", event),
+    }
+  }
+
+  /// Check that bars of symbols with sparse seconds are finished by
+  /// second aggregates of other symbols.
+  #[test(tokio::test)]
+  async fn consolidate_sparse_symbols() {
+    let events = vec![
+      second("SPY", 0, 10, [1, 1, 1, 1, 1]),
+      second("UFO", 30, 5, [2, 3, 3, 2, 2]),
+      second("SPY", 61, 10, [1, 1, 1, 1, 1]),
+      // A late aggregate for an already reported minute.
+      second("UFO", 59, 5, [9, 9, 9, 9, 9]),
+      second("SPY", 125, 10, [1, 1, 1, 1, 1]),
+    ];
+    let aggregates = consolidate(events).await;
+    let bars = aggregates
+      .iter()
+      .map(|aggregate| {
+        (
+          aggregate.symbol.as_str(),
+          (aggregate.start_timestamp_millis() - START) / 60000,
+          aggregate.volume,
+        )
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      bars,
+      vec![("SPY", 0, 10), ("UFO", 0, 5), ("SPY", 1, 10), ("SPY", 2, 10)]
+    );
+    assert_eq!(aggregates[1].close_price, Num::from(3));
+  }
+}
@@ -0,0 +1,248 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use futures::Stream;
+
+use num_decimal::Num;
+
+use crate::events::combinators::accumulate::accumulate;
+use crate::events::combinators::accumulate::Accumulator;
+use crate::events::Aggregate;
+use crate::events::Event;
+use crate::events::StreamItem;
+use crate::events::Trade;
+
+
+/// A count based bar in the making.
+#[derive(Debug)]
+struct Bar {
+  /// The aggregate, with prices and volume accounting for all trades
+  /// seen so far.
+  aggregate: Aggregate,
+  /// The sum of the products of price and quantity of all trades.
+  weighted_price: Num,
+  /// The number of trades accounted for.
+  trades: usize,
+}
+
+impl Bar {
+  /// Start a new bar with the given trade.
+  fn new(trade: &Trade) -> Self {
+    Self {
+      aggregate: Aggregate {
+        symbol: trade.symbol.clone(),
+        volume: trade.quantity,
+        accumulated_volume: None,
+        official_open_price: None,
+        tick_vwap: None,
+        day_vwap: None,
+        open_price: trade.price.clone(),
+        close_price: trade.price.clone(),
+        high_price: trade.price.clone(),
+        low_price: trade.price.clone(),
+        start_timestamp: trade.timestamp,
+        end_timestamp: trade.timestamp,
+      },
+      weighted_price: &trade.price * Num::from(trade.quantity),
+      trades: 1,
+    }
+  }
+
+  /// Account for the given trade.
+  fn account(&mut self, trade: &Trade) {
+    let aggregate = &mut self.aggregate;
+    if trade.price > aggregate.high_price {
+      aggregate.high_price = trade.price.clone();
+    }
+    if trade.price < aggregate.low_price {
+      aggregate.low_price = trade.price.clone();
+    }
+    aggregate.close_price = trade.price.clone();
+    aggregate.volume = aggregate.volume.saturating_add(trade.quantity);
+    aggregate.end_timestamp = trade.timestamp;
+
+    self.weighted_price += &trade.price * Num::from(trade.quantity);
+    self.trades += 1;
+  }
+
+  /// Finish the bar, creating the aggregate.
+  fn finish(self) -> Aggregate {
+    let mut aggregate = self.aggregate;
+    aggregate.tick_vwap = if aggregate.volume > 0 {
+      Some(self.weighted_price / aggregate.volume_num())
+    } else {
+      None
+    };
+    aggregate
+  }
+}
+
+
+/// The count based bars in the making.
+struct Bars {
+  /// The number of trades after which a bar is finished.
+  count: usize,
+  /// The bars in the making, per symbol.
+  bars: HashMap<String, Bar>,
+}
+
+impl Accumulator for Bars {
+  type Output = Aggregate;
+
+  /// Account for the given trade, finishing the symbol's bar once it
+  /// covers `count` trades.
+  fn track(&mut self, event: Event, pending: &mut VecDeque<Aggregate>) {
+    let trade = match event {
+      Event::Trade(trade) => trade,
+      _ => return,
+    };
+
+    match self.bars.get_mut(&trade.symbol) {
+      Some(bar) => bar.account(&trade),
+      None => {
+        let _ = self.bars.insert(trade.symbol.clone(), Bar::new(&trade));
+      },
+    }
+
+    if self.bars[&trade.symbol].trades >= self.count {
+      let bar = self.bars.remove(&trade.symbol).unwrap();
+      pending.push_back(bar.finish());
+    }
+  }
+
+  /// Finish all partial bars.
+  fn flush(&mut self, pending: &mut VecDeque<Aggregate>) {
+    let mut bars = self.bars.drain().map(|(_, bar)| bar).collect::<Vec<_>>();
+    // Report bars in a deterministic order.
+    bars.sort_by(|first, second| first.aggregate.symbol.cmp(&second.aggregate.symbol));
+    pending.extend(bars.into_iter().map(Bar::finish));
+  }
+}
+
+
+/// Form bars from every `count` trades of a symbol.
+///
+/// This function wraps a stream as created by `stream_items` and
+/// reports a synthetic aggregate for each symbol once `count` (but at
+/// least one) of its trades were seen. An aggregate uses the price of
+/// the first trade as open and that of the last one as close price,
+/// and the highest and lowest price, as well as the total quantity
+/// traded. Its volume weighted average price is computed over all of
+/// the batch's trades, and its start and end timestamps are those of
+/// the first and last trade, respectively. Fields not derivable from
+/// individual trades are unset.
+///
+/// Partial bars are emitted once the connection got lost or was
+/// re-established, or the underlying stream ends, such that a bar
+/// never spans a gap. All items other than trades are discarded.
+pub fn count_bars<S>(stream: S, count: usize) -> impl Stream<Item = Aggregate>
+where
+  S: Stream<Item = StreamItem>,
+{
+  let bars = Bars {
+    count: count.max(1),
+    bars: HashMap::new(),
+  };
+  accumulate(stream, bars)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::time::SystemTime;
+
+  use futures::stream::iter;
+  use futures::StreamExt as _;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+  use crate::events::DisconnectReason;
+
+
+  /// Create `count` trades for the given symbol, with prices cycling
+  /// through 100 to 109 and a quantity of ten each.
+  fn trades(symbol: &str, count: usize) -> Vec<StreamItem> {
+    (0..count)
+      .map(|i| {
+        let trade = test_util::trade(symbol, 100 + i % 10, 10, TIMESTAMP + i as i64);
+        StreamItem::Event(Event::Trade(trade))
+      })
+      .collect()
+  }
+
+
+  /// Check that we form bars per `count` trades and flush the partial
+  /// one once the stream ends.
+  #[test(tokio::test)]
+  async fn bars_per_count() {
+    let bars = count_bars(iter(trades("SPY", 250)), 100)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(bars.len(), 3);
+
+    let volumes = bars.iter().map(|bar| bar.volume).collect::<Vec<_>>();
+    assert_eq!(volumes, vec![1000, 1000, 500]);
+
+    for bar in &bars {
+      assert_eq!(bar.symbol, "SPY");
+      assert_eq!(bar.open_price, Num::from(100));
+      assert_eq!(bar.close_price, Num::from(109));
+      assert_eq!(bar.high_price, Num::from(109));
+      assert_eq!(bar.low_price, Num::from(100));
+      assert_eq!(bar.tick_vwap, Some(Num::new(1045, 10)));
+    }
+
+    assert_eq!(bars[0].start_timestamp_millis(), 1577818283019);
+    assert_eq!(bars[0].end_timestamp_millis(), 1577818283118);
+    assert_eq!(bars[2].start_timestamp_millis(), 1577818283219);
+    assert_eq!(bars[2].end_timestamp_millis(), 1577818283268);
+  }
+
+  /// Check that partial bars are flushed once the connection got lost.
+  #[test(tokio::test)]
+  async fn flush_on_disconnect() {
+    let mut items = trades("SPY", 30);
+    items.extend(trades("UFO", 5));
+    items.push(StreamItem::Disconnected(DisconnectReason::Other("reset".into())));
+    items.push(StreamItem::Reconnected);
+    items.extend(trades("SPY", 10));
+
+    let bars = count_bars(iter(items), 20)
+      .map(|bar| (bar.symbol, bar.volume))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(
+      bars,
+      vec![
+        ("SPY".to_string(), 200),
+        ("SPY".to_string(), 100),
+        ("UFO".to_string(), 50),
+        ("SPY".to_string(), 100),
+      ]
+    );
+  }
+
+  /// Check that partial bars are flushed on reconnect, even if no
+  /// disconnect was reported before.
+  #[test(tokio::test)]
+  async fn flush_on_reconnect() {
+    let now = SystemTime::now();
+    let mut items = trades("SPY", 30);
+    items.push(StreamItem::Reconnected);
+    items.push(StreamItem::Gap { from: now, to: now });
+    items.extend(trades("SPY", 10));
+
+    let volumes = count_bars(iter(items), 20)
+      .map(|bar| bar.volume)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(volumes, vec![200, 100, 100]);
+  }
+}
@@ -0,0 +1,162 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::Utc;
+
+use futures::future::ready;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use crate::events::Event;
+use crate::events::StreamItem;
+use crate::tz::new_york_time;
+
+
+/// The events to derive cumulative volume from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VolumeSource {
+  /// Accumulate the quantities of trades.
+  Trades,
+  /// Accumulate the volumes of second aggregates.
+  SecondAggregates,
+  /// Accumulate the volumes of minute aggregates.
+  MinuteAggregates,
+}
+
+
+/// The volume a symbol traded so far on a given day, as reported by
+/// `cumulative_volume`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CumulativeVolume {
+  /// The symbol the volume is for.
+  pub symbol: String,
+  /// The trading day, in New York time, the volume was accumulated on.
+  pub date: NaiveDate,
+  /// The timestamp of the event that was last accounted for.
+  pub timestamp: DateTime<Utc>,
+  /// The volume accumulated over the day so far.
+  pub volume: u64,
+}
+
+
+/// Accumulate the volume traded per symbol and day.
+///
+/// This function wraps a stream as created by `stream_items` and
+/// reports the running volume of the event's symbol for each event of
+/// the kind selected by `source`. Accumulation starts over for each
+/// trading day, as determined by the events' timestamps in New York
+/// time. Late events for a day already concluded are accounted for on
+/// the current one. All other items are discarded.
+pub fn cumulative_volume<S>(stream: S, source: VolumeSource) -> impl Stream<Item = CumulativeVolume>
+where
+  S: Stream<Item = StreamItem>,
+{
+  let mut volumes = HashMap::<String, (NaiveDate, u64)>::new();
+
+  stream.filter_map(move |item| {
+    let entry = match (item, source) {
+      (StreamItem::Event(Event::Trade(trade)), VolumeSource::Trades) => {
+        Some((trade.symbol, trade.timestamp, trade.quantity))
+      },
+      (StreamItem::Event(Event::SecondAggregate(aggregate)), VolumeSource::SecondAggregates)
+      | (StreamItem::Event(Event::MinuteAggregate(aggregate)), VolumeSource::MinuteAggregates) => {
+        Some((aggregate.symbol, aggregate.start_timestamp, aggregate.volume))
+      },
+      _ => None,
+    };
+
+    let result = entry.map(|(symbol, timestamp, volume)| {
+      let date = new_york_time(&timestamp).date();
+      let (date, volume) = match volumes.get_mut(&symbol) {
+        // Events for an earlier day than the current one are late and
+        // get accounted for on the current day.
+        Some((day, total)) if *day >= date => {
+          *total = total.saturating_add(volume);
+          (*day, *total)
+        },
+        _ => {
+          let _ = volumes.insert(symbol.clone(), (date, volume));
+          (date, volume)
+        },
+      };
+
+      CumulativeVolume {
+        symbol,
+        date,
+        timestamp,
+        volume,
+      }
+    });
+    ready(result)
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+
+
+  /// Create a trade item for the given symbol with the provided
+  /// quantity.
+  fn trade(symbol: &str, quantity: u64, timestamp: i64) -> StreamItem {
+    let trade = test_util::trade(symbol, "156.98", quantity, timestamp);
+    StreamItem::Event(Event::Trade(trade))
+  }
+
+
+  /// Check that volume accumulates per symbol and starts over on a new
+  /// day in New York.
+  #[test(tokio::test)]
+  async fn reset_on_day_boundary() {
+    let items = vec![
+      // 2022-01-04T15:00:00-05:00
+      trade("SPY", 100, 1641326400000),
+      trade("UFO", 5, 1641326400000),
+      // 2022-01-04T23:30:00-05:00, already the next day in UTC.
+      trade("SPY", 50, 1641357000000),
+      // 2022-01-05T04:00:00-05:00
+      trade("SPY", 20, 1641373200000),
+      trade("UFO", 7, 1641373200000),
+      trade("SPY", 30, 1641373201000),
+    ];
+
+    let volumes = cumulative_volume(iter(items), VolumeSource::Trades)
+      .map(|volume| (volume.symbol, volume.date.to_string(), volume.volume))
+      .collect::<Vec<_>>()
+      .await;
+
+    let expected = vec![
+      ("SPY", "2022-01-04", 100),
+      ("UFO", "2022-01-04", 5),
+      ("SPY", "2022-01-04", 150),
+      ("SPY", "2022-01-05", 20),
+      ("UFO", "2022-01-05", 7),
+      ("SPY", "2022-01-05", 50),
+    ]
+    .into_iter()
+    .map(|(symbol, date, volume)| (symbol.to_string(), date.to_string(), volume))
+    .collect::<Vec<_>>();
+    assert_eq!(volumes, expected);
+  }
+
+  /// Check that only events of the selected kind are accounted for.
+  #[test(tokio::test)]
+  async fn ignore_other_sources() {
+    let items = vec![trade("SPY", 100, 1641326400000)];
+    let volumes = cumulative_volume(iter(items), VolumeSource::MinuteAggregates)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(volumes, Vec::new());
+  }
+}
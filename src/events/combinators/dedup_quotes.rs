@@ -0,0 +1,119 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+
+use futures::future::ready;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::Event;
+use crate::events::Quote;
+
+
+/// Check whether two quotes are identical, ignoring their timestamps.
+fn same_quote(quote: &Quote, other: &Quote) -> bool {
+  quote.bid_exchange == other.bid_exchange
+    && quote.bid_price == other.bid_price
+    && quote.bid_quantity == other.bid_quantity
+    && quote.ask_exchange == other.ask_exchange
+    && quote.ask_price == other.ask_price
+    && quote.ask_quantity == other.ask_quantity
+}
+
+
+/// Drop quotes that are identical to the immediately preceding quote
+/// for the same symbol.
+///
+/// Quotes are considered identical if their bid and ask prices,
+/// quantities, and exchanges match; timestamps are ignored. All other
+/// events, as well as errors, are passed through unchanged.
+pub fn dedup_quotes<S>(
+  stream: S,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  let mut last = HashMap::<String, Quote>::new();
+
+  stream.filter(move |item| {
+    let include = match item {
+      Ok(Ok(Event::Quote(quote))) => match last.get_mut(&quote.symbol) {
+        Some(previous) if same_quote(previous, quote) => false,
+        Some(previous) => {
+          *previous = quote.clone();
+          true
+        },
+        None => {
+          let _ = last.insert(quote.symbol.clone(), quote.clone());
+          true
+        },
+      },
+      _ => true,
+    };
+    ready(include)
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::Quote;
+
+
+  /// Create a quote event for the given symbol, ask quantity, and
+  /// timestamp.
+  fn quote(symbol: &str, ask: u64, timestamp: i64) -> Event {
+    Event::Quote(Quote {
+      ask_quantity: ask,
+      ..test_util::quote(symbol, "26.4", "26.47", timestamp)
+    })
+  }
+
+
+  /// Check that we drop quotes identical to the previous one for the
+  /// same symbol.
+  #[test(tokio::test)]
+  async fn drop_identical_quotes() {
+    let events = vec![
+      Ok(Ok(quote("UFO", 3, 1577818659363))),
+      Ok(Ok(quote("UFO", 3, 1577818659365))),
+      Ok(Ok(quote("SPY", 3, 1577818659366))),
+      Ok(Ok(quote("UFO", 11, 1577818659367))),
+      Ok(Ok(quote("UFO", 3, 1577818659368))),
+    ];
+    let events = dedup_quotes(iter(events))
+      .map(|event| event.unwrap().unwrap())
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(
+      events,
+      vec![
+        quote("UFO", 3, 1577818659363),
+        quote("SPY", 3, 1577818659366),
+        quote("UFO", 11, 1577818659367),
+        quote("UFO", 3, 1577818659368),
+      ]
+    );
+  }
+
+  /// Check that errors are passed through.
+  #[test(tokio::test)]
+  async fn pass_through_errors() {
+    let events = vec![Ok(Ok(quote("UFO", 3, 1))), Err(WebSocketError::AlreadyClosed)];
+    let events = dedup_quotes(iter(events)).collect::<Vec<_>>().await;
+    assert_eq!(events.len(), 2);
+    assert!(events[1].is_err());
+  }
+}
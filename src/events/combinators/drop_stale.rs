@@ -0,0 +1,96 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use chrono::Duration;
+
+use futures::future::ready;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use tracing::trace;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::Clock;
+use crate::events::Event;
+
+
+/// Drop events that are older than `max_age`, as determined by
+/// comparing their timestamp against the time reported by `clock`.
+///
+/// Polygon may deliver events with a delay, e.g., when the connection
+/// is congested. Errors are passed through unchanged.
+pub fn drop_stale<S, C>(
+  stream: S,
+  max_age: Duration,
+  clock: C,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  C: Clock,
+{
+  stream.filter(move |item| {
+    let include = match item {
+      Ok(Ok(event)) => {
+        let fresh = clock.now() - *event.timestamp() <= max_age;
+        if !fresh {
+          trace!(symbol = display(event.symbol()), "dropping stale event");
+        }
+        fresh
+      },
+      _ => true,
+    };
+    ready(include)
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone as _;
+  use chrono::Utc;
+
+  use futures::channel::mpsc::unbounded;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+  use crate::events::MockClock;
+
+
+  /// Create a trade event for the given quantity.
+  fn trade(quantity: u64) -> Event {
+    Event::Trade(test_util::trade("MSFT", "156.9799", quantity, TIMESTAMP))
+  }
+
+
+  /// Check that we drop events once they are stale, as per the mock
+  /// clock.
+  #[test(tokio::test)]
+  async fn drop_stale_events() {
+    let clock = MockClock::new(Utc.timestamp_millis_opt(TIMESTAMP).unwrap());
+    let (sender, receiver) = unbounded();
+    let mut stream = Box::pin(drop_stale(receiver, Duration::seconds(2), clock.clone()));
+
+    sender.unbounded_send(Ok(Ok(trade(1)))).unwrap();
+    let event = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(event, trade(1));
+
+    clock.advance(Duration::seconds(2));
+    sender.unbounded_send(Ok(Ok(trade(2)))).unwrap();
+    let event = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(event, trade(2));
+
+    clock.advance(Duration::milliseconds(1));
+    sender.unbounded_send(Ok(Ok(trade(3)))).unwrap();
+    sender.unbounded_send(Err(WebSocketError::AlreadyClosed)).unwrap();
+    drop(sender);
+    assert!(stream.next().await.unwrap().is_err());
+    assert!(stream.next().await.is_none());
+  }
+}
@@ -0,0 +1,117 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use futures::future::ready;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use crate::events::Event;
+use crate::events::StreamItem;
+
+
+/// The treatment of sequence numbers when the connection gets
+/// re-established.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Numbering {
+  /// Keep counting across reconnects.
+  Continue,
+  /// Restart counting at zero after each reconnect.
+  Reset,
+}
+
+
+/// Tag each event with a sequence number, in the order in which events
+/// arrive.
+///
+/// This function wraps a stream as created by `stream_items`. The
+/// counter starts at zero and increments with each event. Items other
+/// than events, i.e., parse errors and lifecycle notifications, are
+/// not numbered and not reported. Whether numbering restarts once the
+/// connection got re-established is governed by `numbering`.
+pub fn enumerate_events<S>(stream: S, numbering: Numbering) -> impl Stream<Item = (u64, Event)>
+where
+  S: Stream<Item = StreamItem>,
+{
+  let mut next = 0u64;
+
+  stream.filter_map(move |item| {
+    let result = match item {
+      StreamItem::Event(event) => {
+        let sequence = next;
+        next = next.wrapping_add(1);
+        Some((sequence, event))
+      },
+      StreamItem::Reconnected if numbering == Numbering::Reset => {
+        next = 0;
+        None
+      },
+      _ => None,
+    };
+    ready(result)
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+  use crate::events::DisconnectReason;
+
+
+  /// Create a trade event for the given quantity.
+  fn trade(quantity: u64) -> StreamItem {
+    let trade = test_util::trade("MSFT", "156.9799", quantity, TIMESTAMP);
+    StreamItem::Event(Event::Trade(trade))
+  }
+
+  /// Enumerate the given items, reporting only the sequence numbers.
+  async fn sequence(items: Vec<StreamItem>, numbering: Numbering) -> Vec<u64> {
+    enumerate_events(iter(items), numbering)
+      .map(|(sequence, _event)| sequence)
+      .collect()
+      .await
+  }
+
+
+  /// Check that we number events in the order in which they arrive.
+  #[test(tokio::test)]
+  async fn enumerate() {
+    let items = vec![trade(1), trade(2), trade(3)];
+    let events = enumerate_events(iter(items), Numbering::Continue)
+      .collect::<Vec<_>>()
+      .await;
+
+    let events = events
+      .into_iter()
+      .map(|(sequence, event)| match event {
+        Event::Trade(trade) => (sequence, trade.quantity),
+        _ => unreachable!(),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(events, vec![(0, 1), (1, 2), (2, 3)]);
+  }
+
+  /// Check that numbering across reconnects honors the configured
+  /// behavior.
+  #[test(tokio::test)]
+  async fn enumerate_across_reconnect() {
+    let items = || {
+      vec![
+        trade(1),
+        trade(2),
+        StreamItem::Disconnected(DisconnectReason::Other("reset".into())),
+        StreamItem::Reconnected,
+        trade(3),
+      ]
+    };
+    assert_eq!(sequence(items(), Numbering::Continue).await, vec![0, 1, 2]);
+    assert_eq!(sequence(items(), Numbering::Reset).await, vec![0, 1, 0]);
+  }
+}
@@ -0,0 +1,348 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::future::Future as _;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Duration as ChronoDuration;
+use chrono::Utc;
+
+use futures::Stream;
+
+use serde_json::Error as JsonError;
+
+use tokio::time::sleep_until;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+use tracing::trace;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::clock::reset_sleep;
+use crate::events::Aggregate;
+use crate::events::Clock;
+use crate::events::Event;
+
+
+/// The kind of an aggregate event.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Kind {
+  Second,
+  Minute,
+}
+
+impl Kind {
+  fn wrap(self, aggregate: Aggregate) -> Event {
+    match self {
+      Kind::Second => Event::SecondAggregate(aggregate),
+      Kind::Minute => Event::MinuteAggregate(aggregate),
+    }
+  }
+}
+
+
+/// The state we track for each symbol's aggregates.
+#[derive(Debug)]
+struct Entry {
+  /// The most recent (real or synthetic) aggregate.
+  last: Aggregate,
+  /// The point in time by which we expect the next aggregate.
+  deadline: DateTime<Utc>,
+}
+
+
+/// Synthesize a "no-trade" aggregate following the provided one.
+fn synthesize(last: &Aggregate) -> Aggregate {
+  let length = last.end_timestamp - last.start_timestamp;
+
+  Aggregate {
+    symbol: last.symbol.clone(),
+    volume: 0,
+    accumulated_volume: last.accumulated_volume,
+    official_open_price: last.official_open_price.clone(),
+    tick_vwap: None,
+    day_vwap: last.day_vwap.clone(),
+    open_price: last.close_price.clone(),
+    close_price: last.close_price.clone(),
+    high_price: last.close_price.clone(),
+    low_price: last.close_price.clone(),
+    start_timestamp: last.end_timestamp,
+    end_timestamp: last.end_timestamp + length,
+  }
+}
+
+
+/// A stream filling in aggregates for quiet periods.
+#[derive(Debug)]
+struct FillAggregates<S, C> {
+  /// The underlying stream.
+  stream: Pin<Box<S>>,
+  /// The time after which we consider an aggregate missing.
+  interval: ChronoDuration,
+  /// The tracked state, per kind of aggregate and symbol.
+  entries: HashMap<(Kind, String), Entry>,
+  /// The clock providing the current time.
+  clock: C,
+  /// The timer firing once the earliest deadline is reached.
+  sleep: Pin<Box<Sleep>>,
+  /// Whether the underlying stream is exhausted.
+  done: bool,
+}
+
+impl<S, C> FillAggregates<S, C>
+where
+  C: Clock,
+{
+  /// Synthesize an aggregate for the symbol whose deadline expired
+  /// first, if any did. Otherwise arm the timer for the earliest
+  /// deadline.
+  fn poll_expired(&mut self, cx: &mut Context<'_>) -> Option<Event> {
+    loop {
+      let (key, deadline) = self
+        .entries
+        .iter()
+        .min_by_key(|(_, entry)| entry.deadline)
+        .map(|(key, entry)| (key.clone(), entry.deadline))?;
+
+      let now = self.clock.now();
+      if deadline <= now {
+        let entry = self.entries.get_mut(&key).unwrap();
+        let aggregate = synthesize(&entry.last);
+        trace!(symbol = display(&aggregate.symbol), "synthesizing aggregate");
+
+        entry.last = aggregate.clone();
+        entry.deadline = deadline + self.interval;
+        break Some(key.0.wrap(aggregate))
+      }
+
+      reset_sleep(self.sleep.as_mut(), now, deadline);
+      if self.sleep.as_mut().poll(cx).is_pending() {
+        break None
+      }
+    }
+  }
+
+  /// Account for an aggregate we received from the underlying stream.
+  fn track(&mut self, kind: Kind, aggregate: &Aggregate) {
+    let deadline = self.clock.now() + self.interval;
+    let key = (kind, aggregate.symbol.clone());
+
+    match self.entries.get_mut(&key) {
+      // An aggregate may arrive late for a period that we already
+      // synthesized one for. In that case we don't move backwards.
+      Some(entry) if aggregate.end_timestamp < entry.last.end_timestamp => {
+        entry.deadline = deadline;
+      },
+      Some(entry) => {
+        entry.last = aggregate.clone();
+        entry.deadline = deadline;
+      },
+      None => {
+        let entry = Entry {
+          last: aggregate.clone(),
+          deadline,
+        };
+        let _ = self.entries.insert(key, entry);
+      },
+    }
+  }
+}
+
+impl<S, C> Stream for FillAggregates<S, C>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  C: Clock + Unpin,
+{
+  type Item = S::Item;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    if this.done {
+      return Poll::Ready(None)
+    }
+
+    match this.stream.as_mut().poll_next(cx) {
+      Poll::Ready(Some(item)) => {
+        match &item {
+          Ok(Ok(Event::SecondAggregate(aggregate))) => this.track(Kind::Second, aggregate),
+          Ok(Ok(Event::MinuteAggregate(aggregate))) => this.track(Kind::Minute, aggregate),
+          _ => (),
+        }
+        Poll::Ready(Some(item))
+      },
+      Poll::Ready(None) => {
+        this.done = true;
+        Poll::Ready(None)
+      },
+      Poll::Pending => match this.poll_expired(cx) {
+        Some(event) => Poll::Ready(Some(Ok(Ok(event)))),
+        None => Poll::Pending,
+      },
+    }
+  }
+}
+
+
+/// Fill in "no-trade" aggregates for quiet periods.
+///
+/// Polygon only sends an aggregate for a period if trades occurred in
+/// it. Hence, the final bar of a quiet period only arrives once the
+/// next trade happens. This function wraps a stream of events and,
+/// whenever no second or minute aggregate has been received for a
+/// symbol within `interval`, emits a synthetic one for the period
+/// following the most recent aggregate. Synthetic aggregates carry the
+/// previous close price as open, high, low, and close price and have
+/// zero volume.
+///
+/// `interval` is measured, as per `clock`, from the arrival of the most
+/// recent aggregate for a symbol and should be chosen slightly larger
+/// than the aggregate's length, to allow for transmission delays. No
+/// aggregates are synthesized for a symbol before the first real one
+/// for it has been received.
+pub fn fill_aggregates<S, C>(
+  stream: S,
+  interval: Duration,
+  clock: C,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  C: Clock + Unpin,
+{
+  FillAggregates {
+    stream: Box::pin(stream),
+    // An interval this large could not be added to the current time
+    // either.
+    interval: ChronoDuration::from_std(interval).expect("interval out of range"),
+    entries: HashMap::new(),
+    clock,
+    sleep: Box::pin(sleep_until(Instant::now())),
+    done: false,
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone as _;
+
+  use futures::stream::iter;
+  use futures::stream::pending;
+  use futures::FutureExt as _;
+  use futures::StreamExt as _;
+
+  use num_decimal::Num;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+  use crate::events::MockClock;
+
+
+  /// Create a minute aggregate event for MSFT.
+  fn minute_aggregate() -> Event {
+    let prices = ["114.11", "114.14", "114.19", "114.09"];
+    let aggregate = test_util::aggregate("MSFT", 1536036780000, 1536036840000, 10204, prices);
+    Event::MinuteAggregate(Aggregate {
+      tick_vwap: Some(Num::new(1144040, 10000)),
+      ..aggregate
+    })
+  }
+
+
+  /// Create a `MockClock` starting at the end of the minute aggregate
+  /// used.
+  fn clock() -> MockClock {
+    MockClock::new(Utc.timestamp_millis_opt(1536036840000).unwrap())
+  }
+
+
+  /// Check that we synthesize an aggregate once the interval has passed
+  /// without a real one arriving.
+  #[test(tokio::test)]
+  async fn synthesize_missing_aggregate() {
+    let clock = clock();
+    let event = minute_aggregate();
+    let events = iter(vec![Ok(Ok(event.clone()))]).chain(pending());
+    let interval = ChronoDuration::seconds(65);
+    let mut stream = Box::pin(fill_aggregates(
+      events,
+      interval.to_std().unwrap(),
+      clock.clone(),
+    ));
+
+    assert_eq!(stream.next().await.unwrap().unwrap().unwrap(), event);
+
+    clock.advance(interval - ChronoDuration::milliseconds(1));
+    assert!(stream.next().now_or_never().is_none());
+
+    clock.advance(ChronoDuration::milliseconds(1));
+    let synthetic = match stream.next().await.unwrap().unwrap().unwrap() {
+      Event::MinuteAggregate(aggregate) => aggregate,
+      event => panic!("unexpected event: {:?}", event),
+    };
+    assert_eq!(synthetic.symbol, "MSFT");
+    assert_eq!(synthetic.volume, 0);
+    assert_eq!(synthetic.open_price, Num::new(11414, 100));
+    assert_eq!(synthetic.high_price, Num::new(11414, 100));
+    assert_eq!(synthetic.low_price, Num::new(11414, 100));
+    assert_eq!(synthetic.close_price, Num::new(11414, 100));
+    assert_eq!(synthetic.tick_vwap, None);
+    assert_eq!(
+      synthetic.start_timestamp,
+      DateTime::parse_from_rfc3339("2018-09-04T04:54:00Z").unwrap()
+    );
+    assert_eq!(
+      synthetic.end_timestamp,
+      DateTime::parse_from_rfc3339("2018-09-04T04:55:00Z").unwrap()
+    );
+
+    // Synthetic aggregates keep coming for as long as the symbol stays
+    // quiet.
+    assert!(stream.next().now_or_never().is_none());
+    clock.advance(interval);
+    let synthetic = match stream.next().await.unwrap().unwrap().unwrap() {
+      Event::MinuteAggregate(aggregate) => aggregate,
+      event => panic!("unexpected event: {:?}", event),
+    };
+    assert_eq!(
+      synthetic.start_timestamp,
+      DateTime::parse_from_rfc3339("2018-09-04T04:55:00Z").unwrap()
+    );
+  }
+
+  /// Check that we do not synthesize aggregates for symbols for which
+  /// we have not seen a real one.
+  #[test(tokio::test)]
+  async fn no_aggregate_before_first() {
+    let clock = clock();
+    let event = Event::Trade(test_util::trade("MSFT", "156.9799", 3, TIMESTAMP));
+    let events = iter(vec![Ok(Ok(event.clone()))]).chain(pending());
+    let interval = Duration::from_secs(65);
+    let mut stream = Box::pin(fill_aggregates(events, interval, clock.clone()));
+
+    assert_eq!(stream.next().await.unwrap().unwrap().unwrap(), event);
+
+    clock.advance(ChronoDuration::seconds(650));
+    assert!(stream.next().now_or_never().is_none());
+  }
+
+  /// Check that the stream ends once the underlying one does.
+  #[test(tokio::test)]
+  async fn end_with_underlying_stream() {
+    let event = minute_aggregate();
+    let events = iter(vec![Ok(Ok(event))]);
+    let mut stream = Box::pin(fill_aggregates(events, Duration::from_secs(65), clock()));
+
+    assert!(stream.next().await.is_some());
+    assert!(stream.next().await.is_none());
+  }
+}
@@ -0,0 +1,233 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use futures::future::ready;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use crate::events::Event;
+use crate::events::Quote;
+use crate::events::StreamItem;
+use crate::events::Trade;
+
+
+/// A trade along with the quote prevailing at the time it happened, as
+/// reported by `join_quotes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeWithQuote {
+  /// The trade.
+  pub trade: Trade,
+  /// The most recent quote for the trade's symbol with a timestamp
+  /// strictly before that of the trade, if any.
+  pub prevailing_quote: Option<Quote>,
+}
+
+
+/// The recent quotes of a symbol, ordered by their timestamp.
+#[derive(Debug, Default)]
+struct Quotes {
+  /// The quotes, keyed by their timestamp. Of multiple quotes with the
+  /// same timestamp only the most recently received one is kept.
+  quotes: BTreeMap<DateTime<Utc>, Quote>,
+}
+
+impl Quotes {
+  /// Account for the given quote, discarding quotes older than
+  /// `horizon` relative to the newest one.
+  ///
+  /// The newest quote before that cutoff is retained, as it still
+  /// prevails for trades within the horizon.
+  fn track(&mut self, quote: Quote, horizon: Duration) {
+    let _ = self.quotes.insert(quote.timestamp, quote);
+
+    if let Some(newest) = self.quotes.keys().next_back() {
+      let cutoff = *newest - horizon;
+      let keep = match self.quotes.range(..cutoff).next_back() {
+        Some((timestamp, _)) => *timestamp,
+        None => cutoff,
+      };
+      self.quotes = self.quotes.split_off(&keep);
+    }
+  }
+
+  /// Find the quote prevailing at the time of the given trade.
+  fn prevailing(&self, trade: &Trade) -> Option<&Quote> {
+    self
+      .quotes
+      .range(..trade.timestamp)
+      .next_back()
+      .map(|(_, quote)| quote)
+  }
+}
+
+
+/// Join each trade with the quote prevailing at the time it happened.
+///
+/// This function wraps a stream as created by `stream_items` and
+/// reports a `TradeWithQuote` for each trade, carrying the most recent
+/// quote for the trade's symbol that is timestamped strictly before the
+/// trade. Quotes timestamped at or after the trade are never used, in
+/// order to avoid lookahead, even if they were received before it. All
+/// other items are discarded.
+///
+/// Per symbol, quotes are buffered for `horizon` before the newest
+/// quote, so that trades arriving out of order by up to that much are
+/// still joined with the correct quote.
+pub fn join_quotes<S>(stream: S, horizon: Duration) -> impl Stream<Item = TradeWithQuote>
+where
+  S: Stream<Item = StreamItem>,
+{
+  let mut quotes = HashMap::<String, Quotes>::new();
+
+  stream.filter_map(move |item| {
+    let result = match item {
+      StreamItem::Event(Event::Quote(quote)) => {
+        quotes
+          .entry(quote.symbol.clone())
+          .or_default()
+          .track(quote, horizon);
+        None
+      },
+      StreamItem::Event(Event::Trade(trade)) => {
+        let prevailing_quote = quotes
+          .get(&trade.symbol)
+          .and_then(|quotes| quotes.prevailing(&trade))
+          .cloned();
+        Some(TradeWithQuote {
+          trade,
+          prevailing_quote,
+        })
+      },
+      _ => None,
+    };
+    ready(result)
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+
+  use num_decimal::Num;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+
+
+  /// Create a trade item for the given symbol.
+  fn trade(symbol: &str, timestamp: i64) -> StreamItem {
+    let trade = test_util::trade(symbol, "100.01", 1, timestamp);
+    StreamItem::Event(Event::Trade(trade))
+  }
+
+  /// Create a quote item for the given symbol with the provided bid
+  /// price.
+  fn quote(symbol: &str, bid: &str, timestamp: i64) -> StreamItem {
+    let quote = test_util::quote(symbol, bid, "100.05", timestamp);
+    StreamItem::Event(Event::Quote(quote))
+  }
+
+
+  /// Check that trades are joined with the last quote strictly before
+  /// them.
+  #[test(tokio::test)]
+  async fn join_prevailing_quotes() {
+    let items = vec![
+      trade("SPY", 1000),
+      quote("SPY", "100.00", 1000),
+      quote("UFO", "20.00", 1001),
+      // The quote at the same time as the trade must not be used.
+      trade("SPY", 1000),
+      trade("SPY", 1001),
+      quote("SPY", "100.01", 1002),
+      quote("SPY", "100.02", 1002),
+      trade("SPY", 1002),
+      trade("SPY", 1003),
+      // This quote arrives late, but precedes the next trade.
+      quote("SPY", "99.99", 1001),
+      trade("SPY", 1002),
+      trade("MSFT", 1004),
+    ];
+
+    let joined = join_quotes(iter(items), Duration::seconds(60))
+      .map(|joined| {
+        (
+          joined.trade.symbol,
+          joined.trade.timestamp.timestamp_millis(),
+          joined.prevailing_quote.map(|quote| quote.bid_price),
+        )
+      })
+      .collect::<Vec<_>>()
+      .await;
+
+    let expected = vec![
+      ("SPY", 1000, None),
+      ("SPY", 1000, None),
+      ("SPY", 1001, Some(Num::new(100, 1))),
+      ("SPY", 1002, Some(Num::new(100, 1))),
+      ("SPY", 1003, Some(Num::new(10002, 100))),
+      ("SPY", 1002, Some(Num::new(9999, 100))),
+      ("MSFT", 1004, None),
+    ]
+    .into_iter()
+    .map(|(symbol, timestamp, bid)| (symbol.to_string(), timestamp, bid))
+    .collect::<Vec<_>>();
+    assert_eq!(joined, expected);
+  }
+
+  /// Check that a trade is joined with the last quote strictly before
+  /// it, even if later quotes were received already.
+  #[test(tokio::test)]
+  async fn join_out_of_order_trade() {
+    let items = vec![
+      quote("SPY", "100.05", 5),
+      quote("SPY", "100.06", 6),
+      quote("SPY", "100.07", 7),
+      trade("SPY", 6),
+    ];
+
+    let joined = join_quotes(iter(items), Duration::seconds(60))
+      .map(|joined| joined.prevailing_quote.map(|quote| quote.bid_price))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(joined, vec![Some(Num::new(10005, 100))]);
+  }
+
+  /// Check that quotes older than the horizon are discarded, except for
+  /// the one still prevailing at its start.
+  #[test(tokio::test)]
+  async fn prune_quotes_beyond_horizon() {
+    let items = vec![
+      quote("SPY", "100.01", 1000),
+      quote("SPY", "100.02", 2000),
+      quote("SPY", "100.03", 5000),
+      // The quote at 1000 got pruned, the one at 2000 still prevails
+      // at the start of the horizon.
+      trade("SPY", 1500),
+      trade("SPY", 2500),
+      trade("SPY", 5500),
+    ];
+
+    let joined = join_quotes(iter(items), Duration::seconds(2))
+      .map(|joined| joined.prevailing_quote.map(|quote| quote.bid_price))
+      .collect::<Vec<_>>()
+      .await;
+    let expected = vec![
+      None,
+      Some(Num::new(10002, 100)),
+      Some(Num::new(10003, 100)),
+    ];
+    assert_eq!(joined, expected);
+  }
+}
@@ -0,0 +1,239 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::future::Future as _;
+use std::mem::take;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Duration as ChronoDuration;
+use chrono::Utc;
+
+use futures::Stream;
+
+use serde_json::Error as JsonError;
+
+use tokio::time::sleep_until;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::clock::reset_sleep;
+use crate::events::Clock;
+use crate::events::Event;
+
+
+/// A stream coalescing events into per-symbol snapshots.
+#[derive(Debug)]
+struct LatestPerSymbol<S, C> {
+  /// The underlying stream.
+  stream: Pin<Box<S>>,
+  /// The length of a window.
+  interval: ChronoDuration,
+  /// The latest event per symbol observed in the current window.
+  latest: HashMap<String, Event>,
+  /// The clock providing the current time.
+  clock: C,
+  /// The end of the current window.
+  end: DateTime<Utc>,
+  /// The timer firing at the end of the current window.
+  sleep: Pin<Box<Sleep>>,
+  /// Whether the underlying stream is exhausted.
+  done: bool,
+}
+
+impl<S, C> Stream for LatestPerSymbol<S, C>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  C: Clock + Unpin,
+{
+  type Item = Result<Result<HashMap<String, Event>, JsonError>, WebSocketError>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+
+    while !this.done {
+      match this.stream.as_mut().poll_next(cx) {
+        Poll::Ready(Some(Ok(Ok(event)))) => {
+          let _ = this.latest.insert(event.symbol().to_string(), event);
+        },
+        Poll::Ready(Some(Ok(Err(err)))) => return Poll::Ready(Some(Ok(Err(err)))),
+        Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+        Poll::Ready(None) => this.done = true,
+        Poll::Pending => break,
+      }
+    }
+
+    if this.done {
+      // Report whatever we have seen in the final, partial window.
+      return if this.latest.is_empty() {
+        Poll::Ready(None)
+      } else {
+        Poll::Ready(Some(Ok(Ok(take(&mut this.latest)))))
+      }
+    }
+
+    loop {
+      let now = this.clock.now();
+      if now < this.end {
+        reset_sleep(this.sleep.as_mut(), now, this.end);
+        if this.sleep.as_mut().poll(cx).is_pending() {
+          break Poll::Pending
+        }
+        continue
+      }
+
+      this.end += this.interval;
+
+      if !this.latest.is_empty() {
+        break Poll::Ready(Some(Ok(Ok(take(&mut this.latest)))))
+      }
+    }
+  }
+}
+
+
+/// Coalesce events into snapshots containing only the latest event per
+/// symbol.
+///
+/// This function is meant for consumers that are only interested in
+/// the most recent state of each symbol periodically, such as a user
+/// interface refreshing at a fixed rate. Events are collected for
+/// windows of length `interval`, as measured by `clock`, and for each
+/// window a map from symbol to the last event observed for it is
+/// emitted. Symbols without any activity in a window are omitted and
+/// no snapshot is emitted for a window without any events at all.
+/// Errors are passed through as they occur. Once the underlying stream
+/// ends, the events seen in the final window are emitted.
+///
+/// Please note that events are keyed by symbol only, meaning that if a
+/// subscription covers multiple kinds of events (say, trades and
+/// quotes) for a symbol, the most recent one of any kind is reported.
+pub fn latest_per_symbol<S, C>(
+  stream: S,
+  interval: Duration,
+  clock: C,
+) -> impl Stream<Item = Result<Result<HashMap<String, Event>, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  C: Clock + Unpin,
+{
+  // An interval this large could not be added to the current time
+  // either.
+  let interval = ChronoDuration::from_std(interval).expect("interval out of range");
+  let end = clock.now() + interval;
+
+  LatestPerSymbol {
+    stream: Box::pin(stream),
+    interval,
+    latest: HashMap::new(),
+    clock,
+    end,
+    sleep: Box::pin(sleep_until(Instant::now())),
+    done: false,
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone as _;
+
+  use futures::channel::mpsc::unbounded;
+  use futures::stream::iter;
+  use futures::stream::pending;
+  use futures::FutureExt as _;
+  use futures::StreamExt as _;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+  use crate::events::MockClock;
+
+
+  /// Create a quote event for the given symbol and bid price.
+  fn quote(symbol: &str, bid: u32) -> Event {
+    Event::Quote(test_util::quote(symbol, bid, 200, TIMESTAMP))
+  }
+
+  /// Create a `MockClock` starting at the timestamp of the quotes used.
+  fn clock() -> MockClock {
+    MockClock::new(Utc.timestamp_millis_opt(TIMESTAMP).unwrap())
+  }
+
+
+  /// Check that we only report the latest event per symbol observed in
+  /// a window.
+  #[test(tokio::test)]
+  async fn report_latest_event() {
+    let clock = clock();
+    let events = vec![
+      Ok(Ok(quote("MSFT", 100))),
+      Ok(Ok(quote("MSFT", 101))),
+      Ok(Ok(quote("AAPL", 50))),
+      Ok(Ok(quote("MSFT", 102))),
+    ];
+    let interval = Duration::from_millis(100);
+    let mut stream = Box::pin(latest_per_symbol(
+      iter(events).chain(pending()),
+      interval,
+      clock.clone(),
+    ));
+
+    assert!(stream.next().now_or_never().is_none());
+
+    clock.advance(ChronoDuration::milliseconds(100));
+    let snapshot = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(snapshot.len(), 2);
+    assert_eq!(snapshot["MSFT"], quote("MSFT", 102));
+    assert_eq!(snapshot["AAPL"], quote("AAPL", 50));
+
+    // Without any further activity no snapshots are emitted.
+    clock.advance(ChronoDuration::seconds(1));
+    assert!(stream.next().now_or_never().is_none());
+  }
+
+  /// Check that symbols without activity in a window are omitted from
+  /// its snapshot.
+  #[test(tokio::test)]
+  async fn omit_inactive_symbols() {
+    let clock = clock();
+    let (sender, receiver) = unbounded();
+    let interval = Duration::from_millis(100);
+    let mut stream = Box::pin(latest_per_symbol(receiver, interval, clock.clone()));
+
+    sender.unbounded_send(Ok(Ok(quote("MSFT", 100)))).unwrap();
+    sender.unbounded_send(Ok(Ok(quote("AAPL", 50)))).unwrap();
+    assert!(stream.next().now_or_never().is_none());
+
+    clock.advance(ChronoDuration::milliseconds(100));
+    let snapshot = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(snapshot.len(), 2);
+
+    sender.unbounded_send(Ok(Ok(quote("AAPL", 51)))).unwrap();
+    clock.advance(ChronoDuration::milliseconds(100));
+    let snapshot = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(snapshot.len(), 1);
+    assert_eq!(snapshot["AAPL"], quote("AAPL", 51));
+  }
+
+  /// Check that we flush the final window once the underlying stream
+  /// ends.
+  #[test(tokio::test)]
+  async fn flush_on_end() {
+    let events = iter(vec![Ok(Ok(quote("MSFT", 100)))]);
+    let mut stream = Box::pin(latest_per_symbol(events, Duration::from_secs(1), clock()));
+
+    let snapshot = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(snapshot["MSFT"], quote("MSFT", 100));
+    assert!(stream.next().await.is_none());
+  }
+}
@@ -0,0 +1,123 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use futures::future::ready;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::Event;
+
+
+/// Drop trades for fewer than `size` shares.
+///
+/// All other events, as well as errors, are passed through unchanged.
+pub fn min_trade_size<S>(
+  stream: S,
+  size: u64,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  stream.filter(move |item| {
+    let include = match item {
+      Ok(Ok(Event::Trade(trade))) => trade.quantity >= size,
+      _ => true,
+    };
+    ready(include)
+  })
+}
+
+
+/// Drop quotes for which the bid and ask quantity combined is less
+/// than `size`.
+///
+/// All other events, as well as errors, are passed through unchanged.
+pub fn min_quote_size<S>(
+  stream: S,
+  size: u64,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  stream.filter(move |item| {
+    let include = match item {
+      Ok(Ok(Event::Quote(quote))) => {
+        quote.bid_quantity.saturating_add(quote.ask_quantity) >= size
+      },
+      _ => true,
+    };
+    ready(include)
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+  use crate::events::Quote;
+
+
+  /// Create a trade event for the given number of shares.
+  fn trade(quantity: u64) -> Event {
+    Event::Trade(test_util::trade("MSFT", "156.9799", quantity, TIMESTAMP))
+  }
+
+  /// Create a quote event for the given bid and ask quantities.
+  fn quote(bid: u64, ask: u64) -> Event {
+    Event::Quote(Quote {
+      bid_quantity: bid,
+      ask_quantity: ask,
+      ..test_util::quote("MSFT", 100, 101, TIMESTAMP)
+    })
+  }
+
+
+  /// Check that we drop trades below the minimum size.
+  #[test(tokio::test)]
+  async fn filter_small_trades() {
+    let events = vec![Ok(Ok(trade(1))), Ok(Ok(trade(100))), Ok(Ok(quote(1, 1)))];
+    let events = min_trade_size(iter(events), 10)
+      .map(|event| event.unwrap().unwrap())
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(events, vec![trade(100), quote(1, 1)]);
+  }
+
+  /// Check that we drop quotes below the minimum size.
+  #[test(tokio::test)]
+  async fn filter_small_quotes() {
+    let events = vec![
+      Ok(Ok(quote(1, 0))),
+      Ok(Ok(quote(1, 1))),
+      Ok(Ok(quote(3, 100))),
+      Ok(Ok(trade(1))),
+    ];
+    let events = min_quote_size(iter(events), 2)
+      .map(|event| event.unwrap().unwrap())
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(events, vec![quote(1, 1), quote(3, 100), trade(1)]);
+  }
+
+  /// Check that errors are passed through.
+  #[test(tokio::test)]
+  async fn pass_through_errors() {
+    let events = vec![Ok(Ok(trade(1))), Err(WebSocketError::AlreadyClosed)];
+    let events = min_quote_size(min_trade_size(iter(events), 10), 10)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(events.len(), 1);
+    assert!(events[0].is_err());
+  }
+}
@@ -0,0 +1,49 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod accumulate;
+mod consolidate_aggregates;
+mod count_bars;
+mod cumulative_volume;
+mod dedup_quotes;
+mod drop_stale;
+mod enumerate_events;
+mod fill_aggregates;
+mod join_quotes;
+mod latest_per_symbol;
+mod min_size;
+mod route_by_symbol;
+mod sample_aggregates;
+mod spread_summaries;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod throttle;
+mod typed_events;
+mod wait_for_symbols;
+
+pub use consolidate_aggregates::consolidate_aggregates;
+pub use count_bars::count_bars;
+pub use cumulative_volume::cumulative_volume;
+pub use cumulative_volume::CumulativeVolume;
+pub use cumulative_volume::VolumeSource;
+pub use dedup_quotes::dedup_quotes;
+pub use drop_stale::drop_stale;
+pub use enumerate_events::enumerate_events;
+pub use enumerate_events::Numbering;
+pub use fill_aggregates::fill_aggregates;
+pub use join_quotes::join_quotes;
+pub use join_quotes::TradeWithQuote;
+pub use latest_per_symbol::latest_per_symbol;
+pub use min_size::min_quote_size;
+pub use min_size::min_trade_size;
+pub use route_by_symbol::route_by_symbol;
+pub use sample_aggregates::sample_aggregates;
+pub use sample_aggregates::Sampling;
+pub use spread_summaries::spread_summaries;
+pub use spread_summaries::SpreadSummary;
+pub use throttle::throttle;
+pub use throttle::Overflow;
+pub use typed_events::aggregates_only;
+pub use typed_events::quotes_only;
+pub use typed_events::trades_only;
+pub use wait_for_symbols::wait_for_symbols;
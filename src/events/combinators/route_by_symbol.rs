@@ -0,0 +1,152 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+
+use futures::stream::unfold;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::Sender;
+
+use tracing::debug;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::Event;
+
+
+/// Route the events of each symbol to a dedicated channel.
+///
+/// This function wraps a stream of events and reports a channel
+/// receiver along with the symbol it is for, once per symbol, as soon
+/// as the first event for the symbol is seen. This event and all
+/// subsequent ones for the symbol are sent to this receiver. Each
+/// channel buffers up to `capacity` (but at least one) events, after
+/// which routing waits for the receiving side to catch up. Events for
+/// symbols whose receiver was dropped are discarded.
+///
+/// Note that routing happens only while the returned stream is being
+/// polled. Events that could not be decoded are discarded. Once the
+/// underlying stream reports an error or ends, all channels are
+/// closed and the returned stream ends.
+pub fn route_by_symbol<S>(
+  stream: S,
+  capacity: usize,
+) -> impl Stream<Item = (String, Receiver<Event>)>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  let capacity = capacity.max(1);
+  let stream = Box::pin(stream);
+  let senders = HashMap::<String, Sender<Event>>::new();
+
+  unfold((stream, senders), move |(mut stream, mut senders)| async move {
+    loop {
+      let event = match stream.next().await? {
+        Ok(Ok(event)) => event,
+        Ok(Err(err)) => {
+          debug!(error = display(&err), "discarding undecodable event");
+          continue
+        },
+        Err(err) => {
+          debug!(error = display(&err), "closing symbol channels");
+          return None
+        },
+      };
+
+      if let Some(sender) = senders.get(event.symbol()) {
+        if sender.send(event).await.is_err() {
+          // The receiver is gone; we just drop all further events for
+          // the symbol.
+          debug!("discarding event for dropped receiver");
+        }
+        continue
+      }
+
+      let symbol = event.symbol().to_string();
+      let (sender, receiver) = channel(capacity);
+      // The channel is empty and has room for at least one event, so
+      // sending cannot fail.
+      let () = sender.try_send(event).unwrap();
+      let _ = senders.insert(symbol.clone(), sender);
+      break Some(((symbol, receiver), (stream, senders)))
+    }
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+
+
+  /// Create a trade event for the given symbol and quantity.
+  fn trade(symbol: &str, quantity: u64) -> Event {
+    Event::Trade(test_util::trade(symbol, "156.9799", quantity, TIMESTAMP))
+  }
+
+  /// Drain the given receiver, reporting the quantities of all trades.
+  async fn quantities(mut receiver: Receiver<Event>) -> Vec<u64> {
+    let mut quantities = Vec::new();
+    while let Some(event) = receiver.recv().await {
+      match event {
+        Event::Trade(trade) => quantities.push(trade.quantity),
+        _ => unreachable!(),
+      }
+    }
+    quantities
+  }
+
+
+  /// Check that we route events to a channel per symbol.
+  #[test(tokio::test)]
+  async fn route_symbols() {
+    let events = vec![
+      Ok(Ok(trade("SPY", 1))),
+      Ok(Ok(trade("UFO", 2))),
+      Ok(Ok(trade("SPY", 3))),
+      Ok(Ok(trade("UFO", 4))),
+    ];
+    let receivers = route_by_symbol(iter(events), 8)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(receivers.len(), 2);
+
+    let mut receivers = receivers.into_iter();
+    let (symbol, receiver) = receivers.next().unwrap();
+    assert_eq!(symbol, "SPY");
+    assert_eq!(quantities(receiver).await, vec![1, 3]);
+
+    let (symbol, receiver) = receivers.next().unwrap();
+    assert_eq!(symbol, "UFO");
+    assert_eq!(quantities(receiver).await, vec![2, 4]);
+  }
+
+  /// Check that an error closes all channels.
+  #[test(tokio::test)]
+  async fn close_on_error() {
+    let events = vec![
+      Ok(Ok(trade("SPY", 1))),
+      Err(WebSocketError::AlreadyClosed),
+      Ok(Ok(trade("SPY", 2))),
+      Ok(Ok(trade("UFO", 3))),
+    ];
+    let mut receivers = Box::pin(route_by_symbol(iter(events), 8));
+    let (symbol, receiver) = receivers.next().await.unwrap();
+    assert_eq!(symbol, "SPY");
+    assert!(receivers.next().await.is_none());
+    assert_eq!(quantities(receiver).await, vec![1]);
+  }
+}
@@ -0,0 +1,211 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hash as _;
+use std::hash::Hasher as _;
+
+use futures::future::ready;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::Event;
+
+
+/// The strategy for selecting the symbols to sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+  /// Include every nth symbol, in the order in which symbols are first
+  /// seen. A value of zero or one includes all symbols.
+  EveryNth(usize),
+  /// Include a pseudo-random fraction (between 0 and 1) of symbols.
+  ///
+  /// Whether a symbol is included is derived from the symbol and the
+  /// provided seed only, meaning that the same seed selects the same
+  /// symbols.
+  Fraction {
+    /// The fraction of symbols to include.
+    fraction: f64,
+    /// The seed for selecting symbols.
+    seed: u64,
+  },
+}
+
+
+/// The state for deciding which symbols to include.
+#[derive(Debug)]
+struct Sampler {
+  /// The sampling strategy.
+  sampling: Sampling,
+  /// The decisions made so far, per symbol.
+  decisions: HashMap<String, bool>,
+  /// The number of new symbols to skip before including the next one.
+  skip: usize,
+}
+
+impl Sampler {
+  /// Check whether the aggregate for the given symbol is to be
+  /// included. Once made, the decision for a symbol never changes.
+  fn include(&mut self, symbol: &str) -> bool {
+    if let Some(include) = self.decisions.get(symbol) {
+      return *include
+    }
+
+    let include = match self.sampling {
+      Sampling::EveryNth(n) => {
+        let include = self.skip == 0;
+        self.skip = if include {
+          n.saturating_sub(1)
+        } else {
+          self.skip - 1
+        };
+        include
+      },
+      Sampling::Fraction { fraction, seed } => {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        symbol.hash(&mut hasher);
+        // Map the hash onto [0, 1), using the 53 bits a `f64` can
+        // represent exactly.
+        let value = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+        value < fraction
+      },
+    };
+
+    let _ = self.decisions.insert(symbol.to_string(), include);
+    include
+  }
+}
+
+
+/// Sample second and minute aggregates, only passing through those for
+/// a subset of symbols.
+///
+/// This function is meant to reduce the processing load of a
+/// market-wide subscription such as `MinuteAggregates(Stock::All)`,
+/// for cases where a representative subset suffices. A symbol is
+/// consistently either included in or excluded from the sample. All
+/// other events, as well as errors, are passed through unchanged.
+pub fn sample_aggregates<S>(
+  stream: S,
+  sampling: Sampling,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  let mut sampler = Sampler {
+    sampling,
+    decisions: HashMap::new(),
+    skip: 0,
+  };
+
+  stream.filter(move |item| {
+    let include = match item {
+      Ok(Ok(Event::SecondAggregate(aggregate))) | Ok(Ok(Event::MinuteAggregate(aggregate))) => {
+        sampler.include(&aggregate.symbol)
+      },
+      _ => true,
+    };
+    ready(include)
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+
+
+  /// Create a minute aggregate event for the given symbol.
+  fn aggregate(symbol: &str, start: i64) -> Result<Result<Event, JsonError>, WebSocketError> {
+    let aggregate = test_util::aggregate(symbol, start, start + 60000, 100, [1; 4]);
+    Ok(Ok(Event::MinuteAggregate(aggregate)))
+  }
+
+  /// Sample three bars for each of the provided symbols, reporting the
+  /// symbols of the aggregates passed through.
+  async fn sample(symbols: &[&str], sampling: Sampling) -> Vec<String> {
+    let events = (0..3)
+      .flat_map(|bar| {
+        symbols
+          .iter()
+          .map(move |symbol| aggregate(symbol, 1536036780000 + bar * 60000))
+      })
+      .collect::<Vec<_>>();
+
+    sample_aggregates(iter(events), sampling)
+      .map(|event| event.unwrap().unwrap().symbol().to_string())
+      .collect::<Vec<_>>()
+      .await
+  }
+
+
+  /// Check that sampling every nth symbol consistently includes the
+  /// same symbols for all bars.
+  #[test(tokio::test)]
+  async fn sample_every_nth_symbol() {
+    let symbols = ["AAPL", "MSFT", "SPY", "QQQ", "IBM"];
+    let sampled = sample(&symbols, Sampling::EveryNth(2)).await;
+    let expected = ["AAPL", "SPY", "IBM"];
+    assert_eq!(sampled, [expected, expected, expected].concat());
+  }
+
+  /// Check that a random sample is consistent for a symbol across bars
+  /// and for a given seed.
+  #[test(tokio::test)]
+  async fn sample_fraction_consistently() {
+    let symbols = (0..100).map(|i| format!("SYM{}", i)).collect::<Vec<_>>();
+    let symbols = symbols.iter().map(String::as_str).collect::<Vec<_>>();
+    let sampling = Sampling::Fraction {
+      fraction: 0.25,
+      seed: 42,
+    };
+
+    let sampled = sample(&symbols, sampling).await;
+    assert_eq!(sampled.len() % 3, 0);
+
+    let count = sampled.len() / 3;
+    assert!(count > 0 && count < symbols.len(), "{}", count);
+    assert_eq!(sampled[..count], sampled[count..2 * count]);
+    assert_eq!(sampled[..count], sampled[2 * count..]);
+
+    // The same seed selects the same symbols.
+    assert_eq!(sample(&symbols, sampling).await, sampled);
+
+    let all = Sampling::Fraction {
+      fraction: 1.0,
+      seed: 42,
+    };
+    assert_eq!(sample(&symbols, all).await.len(), 300);
+
+    let none = Sampling::Fraction {
+      fraction: 0.0,
+      seed: 42,
+    };
+    assert!(sample(&symbols, none).await.is_empty());
+  }
+
+  /// Check that events other than aggregates are passed through.
+  #[test(tokio::test)]
+  async fn pass_through_other_events() {
+    let trade = Event::Trade(test_util::trade("MSFT", "156.9799", 3, TIMESTAMP));
+    let events = vec![Ok(Ok(trade.clone())), Ok(Ok(trade.clone()))];
+
+    let sampled = sample_aggregates(iter(events), Sampling::EveryNth(1000))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(sampled.len(), 2);
+  }
+}
@@ -0,0 +1,302 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use futures::Stream;
+
+use num_decimal::Num;
+
+use crate::events::combinators::accumulate::accumulate;
+use crate::events::combinators::accumulate::Accumulator;
+use crate::events::Event;
+use crate::events::StreamItem;
+
+
+/// A summary of the bid-ask spreads of a symbol's quotes over a window
+/// of time, as reported by `spread_summaries`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpreadSummary {
+  /// The symbol the quotes are for.
+  pub symbol: String,
+  /// The start of the window the quotes fall into.
+  pub start: DateTime<Utc>,
+  /// The smallest spread seen.
+  pub min: Num,
+  /// The largest spread seen.
+  pub max: Num,
+  /// The mean of all spreads seen.
+  ///
+  /// The mean is accumulated exactly but reported as a `f64`, as it
+  /// is typically not representable as a terminating decimal.
+  pub mean: f64,
+  /// The number of quotes accounted for.
+  pub count: usize,
+}
+
+
+/// A spread summary in the making.
+#[derive(Debug)]
+struct Window {
+  /// The start of the window.
+  start: DateTime<Utc>,
+  /// The smallest spread seen so far.
+  min: Num,
+  /// The largest spread seen so far.
+  max: Num,
+  /// The sum of all spreads seen so far.
+  sum: Num,
+  /// The number of quotes accounted for.
+  count: usize,
+}
+
+impl Window {
+  /// Start a new window at `start` with the given spread.
+  fn new(start: DateTime<Utc>, spread: Num) -> Self {
+    Self {
+      start,
+      min: spread.clone(),
+      max: spread.clone(),
+      sum: spread,
+      count: 1,
+    }
+  }
+
+  /// Account for the given spread.
+  fn account(&mut self, spread: Num) {
+    if spread < self.min {
+      self.min = spread.clone();
+    }
+    if spread > self.max {
+      self.max = spread.clone();
+    }
+    self.sum += spread;
+    self.count += 1;
+  }
+
+  /// Finish the window, creating the summary for the given symbol.
+  fn finish(self, symbol: String) -> SpreadSummary {
+    let mean = (self.sum / self.count).to_f64().unwrap_or(f64::NAN);
+    SpreadSummary {
+      symbol,
+      start: self.start,
+      min: self.min,
+      max: self.max,
+      mean,
+      count: self.count,
+    }
+  }
+}
+
+
+/// The spread summaries in the making.
+struct Windows {
+  /// The length of a window, in milliseconds.
+  interval: i64,
+  /// The windows in the making, per symbol.
+  windows: HashMap<String, Window>,
+  /// The start of the latest window any quote fell into.
+  watermark: Option<DateTime<Utc>>,
+}
+
+impl Accumulator for Windows {
+  type Output = SpreadSummary;
+
+  /// Account for the given quote, finishing all windows that end
+  /// before the window the quote falls into, irrespective of symbol.
+  fn track(&mut self, event: Event, pending: &mut VecDeque<SpreadSummary>) {
+    let quote = match event {
+      Event::Quote(quote) => quote,
+      _ => return,
+    };
+    let millis = quote.timestamp_millis();
+    let start = quote.timestamp - Duration::milliseconds(millis.rem_euclid(self.interval));
+
+    match self.watermark {
+      // Quotes for windows already reported are late and can no longer
+      // be accounted for.
+      Some(watermark) if start < watermark => return,
+      Some(watermark) if start == watermark => (),
+      // All open windows share the watermark as their start and so
+      // end before the window the quote falls into.
+      _ => {
+        self.watermark = Some(start);
+        self.flush(pending);
+      },
+    }
+
+    let spread = quote.spread();
+    match self.windows.get_mut(&quote.symbol) {
+      Some(window) => window.account(spread),
+      None => {
+        let window = Window::new(start, spread);
+        let _ = self.windows.insert(quote.symbol, window);
+      },
+    }
+  }
+
+  /// Finish all partial windows.
+  fn flush(&mut self, pending: &mut VecDeque<SpreadSummary>) {
+    let mut windows = self.windows.drain().collect::<Vec<_>>();
+    // Report summaries in a deterministic order.
+    windows.sort_by(|(first, _), (second, _)| first.cmp(second));
+    pending.extend(
+      windows
+        .into_iter()
+        .map(|(symbol, window)| window.finish(symbol)),
+    );
+  }
+}
+
+
+/// Summarize the bid-ask spreads of quotes per symbol and window of
+/// time.
+///
+/// This function wraps a stream as created by `stream_items` and
+/// reports a `SpreadSummary` for each symbol and window of length
+/// `interval` (but at least one millisecond) that saw at least one
+/// quote. Windows are aligned to multiples of `interval` since the
+/// Unix epoch and are based on the quotes' timestamps. Windows are
+/// reported once a quote for a later window arrives, irrespective of
+/// the quote's symbol, such that a symbol no longer quoted does not
+/// hold back its summary. Quotes for windows already reported are
+/// ignored.
+///
+/// Partial windows are emitted once the connection got lost or was
+/// re-established, or the underlying stream ends, such that a summary
+/// never spans a gap. All items other than quotes are discarded.
+pub fn spread_summaries<S>(stream: S, interval: Duration) -> impl Stream<Item = SpreadSummary>
+where
+  S: Stream<Item = StreamItem>,
+{
+  let windows = Windows {
+    interval: interval.num_milliseconds().max(1),
+    windows: HashMap::new(),
+    watermark: None,
+  };
+  accumulate(stream, windows)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::stream::iter;
+  use futures::StreamExt as _;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::DisconnectReason;
+
+
+  /// Create a quote item for the given symbol with the provided bid and
+  /// ask prices.
+  fn quote(symbol: &str, bid: &str, ask: &str, timestamp: i64) -> StreamItem {
+    let quote = test_util::quote(symbol, bid, ask, timestamp);
+    StreamItem::Event(Event::Quote(quote))
+  }
+
+
+  /// Check that we summarize the spreads of the quotes in a window.
+  #[test(tokio::test)]
+  async fn summarize_window() {
+    let items = vec![
+      quote("SPY", "100.00", "100.02", 1577818800000),
+      quote("SPY", "100.00", "100.05", 1577818801000),
+      quote("UFO", "20.00", "20.10", 1577818802000),
+      quote("SPY", "100.01", "100.02", 1577818859999),
+      // This quote opens the next window for `SPY`.
+      quote("SPY", "100.00", "100.04", 1577818860000),
+    ];
+
+    let summaries = spread_summaries(iter(items), Duration::minutes(1))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(summaries.len(), 3);
+
+    let summary = &summaries[0];
+    assert_eq!(summary.symbol, "SPY");
+    assert_eq!(summary.start.timestamp_millis(), 1577818800000);
+    assert_eq!(summary.min, Num::new(1, 100));
+    assert_eq!(summary.max, Num::new(5, 100));
+    assert_eq!(summary.count, 3);
+    assert!((summary.mean - 0.08 / 3.0).abs() < 1e-12, "{}", summary.mean);
+
+    // The `UFO` window is closed by the later `SPY` quote as well.
+    assert_eq!(summaries[1].symbol, "UFO");
+    assert_eq!(summaries[1].min, Num::new(1, 10));
+    assert_eq!(summaries[1].max, Num::new(1, 10));
+
+    // The remaining window is flushed once the stream ends.
+    assert_eq!(summaries[2].symbol, "SPY");
+    assert_eq!(summaries[2].start.timestamp_millis(), 1577818860000);
+    assert_eq!(summaries[2].count, 1);
+  }
+
+  /// Check that the window of a symbol that stopped quoting is reported
+  /// once any later window is opened.
+  #[test(tokio::test)]
+  async fn close_window_of_stopped_symbol() {
+    let items = vec![
+      quote("UFO", "20.00", "20.10", 1577818800000),
+      quote("SPY", "100.00", "100.02", 1577818801000),
+      quote("SPY", "100.00", "100.03", 1577818860000),
+      quote("SPY", "100.00", "100.04", 1577818920000),
+      // A late quote for a window already reported is ignored.
+      quote("UFO", "20.00", "20.20", 1577818810000),
+    ];
+
+    let mut stream = Box::pin(spread_summaries(iter(items), Duration::minutes(1)));
+    let summary = stream.next().await.unwrap();
+    assert_eq!(summary.symbol, "SPY");
+    assert_eq!(summary.start.timestamp_millis(), 1577818800000);
+
+    let summary = stream.next().await.unwrap();
+    assert_eq!(summary.symbol, "UFO");
+    assert_eq!(summary.start.timestamp_millis(), 1577818800000);
+    assert_eq!(summary.count, 1);
+
+    let summaries = stream
+      .map(|summary| (summary.symbol, summary.start.timestamp_millis()))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(
+      summaries,
+      vec![
+        ("SPY".to_string(), 1577818860000),
+        ("SPY".to_string(), 1577818920000),
+      ]
+    );
+  }
+
+  /// Check that partial windows are flushed once the connection got
+  /// lost.
+  #[test(tokio::test)]
+  async fn flush_on_disconnect() {
+    let items = vec![
+      quote("SPY", "100.00", "100.02", 1577818800000),
+      StreamItem::Disconnected(DisconnectReason::Other("reset".into())),
+      StreamItem::Reconnected,
+      quote("SPY", "100.00", "100.04", 1577818801000),
+    ];
+
+    let summaries = spread_summaries(iter(items), Duration::minutes(1))
+      .map(|summary| (summary.start.timestamp_millis(), summary.max))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(
+      summaries,
+      vec![
+        (1577818800000, Num::new(2, 100)),
+        (1577818800000, Num::new(4, 100)),
+      ]
+    );
+  }
+}
@@ -0,0 +1,66 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A module providing event fixtures shared by the combinator tests.
+
+use std::fmt::Display;
+
+use serde_json::from_str as from_json;
+
+use crate::events::Aggregate;
+use crate::events::Quote;
+use crate::events::Trade;
+
+
+/// The timestamp used by fixtures for which time does not matter, in
+/// milliseconds since the Unix epoch.
+pub(crate) const TIMESTAMP: i64 = 1577818283019;
+
+
+/// Create a trade of `quantity` shares of the given symbol at `price`.
+pub(crate) fn trade<P>(symbol: &str, price: P, quantity: u64, timestamp: i64) -> Trade
+where
+  P: Display,
+{
+  let json = format!(
+    r#"{{"sym":"{}","x":4,"p":{},"s":{},"t":{}}}"#,
+    symbol, price, quantity, timestamp
+  );
+  from_json::<Trade>(&json).unwrap()
+}
+
+
+/// Create a quote for the given symbol with the provided bid and ask
+/// price, each for a single share.
+pub(crate) fn quote<P>(symbol: &str, bid: P, ask: P, timestamp: i64) -> Quote
+where
+  P: Display,
+{
+  let json = format!(
+    r#"{{"sym":"{}","bx":8,"bp":{},"bs":1,"ax":12,"ap":{},"as":1,"t":{}}}"#,
+    symbol, bid, ask, timestamp
+  );
+  from_json::<Quote>(&json).unwrap()
+}
+
+
+/// Create an aggregate for the given symbol spanning `start` to `end`,
+/// with the provided volume as well as open, close, high, and low
+/// price, in that order.
+pub(crate) fn aggregate<P>(
+  symbol: &str,
+  start: i64,
+  end: i64,
+  volume: u64,
+  prices: [P; 4],
+) -> Aggregate
+where
+  P: Display,
+{
+  let [open, close, high, low] = prices;
+  let json = format!(
+    r#"{{"sym":"{}","v":{},"o":{},"c":{},"h":{},"l":{},"s":{},"e":{}}}"#,
+    symbol, volume, open, close, high, low, start, end
+  );
+  from_json::<Aggregate>(&json).unwrap()
+}
@@ -0,0 +1,342 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::future::Future as _;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use futures::Stream;
+
+use serde_json::Error as JsonError;
+
+use tokio::time::sleep_until;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+use tracing::trace;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::clock::reset_sleep;
+use crate::events::Clock;
+use crate::events::Event;
+
+
+/// The treatment of events exceeding the rate of a symbol.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+  /// Drop excess events.
+  Drop,
+  /// Retain only the most recent excess event for a symbol and emit
+  /// it as soon as the rate permits.
+  Coalesce,
+}
+
+
+/// The token bucket of a single symbol.
+#[derive(Debug)]
+struct Bucket {
+  /// The number of events that may currently be emitted.
+  tokens: f64,
+  /// The point in time at which `tokens` was last updated.
+  updated: DateTime<Utc>,
+  /// The most recent event not yet emitted, if coalescing.
+  pending: Option<Event>,
+}
+
+impl Bucket {
+  /// Refill the bucket according to the time passed since the last
+  /// update.
+  fn refill(&mut self, rate: f64, now: DateTime<Utc>) {
+    let elapsed = (now - self.updated)
+      .to_std()
+      .unwrap_or_default()
+      .as_secs_f64();
+    self.tokens = (self.tokens + elapsed * rate).min(rate);
+    self.updated = now;
+  }
+
+  /// The point in time at which the next token becomes available.
+  fn available_at(&self, rate: f64) -> DateTime<Utc> {
+    let missing = (1.0 - self.tokens).max(0.0);
+    self.updated + Duration::nanoseconds((missing / rate * 1e9) as i64)
+  }
+}
+
+
+/// A stream throttling the events for each symbol.
+#[derive(Debug)]
+struct Throttle<S, C> {
+  /// The underlying stream.
+  stream: Pin<Box<S>>,
+  /// The maximum number of events per second and symbol.
+  rate: f64,
+  /// The treatment of excess events.
+  overflow: Overflow,
+  /// The token buckets, per symbol.
+  buckets: HashMap<String, Bucket>,
+  /// The clock providing the current time.
+  clock: C,
+  /// The timer firing once the next coalesced event may be emitted.
+  sleep: Pin<Box<Sleep>>,
+  /// Whether the underlying stream is exhausted.
+  done: bool,
+}
+
+impl<S, C> Throttle<S, C>
+where
+  C: Clock,
+{
+  /// Check whether the given event may be emitted, retaining it if it
+  /// is to be coalesced.
+  fn admit(&mut self, event: Event) -> Option<Event> {
+    let now = self.clock.now();
+    let rate = self.rate;
+    let bucket = self
+      .buckets
+      .entry(event.symbol().to_string())
+      .or_insert_with(|| Bucket {
+        tokens: rate,
+        updated: now,
+        pending: None,
+      });
+    bucket.refill(rate, now);
+
+    if bucket.tokens >= 1.0 {
+      bucket.tokens -= 1.0;
+      // The event is more recent than any coalesced one.
+      bucket.pending = None;
+      Some(event)
+    } else {
+      trace!(symbol = display(event.symbol()), "throttling event");
+      if self.overflow == Overflow::Coalesce {
+        bucket.pending = Some(event);
+      }
+      None
+    }
+  }
+
+  /// Emit a coalesced event once the rate for its symbol permits.
+  /// Otherwise arm the timer for the earliest such point in time.
+  fn poll_pending(&mut self, cx: &mut Context<'_>) -> Option<Event> {
+    let rate = self.rate;
+    loop {
+      let (symbol, at) = self
+        .buckets
+        .iter()
+        .filter(|(_, bucket)| bucket.pending.is_some())
+        .map(|(symbol, bucket)| (symbol, bucket.available_at(rate)))
+        .min_by_key(|(_, at)| *at)
+        .map(|(symbol, at)| (symbol.clone(), at))?;
+
+      let now = self.clock.now();
+      if at <= now {
+        let bucket = self.buckets.get_mut(&symbol).unwrap();
+        bucket.refill(rate, now);
+        bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+        break bucket.pending.take()
+      }
+
+      reset_sleep(self.sleep.as_mut(), now, at);
+      if self.sleep.as_mut().poll(cx).is_pending() {
+        break None
+      }
+    }
+  }
+
+  /// Retrieve any coalesced event, irrespective of the rate.
+  fn flush(&mut self) -> Option<Event> {
+    self
+      .buckets
+      .values_mut()
+      .find_map(|bucket| bucket.pending.take())
+  }
+}
+
+impl<S, C> Stream for Throttle<S, C>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  C: Clock + Unpin,
+{
+  type Item = S::Item;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+
+    loop {
+      if this.done {
+        break Poll::Ready(this.flush().map(|event| Ok(Ok(event))))
+      }
+
+      match this.stream.as_mut().poll_next(cx) {
+        Poll::Ready(Some(Ok(Ok(event)))) if this.rate > 0.0 => {
+          if let Some(event) = this.admit(event) {
+            break Poll::Ready(Some(Ok(Ok(event))))
+          }
+        },
+        Poll::Ready(Some(item)) => break Poll::Ready(Some(item)),
+        Poll::Ready(None) => this.done = true,
+        Poll::Pending => match this.poll_pending(cx) {
+          Some(event) => break Poll::Ready(Some(Ok(Ok(event)))),
+          None => break Poll::Pending,
+        },
+      }
+    }
+  }
+}
+
+
+/// Throttle the events for each symbol to at most `rate` per second.
+///
+/// Each symbol is assigned a token bucket allowing for bursts of up to
+/// `rate` events, keyed by `Event::symbol`. Hence, a symbol flooding
+/// the stream does not affect the events of quieter ones. Events
+/// exceeding the rate are dropped or coalesced, depending on
+/// `overflow`. Coalesced events still pending are emitted once the
+/// underlying stream ends. A `rate` of zero disables throttling.
+/// Tokens are replenished based on the time reported by `clock`.
+/// Errors are passed through unchanged.
+pub fn throttle<S, C>(
+  stream: S,
+  rate: u32,
+  overflow: Overflow,
+  clock: C,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  C: Clock + Unpin,
+{
+  Throttle {
+    stream: Box::pin(stream),
+    rate: f64::from(rate),
+    overflow,
+    buckets: HashMap::new(),
+    clock,
+    sleep: Box::pin(sleep_until(Instant::now())),
+    done: false,
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone as _;
+
+  use futures::stream::iter;
+  use futures::stream::pending;
+  use futures::FutureExt as _;
+  use futures::StreamExt as _;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+  use crate::events::MockClock;
+
+
+  /// Create a `MockClock` starting at the timestamp of the trades used.
+  fn clock() -> MockClock {
+    MockClock::new(Utc.timestamp_millis_opt(TIMESTAMP).unwrap())
+  }
+
+
+  /// Create a trade event for the given symbol and quantity.
+  fn trade(symbol: &str, quantity: u64) -> Event {
+    Event::Trade(test_util::trade(symbol, "156.9799", quantity, TIMESTAMP))
+  }
+
+  /// Create a flood of trades for SPY, interspersed with a few trades
+  /// for UFO.
+  fn flood() -> Vec<Event> {
+    (1..=100)
+      .map(|quantity| {
+        if quantity % 50 == 0 {
+          trade("UFO", quantity)
+        } else {
+          trade("SPY", quantity)
+        }
+      })
+      .collect()
+  }
+
+
+  /// Check that we drop excess events of a busy symbol without
+  /// affecting a quiet one.
+  #[test(tokio::test)]
+  async fn drop_excess_events() {
+    let events = flood().into_iter().map(|event| Ok(Ok(event)));
+    let events = throttle(iter(events), 5, Overflow::Drop, clock())
+      .map(|event| event.unwrap().unwrap())
+      .collect::<Vec<_>>()
+      .await;
+
+    let count = |symbol| events.iter().filter(|e| e.symbol() == symbol).count();
+    assert_eq!(count("SPY"), 5);
+    assert_eq!(count("UFO"), 2);
+  }
+
+  /// Check that we coalesce excess events to the most recent one,
+  /// emitting it once the rate permits.
+  #[test(tokio::test)]
+  async fn coalesce_excess_events() {
+    let clock = clock();
+    let events = flood().into_iter().map(|event| Ok(Ok(event)));
+    let mut stream = Box::pin(throttle(
+      iter(events).chain(pending()),
+      5,
+      Overflow::Coalesce,
+      clock.clone(),
+    ));
+
+    let mut events = Vec::new();
+    for _ in 0..7 {
+      events.push(stream.next().await.unwrap().unwrap().unwrap());
+    }
+
+    // The coalesced event has to wait for a token to become available.
+    clock.advance(Duration::milliseconds(199));
+    assert!(stream.next().now_or_never().is_none());
+
+    clock.advance(Duration::milliseconds(1));
+    events.push(stream.next().await.unwrap().unwrap().unwrap());
+
+    let quantities = events
+      .iter()
+      .map(|event| match event {
+        Event::Trade(trade) => (trade.symbol.as_str(), trade.quantity),
+        _ => unreachable!(),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      quantities,
+      vec![
+        ("SPY", 1),
+        ("SPY", 2),
+        ("SPY", 3),
+        ("SPY", 4),
+        ("SPY", 5),
+        ("UFO", 50),
+        ("UFO", 100),
+        ("SPY", 99),
+      ]
+    );
+  }
+
+  /// Check that a rate of zero disables throttling.
+  #[test(tokio::test)]
+  async fn no_throttling() {
+    let events = flood().into_iter().map(|event| Ok(Ok(event)));
+    let events = throttle(iter(events), 0, Overflow::Drop, clock())
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(events.len(), 100);
+  }
+}
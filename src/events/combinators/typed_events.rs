@@ -0,0 +1,176 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use futures::future::ready;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::error::Error;
+use crate::events::Aggregate;
+use crate::events::Event;
+use crate::events::Quote;
+use crate::events::Trade;
+
+
+/// Retain only the events `select` maps to an item, reporting errors
+/// as `Error`.
+fn select_events<S, T, F>(stream: S, select: F) -> impl Stream<Item = Result<T, Error>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  F: Fn(Event) -> Option<T>,
+{
+  stream.filter_map(move |result| {
+    let result = match result {
+      Ok(Ok(event)) => select(event).map(Ok),
+      Ok(Err(err)) => Some(Err(Error::Json(err))),
+      Err(err) => Some(Err(Error::WebSocket(err))),
+    };
+    ready(result)
+  })
+}
+
+
+/// Retain only the trades of a stream of events.
+///
+/// This function wraps a stream of events and reports the contained
+/// trades, discarding all other events. Events that could not be
+/// decoded are reported as `Error::Json`, as it is unknown whether
+/// they were trades, and errors of the underlying stream, including
+/// disconnects, as `Error::WebSocket`.
+pub fn trades_only<S>(stream: S) -> impl Stream<Item = Result<Trade, Error>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  select_events(stream, |event| match event {
+    Event::Trade(trade) => Some(trade),
+    _ => None,
+  })
+}
+
+
+/// Retain only the quotes of a stream of events.
+///
+/// Please refer to `trades_only` for the treatment of errors.
+pub fn quotes_only<S>(stream: S) -> impl Stream<Item = Result<Quote, Error>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  select_events(stream, |event| match event {
+    Event::Quote(quote) => Some(quote),
+    _ => None,
+  })
+}
+
+
+/// Retain only the second and minute aggregates of a stream of events.
+///
+/// Please refer to `trades_only` for the treatment of errors.
+pub fn aggregates_only<S>(stream: S) -> impl Stream<Item = Result<Aggregate, Error>>
+where
+  S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+{
+  select_events(stream, |event| match event {
+    Event::SecondAggregate(aggregate) | Event::MinuteAggregate(aggregate) => Some(aggregate),
+    _ => None,
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::io::Error as IoError;
+  use std::io::ErrorKind as IoErrorKind;
+
+  use futures::stream::iter;
+
+  use serde_json::from_str as from_json;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+  use crate::events::DisconnectReason;
+
+
+  /// Create a stream comprising events of all kinds.
+  fn events() -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>> {
+    let events = vec![
+      Event::Trade(test_util::trade("MSFT", "156.9799", 3, TIMESTAMP)),
+      Event::Quote(test_util::quote("SPY", "287.45", "287.46", TIMESTAMP)),
+      Event::SecondAggregate(test_util::aggregate(
+        "AAPL",
+        1577818283000,
+        1577818284000,
+        100,
+        [1, 2, 3, 1],
+      )),
+      Event::MinuteAggregate(test_util::aggregate(
+        "AAPL",
+        1577818260000,
+        1577818320000,
+        200,
+        [1, 2, 3, 1],
+      )),
+      Event::Trade(test_util::trade("UFO", "26.01", 5, TIMESTAMP + 1)),
+    ];
+    iter(events.into_iter().map(|event| Ok(Ok(event))))
+  }
+
+
+  /// Check that we retain only trades.
+  #[test(tokio::test)]
+  async fn select_trades() {
+    let symbols = trades_only(events())
+      .map(|trade| trade.unwrap().symbol)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(symbols, vec!["MSFT", "UFO"]);
+  }
+
+  /// Check that we retain only quotes.
+  #[test(tokio::test)]
+  async fn select_quotes() {
+    let quotes = quotes_only(events())
+      .map(|quote| quote.unwrap())
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(quotes.len(), 1);
+    assert_eq!(quotes[0].symbol, "SPY");
+  }
+
+  /// Check that we retain second and minute aggregates.
+  #[test(tokio::test)]
+  async fn select_aggregates() {
+    let volumes = aggregates_only(events())
+      .map(|aggregate| aggregate.unwrap().volume)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(volumes, vec![100, 200]);
+  }
+
+  /// Check that decoding errors and disconnects are passed through.
+  #[test(tokio::test)]
+  async fn pass_through_errors() {
+    let reason = DisconnectReason::MaxConnections;
+    let items = vec![
+      Ok(Err(from_json::<Event>("{}").unwrap_err())),
+      Err(WebSocketError::Io(IoError::new(
+        IoErrorKind::ConnectionAborted,
+        reason.clone(),
+      ))),
+    ];
+    let results = quotes_only(iter(items)).collect::<Vec<_>>().await;
+    assert_eq!(results.len(), 2);
+    assert!(matches!(results[0], Err(Error::Json(..))), "{:?}", results[0]);
+    match &results[1] {
+      Err(Error::WebSocket(err)) => assert_eq!(DisconnectReason::from_error(err), Some(&reason)),
+      result => panic!("unexpected result: {:?}", result),
+    }
+  }
+}
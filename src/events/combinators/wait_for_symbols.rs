@@ -0,0 +1,216 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use tokio::sync::oneshot::channel;
+use tokio::sync::oneshot::Sender;
+use tokio::time::timeout;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::Event;
+use crate::events::Stock;
+use crate::events::Subscription;
+
+
+/// The type of item produced by an event stream.
+type EventResult = Result<Result<Event, JsonError>, WebSocketError>;
+
+
+/// The symbols still awaiting their first event.
+#[derive(Debug)]
+struct Pending {
+  /// The symbols no event has been seen for yet.
+  symbols: HashSet<String>,
+  /// Whether a wildcard subscription still awaits any event.
+  any: bool,
+  /// The sender to signal once all symbols were seen, if not yet
+  /// signaled.
+  sender: Option<Sender<()>>,
+}
+
+impl Pending {
+  /// Account for an event for the given symbol.
+  fn see(&mut self, symbol: &str) {
+    self.any = false;
+    let _ = self.symbols.remove(symbol);
+
+    if self.symbols.is_empty() {
+      if let Some(sender) = self.sender.take() {
+        let _ = sender.send(());
+      }
+    }
+  }
+
+  /// Retrieve the symbols not yet seen, in sorted order. A wildcard
+  /// not yet satisfied is reported as `*`.
+  fn missing(&self) -> Vec<String> {
+    let mut missing = self.symbols.iter().cloned().collect::<Vec<_>>();
+    if self.any {
+      missing.push(Stock::All.to_string());
+    }
+    missing.sort();
+    missing
+  }
+}
+
+
+/// Wait until at least one event was received for each of the
+/// subscribed symbols.
+///
+/// This function wraps a stream of events and reports it, unchanged,
+/// along with a future that resolves once an event was seen for each
+/// symbol of the provided subscriptions, irrespective of the event
+/// type. Wildcard subscriptions are satisfied by any event. If not all
+/// symbols were seen within `timeout_`, the future resolves to an
+/// error listing the missing symbols.
+///
+/// Note that events are only accounted for while the returned stream
+/// is being polled.
+pub fn wait_for_symbols<S, I>(
+  stream: S,
+  subscriptions: I,
+  timeout_: Duration,
+) -> (
+  impl Stream<Item = EventResult>,
+  impl Future<Output = Result<(), Vec<String>>>,
+)
+where
+  S: Stream<Item = EventResult>,
+  I: IntoIterator<Item = Subscription>,
+{
+  let mut symbols = HashSet::new();
+  let mut any = false;
+  for subscription in subscriptions {
+    match subscription.stock() {
+      Stock::Symbol(symbol) => {
+        let _ = symbols.insert(symbol.to_string());
+      },
+      Stock::All => any = true,
+    }
+  }
+
+  let (sender, receiver) = channel();
+  let sender = if symbols.is_empty() && !any {
+    let _ = sender.send(());
+    None
+  } else {
+    Some(sender)
+  };
+  let pending = Arc::new(Mutex::new(Pending {
+    symbols,
+    any,
+    sender,
+  }));
+
+  let stream = stream.inspect({
+    let pending = pending.clone();
+    move |item| {
+      if let Ok(Ok(event)) = item {
+        pending.lock().unwrap().see(event.symbol());
+      }
+    }
+  });
+
+  let gate = async move {
+    match timeout(timeout_, receiver).await {
+      Ok(Ok(())) => Ok(()),
+      // The sender is only dropped along with `pending`, which we
+      // still hold on to. Should it be gone nevertheless, we report
+      // the symbols not seen just like on timeout.
+      Ok(Err(..)) | Err(..) => Err(pending.lock().unwrap().missing()),
+    }
+  };
+
+  (stream, gate)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::pin_mut;
+  use futures::stream::iter;
+  use futures::FutureExt as _;
+
+  use test_log::test;
+
+  use crate::events::combinators::test_util;
+  use crate::events::combinators::test_util::TIMESTAMP;
+
+
+  /// Create a trade event for the given symbol.
+  fn trade(symbol: &str) -> EventResult {
+    let trade = test_util::trade(symbol, "156.9799", 1, TIMESTAMP);
+    Ok(Ok(Event::Trade(trade)))
+  }
+
+
+  /// Check that the gate resolves once both subscribed symbols saw an
+  /// event.
+  #[test(tokio::test)]
+  async fn resolve_after_all_symbols() {
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("SPY".into())),
+      Subscription::Quotes(Stock::Symbol("AAPL".into())),
+    ];
+    let events = vec![trade("SPY"), trade("MSFT"), trade("SPY"), trade("AAPL")];
+    let (stream, gate) = wait_for_symbols(iter(events), subscriptions, Duration::from_secs(60));
+    pin_mut!(stream);
+    pin_mut!(gate);
+
+    for _ in 0..3 {
+      let _ = stream.next().await.unwrap();
+      assert_eq!((&mut gate).now_or_never(), None);
+    }
+
+    let _ = stream.next().await.unwrap();
+    assert_eq!(gate.await, Ok(()));
+  }
+
+  /// Check that a wildcard subscription is satisfied by any event.
+  #[test(tokio::test)]
+  async fn resolve_wildcard() {
+    let subscriptions = vec![Subscription::Trades(Stock::All)];
+    let (stream, gate) = wait_for_symbols(
+      iter(vec![trade("MSFT")]),
+      subscriptions,
+      Duration::from_secs(60),
+    );
+
+    let _ = stream.collect::<Vec<_>>().await;
+    assert_eq!(gate.await, Ok(()));
+  }
+
+  /// Check that the gate reports the symbols not seen once it timed
+  /// out.
+  #[test(tokio::test)]
+  async fn report_missing_on_timeout() {
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("SPY".into())),
+      Subscription::Trades(Stock::Symbol("UFO".into())),
+      Subscription::Trades(Stock::Symbol("AAPL".into())),
+      Subscription::Trades(Stock::All),
+    ];
+    let (stream, gate) = wait_for_symbols(
+      iter(vec![trade("SPY")]),
+      subscriptions,
+      Duration::from_millis(10),
+    );
+
+    let _ = stream.collect::<Vec<_>>().await;
+    let missing = gate.await.unwrap_err();
+    assert_eq!(missing, vec!["AAPL".to_string(), "UFO".to_string()]);
+  }
+}
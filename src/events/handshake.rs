@@ -1,6 +1,8 @@
 // Copyright (C) 2019-2021 Daniel Mueller <deso@posteo.net>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::time::Duration;
+
 use futures::Sink;
 use futures::SinkExt;
 use futures::Stream;
@@ -12,26 +14,39 @@ use tracing::error;
 use tracing::instrument;
 use tracing::trace;
 
+use tokio::time::timeout_at;
+use tokio::time::Instant;
+
+use serde::de::DeserializeOwned;
 use serde::Serialize;
-use serde_json::from_slice as from_json;
 use serde_json::to_string as to_json;
 
 use websocket_util::tungstenite::Error as WebSocketError;
 use websocket_util::tungstenite::Message as WebSocketMsg;
 
-use crate::Error;
+use crate::api_info::redact;
+use crate::events::stream::decode_messages;
+use crate::events::stream::process_message;
 use crate::events::stream::Code;
+use crate::events::stream::DisconnectReason;
+use crate::events::stream::LightQuote;
 use crate::events::stream::Message;
-use crate::events::stream::Messages;
+use crate::events::stream::Quote;
+use crate::events::stream::StreamConfig;
+use crate::events::subscription::normalize;
+use crate::events::Event;
 use crate::events::Subscription;
+use crate::Error;
 
 
 #[derive(Clone, Copy, Debug, Serialize)]
-enum Action {
+pub(crate) enum Action {
   #[serde(rename = "auth")]
   Authenticate,
   #[serde(rename = "subscribe")]
   Subscribe,
+  #[serde(rename = "unsubscribe")]
+  Unsubscribe,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -52,9 +67,11 @@ async fn auth<S>(stream: &mut S, api_key: String) -> Result<(), WebSocketError>
 where
   S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
 {
+  let redacted = Request::new(Action::Authenticate, redact(&api_key));
+  trace!(request = display(&to_json(&redacted).unwrap()));
+
   let request = Request::new(Action::Authenticate, api_key);
   let json = to_json(&request).unwrap();
-  trace!(request = display(&json));
 
   stream
     .send(WebSocketMsg::text(json))
@@ -66,37 +83,72 @@ where
 }
 
 /// Create a request to subscribe to events for certain assets.
-fn make_subscribe_request<I>(subscriptions: I) -> Result<(Request, usize), Error>
+///
+/// Besides the request, the subscriptions expected to be confirmed are
+/// reported back. Subscriptions made redundant by others (e.g., ones
+/// for a specific stock, when all stocks of the same type are
+/// subscribed to as well) are not part of the request and are not
+/// expected to be confirmed.
+fn make_subscribe_request<I>(subscriptions: I) -> Result<(Request, Vec<String>), Error>
 where
   I: IntoIterator<Item = Subscription>,
 {
-  let mut iter = subscriptions.into_iter();
-  let first = iter
-    .next()
-    .ok_or_else(|| {
-      Error::Str("failed to subscribe to event stream: no subscriptions supplied".into())
-    })?
-    .to_string();
-
-  let (subscriptions, count) = iter.fold((first, 1), |(mut subs, mut cnt), sub| {
-    subs = subs + "," + &sub.to_string();
-    cnt += 1;
-    (subs, cnt)
-  });
-  debug!(subscriptions = display(&subscriptions));
-
-  let request = Request::new(Action::Subscribe, subscriptions);
-  Ok((request, count))
+  let mut subscriptions = normalize(subscriptions);
+  // Sort subscriptions to make the request independent of the order in
+  // which they were provided.
+  let () = subscriptions.sort();
+  let subscriptions = subscriptions
+    .into_iter()
+    .map(|sub| sub.to_string())
+    .collect::<Vec<_>>();
+
+  if subscriptions.is_empty() {
+    return Err(Error::Str(
+      "failed to subscribe to event stream: no subscriptions supplied".into(),
+    ))
+  }
+
+  let params = subscriptions.join(",");
+  debug!(subscriptions = display(&params));
+
+  let request = Request::new(Action::Subscribe, params);
+  Ok((request, subscriptions))
+}
+
+
+/// Create the JSON encoded request for changing subscriptions of an
+/// already established stream.
+///
+/// `None` is returned if no subscriptions are provided.
+pub(crate) fn make_change_request<I>(action: Action, subscriptions: I) -> Option<String>
+where
+  I: IntoIterator<Item = Subscription>,
+{
+  let mut subscriptions = normalize(subscriptions);
+  if subscriptions.is_empty() {
+    return None
+  }
+  let () = subscriptions.sort();
+
+  let params = subscriptions
+    .iter()
+    .map(ToString::to_string)
+    .collect::<Vec<_>>()
+    .join(",");
+  debug!(action = debug(action), subscriptions = display(&params));
+
+  let request = Request::new(action, params);
+  Some(to_json(&request).unwrap())
 }
 
 
 /// Subscribe to the given subscriptions.
-async fn subscribe_stocks<S, I>(stream: &mut S, subscriptions: I) -> Result<usize, Error>
+async fn subscribe_stocks<S, I>(stream: &mut S, subscriptions: I) -> Result<Vec<String>, Error>
 where
   S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
   I: IntoIterator<Item = Subscription>,
 {
-  let (request, count) = make_subscribe_request(subscriptions)?;
+  let (request, subscriptions) = make_subscribe_request(subscriptions)?;
   let json = to_json(&request).unwrap();
   trace!(request = display(&json));
 
@@ -108,7 +160,20 @@ where
     })
     .await?;
 
-  Ok(count)
+  Ok(subscriptions)
+}
+
+
+/// Mark the confirmation described by the given status message as
+/// received, removing it from the list of pending ones.
+fn confirm(pending: &mut Vec<String>, message: &str) {
+  let idx = message
+    .strip_prefix("subscribed to: ")
+    .and_then(|subscription| pending.iter().position(|pending| pending == subscription))
+    // If we can't tell what got confirmed we just account for it, in
+    // order of expectation.
+    .unwrap_or(0);
+  let _ = pending.remove(idx);
 }
 
 
@@ -116,118 +181,388 @@ where
 ///
 /// Note that because Polygon intermixes status messages with actual
 /// event data, we need to inspect messages received for whether they
-/// are actual status indications and only evaluate those.
-#[allow(clippy::absurd_extreme_comparisons, clippy::single_match)]
-fn check_responses(
+/// are actual status indications and only evaluate those. Event data
+/// is retained in `early`, if provided, and dropped otherwise. Quotes
+/// are decoded as `Q`.
+fn check_responses<Q>(
   msg: &[u8],
   expected: Code,
-  mut count: usize,
+  pending: &mut Vec<String>,
   operation: &str,
-) -> Result<usize, Error> {
-  debug_assert!(count > 0, "{}", count);
+  mut early: Option<&mut Vec<Event>>,
+) -> Result<(), Error>
+where
+  Q: DeserializeOwned + Into<Event>,
+{
+  debug_assert!(!pending.is_empty());
 
-  let messages = from_json::<Messages>(msg)?;
+  let messages = decode_messages::<Q>(msg)?;
   for message in messages {
     match message {
+      // Once everything is confirmed we are only interested in the
+      // remaining event data.
+      Message::Status(..) if pending.is_empty() => (),
       Message::Status(status) => {
+        if status.code == Code::Disconnected {
+          return Err(Error::Disconnected(DisconnectReason::from_message(
+            &status.message,
+          )))
+        }
+        if status.code == Code::AuthFailure {
+          return Err(Error::AuthenticationFailed(status.message))
+        }
         if status.code != expected {
           let err = format!("{} not successful: {}", operation, status.message);
           return Err(Error::Str(err.into()))
         }
-        count -= 1;
-
-        if count <= 0 {
+        debug!(
+          operation = display(operation),
+          status = display(&status.message),
+          "received confirmation"
+        );
+        confirm(pending, &status.message);
+
+        if pending.is_empty() && early.is_none() {
           break
         }
       },
-      // If it's not a status we don't care about it here. In fact, we
-      // just drop it. That's fine, because clients can't rely on the
-      // fact that certain events are to be received after subscription
-      // (there is no guarantee when the request is received after all).
-      _ => (),
+      // If it's not a status we don't care about it here, unless asked
+      // to retain it. Dropping it is fine, because clients can't rely
+      // on the fact that certain events are to be received after
+      // subscription (there is no guarantee when the request is
+      // received after all).
+      message => {
+        if let Some(early) = early.as_deref_mut() {
+          if let Some(Ok(event)) = process_message(message) {
+            early.push(event)
+          }
+        }
+      },
     }
   }
-  Ok(count)
+  Ok(())
+}
+
+
+/// Create the error reported when the confirmations still `pending`
+/// did not arrive in time.
+///
+/// A partial confirmation hints at a limit being exceeded, which
+/// retrying won't fix. Hence, it is not reported as a timeout.
+fn timeout_error(operation: &str, pending: &[String], total: usize) -> Error {
+  let confirmed = total - pending.len();
+  if confirmed > 0 {
+    let err = format!(
+      "{} only partially confirmed ({} of {}), subscription limit likely exceeded; unconfirmed: {}",
+      operation,
+      confirmed,
+      total,
+      pending.join(","),
+    );
+    Error::Str(err.into())
+  } else {
+    let err = format!(
+      "{} not confirmed in time; unconfirmed: {}",
+      operation,
+      pending.join(","),
+    );
+    Error::Timeout(err.into())
+  }
 }
 
 
-/// Wait for a certain number of status codes to appear on the channel
-/// and evaluate them.
-async fn await_responses<S>(
+/// Wait for status messages confirming each of the `pending`
+/// confirmations to appear on the channel and evaluate them.
+///
+/// If `idle_timeout` is provided, waiting stops if no message at all is
+/// received for that long. If `total_timeout` is provided, waiting
+/// stops if not all confirmations were received within that time,
+/// irrespective of other messages. In both cases `false` is reported
+/// and `pending` contains the confirmations not received. Event data
+/// received in the process is retained in `early`, if provided, with
+/// quotes decoded as `Q`.
+async fn await_confirmations<S, Q>(
   stream: &mut S,
   expected: Code,
-  mut count: usize,
+  pending: &mut Vec<String>,
   operation: &str,
-) -> Result<(), Error>
+  idle_timeout: Option<Duration>,
+  total_timeout: Option<Duration>,
+  mut early: Option<&mut Vec<Event>>,
+) -> Result<bool, Error>
 where
   S: Stream<Item = Result<WebSocketMsg, WebSocketError>>,
   S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
+  Q: DeserializeOwned + Into<Event>,
 {
-  while count > 0 {
-    let result = stream
-      .next()
-      .await
-      .ok_or_else(|| Error::Str("websocket connection closed unexpectedly".into()))?;
-    let msg = result?;
-    trace!(message = display(&msg));
-
-    count = match msg {
-      WebSocketMsg::Text(text) => check_responses(text.as_bytes(), expected, count, operation)?,
-      WebSocketMsg::Binary(data) => check_responses(data.as_slice(), expected, count, operation)?,
-      WebSocketMsg::Ping(dat) => {
-        stream.send(WebSocketMsg::Pong(dat)).await?;
-        count
-      },
-      WebSocketMsg::Pong(..) => count,
-      WebSocketMsg::Close(..) => {
-        return Err(Error::Str(
-          "websocket connection closed unexpectedly".into(),
-        ))
-      },
+  let total = pending.len();
+  let total_deadline = total_timeout.map(|duration| Instant::now() + duration);
+  let result = async {
+    while !pending.is_empty() {
+      let idle_deadline = idle_timeout.map(|duration| Instant::now() + duration);
+      let deadline = match (idle_deadline, total_deadline) {
+        (Some(idle), Some(total)) => Some(idle.min(total)),
+        (idle, total) => idle.or(total),
+      };
+      let next = match deadline {
+        Some(deadline) => match timeout_at(deadline, stream.next()).await {
+          Ok(next) => next,
+          Err(..) => return Ok(false),
+        },
+        None => stream.next().await,
+      };
+      let result =
+        next.ok_or_else(|| Error::Str("websocket connection closed unexpectedly".into()))?;
+      let msg = result?;
+      trace!(message = display(&msg));
+
+      match msg {
+        WebSocketMsg::Text(text) => check_responses::<Q>(
+          text.as_bytes(),
+          expected,
+          pending,
+          operation,
+          early.as_deref_mut(),
+        )?,
+        WebSocketMsg::Binary(data) => check_responses::<Q>(
+          data.as_slice(),
+          expected,
+          pending,
+          operation,
+          early.as_deref_mut(),
+        )?,
+        WebSocketMsg::Ping(dat) => stream.send(WebSocketMsg::Pong(dat)).await?,
+        WebSocketMsg::Pong(..) => (),
+        WebSocketMsg::Close(..) => {
+          return Err(Error::Str(
+            "websocket connection closed unexpectedly".into(),
+          ))
+        },
+      }
     }
+    Ok(true)
+  }
+  .await;
+
+  debug!(
+    operation = display(operation),
+    confirmed = total - pending.len(),
+    expected = total,
+    "confirmations received"
+  );
+  result
+}
+
+
+/// Wait for status messages confirming each of the `pending`
+/// confirmations, failing if not all of them are received in time.
+///
+/// See `await_confirmations` for details.
+async fn await_responses<S, Q>(
+  stream: &mut S,
+  expected: Code,
+  mut pending: Vec<String>,
+  operation: &str,
+  idle_timeout: Option<Duration>,
+  total_timeout: Option<Duration>,
+  early: Option<&mut Vec<Event>>,
+) -> Result<(), Error>
+where
+  S: Stream<Item = Result<WebSocketMsg, WebSocketError>>,
+  S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
+  Q: DeserializeOwned + Into<Event>,
+{
+  let total = pending.len();
+  let confirmed = await_confirmations::<_, Q>(
+    stream,
+    expected,
+    &mut pending,
+    operation,
+    idle_timeout,
+    total_timeout,
+    early,
+  )
+  .await?;
+
+  if confirmed {
+    Ok(())
+  } else {
+    Err(timeout_error(operation, &pending, total))
   }
-  Ok(())
 }
 
 
-#[instrument(level = "trace", skip(stream, api_key))]
-async fn authenticate<S>(stream: &mut S, api_key: String) -> Result<(), Error>
+#[instrument(level = "trace", skip(stream, api_key, config))]
+async fn authenticate<S>(
+  stream: &mut S,
+  api_key: String,
+  config: &StreamConfig,
+) -> Result<(), Error>
 where
   S: Stream<Item = Result<WebSocketMsg, WebSocketError>>,
   S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
 {
+  let operation = "authentication";
+  let pending = vec![operation.to_string()];
+
   auth(stream, api_key).await?;
-  await_responses(stream, Code::AuthSuccess, 1, "authentication").await?;
+  await_responses::<_, Quote>(
+    stream,
+    Code::AuthSuccess,
+    pending,
+    operation,
+    config.confirmation_timeout,
+    None,
+    None,
+  )
+  .await?;
   Ok(())
 }
 
 
-#[instrument(level = "trace", skip(stream, subscriptions))]
-async fn subscribe<S, I>(stream: &mut S, subscriptions: I) -> Result<(), Error>
+/// Subscribe to the given subscriptions.
+///
+/// Events received before all subscriptions were confirmed are
+/// reported if `StreamConfig::deliver_early_events` is set.
+#[instrument(level = "trace", skip(stream, subscriptions, config))]
+pub(crate) async fn subscribe<S, I>(
+  stream: &mut S,
+  subscriptions: I,
+  config: &StreamConfig,
+) -> Result<Vec<Event>, Error>
 where
   S: Stream<Item = Result<WebSocketMsg, WebSocketError>>,
   S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
   I: IntoIterator<Item = Subscription>,
 {
-  let count = subscribe_stocks(stream, subscriptions).await?;
-  await_responses(stream, Code::Success, count, "subscription").await?;
-  Ok(())
+  let pending = subscribe_stocks(stream, subscriptions).await?;
+  if config.light_quotes {
+    await_subscriptions::<_, LightQuote>(stream, pending, config).await
+  } else {
+    await_subscriptions::<_, Quote>(stream, pending, config).await
+  }
+}
+
+
+/// Await the confirmation of the `pending` subscriptions, decoding
+/// quotes received in the meantime as `Q`.
+async fn await_subscriptions<S, Q>(
+  stream: &mut S,
+  pending: Vec<String>,
+  config: &StreamConfig,
+) -> Result<Vec<Event>, Error>
+where
+  S: Stream<Item = Result<WebSocketMsg, WebSocketError>>,
+  S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
+  Q: DeserializeOwned + Into<Event>,
+{
+  let mut early = Vec::new();
+  await_responses::<_, Q>(
+    stream,
+    Code::Success,
+    pending,
+    "subscription",
+    config.confirmation_timeout,
+    config.subscription_timeout,
+    if config.deliver_early_events {
+      Some(&mut early)
+    } else {
+      None
+    },
+  )
+  .await?;
+  Ok(early)
+}
+
+
+/// Subscribe to the given subscriptions, reporting the ones Polygon
+/// confirmed.
+///
+/// Contrary to `subscribe`, running into a timeout after only some of
+/// the subscriptions were confirmed is not an error. Rather, the
+/// confirmed subset is reported. Events received are dropped.
+pub(crate) async fn subscribe_confirmed<S>(
+  stream: &mut S,
+  subscriptions: &[Subscription],
+  config: &StreamConfig,
+) -> Result<Vec<Subscription>, Error>
+where
+  S: Stream<Item = Result<WebSocketMsg, WebSocketError>>,
+  S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
+{
+  let operation = "subscription";
+  let mut pending = subscribe_stocks(stream, subscriptions.iter().cloned()).await?;
+  let total = pending.len();
+  let confirmed = await_confirmations::<_, Quote>(
+    stream,
+    Code::Success,
+    &mut pending,
+    operation,
+    config.confirmation_timeout,
+    config.subscription_timeout,
+    None,
+  )
+  .await?;
+
+  if !confirmed && pending.len() == total {
+    return Err(timeout_error(operation, &pending, total))
+  }
+
+  let confirmed = subscriptions
+    .iter()
+    .filter(|subscription| !pending.contains(&subscription.to_string()))
+    .cloned()
+    .collect();
+  Ok(confirmed)
 }
 
 
 /// Authenticate with and subscribe to Polygon ticker events.
-pub async fn handshake<S, I>(stream: &mut S, api_key: String, subscriptions: I) -> Result<(), Error>
+///
+/// Events received before all subscriptions were confirmed are
+/// reported if `StreamConfig::deliver_early_events` is set.
+pub async fn handshake<S, I>(
+  stream: &mut S,
+  api_key: String,
+  subscriptions: I,
+  config: &StreamConfig,
+) -> Result<Vec<Event>, Error>
 where
   S: Stream<Item = Result<WebSocketMsg, WebSocketError>>,
   S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
   I: IntoIterator<Item = Subscription>,
 {
-  // Initial confirmation of connection.
-  await_responses(stream, Code::Connected, 1, "connection").await?;
+  setup(stream, api_key, config).await?;
+  subscribe(stream, subscriptions, config).await
+}
 
-  authenticate(stream, api_key).await?;
-  subscribe(stream, subscriptions).await?;
-  Ok(())
+
+/// Await the confirmation of the connection and authenticate with
+/// Polygon, without subscribing to any events.
+pub(crate) async fn setup<S>(
+  stream: &mut S,
+  api_key: String,
+  config: &StreamConfig,
+) -> Result<(), Error>
+where
+  S: Stream<Item = Result<WebSocketMsg, WebSocketError>>,
+  S: Sink<WebSocketMsg, Error = WebSocketError> + Unpin,
+{
+  // Initial confirmation of connection.
+  let operation = "connection";
+  let pending = vec![operation.to_string()];
+  await_responses::<_, Quote>(
+    stream,
+    Code::Connected,
+    pending,
+    operation,
+    config.confirmation_timeout,
+    None,
+    None,
+  )
+  .await?;
+
+  authenticate(stream, api_key, config).await
 }
 
 
@@ -235,12 +570,38 @@ where
 mod tests {
   use super::*;
 
+  use std::collections::HashSet;
+  use std::io::Result as IoResult;
+  use std::io::Write;
+  use std::sync::Arc;
+  use std::sync::Mutex;
+
   use serde_json::from_str as from_json;
   use serde_json::to_string as to_json;
 
+  use tracing::subscriber::with_default;
+  use tracing::Level;
+  use tracing_subscriber::fmt::Subscriber;
+
+  use crate::events::stream::Messages;
   use crate::events::Stock;
 
 
+  /// A writer capturing all data written in a shared buffer.
+  #[derive(Clone, Default)]
+  struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+  impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+      self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+      Ok(())
+    }
+  }
+
+
   #[test]
   fn encode_auth_request() {
     let api_key = "some-key".to_string();
@@ -258,8 +619,8 @@ mod tests {
       Subscription::Trades(Stock::Symbol("MSFT".into())),
       Subscription::Quotes(Stock::All),
     ];
-    let (request, count) = make_subscribe_request(subscriptions).unwrap();
-    assert_eq!(count, 2);
+    let (request, pending) = make_subscribe_request(subscriptions).unwrap();
+    assert_eq!(pending, vec!["T.MSFT", "Q.*"]);
 
     let expected = r#"{"action":"subscribe","params":"T.MSFT,Q.*"}"#;
     let json = to_json(&request).unwrap();
@@ -267,6 +628,110 @@ mod tests {
     assert_eq!(json, expected)
   }
 
+  /// Check that the subscription request is independent of the order
+  /// in which subscriptions are provided.
+  #[test]
+  fn encode_subscribe_request_sorted() {
+    let subscriptions = vec![
+      Subscription::Quotes(Stock::Symbol("SPY".into())),
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::Symbol("AAPL".into())),
+      Subscription::SecondAggregates(Stock::All),
+      Subscription::Trades(Stock::Symbol("AAPL".into())),
+    ]
+    .into_iter()
+    .collect::<HashSet<_>>();
+    let (request, pending) = make_subscribe_request(subscriptions).unwrap();
+    assert_eq!(pending, vec!["A.*", "T.AAPL", "T.MSFT", "Q.AAPL", "Q.SPY"]);
+
+    let expected = r#"{"action":"subscribe","params":"A.*,T.AAPL,T.MSFT,Q.AAPL,Q.SPY"}"#;
+    let json = to_json(&request).unwrap();
+
+    assert_eq!(json, expected)
+  }
+
+  #[test]
+  fn encode_subscribe_request_redundant() {
+    let subscriptions = vec![
+      Subscription::Quotes(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+      Subscription::Quotes(Stock::Symbol("AAPL".into())),
+    ];
+    let (request, pending) = make_subscribe_request(subscriptions).unwrap();
+    assert_eq!(pending, vec!["Q.*"]);
+
+    let expected = r#"{"action":"subscribe","params":"Q.*"}"#;
+    let json = to_json(&request).unwrap();
+
+    assert_eq!(json, expected)
+  }
+
+  #[test]
+  fn encode_fmv_subscribe_request() {
+    let subscriptions = vec![Subscription::Fmv(Stock::Symbol("AAPL".into()))];
+    let (request, pending) = make_subscribe_request(subscriptions).unwrap();
+    assert_eq!(pending, vec!["FMV.AAPL"]);
+
+    let expected = r#"{"action":"subscribe","params":"FMV.AAPL"}"#;
+    let json = to_json(&request).unwrap();
+
+    assert_eq!(json, expected)
+  }
+
+  /// Check that we log each subscription confirmation as it arrives.
+  #[test]
+  fn log_subscription_confirmations() {
+    let json = r#"[
+      {"ev":"status","status":"success","message":"subscribed to: T.MSFT"},
+      {"ev":"status","status":"success","message":"subscribed to: Q.*"}
+    ]"#;
+
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = Subscriber::builder()
+      .with_max_level(Level::DEBUG)
+      .with_ansi(false)
+      .with_writer(move || writer.clone())
+      .finish();
+
+    let mut pending = vec!["T.MSFT".to_string(), "Q.*".to_string()];
+    with_default(subscriber, || {
+      check_responses::<Quote>(
+        json.as_bytes(),
+        Code::Success,
+        &mut pending,
+        "subscription",
+        None,
+      )
+      .unwrap()
+    });
+    assert!(pending.is_empty(), "{:?}", pending);
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines = output
+      .lines()
+      .filter(|line| line.contains("received confirmation"))
+      .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2, "{}", output);
+    assert!(lines[0].contains("operation=subscription"), "{}", lines[0]);
+    assert!(lines[0].contains("status=subscribed to: T.MSFT"), "{}", lines[0]);
+    assert!(lines[1].contains("status=subscribed to: Q.*"), "{}", lines[1]);
+  }
+
+  #[test]
+  fn encode_unsubscribe_request() {
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::Symbol("AAPL".into())),
+    ];
+    let json = make_change_request(Action::Unsubscribe, subscriptions).unwrap();
+    let expected = r#"{"action":"unsubscribe","params":"T.MSFT,Q.AAPL"}"#;
+    assert_eq!(json, expected);
+
+    assert_eq!(make_change_request(Action::Subscribe, Vec::new()), None);
+  }
+
   #[test]
   fn decode_auth_response() {
     let json = r#"[{"ev":"status","status":"success","message":"authenticated"}]"#;
@@ -302,4 +767,29 @@ mod tests {
     assert_eq!(status.code, Code::Success);
     assert_eq!(status.message, "subscribed to: T.MSFT".to_string());
   }
+
+  /// Check that we report the reason for Polygon closing the
+  /// connection during setup.
+  #[test]
+  fn decode_disconnect_response() {
+    let json =
+      r#"[{"ev":"status","status":"disconnected","message":"Reason: Max connections reached"}]"#;
+    let mut pending = vec!["connection".to_string()];
+    let err =
+      check_responses::<Quote>(json.as_bytes(), Code::Connected, &mut pending, "connection", None)
+        .unwrap_err();
+    match err {
+      Error::Disconnected(reason) => assert_eq!(reason, DisconnectReason::MaxConnections),
+      err => panic!("unexpected error: {:?}", err),
+    }
+
+    let json = r#"[{"ev":"status","status":"disconnected","message":"Reason: Bye"}]"#;
+    let err =
+      check_responses::<Quote>(json.as_bytes(), Code::Connected, &mut pending, "connection", None)
+        .unwrap_err();
+    match err {
+      Error::Disconnected(reason) => assert_eq!(reason, DisconnectReason::Other("Bye".into())),
+      err => panic!("unexpected error: {:?}", err),
+    }
+  }
 }
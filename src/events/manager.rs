@@ -0,0 +1,455 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+
+use tracing::debug;
+use tracing::warn;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::events::Event;
+use crate::events::Stock;
+use crate::events::Subscription;
+use crate::events::SubscriptionHandle;
+use crate::Error;
+
+
+/// Check whether the given event is covered by a subscription.
+fn matches(subscription: &Subscription, event: &Event) -> bool {
+  let stock = match (subscription, event) {
+    (Subscription::SecondAggregates(stock), Event::SecondAggregate(..))
+    | (Subscription::MinuteAggregates(stock), Event::MinuteAggregate(..))
+    | (Subscription::Trades(stock), Event::Trade(..))
+    | (Subscription::Quotes(stock), Event::Quote(..) | Event::LightQuote(..))
+    | (Subscription::Fmv(stock), Event::FairMarketValue(..)) => stock,
+    _ => return false,
+  };
+
+  match stock {
+    Stock::All => true,
+    Stock::Symbol(symbol) => symbol == event.symbol(),
+  }
+}
+
+
+/// The state kept for a single consumer.
+#[derive(Debug)]
+struct ConsumerState {
+  /// The subscriptions the consumer is interested in.
+  interests: HashSet<Subscription>,
+  /// The channel over which to send events to the consumer.
+  sender: UnboundedSender<Event>,
+}
+
+
+/// State shared between the manager, the consumers, and the task
+/// driving the stream.
+#[derive(Debug, Default)]
+struct State {
+  /// The ID to assign to the next consumer.
+  next_id: usize,
+  /// All active consumers.
+  consumers: HashMap<usize, ConsumerState>,
+  /// The number of consumers interested in each subscription.
+  counts: HashMap<Subscription, usize>,
+}
+
+impl State {
+  /// Register interest of a consumer in the given subscriptions,
+  /// returning the ones that are now needed and were not before.
+  fn acquire<S>(&mut self, id: usize, subscriptions: S) -> Vec<Subscription>
+  where
+    S: IntoIterator<Item = Subscription>,
+  {
+    let consumer = match self.consumers.get_mut(&id) {
+      Some(consumer) => consumer,
+      None => return Vec::new(),
+    };
+
+    let mut added = Vec::new();
+    for subscription in subscriptions {
+      if consumer.interests.insert(subscription.clone()) {
+        let count = self.counts.entry(subscription.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+          added.push(subscription);
+        }
+      }
+    }
+    added
+  }
+
+  /// Release interest of a consumer in the given subscriptions,
+  /// returning the ones no longer needed by anybody.
+  fn release<S>(&mut self, id: usize, subscriptions: S) -> Vec<Subscription>
+  where
+    S: IntoIterator<Item = Subscription>,
+  {
+    let consumer = match self.consumers.get_mut(&id) {
+      Some(consumer) => consumer,
+      None => return Vec::new(),
+    };
+
+    let mut removed = Vec::new();
+    for subscription in subscriptions {
+      if consumer.interests.remove(&subscription) {
+        if let Some(count) = self.counts.get_mut(&subscription) {
+          *count -= 1;
+          if *count == 0 {
+            let _ = self.counts.remove(&subscription);
+            removed.push(subscription);
+          }
+        }
+      }
+    }
+    removed
+  }
+
+  /// Hand out an event to all consumers interested in it.
+  fn dispatch(&mut self, event: &Event) {
+    for consumer in self.consumers.values() {
+      if consumer
+        .interests
+        .iter()
+        .any(|subscription| matches(subscription, event))
+      {
+        // The consumer may be in the process of being dropped, in
+        // which case we just skip over it.
+        let _ = consumer.sender.send(event.clone());
+      }
+    }
+  }
+}
+
+
+/// A manager sharing a single event stream between multiple
+/// consumers.
+///
+/// Each `Consumer` subscribes to the events it is interested in and
+/// only receives those. Subscriptions are reference counted: Polygon
+/// is asked to subscribe to an event once the first consumer is
+/// interested in it and to unsubscribe once the last consumer
+/// interested in it unsubscribed or got dropped.
+#[derive(Clone, Debug)]
+pub struct SubscriptionManager {
+  /// The state shared with consumers and the driving task.
+  state: Arc<Mutex<State>>,
+  /// The handle used for changing subscriptions.
+  handle: SubscriptionHandle,
+}
+
+impl SubscriptionManager {
+  /// Create a new `SubscriptionManager` using the provided stream and
+  /// associated handle, as created by `stream_with_handle`.
+  ///
+  /// Besides the manager, a future driving the stream is returned. It
+  /// has to be polled (e.g., by spawning it as a task) for consumers to
+  /// receive events and for subscription changes to take effect. The
+  /// future resolves once the stream ended, at which point consumers
+  /// stop receiving events.
+  pub fn new<S>(
+    stream: S,
+    handle: SubscriptionHandle,
+  ) -> (Self, impl Future<Output = Result<(), WebSocketError>>)
+  where
+    S: Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+  {
+    let state = Arc::new(Mutex::new(State::default()));
+    let manager = Self {
+      state: state.clone(),
+      handle,
+    };
+
+    let driver = async move {
+      let mut stream = Box::pin(stream);
+      while let Some(result) = stream.next().await {
+        match result? {
+          Ok(event) => state.lock().unwrap().dispatch(&event),
+          Err(err) => warn!("failed to parse event: {}", err),
+        }
+      }
+
+      // Forget about all consumers and, by extension, drop their
+      // senders, so that they learn that the stream ended.
+      let mut state = state.lock().unwrap();
+      state.consumers.clear();
+      state.counts.clear();
+      Ok(())
+    };
+
+    (manager, driver)
+  }
+
+  /// Create a new consumer, initially not subscribed to any events.
+  pub fn consumer(&self) -> Consumer {
+    let (sender, receiver) = unbounded_channel();
+    let consumer = ConsumerState {
+      interests: HashSet::new(),
+      sender,
+    };
+
+    let mut state = self.state.lock().unwrap();
+    let id = state.next_id;
+    state.next_id += 1;
+    let _ = state.consumers.insert(id, consumer);
+
+    Consumer {
+      id,
+      manager: self.clone(),
+      receiver,
+    }
+  }
+}
+
+
+/// A consumer of events shared through a `SubscriptionManager`.
+///
+/// A `Consumer` is a `Stream` of the events it subscribed to. Dropping
+/// it releases all its subscriptions.
+#[derive(Debug)]
+pub struct Consumer {
+  /// The consumer's ID.
+  id: usize,
+  /// The manager this consumer belongs to.
+  manager: SubscriptionManager,
+  /// The channel over which we receive events.
+  receiver: UnboundedReceiver<Event>,
+}
+
+impl Consumer {
+  /// Subscribe to the given events.
+  pub fn subscribe<S>(&self, subscriptions: S) -> Result<(), Error>
+  where
+    S: IntoIterator<Item = Subscription>,
+  {
+    let added = self
+      .manager
+      .state
+      .lock()
+      .unwrap()
+      .acquire(self.id, subscriptions);
+
+    if added.is_empty() {
+      Ok(())
+    } else {
+      debug!(subscriptions = debug(&added), "subscribing");
+      self.manager.handle.subscribe(added)
+    }
+  }
+
+  /// Unsubscribe from the given events.
+  pub fn unsubscribe<S>(&self, subscriptions: S) -> Result<(), Error>
+  where
+    S: IntoIterator<Item = Subscription>,
+  {
+    let removed = self
+      .manager
+      .state
+      .lock()
+      .unwrap()
+      .release(self.id, subscriptions);
+
+    if removed.is_empty() {
+      Ok(())
+    } else {
+      debug!(subscriptions = debug(&removed), "unsubscribing");
+      self.manager.handle.unsubscribe(removed)
+    }
+  }
+}
+
+impl Stream for Consumer {
+  type Item = Event;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.get_mut().receiver.poll_recv(cx)
+  }
+}
+
+impl Drop for Consumer {
+  fn drop(&mut self) {
+    let removed = {
+      let mut state = self
+        .manager
+        .state
+        .lock()
+        .map_err(|err| err.into_inner())
+        .unwrap_or_else(|err| err);
+
+      let interests = state
+        .consumers
+        .get(&self.id)
+        .map(|consumer| consumer.interests.iter().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+      let removed = state.release(self.id, interests);
+      let _ = state.consumers.remove(&self.id);
+      removed
+    };
+
+    if !removed.is_empty() {
+      debug!(subscriptions = debug(&removed), "unsubscribing");
+      // If the stream is gone already there is nothing left to
+      // unsubscribe from.
+      let _ = self.manager.handle.unsubscribe(removed);
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::SinkExt as _;
+
+  use test_log::test;
+
+  use tokio::spawn;
+
+  use tungstenite::tungstenite::Message as WebSocketMessage;
+
+  use url::Url;
+
+  use websocket_util::test::mock_server;
+  use websocket_util::test::WebSocketStream;
+
+  use crate::events::stream_with_handle;
+  use crate::events::StreamConfig;
+  use crate::ApiInfo;
+
+
+  const CONNECTED_MSG: &str =
+    r#"[{"ev":"status","status":"connected","message":"Connected Successfully"}]"#;
+  const AUTH_REQ: &str = r#"{"action":"auth","params":"USER12345678"}"#;
+  const AUTH_RESP: &str = r#"[{"ev":"status","status":"auth_success","message":"authenticated"}]"#;
+  const SUB_REQ: &str = r#"{"action":"subscribe","params":"T.MSFT"}"#;
+  const SUB_RESP: &str =
+    r#"[{"ev":"status","status":"success","message":"subscribed to: T.MSFT"}]"#;
+  const UNSUB_REQ: &str = r#"{"action":"unsubscribe","params":"T.MSFT"}"#;
+  const MSFT_TRADE_MSG: &str = {
+    r#"[{"ev":"T","sym":"MSFT","i":8310,"x":4,"p":156.9799,"s":3,"c":[37],"t":1577818283019,"z":3}]"#
+  };
+
+
+  /// Check that events are routed to the interested consumers only.
+  #[test]
+  fn dispatch_events() {
+    let mut state = State::default();
+    let (trades, mut trade_receiver) = unbounded_channel();
+    let (quotes, mut quote_receiver) = unbounded_channel();
+    let _ = state.consumers.insert(
+      0,
+      ConsumerState {
+        interests: HashSet::new(),
+        sender: trades,
+      },
+    );
+    let _ = state.consumers.insert(
+      1,
+      ConsumerState {
+        interests: HashSet::new(),
+        sender: quotes,
+      },
+    );
+
+    let msft = Subscription::Trades(Stock::Symbol("MSFT".into()));
+    assert_eq!(state.acquire(0, vec![msft.clone()]), vec![msft.clone()]);
+    assert_eq!(state.acquire(1, vec![msft.clone()]), Vec::new());
+    assert_eq!(state.release(1, vec![msft]), Vec::new());
+
+    let all = Subscription::Quotes(Stock::All);
+    assert_eq!(state.acquire(1, vec![all.clone()]), vec![all]);
+
+    let json = &MSFT_TRADE_MSG[1..MSFT_TRADE_MSG.len() - 1];
+    let event = serde_json::from_str::<Event>(json).unwrap();
+    state.dispatch(&event);
+
+    assert_eq!(trade_receiver.try_recv().unwrap(), event);
+    assert!(quote_receiver.try_recv().is_err());
+  }
+
+  /// Check that we only unsubscribe once the last consumer interested
+  /// in a subscription is gone.
+  #[test(tokio::test)]
+  async fn unsubscribe_after_last_consumer() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      // Only a single subscription request should make it to us...
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await?;
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
+
+      // ... and a single unsubscription once both consumers are gone.
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(UNSUB_REQ.to_string()),
+      );
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let addr = mock_server(test).await;
+    let api_info = ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: "USER12345678".to_string(),
+    };
+
+    let (stream, handle) = stream_with_handle(api_info, Vec::new(), StreamConfig::default())
+      .await
+      .unwrap();
+    let (manager, driver) = SubscriptionManager::new(stream, handle);
+    let driver = spawn(driver);
+
+    let subscriptions = vec![Subscription::Trades(Stock::Symbol("MSFT".into()))];
+    let mut consumer1 = manager.consumer();
+    let mut consumer2 = manager.consumer();
+    consumer1.subscribe(subscriptions.clone()).unwrap();
+    consumer2.subscribe(subscriptions).unwrap();
+
+    let trade = consumer1.next().await.unwrap();
+    assert_eq!(trade.symbol(), "MSFT");
+    let trade = consumer2.next().await.unwrap();
+    assert_eq!(trade.symbol(), "MSFT");
+
+    drop(consumer1);
+    drop(consumer2);
+
+    let () = driver.await.unwrap().unwrap();
+  }
+}
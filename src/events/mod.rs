@@ -1,18 +1,91 @@
 // Copyright (C) 2020 Daniel Mueller <deso@posteo.net>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+#[cfg(not(target_arch = "wasm32"))]
+mod clock;
+#[cfg(not(target_arch = "wasm32"))]
+mod combinators;
 #[cfg(not(target_arch = "wasm32"))]
 mod handshake;
 #[cfg(not(target_arch = "wasm32"))]
+mod manager;
+#[cfg(not(target_arch = "wasm32"))]
+mod reconnect;
+#[cfg(not(target_arch = "wasm32"))]
+mod session;
+#[cfg(not(target_arch = "wasm32"))]
 mod stream;
 mod subscription;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use clock::{
+  Clock,
+  MockClock,
+  SystemClock,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use combinators::{
+  aggregates_only,
+  consolidate_aggregates,
+  count_bars,
+  cumulative_volume,
+  dedup_quotes,
+  drop_stale,
+  enumerate_events,
+  fill_aggregates,
+  join_quotes,
+  latest_per_symbol,
+  min_quote_size,
+  min_trade_size,
+  quotes_only,
+  route_by_symbol,
+  sample_aggregates,
+  spread_summaries,
+  throttle,
+  trades_only,
+  wait_for_symbols,
+  CumulativeVolume,
+  Numbering,
+  Overflow,
+  Sampling,
+  SpreadSummary,
+  TradeWithQuote,
+  VolumeSource,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use manager::{
+  Consumer,
+  SubscriptionManager,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use reconnect::{
+  stream_items,
+  stream_with_reconnect,
+  StreamEvent,
+  StreamItem,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use session::MarketSession;
 #[cfg(not(target_arch = "wasm32"))]
 pub use stream::{
+  check_subscriptions,
+  is_retryable,
   stream,
+  stream_with_config,
+  stream_with_handle,
   Aggregate,
+  ConnectionMetadata,
+  ConnectionTap,
+  DisconnectReason,
   Event,
+  FairMarketValue,
+  FrameTap,
+  LightQuote,
   Quote,
+  StartupJitter,
+  StreamConfig,
+  SubscriptionHandle,
+  Tape,
   Trade,
 };
 pub use subscription::Stock;
@@ -0,0 +1,688 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::pin::Pin;
+use std::time::SystemTime;
+
+use futures::future::ready;
+use futures::stream::unfold;
+use futures::Stream;
+use futures::StreamExt as _;
+
+use serde_json::Error as JsonError;
+
+use tokio::time::sleep;
+use tokio::time::timeout_at;
+use tokio::time::Instant;
+
+use tracing::debug;
+use tracing::error;
+use tracing::warn;
+
+use websocket_util::tungstenite::Error as WebSocketError;
+
+use crate::api_info::ApiInfo;
+use crate::error::Error;
+use crate::events::stream::raw_stream_with_config;
+use crate::events::stream::DisconnectReason;
+use crate::events::stream::Event;
+use crate::events::stream::ParseError;
+use crate::events::stream::StreamConfig;
+use crate::events::subscription::Subscription;
+
+
+/// The type of the stream of events of a single connection.
+type Events = Pin<Box<dyn Stream<Item = Result<Result<Event, ParseError>, WebSocketError>> + Send>>;
+
+
+/// An item reported by a stream that reconnects transparently,
+/// distinguishing data from control and lifecycle events.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum StreamItem {
+  /// An event.
+  Event(Event),
+  /// A frame could not be parsed.
+  ParseError {
+    /// The error encountered while parsing.
+    error: JsonError,
+    /// The raw contents of the frame.
+    raw: String,
+  },
+  /// The connection got lost. If the connection was closed by Polygon,
+  /// the reason it reported is contained. Otherwise the reason is
+  /// `DisconnectReason::Other`, describing the transport error or the
+  /// cause of a proactive reconnect.
+  Disconnected(DisconnectReason),
+  /// The connection was re-established and all subscriptions are
+  /// active again.
+  Reconnected,
+  /// The window in which events may have been missed. A `Gap`
+  /// immediately follows each `Reconnected` item.
+  Gap {
+    /// The time at which the connection loss was detected.
+    from: SystemTime,
+    /// The time at which the subscriptions were confirmed on the new
+    /// connection.
+    to: SystemTime,
+  },
+}
+
+
+/// An item reported by a stream that reconnects transparently.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum StreamEvent {
+  /// An event, or the error encountered while decoding it.
+  Event(Result<Event, JsonError>),
+  /// The connection got lost and was re-established. Events occurring
+  /// between `from` and `to` may have been missed.
+  Gap {
+    /// The time at which the connection loss was detected.
+    from: SystemTime,
+    /// The time at which the subscriptions were confirmed on the new
+    /// connection.
+    to: SystemTime,
+  },
+}
+
+
+/// The state of a reconnecting stream.
+struct State {
+  /// Information about the API to connect to.
+  api_info: ApiInfo,
+  /// The subscriptions to (re-)establish on each connection.
+  subscriptions: Vec<Subscription>,
+  /// The configuration for each connection.
+  config: StreamConfig,
+  /// The events of the current connection, if any.
+  events: Option<Events>,
+  /// The time at which to reconnect the current connection, if
+  /// reconnects are scheduled.
+  reconnect_at: Option<Instant>,
+  /// The number of events delivered on the current connection.
+  delivered: usize,
+  /// The time at which the current outage started, if any.
+  disconnected: Option<SystemTime>,
+  /// The gap to report next, if any.
+  gap: Option<StreamItem>,
+}
+
+impl State {
+  /// Connect to the Polygon service and subscribe to the configured
+  /// events.
+  async fn connect(&mut self) -> Result<Events, Error> {
+    let events = raw_stream_with_config(
+      self.api_info.clone(),
+      self.subscriptions.clone(),
+      self.config.clone(),
+    )
+    .await?;
+    self.reconnect_at = self
+      .config
+      .reconnect_interval
+      .map(|interval| Instant::now() + interval);
+    self.delivered = 0;
+    Ok(Box::pin(events))
+  }
+
+  /// Retrieve the next item, reconnecting as necessary.
+  ///
+  /// `None` is returned once reconnecting failed with a fatal error.
+  async fn next(&mut self) -> Option<StreamItem> {
+    if let Some(gap) = self.gap.take() {
+      return Some(gap)
+    }
+
+    loop {
+      if let Some(budget) = self.config.reconnect_after_events {
+        if self.events.is_some() && self.delivered >= budget.get() {
+          debug!("event budget exhausted; performing reconnect");
+          self.events = None;
+          self.disconnected = Some(SystemTime::now());
+          let reason = DisconnectReason::Other("event budget exhausted".to_string());
+          break Some(StreamItem::Disconnected(reason))
+        }
+      }
+
+      if let Some(events) = &mut self.events {
+        let next = match self.reconnect_at {
+          Some(at) => match timeout_at(at, events.next()).await {
+            Ok(next) => next,
+            Err(..) => {
+              debug!("performing scheduled reconnect");
+              self.events = None;
+              self.disconnected = Some(SystemTime::now());
+              let reason = DisconnectReason::Other("scheduled reconnect".to_string());
+              break Some(StreamItem::Disconnected(reason))
+            },
+          },
+          None => events.next().await,
+        };
+
+        let reason = match next {
+          Some(Ok(Ok(event))) => {
+            self.delivered += 1;
+            break Some(StreamItem::Event(event))
+          },
+          Some(Ok(Err(ParseError { error, raw }))) => {
+            break Some(StreamItem::ParseError { error, raw })
+          },
+          Some(Err(err)) => match DisconnectReason::from_error(&err) {
+            Some(reason) => {
+              warn!("connection closed by Polygon: {}", reason);
+              reason.clone()
+            },
+            None => {
+              warn!("connection failed: {}", err);
+              DisconnectReason::Other(err.to_string())
+            },
+          },
+          None => {
+            warn!("connection closed");
+            DisconnectReason::Other("connection closed".to_string())
+          },
+        };
+        self.events = None;
+        self.disconnected = Some(SystemTime::now());
+        break Some(StreamItem::Disconnected(reason))
+      }
+
+      match self.connect().await {
+        Ok(events) => {
+          let to = SystemTime::now();
+          let from = self.disconnected.take().unwrap_or(to);
+          debug!("reconnected");
+
+          self.events = Some(events);
+          self.gap = Some(StreamItem::Gap { from, to });
+          break Some(StreamItem::Reconnected)
+        },
+        Err(err) if !err.is_retryable() => {
+          error!("failed to reconnect: {}; giving up", err);
+          break None
+        },
+        Err(err) => {
+          warn!("failed to reconnect: {}", err);
+          sleep(self.config.reconnect_delay).await;
+        },
+      }
+    }
+  }
+}
+
+
+/// Subscribe to and stream events from the Polygon service,
+/// transparently reconnecting and re-subscribing once a connection is
+/// lost.
+///
+/// Each reconnect is reported as a `StreamEvent::Gap`, describing the
+/// window in which events may have been missed, so that consumers can
+/// invalidate state derived from it. Failed reconnection attempts are
+/// retried after `StreamConfig::reconnect_delay`, indefinitely, unless
+/// the error is fatal as per `Error::is_retryable`, in which case the
+/// stream ends. Only the initial connection attempt reports an error.
+pub async fn stream_with_reconnect<S>(
+  api_info: ApiInfo,
+  subscriptions: S,
+  config: StreamConfig,
+) -> Result<impl Stream<Item = StreamEvent>, Error>
+where
+  S: IntoIterator<Item = Subscription>,
+{
+  let stream = stream_items(api_info, subscriptions, config)
+    .await?
+    .filter_map(|item| {
+      let event = match item {
+        StreamItem::Event(event) => Some(StreamEvent::Event(Ok(event))),
+        StreamItem::ParseError { error, .. } => Some(StreamEvent::Event(Err(error))),
+        StreamItem::Gap { from, to } => Some(StreamEvent::Gap { from, to }),
+        StreamItem::Disconnected(..) | StreamItem::Reconnected => None,
+      };
+      ready(event)
+    });
+  Ok(stream)
+}
+
+
+/// Subscribe to and stream events from the Polygon service,
+/// transparently reconnecting and re-subscribing once a connection is
+/// lost, reporting `StreamItem` objects.
+///
+/// In contrast to `stream_with_reconnect`, connection losses and
+/// reconnects are reported explicitly, and frames that failed to parse
+/// are reported along with their raw contents. Each connection loss
+/// results in a `StreamItem::Disconnected`, followed by a
+/// `StreamItem::Reconnected` and a `StreamItem::Gap` once the
+/// connection has been re-established. The stream ends once
+/// reconnecting fails with an error that is not retryable.
+pub async fn stream_items<S>(
+  api_info: ApiInfo,
+  subscriptions: S,
+  config: StreamConfig,
+) -> Result<impl Stream<Item = StreamItem>, Error>
+where
+  S: IntoIterator<Item = Subscription>,
+{
+  let mut state = State {
+    api_info,
+    subscriptions: subscriptions.into_iter().collect(),
+    config,
+    events: None,
+    reconnect_at: None,
+    delivered: 0,
+    disconnected: None,
+    gap: None,
+  };
+  state.events = Some(state.connect().await?);
+
+  let stream = unfold(state, |mut state| async move {
+    let item = state.next().await?;
+    Some((item, state))
+  });
+  Ok(stream)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::future::Future;
+  use std::net::SocketAddr;
+  use std::num::NonZeroUsize;
+  use std::sync::atomic::AtomicUsize;
+  use std::sync::atomic::Ordering;
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use futures::SinkExt as _;
+
+  use test_log::test;
+
+  use tokio::net::TcpListener;
+  use tokio::spawn;
+
+  use tungstenite::accept_async;
+  use tungstenite::MaybeTlsStream;
+  use tungstenite::tungstenite::Message as WebSocketMessage;
+
+  use url::Url;
+
+  use websocket_util::test::WebSocketStream;
+
+  use crate::events::subscription::Stock;
+
+
+  const API_KEY: &str = "USER12345678";
+  const CONNECTED_MSG: &str =
+    r#"[{"ev":"status","status":"connected","message":"Connected Successfully"}]"#;
+  const SUB_REQ: &str = r#"{"action":"subscribe","params":"T.MSFT"}"#;
+  const AUTH_RESP: &str = r#"[{"ev":"status","status":"auth_success","message":"authenticated"}]"#;
+  const AUTH_FAILED_RESP: &str =
+    r#"[{"ev":"status","status":"auth_failed","message":"authentication failed"}]"#;
+  const SUB_RESP: &str = r#"[{"ev":"status","status":"success","message":"subscribed to: T.MSFT"}]"#;
+  const MSFT_TRADE_MSG: &str =
+    r#"[{"ev":"T","sym":"MSFT","i":8310,"x":4,"p":156.9799,"s":3,"t":1577818283019,"z":3}]"#;
+  const DISCONNECTED_MSG: &str =
+    r#"[{"ev":"status","status":"disconnected","message":"Reason: Max connections reached"}]"#;
+
+
+  /// Create a WebSocket server invoking `f` with the index of each
+  /// connection accepted.
+  async fn mock_server<F, R>(f: F) -> SocketAddr
+  where
+    F: Fn(usize, WebSocketStream) -> R + Send + 'static,
+    R: Future<Output = Result<(), WebSocketError>> + Send + 'static,
+  {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    spawn(async move {
+      for index in 0.. {
+        let (stream, _addr) = listener.accept().await.unwrap();
+        let stream = accept_async(MaybeTlsStream::Plain(stream))
+          .await
+          .unwrap();
+        spawn(f(index, stream));
+      }
+    });
+    addr
+  }
+
+  /// Handle the connection setup and subscription on the server side,
+  /// then send a trade.
+  async fn serve(stream: &mut WebSocketStream) -> Result<(), WebSocketError> {
+    stream
+      .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+      .await?;
+    let _auth = stream.next().await.unwrap()?;
+    stream
+      .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+      .await?;
+    let sub = stream.next().await.unwrap()?;
+    assert_eq!(sub, WebSocketMessage::Text(SUB_REQ.to_string()));
+    stream
+      .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+      .await?;
+    stream
+      .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+      .await?;
+    Ok(())
+  }
+
+
+  /// Create the API information for connecting to the mock server at
+  /// the given address.
+  fn api_info(addr: SocketAddr) -> ApiInfo {
+    ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: API_KEY.to_string(),
+    }
+  }
+
+  /// Create a stream of `StreamItem` objects connected to the mock
+  /// server at the given address.
+  async fn mock_items(addr: SocketAddr) -> Pin<Box<impl Stream<Item = StreamItem>>> {
+    let subscriptions = vec![Subscription::Trades(Stock::Symbol("MSFT".into()))];
+    let config = StreamConfig {
+      reconnect_delay: Duration::from_millis(10),
+      ..Default::default()
+    };
+
+    Box::pin(
+      stream_items(api_info(addr), subscriptions, config)
+        .await
+        .unwrap(),
+    )
+  }
+
+  /// Check that the reconnect scenario is reported by means of the
+  /// corresponding `StreamItem` variants.
+  #[test(tokio::test)]
+  async fn items_reconnect() {
+    let addr = mock_server(|index, mut stream| async move {
+      serve(&mut stream).await?;
+      if index == 0 {
+        stream.close(None).await
+      } else {
+        let _ = stream.next().await;
+        Ok(())
+      }
+    })
+    .await;
+
+    let mut stream = mock_items(addr).await;
+    match stream.next().await.unwrap() {
+      StreamItem::Event(Event::Trade(trade)) => assert_eq!(trade.symbol, "MSFT"),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Disconnected(DisconnectReason::Other(..)) => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Reconnected => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Gap { from, to } => assert!(from <= to, "{:?} {:?}", from, to),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Event(Event::Trade(trade)) => assert_eq!(trade.symbol, "MSFT"),
+      item => panic!("unexpected item: {:?}", item),
+    }
+  }
+
+  /// Check that parse errors are reported with the raw frame and that
+  /// Polygon initiated disconnects carry the reason reported.
+  #[test(tokio::test)]
+  async fn items_parse_error_and_disconnect() {
+    let addr = mock_server(|index, mut stream| async move {
+      serve(&mut stream).await?;
+      if index == 0 {
+        stream
+          .send(WebSocketMessage::Text("[{invalid".to_string()))
+          .await?;
+        stream
+          .send(WebSocketMessage::Text(DISCONNECTED_MSG.to_string()))
+          .await?;
+      }
+      let _ = stream.next().await;
+      Ok(())
+    })
+    .await;
+
+    let mut stream = mock_items(addr).await;
+    match stream.next().await.unwrap() {
+      StreamItem::Event(Event::Trade(..)) => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::ParseError { raw, .. } => assert_eq!(raw, "[{invalid"),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Disconnected(reason) => assert_eq!(reason, DisconnectReason::MaxConnections),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Reconnected => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Gap { .. } => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Event(Event::Trade(..)) => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+  }
+
+  /// Check that the stream ends once reconnecting fails with a fatal
+  /// error.
+  #[test(tokio::test)]
+  async fn items_end_on_fatal_error() {
+    let addr = mock_server(|index, mut stream| async move {
+      if index == 0 {
+        serve(&mut stream).await?;
+        stream.close(None).await
+      } else {
+        stream
+          .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+          .await?;
+        let _auth = stream.next().await.unwrap()?;
+        stream
+          .send(WebSocketMessage::Text(AUTH_FAILED_RESP.to_string()))
+          .await?;
+        let _ = stream.next().await;
+        Ok(())
+      }
+    })
+    .await;
+
+    let mut stream = mock_items(addr).await;
+    match stream.next().await.unwrap() {
+      StreamItem::Event(Event::Trade(..)) => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Disconnected(..) => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    assert!(stream.next().await.is_none());
+  }
+
+  /// Check that we reconnect on schedule, replaying all subscriptions
+  /// and reporting the gap in between.
+  #[test(tokio::test)]
+  async fn scheduled_reconnect() {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let addr = mock_server({
+      let connections = connections.clone();
+      move |_index, mut stream| {
+        let connections = connections.clone();
+        async move {
+          let _ = connections.fetch_add(1, Ordering::SeqCst);
+          // `serve` checks that the subscription is requested again.
+          serve(&mut stream).await?;
+          // Keep the connection open; only the client may close it.
+          let _ = stream.next().await;
+          Ok(())
+        }
+      }
+    })
+    .await;
+
+    let subscriptions = vec![Subscription::Trades(Stock::Symbol("MSFT".into()))];
+    let config = StreamConfig {
+      reconnect_interval: Some(Duration::from_millis(100)),
+      ..Default::default()
+    };
+    let mut stream = Box::pin(
+      stream_items(api_info(addr), subscriptions, config)
+        .await
+        .unwrap(),
+    );
+
+    match stream.next().await.unwrap() {
+      StreamItem::Event(Event::Trade(..)) => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Disconnected(reason) => {
+        assert_eq!(reason, DisconnectReason::Other("scheduled reconnect".into()))
+      },
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Reconnected => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Gap { from, to } => assert!(from <= to, "{:?} {:?}", from, to),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    match stream.next().await.unwrap() {
+      StreamItem::Event(Event::Trade(..)) => (),
+      item => panic!("unexpected item: {:?}", item),
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+  }
+
+  /// Check that we reconnect once a connection delivered the
+  /// configured number of events, with the count restarting on each
+  /// connection.
+  #[test(tokio::test)]
+  async fn event_budget_reconnect() {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let addr = mock_server({
+      let connections = connections.clone();
+      move |_index, mut stream| {
+        let connections = connections.clone();
+        async move {
+          let _ = connections.fetch_add(1, Ordering::SeqCst);
+          serve(&mut stream).await?;
+          stream
+            .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+            .await?;
+          stream
+            .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+            .await?;
+          let _ = stream.next().await;
+          Ok(())
+        }
+      }
+    })
+    .await;
+
+    let subscriptions = vec![Subscription::Trades(Stock::Symbol("MSFT".into()))];
+    let config = StreamConfig {
+      reconnect_after_events: NonZeroUsize::new(2),
+      ..Default::default()
+    };
+    let mut stream = Box::pin(
+      stream_items(api_info(addr), subscriptions, config)
+        .await
+        .unwrap(),
+    );
+
+    for _ in 0..2 {
+      for _ in 0..2 {
+        match stream.next().await.unwrap() {
+          StreamItem::Event(Event::Trade(..)) => (),
+          item => panic!("unexpected item: {:?}", item),
+        }
+      }
+      match stream.next().await.unwrap() {
+        StreamItem::Disconnected(reason) => {
+          assert_eq!(reason, DisconnectReason::Other("event budget exhausted".into()))
+        },
+        item => panic!("unexpected item: {:?}", item),
+      }
+      match stream.next().await.unwrap() {
+        StreamItem::Reconnected => (),
+        item => panic!("unexpected item: {:?}", item),
+      }
+      match stream.next().await.unwrap() {
+        StreamItem::Gap { from, to } => assert!(from <= to, "{:?} {:?}", from, to),
+        item => panic!("unexpected item: {:?}", item),
+      }
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 3);
+  }
+
+  /// Check that we reconnect once the connection is lost and report
+  /// the gap in between.
+  #[test(tokio::test)]
+  async fn reconnect_with_gap() {
+    let addr = mock_server(|index, mut stream| async move {
+      serve(&mut stream).await?;
+      if index == 0 {
+        // Drop the first connection after the first trade.
+        stream.close(None).await
+      } else {
+        // Keep subsequent connections open.
+        let _ = stream.next().await;
+        Ok(())
+      }
+    })
+    .await;
+
+    let subscriptions = vec![Subscription::Trades(Stock::Symbol("MSFT".into()))];
+    let config = StreamConfig {
+      reconnect_delay: Duration::from_millis(10),
+      ..Default::default()
+    };
+
+    let before = SystemTime::now();
+    let mut stream = Box::pin(
+      stream_with_reconnect(api_info(addr), subscriptions, config)
+        .await
+        .unwrap(),
+    );
+
+    match stream.next().await.unwrap() {
+      StreamEvent::Event(Ok(Event::Trade(trade))) => assert_eq!(trade.symbol, "MSFT"),
+      item => panic!("unexpected item: {:?}", item),
+    }
+
+    match stream.next().await.unwrap() {
+      StreamEvent::Gap { from, to } => {
+        assert!(before <= from, "{:?} {:?}", before, from);
+        assert!(from <= to, "{:?} {:?}", from, to);
+        assert!(to <= SystemTime::now());
+      },
+      item => panic!("unexpected item: {:?}", item),
+    }
+
+    // The subscription gets re-established on the new connection.
+    match stream.next().await.unwrap() {
+      StreamEvent::Event(Ok(Event::Trade(trade))) => assert_eq!(trade.symbol, "MSFT"),
+      item => panic!("unexpected item: {:?}", item),
+    }
+  }
+}
@@ -0,0 +1,105 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use chrono::DateTime;
+use chrono::Datelike as _;
+use chrono::Timelike as _;
+use chrono::Utc;
+use chrono::Weekday;
+
+use crate::tz::new_york_time;
+
+
+/// The trading session of the US equity markets a point in time falls
+/// into.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MarketSession {
+  /// The pre-market session, from 4:00 to 9:30 ET.
+  PreMarket,
+  /// The regular session, from 9:30 to 16:00 ET.
+  Regular,
+  /// The after-hours session, from 16:00 to 20:00 ET.
+  AfterHours,
+  /// Outside of all sessions, i.e., between 20:00 and 4:00 ET or on a
+  /// weekend.
+  Closed,
+}
+
+impl MarketSession {
+  /// Classify the provided point in time into a session, based on New
+  /// York exchange hours.
+  ///
+  /// Only weekends are recognized as days without trading. Exchange
+  /// holidays and early closes are not taken into account.
+  pub fn classify(time: &DateTime<Utc>) -> Self {
+    let local = new_york_time(time);
+    if matches!(local.weekday(), Weekday::Sat | Weekday::Sun) {
+      return MarketSession::Closed
+    }
+
+    match (local.hour(), local.minute()) {
+      (0..=3, _) => MarketSession::Closed,
+      (4..=8, _) | (9, 0..=29) => MarketSession::PreMarket,
+      (9..=15, _) => MarketSession::Regular,
+      (16..=19, _) => MarketSession::AfterHours,
+      _ => MarketSession::Closed,
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Parse an RFC 3339 time stamp.
+  fn time(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().into()
+  }
+
+
+  /// Check that we classify points in time during standard time.
+  #[test]
+  fn classify_standard_time() {
+    let sessions = [
+      ("2022-01-04T03:59:59-05:00", MarketSession::Closed),
+      ("2022-01-04T04:00:00-05:00", MarketSession::PreMarket),
+      ("2022-01-04T09:29:59-05:00", MarketSession::PreMarket),
+      ("2022-01-04T09:30:00-05:00", MarketSession::Regular),
+      ("2022-01-04T15:59:59-05:00", MarketSession::Regular),
+      ("2022-01-04T16:00:00-05:00", MarketSession::AfterHours),
+      ("2022-01-04T19:59:59-05:00", MarketSession::AfterHours),
+      ("2022-01-04T20:00:00-05:00", MarketSession::Closed),
+      ("2022-01-08T12:00:00-05:00", MarketSession::Closed),
+    ];
+
+    for (time_, session) in sessions {
+      assert_eq!(MarketSession::classify(&time(time_)), session, "{}", time_);
+    }
+  }
+
+  /// Check that we classify points in time during daylight saving
+  /// time, including around the transitions.
+  #[test]
+  fn classify_daylight_saving_time() {
+    let sessions = [
+      ("2022-07-12T04:00:00-04:00", MarketSession::PreMarket),
+      ("2022-07-12T09:30:00-04:00", MarketSession::Regular),
+      ("2022-07-12T16:00:00-04:00", MarketSession::AfterHours),
+      ("2022-07-12T20:00:00-04:00", MarketSession::Closed),
+      // The Monday after the switch to daylight saving time.
+      ("2022-03-14T09:30:00-04:00", MarketSession::Regular),
+      // The Friday before the switch to daylight saving time.
+      ("2022-03-11T09:29:59-05:00", MarketSession::PreMarket),
+      // The Friday before and the Monday after the switch back to
+      // standard time.
+      ("2022-11-04T15:59:59-04:00", MarketSession::Regular),
+      ("2022-11-07T16:00:00-05:00", MarketSession::AfterHours),
+    ];
+
+    for (time_, session) in sessions {
+      assert_eq!(MarketSession::classify(&time(time_)), session, "{}", time_);
+    }
+  }
+}
@@ -1,59 +1,221 @@
 // Copyright (C) 2019-2022 Daniel Mueller <deso@posteo.net>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::hash::BuildHasher as _;
+use std::hash::Hasher as _;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
 use chrono::serde::ts_milliseconds::deserialize as datetime_from_timestamp;
+use chrono::serde::ts_milliseconds::serialize as datetime_to_timestamp;
 use chrono::DateTime;
 use chrono::Utc;
 
+use futures::future::select;
+use futures::future::Either;
+use futures::pin_mut;
+use futures::stream::iter;
 use futures::stream::unfold;
+use futures::Sink;
+use futures::SinkExt as _;
 use futures::Stream;
 use futures::StreamExt;
 
+use http::header::USER_AGENT as USER_AGENT_HEADER;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::StatusCode;
+
 use num_decimal::Num;
 
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
 use serde_json::from_slice as from_json_slice;
-use serde_json::from_str as from_json_str;
 use serde_json::Error as JsonError;
 
+use thiserror::Error as ThisError;
+
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::sleep;
+
 use tracing::debug;
 use tracing::trace;
+use tracing::warn;
+
+use url::Url;
+
+use native_tls::TlsConnector;
 
 use tungstenite::connect_async;
+use tungstenite::connect_async_tls_with_config;
+use tungstenite::tungstenite::client::IntoClientRequest as _;
+use tungstenite::tungstenite::handshake::client::Response as UpgradeResponse;
+use tungstenite::tungstenite::error::TlsError;
+use tungstenite::tungstenite::Message as RawMessage;
+use tungstenite::Connector;
 
 use websocket_util::tungstenite::Error as WebSocketError;
 use websocket_util::wrap::Message as WebSocketMessage;
 use websocket_util::wrap::Wrapper;
 
 use crate::api_info::ApiInfo;
+use crate::de::num_from_json;
+use crate::de::opt_num_from_json;
+use crate::de::opt_string_from_json;
 use crate::error::Error;
 use crate::events::handshake::handshake;
+use crate::events::handshake::make_change_request;
+use crate::events::handshake::setup;
+use crate::events::handshake::subscribe;
+use crate::events::handshake::subscribe_confirmed;
+use crate::events::handshake::Action;
+use crate::events::session::MarketSession;
+use crate::events::subscription::normalize;
 use crate::events::subscription::Subscription;
+use crate::Str;
+use crate::USER_AGENT;
+
+
+/// The tape on which a security is reported to the consolidated feed.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Tape {
+  /// Tape A, covering NYSE listed securities.
+  Nyse,
+  /// Tape B, covering NYSE Arca, NYSE American, and other regionally
+  /// listed securities.
+  AmexRegional,
+  /// Tape C, covering Nasdaq listed securities.
+  Nasdaq,
+  /// A tape not known to this crate.
+  Unknown(u8),
+}
+
+impl Tape {
+  /// Retrieve a human readable name of the tape.
+  pub fn name(&self) -> &'static str {
+    match self {
+      Tape::Nyse => "NYSE",
+      Tape::AmexRegional => "AMEX/Regional",
+      Tape::Nasdaq => "Nasdaq",
+      Tape::Unknown(..) => "unknown",
+    }
+  }
+}
+
+impl From<u8> for Tape {
+  fn from(code: u8) -> Self {
+    match code {
+      1 => Tape::Nyse,
+      2 => Tape::AmexRegional,
+      3 => Tape::Nasdaq,
+      code => Tape::Unknown(code),
+    }
+  }
+}
+
+impl From<Tape> for u8 {
+  fn from(tape: Tape) -> Self {
+    match tape {
+      Tape::Nyse => 1,
+      Tape::AmexRegional => 2,
+      Tape::Nasdaq => 3,
+      Tape::Unknown(code) => code,
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Tape {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    u8::deserialize(deserializer).map(Tape::from)
+  }
+}
+
+impl Serialize for Tape {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    u8::from(*self).serialize(serializer)
+  }
+}
 
 
 /// A data point for a trade.
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Trade {
   /// The stock's symbol.
   #[serde(rename = "sym")]
   pub symbol: String,
+  /// The trade's ID, if reported.
+  #[serde(
+    rename = "i",
+    default,
+    deserialize_with = "opt_string_from_json",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub id: Option<String>,
   /// The exchange the trade occurred on.
   #[serde(rename = "x")]
   pub exchange: u64,
   /// The price.
-  #[serde(rename = "p")]
+  #[serde(rename = "p", deserialize_with = "num_from_json")]
   pub price: Num,
   /// The number of shares traded.
   #[serde(rename = "s")]
   pub quantity: u64,
+  /// The trade's condition codes.
+  ///
+  /// The `conditions` module provides predicates for interpreting
+  /// them.
+  #[serde(rename = "c", default, skip_serializing_if = "Vec::is_empty")]
+  pub conditions: Vec<u64>,
+  /// The tape the trade was reported on.
+  #[serde(rename = "z", default, skip_serializing_if = "Option::is_none")]
+  pub tape: Option<Tape>,
   /// The trade's timestamp.
-  #[serde(rename = "t", deserialize_with = "datetime_from_timestamp")]
+  #[serde(
+    rename = "t",
+    deserialize_with = "datetime_from_timestamp",
+    serialize_with = "datetime_to_timestamp"
+  )]
   pub timestamp: DateTime<Utc>,
 }
 
 
+impl Trade {
+  /// Retrieve the trade's timestamp as milliseconds since the Unix
+  /// epoch, as originally reported by Polygon.
+  pub fn timestamp_millis(&self) -> i64 {
+    self.timestamp.timestamp_millis()
+  }
+
+  /// Calculate the trade's notional value, i.e., the product of price
+  /// and quantity.
+  pub fn notional(&self) -> Num {
+    &self.price * Num::from(self.quantity)
+  }
+}
+
+
 /// A quote for a stock.
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Quote {
   /// The stock's symbol.
   #[serde(rename = "sym")]
@@ -62,7 +224,7 @@ pub struct Quote {
   #[serde(rename = "bx")]
   pub bid_exchange: u64,
   /// The bid price.
-  #[serde(rename = "bp")]
+  #[serde(rename = "bp", deserialize_with = "num_from_json")]
   pub bid_price: Num,
   /// The bid quantity
   #[serde(rename = "bs")]
@@ -71,11 +233,65 @@ pub struct Quote {
   #[serde(rename = "ax")]
   pub ask_exchange: u64,
   /// The ask price.
-  #[serde(rename = "ap")]
+  #[serde(rename = "ap", deserialize_with = "num_from_json")]
   pub ask_price: Num,
   /// The bid quantity
   #[serde(rename = "as")]
   pub ask_quantity: u64,
+  /// The tape the quote was reported on.
+  #[serde(rename = "z", default, skip_serializing_if = "Option::is_none")]
+  pub tape: Option<Tape>,
+  /// The quote's timestamp.
+  #[serde(
+    rename = "t",
+    deserialize_with = "datetime_from_timestamp",
+    serialize_with = "datetime_to_timestamp"
+  )]
+  pub timestamp: DateTime<Utc>,
+}
+
+
+impl Quote {
+  /// Retrieve the quote's timestamp as milliseconds since the Unix
+  /// epoch, as originally reported by Polygon.
+  pub fn timestamp_millis(&self) -> i64 {
+    self.timestamp.timestamp_millis()
+  }
+
+  /// Calculate the quote's bid-ask spread, i.e., the difference of ask
+  /// and bid price.
+  ///
+  /// Note that for locked or crossed markets the spread is zero or
+  /// negative, respectively.
+  pub fn spread(&self) -> Num {
+    &self.ask_price - &self.bid_price
+  }
+
+  /// Calculate the quote's mid price, i.e., the average of bid and ask
+  /// price.
+  pub fn mid_price(&self) -> Num {
+    (&self.bid_price + &self.ask_price) / 2
+  }
+}
+
+
+/// A quote for a stock, reduced to its prices.
+///
+/// Light quotes are reported instead of `Quote` objects if
+/// `StreamConfig::light_quotes` is set. Prices are parsed as `f64`
+/// and all other fields are skipped, which is considerably cheaper
+/// when streaming quotes at high volume.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct LightQuote {
+  /// The stock's symbol.
+  #[serde(rename = "sym")]
+  pub symbol: String,
+  /// The bid price.
+  #[serde(rename = "bp")]
+  pub bid_price: f64,
+  /// The ask price.
+  #[serde(rename = "ap")]
+  pub ask_price: f64,
   /// The quote's timestamp.
   #[serde(rename = "t", deserialize_with = "datetime_from_timestamp")]
   pub timestamp: DateTime<Utc>,
@@ -83,29 +299,46 @@ pub struct Quote {
 
 
 /// An aggregate for a stock.
+///
+/// Polygon reports two volume weighted average prices (VWAPs):
+/// `tick_vwap` covers only the trades within the aggregate's time
+/// frame, whereas `day_vwap` covers all trades of the day up to and
+/// including the aggregate.
 // TODO: Not all fields are hooked up.
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Aggregate {
   /// The stock's symbol.
-  #[serde(rename = "sym")]
+  #[serde(rename = "sym", alias = "T")]
   pub symbol: String,
   /// The tick volume.
   #[serde(rename = "v")]
   pub volume: u64,
-  /// Volume weighted average price.
-  #[serde(rename = "vw")]
-  pub volume_weighted_average_price: Num,
+  /// The accumulated volume for the day.
+  #[serde(rename = "av")]
+  pub accumulated_volume: Option<u64>,
+  /// The official opening price for the day.
+  #[serde(rename = "op", default, deserialize_with = "opt_num_from_json")]
+  pub official_open_price: Option<Num>,
+  /// The volume weighted average price of the tick.
+  ///
+  /// Polygon omits this field for ticks without trades.
+  #[serde(rename = "vw", default, deserialize_with = "opt_num_from_json")]
+  pub tick_vwap: Option<Num>,
+  /// The volume weighted average price for the day, as of the end of
+  /// the tick.
+  #[serde(rename = "a", default, deserialize_with = "opt_num_from_json")]
+  pub day_vwap: Option<Num>,
   /// The tick's open price.
-  #[serde(rename = "o")]
+  #[serde(rename = "o", deserialize_with = "num_from_json")]
   pub open_price: Num,
   /// The tick's close price.
-  #[serde(rename = "c")]
+  #[serde(rename = "c", deserialize_with = "num_from_json")]
   pub close_price: Num,
   /// The tick's high price.
-  #[serde(rename = "h")]
+  #[serde(rename = "h", deserialize_with = "num_from_json")]
   pub high_price: Num,
   /// The tick's low price.
-  #[serde(rename = "l")]
+  #[serde(rename = "l", deserialize_with = "num_from_json")]
   pub low_price: Num,
   /// The tick's start timestamp.
   #[serde(rename = "s", deserialize_with = "datetime_from_timestamp")]
@@ -116,6 +349,57 @@ pub struct Aggregate {
 }
 
 
+impl Aggregate {
+  /// Retrieve the volume weighted average price of the tick.
+  #[deprecated(note = "use the `tick_vwap` field instead")]
+  pub fn volume_weighted_average_price(&self) -> Option<&Num> {
+    self.tick_vwap.as_ref()
+  }
+
+  /// Retrieve the tick volume as a `Num`, for arithmetic in combination
+  /// with other prices or volumes.
+  pub fn volume_num(&self) -> Num {
+    Num::from(self.volume)
+  }
+
+  /// Retrieve the tick's start timestamp as milliseconds since the Unix
+  /// epoch, as originally reported by Polygon.
+  pub fn start_timestamp_millis(&self) -> i64 {
+    self.start_timestamp.timestamp_millis()
+  }
+
+  /// Retrieve the tick's end timestamp as milliseconds since the Unix
+  /// epoch, as originally reported by Polygon.
+  pub fn end_timestamp_millis(&self) -> i64 {
+    self.end_timestamp.timestamp_millis()
+  }
+}
+
+
+/// A fair market value (FMV) indication for a stock.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct FairMarketValue {
+  /// The stock's symbol.
+  #[serde(rename = "sym")]
+  pub symbol: String,
+  /// The fair market value price.
+  #[serde(rename = "fmv", deserialize_with = "num_from_json")]
+  pub price: Num,
+  /// The indication's timestamp.
+  #[serde(rename = "t", deserialize_with = "datetime_from_timestamp")]
+  pub timestamp: DateTime<Utc>,
+}
+
+
+impl FairMarketValue {
+  /// Retrieve the indication's timestamp as milliseconds since the
+  /// Unix epoch, as originally reported by Polygon.
+  pub fn timestamp_millis(&self) -> i64 {
+    self.timestamp.timestamp_millis()
+  }
+}
+
+
 /// A status code indication for an operation.
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 pub(crate) enum Code {
@@ -141,17 +425,97 @@ pub(crate) struct Status {
 }
 
 
+/// The reason for Polygon closing a connection, as indicated by a
+/// status message.
+#[derive(Clone, Debug, PartialEq, ThisError)]
+pub enum DisconnectReason {
+  /// The maximum number of concurrent connections permitted for the
+  /// API key was reached. Connecting again is futile unless another
+  /// connection is closed first.
+  #[error("maximum number of connections reached")]
+  MaxConnections,
+  /// Some other reason, as reported by Polygon.
+  #[error("{0}")]
+  Other(String),
+}
+
+impl DisconnectReason {
+  /// Parse the reason from the message of a disconnect status message.
+  pub(crate) fn from_message(message: &str) -> Self {
+    let reason = message.strip_prefix("Reason: ").unwrap_or(message);
+    if reason.eq_ignore_ascii_case("max connections reached") {
+      Self::MaxConnections
+    } else {
+      Self::Other(reason.to_string())
+    }
+  }
+
+  /// Retrieve the disconnect reason carried by an error reported by a
+  /// stream of events, if the error was caused by Polygon closing the
+  /// connection.
+  pub fn from_error(err: &WebSocketError) -> Option<&Self> {
+    match err {
+      WebSocketError::Io(err) => err.get_ref()?.downcast_ref::<Self>(),
+      _ => None,
+    }
+  }
+}
+
+
+/// Check whether an error reported by a stream of events is transient,
+/// i.e., whether connecting again may succeed.
+///
+/// Connection losses, including Polygon closing the connection for
+/// reasons other than the maximum number of connections being reached,
+/// transport failures such as resets or timeouts, and server side HTTP
+/// failures during the WebSocket upgrade are considered retryable.
+/// Protocol violations, TLS and URL errors, as well as other HTTP
+/// failures (e.g., due to authentication issues) are considered fatal.
+pub fn is_retryable(err: &WebSocketError) -> bool {
+  match err {
+    WebSocketError::ConnectionClosed
+    | WebSocketError::AlreadyClosed
+    | WebSocketError::SendQueueFull(..) => true,
+    WebSocketError::Io(..) => !matches!(
+      DisconnectReason::from_error(err),
+      Some(DisconnectReason::MaxConnections)
+    ),
+    WebSocketError::Http(response) => {
+      let status = response.status();
+      status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+    },
+    WebSocketError::Tls(..)
+    | WebSocketError::Capacity(..)
+    | WebSocketError::Protocol(..)
+    | WebSocketError::Utf8
+    | WebSocketError::Url(..)
+    | WebSocketError::HttpFormat(..) => false,
+  }
+}
+
+
+/// A frame that failed to parse, along with its raw contents.
+#[derive(Debug)]
+pub(crate) struct ParseError {
+  /// The error encountered while parsing.
+  pub error: JsonError,
+  /// The raw contents of the frame.
+  pub raw: String,
+}
+
+
 /// A message as we receive it from the Polygon API.
 ///
 /// The Polygon API mixes control messages (status messages) with actual
 /// event data freely. We do not want to expose control messages to
 /// clients and so we have our own type for evaluating them. In a
 /// nutshell, while we still accept actual event data, it is not parsed
-/// and simply ignored by the logic.
+/// and simply ignored by the logic. Quotes are decoded as `Q`, which is
+/// either `Quote` or `LightQuote`.
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 #[allow(clippy::large_enum_variant)]
 #[serde(tag = "ev")]
-pub(crate) enum Message {
+pub(crate) enum Message<Q = Quote> {
   #[serde(rename = "status")]
   Status(Status),
   #[serde(rename = "A")]
@@ -161,11 +525,13 @@ pub(crate) enum Message {
   #[serde(rename = "T")]
   Trade(Trade),
   #[serde(rename = "Q")]
-  Quote(Quote),
+  Quote(Q),
+  #[serde(rename = "FMV")]
+  FairMarketValue(FairMarketValue),
 }
 
 #[cfg(test)]
-impl Message {
+impl<Q> Message<Q> {
   pub fn into_status(self) -> Option<Status> {
     match self {
       Message::Status(status) => Some(status),
@@ -175,13 +541,42 @@ impl Message {
 }
 
 
+/// Decode a frame of messages, with quotes decoded as `Q`.
+pub(crate) fn decode_messages<Q>(data: &[u8]) -> Result<Messages<Q>, JsonError>
+where
+  Q: DeserializeOwned,
+{
+  from_json_slice::<Messages<Q>>(data)
+}
+
+
 // Note that Polygon responds with an array of status messages because
 // it supports subscription to multiple streams and sends a response for
 // each.
-pub(crate) type Messages = Vec<Message>;
+pub(crate) type Messages<Q = Quote> = Vec<Message<Q>>;
 
 
 /// An enum representing the type of event we received from Polygon.
+///
+/// The event kind is identified by the `ev` key, side by side with the
+/// event's data. As such, an `Event` can be embedded into a user
+/// defined type by means of `#[serde(flatten)]`, as long as the
+/// surrounding type does not use any of the keys Polygon reports:
+/// ```
+/// # use polyio::events::Event;
+/// # use serde::Deserialize;
+/// #[derive(Deserialize)]
+/// struct Envelope {
+///   strategy: String,
+///   #[serde(flatten)]
+///   event: Event,
+/// }
+///
+/// let json = r#"{"strategy":"momentum","ev":"T","sym":"MSFT","x":4,"p":156.98,"s":3,"t":1577818283019}"#;
+/// let envelope = serde_json::from_str::<Envelope>(json).unwrap();
+/// assert_eq!(envelope.strategy, "momentum");
+/// assert_eq!(envelope.event.symbol(), "MSFT");
+/// ```
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 #[allow(clippy::large_enum_variant)]
 #[serde(tag = "ev")]
@@ -198,6 +593,15 @@ pub enum Event {
   /// A tick for a quote for a stock.
   #[serde(rename = "Q")]
   Quote(Quote),
+  /// A fair market value indication for a stock.
+  #[serde(rename = "FMV")]
+  FairMarketValue(FairMarketValue),
+  /// A tick for a quote for a stock, reduced to its prices.
+  ///
+  /// Reported instead of `Quote` if `StreamConfig::light_quotes` is
+  /// set.
+  #[serde(skip_deserializing)]
+  LightQuote(LightQuote),
 }
 
 impl Event {
@@ -207,9 +611,33 @@ impl Event {
       Event::SecondAggregate(aggregate) | Event::MinuteAggregate(aggregate) => &aggregate.symbol,
       Event::Trade(trade) => &trade.symbol,
       Event::Quote(quote) => &quote.symbol,
+      Event::FairMarketValue(fmv) => &fmv.symbol,
+      Event::LightQuote(quote) => &quote.symbol,
+    }
+  }
+
+  /// Retrieve the time the event's data is current as of.
+  ///
+  /// For aggregates, this is the end of the aggregated time frame.
+  pub fn timestamp(&self) -> &DateTime<Utc> {
+    match self {
+      Event::SecondAggregate(aggregate) | Event::MinuteAggregate(aggregate) => {
+        &aggregate.end_timestamp
+      },
+      Event::Trade(trade) => &trade.timestamp,
+      Event::Quote(quote) => &quote.timestamp,
+      Event::FairMarketValue(fmv) => &fmv.timestamp,
+      Event::LightQuote(quote) => &quote.timestamp,
     }
   }
 
+  /// Classify the event's timestamp into a trading session.
+  ///
+  /// See `MarketSession::classify` for details.
+  pub fn session(&self) -> MarketSession {
+    MarketSession::classify(self.timestamp())
+  }
+
   #[cfg(test)]
   fn to_trade(&self) -> Option<&Trade> {
     match self {
@@ -227,15 +655,32 @@ impl Event {
   }
 }
 
+impl From<Quote> for Event {
+  fn from(quote: Quote) -> Self {
+    Event::Quote(quote)
+  }
+}
+
+impl From<LightQuote> for Event {
+  fn from(quote: LightQuote) -> Self {
+    Event::LightQuote(quote)
+  }
+}
+
 
 /// Process the given messages, converting them into events and checking
 /// for disconnects. On disconnect (and only then) a `WebSocketError` is
-/// returned.
-fn process_message(message: Message) -> Option<Result<Event, WebSocketError>> {
+/// returned, carrying the `DisconnectReason`.
+pub(crate) fn process_message<Q>(message: Message<Q>) -> Option<Result<Event, WebSocketError>>
+where
+  Q: Into<Event>,
+{
   let event = match message {
     Message::Status(status) => {
       if status.code == Code::Disconnected {
-        return Some(Err(WebSocketError::AlreadyClosed))
+        let reason = DisconnectReason::from_message(&status.message);
+        let err = IoError::new(IoErrorKind::ConnectionAborted, reason);
+        return Some(Err(WebSocketError::Io(err)))
       } else {
         return None
       }
@@ -243,20 +688,199 @@ fn process_message(message: Message) -> Option<Result<Event, WebSocketError>> {
     Message::SecondAggregate(aggregate) => Event::SecondAggregate(aggregate),
     Message::MinuteAggregate(aggregate) => Event::MinuteAggregate(aggregate),
     Message::Trade(trade) => Event::Trade(trade),
-    Message::Quote(quote) => Event::Quote(quote),
+    Message::Quote(quote) => quote.into(),
+    Message::FairMarketValue(fmv) => Event::FairMarketValue(fmv),
   };
 
   Some(Ok(event))
 }
 
 
-async fn handle_msg<S>(
+/// A request to change the subscriptions or the state of an active
+/// stream.
+#[derive(Debug)]
+enum Command {
+  Subscribe(Vec<Subscription>),
+  Unsubscribe(Vec<Subscription>),
+  Pause,
+  Resume,
+}
+
+
+/// A handle for changing the subscriptions of a stream created by
+/// `stream_with_handle`.
+///
+/// Note that requests only get sent while the associated stream is
+/// being polled. Requests take precedence over reading events.
+#[derive(Clone, Debug)]
+pub struct SubscriptionHandle {
+  sender: UnboundedSender<Command>,
+  /// The subscriptions currently in effect, shared among all clones
+  /// of the handle.
+  subscriptions: Arc<Mutex<BTreeSet<Subscription>>>,
+}
+
+impl SubscriptionHandle {
+  fn send(&self, command: Command) -> Result<(), Error> {
+    self
+      .sender
+      .send(command)
+      .map_err(|_| Error::Str("event stream is no longer active".into()))
+  }
+
+  /// Subscribe to the given events, in addition to the ones already
+  /// subscribed to.
+  pub fn subscribe<S>(&self, subscriptions: S) -> Result<(), Error>
+  where
+    S: IntoIterator<Item = Subscription>,
+  {
+    let subscriptions = subscriptions.into_iter().collect::<Vec<_>>();
+    let mut registry = self.subscriptions.lock().unwrap();
+    let () = self.send(Command::Subscribe(subscriptions.clone()))?;
+    registry.extend(subscriptions);
+    Ok(())
+  }
+
+  /// Unsubscribe from the given events.
+  pub fn unsubscribe<S>(&self, subscriptions: S) -> Result<(), Error>
+  where
+    S: IntoIterator<Item = Subscription>,
+  {
+    let subscriptions = subscriptions.into_iter().collect::<Vec<_>>();
+    let mut registry = self.subscriptions.lock().unwrap();
+    let () = self.send(Command::Unsubscribe(subscriptions.clone()))?;
+    subscriptions.iter().for_each(|subscription| {
+      let _ = registry.remove(subscription);
+    });
+    Ok(())
+  }
+
+  /// Unsubscribe from all events currently subscribed to.
+  ///
+  /// This covers the subscriptions the stream was created with as
+  /// well as all those made through any clone of this handle, and it
+  /// requires but a single request. Please note that unsubscribing
+  /// from the events for a single symbol does not cancel a wildcard
+  /// subscription, nor vice versa.
+  pub fn unsubscribe_all(&self) -> Result<(), Error> {
+    let mut registry = self.subscriptions.lock().unwrap();
+    let subscriptions = registry.iter().cloned().collect();
+    let () = self.send(Command::Unsubscribe(subscriptions))?;
+    let () = registry.clear();
+    Ok(())
+  }
+
+  /// Stop reading from the connection, without closing it.
+  ///
+  /// While paused, the stream does not report any events and events
+  /// sent by Polygon queue up in the connection's buffers, eventually
+  /// applying back pressure. Subscription changes continue to be sent.
+  /// Events already received as part of the most recent message may
+  /// still be reported.
+  ///
+  /// Please note that because nothing is read, pings sent by Polygon
+  /// go unanswered as well. Polygon may hence consider a connection
+  /// paused for too long dead and close it, which is reported once
+  /// the stream is resumed.
+  pub fn pause(&self) -> Result<(), Error> {
+    self.send(Command::Pause)
+  }
+
+  /// Resume reading from a connection paused by means of `pause`,
+  /// reporting the events that queued up in the meantime.
+  pub fn resume(&self) -> Result<(), Error> {
+    self.send(Command::Resume)
+  }
+}
+
+
+/// Send the request corresponding to the given command.
+async fn send_command<S>(stream: &mut S, command: Command) -> Result<(), WebSocketError>
+where
+  S: Sink<WebSocketMessage, Error = WebSocketError> + Unpin,
+{
+  let json = match command {
+    Command::Subscribe(subscriptions) => make_change_request(Action::Subscribe, subscriptions),
+    Command::Unsubscribe(subscriptions) => make_change_request(Action::Unsubscribe, subscriptions),
+    Command::Pause | Command::Resume => None,
+  };
+
+  match json {
+    Some(json) => {
+      trace!(request = display(&json));
+      stream.send(WebSocketMessage::Text(json)).await
+    },
+    None => Ok(()),
+  }
+}
+
+
+/// Wait for the next message on the stream, while sending requests
+/// for any commands received in the meantime.
+///
+/// While `paused` is set, only commands are processed.
+async fn next_message<S, Q>(
+  stream: &mut S,
+  commands: &mut Option<UnboundedReceiver<Command>>,
+  paused: &mut bool,
+) -> Option<S::Item>
+where
+  S: Stream + Sink<WebSocketMessage, Error = WebSocketError> + Unpin,
+  S: Stream<Item = Result<Result<Messages<Q>, ParseError>, WebSocketError>>,
+{
+  loop {
+    let receiver = match commands {
+      Some(receiver) => receiver,
+      None => return StreamExt::next(stream).await,
+    };
+
+    let result = if *paused {
+      Either::Right(receiver.recv().await)
+    } else {
+      let command = receiver.recv();
+      pin_mut!(command);
+
+      // Poll for commands first, so that a pause takes effect even if
+      // messages are ready.
+      match select(command, StreamExt::next(stream)).await {
+        Either::Left((command, _)) => Either::Right(command),
+        Either::Right((next_msg, _)) => Either::Left(next_msg),
+      }
+    };
+
+    match result {
+      Either::Left(next_msg) => return next_msg,
+      Either::Right(Some(Command::Pause)) => {
+        debug!("pausing event stream");
+        *paused = true
+      },
+      Either::Right(Some(Command::Resume)) => {
+        debug!("resuming event stream");
+        *paused = false
+      },
+      Either::Right(Some(command)) => {
+        if let Err(err) = send_command(stream, command).await {
+          return Some(Err(err))
+        }
+      },
+      // All handles are gone.
+      Either::Right(None) => *commands = None,
+    }
+  }
+}
+
+
+async fn handle_msg<S, Q>(
   stop: &mut bool,
   stream: &mut S,
-  messages: &mut Vec<Message>,
-) -> Option<Result<Result<Event, JsonError>, WebSocketError>>
+  messages: &mut Messages<Q>,
+  commands: &mut Option<UnboundedReceiver<Command>>,
+  paused: &mut bool,
+) -> Option<Result<Result<Event, ParseError>, WebSocketError>>
 where
-  S: Stream<Item = Result<Result<Vec<Message>, JsonError>, WebSocketError>> + Unpin,
+  S: Stream<Item = Result<Result<Messages<Q>, ParseError>, WebSocketError>> + Unpin,
+  S: Sink<WebSocketMessage, Error = WebSocketError>,
+  Q: Into<Event>,
 {
   if *stop {
     None
@@ -281,7 +905,7 @@ where
           }
         },
         None => {
-          let next_msg = StreamExt::next(stream).await;
+          let next_msg = next_message(stream, commands, paused).await;
 
           if let Some(result) = next_msg {
             match result {
@@ -306,83 +930,582 @@ where
 }
 
 
-/// Subscribe to and stream events from the Polygon service.
-#[allow(clippy::cognitive_complexity)]
-pub async fn stream<S>(
-  api_info: ApiInfo,
-  subscriptions: S,
-) -> Result<impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>, Error>
-where
-  S: IntoIterator<Item = Subscription>,
-{
-  let ApiInfo {
-    stream_url: url,
-    api_key,
-    ..
-  } = api_info;
+/// A callback observing raw WebSocket frames, as used by
+/// `StreamConfig::frame_tap`.
+#[derive(Clone)]
+pub struct FrameTap(Arc<dyn Fn(&WebSocketMessage) + Send + Sync>);
 
-  debug!(message = "connecting", url = display(&url));
+impl FrameTap {
+  /// Create a new `FrameTap` invoking the provided function.
+  pub fn new<F>(f: F) -> Self
+  where
+    F: Fn(&WebSocketMessage) + Send + Sync + 'static,
+  {
+    Self(Arc::new(f))
+  }
+}
 
-  let (mut stream, response) = connect_async(url).await?;
-  debug!("connection successful");
-  trace!(response = debug(&response));
+impl Debug for FrameTap {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    fmt.debug_tuple("FrameTap").finish()
+  }
+}
 
-  handshake(&mut stream, api_key, subscriptions).await?;
-  debug!("subscription successful");
 
-  let stream = Wrapper::builder().build(stream).map(|result| {
-    result.map(|message| match message {
-      WebSocketMessage::Text(string) => from_json_str::<Messages>(&string),
-      WebSocketMessage::Binary(data) => from_json_slice::<Messages>(&data),
-    })
-  });
-  let stream = Box::pin(stream);
-  let stream = unfold(
-    (false, (stream, Vec::new())),
-    |(mut stop, (mut stream, mut messages))| async move {
-      let result = handle_msg(&mut stop, &mut stream, &mut messages).await;
-      result.map(|result| (result, (stop, (stream, messages))))
-    },
-  );
+/// A callback observing metadata about established connections, as
+/// used by `StreamConfig::connection_tap`.
+#[derive(Clone)]
+pub struct ConnectionTap(Arc<dyn Fn(&ConnectionMetadata) + Send + Sync>);
 
-  Ok(stream)
+impl ConnectionTap {
+  /// Create a new `ConnectionTap` invoking the provided function.
+  pub fn new<F>(f: F) -> Self
+  where
+    F: Fn(&ConnectionMetadata) + Send + Sync + 'static,
+  {
+    Self(Arc::new(f))
+  }
 }
 
+impl Debug for ConnectionTap {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    fmt.debug_tuple("ConnectionTap").finish()
+  }
+}
 
-#[cfg(test)]
-mod tests {
-  use super::*;
 
-  use std::future::Future;
-  use std::time::Duration;
+/// A random delay applied before connecting, as used by
+/// `StreamConfig::startup_jitter`.
+#[derive(Clone)]
+pub struct StartupJitter {
+  /// The maximum delay.
+  max: Duration,
+  /// The source of random numbers in the range `[0, 1)`.
+  rng: Arc<dyn Fn() -> f64 + Send + Sync>,
+}
 
-  use futures::future::ready;
-  use futures::SinkExt;
-  use futures::StreamExt;
-  use futures::TryStreamExt;
+impl StartupJitter {
+  /// Create a new `StartupJitter` delaying by at most `max`.
+  pub fn new(max: Duration) -> Self {
+    Self::with_rng(max, || {
+      // We have no need for high quality random numbers here and so
+      // just rely on the randomly seeded hasher of the standard
+      // library.
+      let hash = RandomState::new().build_hasher().finish();
+      (hash >> 11) as f64 / (1u64 << 53) as f64
+    })
+  }
 
-  use serde_json::from_str as from_json;
+  /// Create a new `StartupJitter` delaying by at most `max`, using the
+  /// provided source of random numbers in the range `[0, 1)`.
+  pub fn with_rng<F>(max: Duration, rng: F) -> Self
+  where
+    F: Fn() -> f64 + Send + Sync + 'static,
+  {
+    Self {
+      max,
+      rng: Arc::new(rng),
+    }
+  }
 
-  use test_log::test;
+  /// Determine the delay to apply.
+  fn delay(&self) -> Duration {
+    let factor = (self.rng)();
+    let factor = if factor.is_finite() {
+      factor.clamp(0.0, 1.0)
+    } else {
+      0.0
+    };
+    self.max.mul_f64(factor)
+  }
+}
 
-  use tokio::time::timeout;
+impl Debug for StartupJitter {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    fmt
+      .debug_struct("StartupJitter")
+      .field("max", &self.max)
+      .finish()
+  }
+}
 
-  use tungstenite::tungstenite::Message as WebSocketMessage;
 
-  use url::Url;
+/// Metadata about an established connection, as reported to
+/// `StreamConfig::connection_tap`.
+#[derive(Clone, Debug)]
+pub struct ConnectionMetadata {
+  /// The headers of the server's response to the WebSocket upgrade
+  /// request.
+  pub headers: HeaderMap,
+  /// The cluster or region the connection was routed to, as reported
+  /// by the upgrade response header named by
+  /// `StreamConfig::region_header`, if any.
+  pub region: Option<String>,
+}
 
-  use websocket_util::test::mock_server;
-  use websocket_util::test::WebSocketStream;
+impl ConnectionMetadata {
+  /// Extract the metadata from the response to the upgrade request.
+  fn from_response(response: &UpgradeResponse, region_header: Option<&str>) -> Self {
+    let headers = response.headers().clone();
+    let region = region_header
+      .and_then(|name| headers.get(name)?.to_str().ok())
+      .map(str::trim)
+      .filter(|region| !region.is_empty())
+      .map(ToString::to_string);
+
+    Self { headers, region }
+  }
+}
 
-  use crate::events::subscription::Stock;
 
-  #[cfg(not(target_arch = "wasm32"))]
-  use crate::Client;
+/// Configuration options for streaming events.
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+  /// The user agent to report when connecting.
+  ///
+  /// Defaults to `polyio/<version>`.
+  pub user_agent: Str,
+  /// The maximum time to wait for the next message while awaiting
+  /// confirmations during connection setup, authentication, and
+  /// subscription.
+  ///
+  /// If the timeout expires, the stream creation fails with an error
+  /// listing the unconfirmed operations or subscriptions. Polygon not
+  /// confirming some subscriptions may indicate that the plan's
+  /// subscription limit was exceeded. `None` waits indefinitely.
+  /// Defaults to 30 seconds.
+  pub confirmation_timeout: Option<Duration>,
+  /// The maximum time to wait for all subscriptions to be confirmed.
+  ///
+  /// Contrary to `confirmation_timeout`, this timeout is not reset
+  /// when messages are received. Hence, it bounds the wait when
+  /// Polygon never confirms some subscriptions (e.g., ones for invalid
+  /// symbols) while events for others keep arriving. If it expires,
+  /// the stream creation fails with an error listing the unconfirmed
+  /// subscriptions. Defaults to `None`, i.e., no limit.
+  pub subscription_timeout: Option<Duration>,
+  /// Whether to deliver events received before all subscriptions were
+  /// confirmed.
+  ///
+  /// Polygon may send events for a subscription before confirming it.
+  /// Such events are dropped unless this flag is set, in which case
+  /// they are reported ahead of all other events. Defaults to `false`.
+  pub deliver_early_events: bool,
+  /// A callback invoked with each raw text or binary frame received
+  /// once the stream is established, before it gets parsed.
+  ///
+  /// This is meant as a debugging aid, e.g., for capturing frames that
+  /// fail to parse. Defaults to `None`.
+  pub frame_tap: Option<FrameTap>,
+  /// A callback invoked with metadata about each connection
+  /// established, including those established on reconnect.
+  ///
+  /// The callback is invoked once the WebSocket upgrade completed,
+  /// before authentication. Defaults to `None`.
+  pub connection_tap: Option<ConnectionTap>,
+  /// The name of the WebSocket upgrade response header identifying
+  /// the cluster or region a connection was routed to, as reported via
+  /// `ConnectionMetadata::region`.
+  ///
+  /// Polygon does not document such a header, so none is inspected by
+  /// default. If the endpoint connected to reports one, its name can
+  /// be provided here. Defaults to `None`.
+  pub region_header: Option<Str>,
+  /// The time to wait before retrying a failed reconnection attempt,
+  /// as performed by `stream_with_reconnect`.
+  ///
+  /// Defaults to one second.
+  pub reconnect_delay: Duration,
+  /// The interval at which to proactively reconnect, as performed by
+  /// `stream_with_reconnect` and `stream_items`.
+  ///
+  /// Long-lived connections may degrade without Polygon closing them
+  /// explicitly. Reconnecting on a schedule (e.g., every six hours)
+  /// refreshes the connection state. Scheduled reconnects are reported
+  /// just like those caused by connection losses. Defaults to `None`,
+  /// i.e., only reconnecting once the connection is lost.
+  pub reconnect_interval: Option<Duration>,
+  /// The number of events after which to proactively reconnect, as
+  /// performed by `stream_with_reconnect` and `stream_items`.
+  ///
+  /// Similar to `reconnect_interval`, recycling a connection once it
+  /// delivered a certain number of events may counter degrading
+  /// connection quality. The count restarts with each connection.
+  /// Reconnects are reported just like those caused by connection
+  /// losses. Defaults to `None`, i.e., no limit.
+  pub reconnect_after_events: Option<NonZeroUsize>,
+  /// A random delay to wait for before connecting.
+  ///
+  /// Spreading out connection attempts helps staying within Polygon's
+  /// connection limits when many processes start up at the same time.
+  /// The delay also applies to reconnects. Defaults to `None`, i.e.,
+  /// connecting right away.
+  pub startup_jitter: Option<StartupJitter>,
+  /// Whether to accept any TLS certificate presented by the server,
+  /// including self-signed and expired ones.
+  ///
+  /// # Warning
+  /// Certificate validation is what protects against
+  /// man-in-the-middle attacks. Disabling it is only meant for
+  /// debugging, e.g., for inspecting traffic through a local
+  /// intercepting proxy, and must never be used in production.
+  /// Defaults to `false`.
+  pub danger_accept_invalid_certs: bool,
+  /// Whether to report quotes as `Event::LightQuote` instead of
+  /// `Event::Quote`.
+  ///
+  /// Light quotes only carry the symbol, bid and ask price, and
+  /// timestamp, with prices parsed as `f64` instead of `Num`. This
+  /// reduces the cost of parsing considerably when subscribing to many
+  /// quotes. The setting applies to all quotes of a stream, including
+  /// those received before all subscriptions were confirmed. Defaults
+  /// to `false`.
+  pub light_quotes: bool,
+}
 
-  const API_KEY: &str = "USER12345678";
-  const CONNECTED_MSG: &str =
-    r#"[{"ev":"status","status":"connected","message":"Connected Successfully"}]"#;
-  const DISCONNECTED_MSG: &str =
+impl Default for StreamConfig {
+  fn default() -> Self {
+    Self {
+      user_agent: USER_AGENT.into(),
+      confirmation_timeout: Some(Duration::from_secs(30)),
+      subscription_timeout: None,
+      deliver_early_events: false,
+      frame_tap: None,
+      connection_tap: None,
+      region_header: None,
+      reconnect_delay: Duration::from_secs(1),
+      reconnect_interval: None,
+      reconnect_after_events: None,
+      startup_jitter: None,
+      danger_accept_invalid_certs: false,
+      light_quotes: false,
+    }
+  }
+}
+
+
+/// Subscribe to and stream events from the Polygon service.
+pub async fn stream<S>(
+  api_info: ApiInfo,
+  subscriptions: S,
+) -> Result<impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>, Error>
+where
+  S: IntoIterator<Item = Subscription>,
+{
+  stream_with_config(api_info, subscriptions, StreamConfig::default()).await
+}
+
+
+/// Establish a WebSocket connection to the given URL.
+async fn connect(
+  url: Url,
+  config: &StreamConfig,
+) -> Result<
+  impl Stream<Item = Result<RawMessage, WebSocketError>>
+    + Sink<RawMessage, Error = WebSocketError>
+    + Unpin,
+  Error,
+> {
+  if let Some(jitter) = &config.startup_jitter {
+    let delay = jitter.delay();
+    debug!(delay = debug(&delay), "delaying connection");
+    let () = sleep(delay).await;
+  }
+
+  debug!(message = "connecting", url = display(&url));
+
+  let user_agent =
+    HeaderValue::from_str(&config.user_agent).map_err(|err| Error::Http(err.into()))?;
+  let mut request = url.into_client_request()?;
+  let _ = request.headers_mut().insert(USER_AGENT_HEADER, user_agent);
+  // Note that we do not negotiate the permessage-deflate extension,
+  // even though Polygon supports it: tungstenite (as of 0.16) has no
+  // support for compressed frames and would reject any such frame as
+  // having reserved bits set. Compression can only be offered once the
+  // WebSocket stack is capable of decompressing messages.
+
+  let (stream, response) = if config.danger_accept_invalid_certs {
+    warn!("TLS certificate validation is disabled");
+    let connector = TlsConnector::builder()
+      .danger_accept_invalid_certs(true)
+      .build()
+      .map_err(|err| WebSocketError::Tls(TlsError::Native(err)))?;
+    connect_async_tls_with_config(request, None, Some(Connector::NativeTls(connector))).await?
+  } else {
+    connect_async(request).await?
+  };
+  debug!("connection successful");
+  trace!(response = debug(&response));
+
+  let metadata = ConnectionMetadata::from_response(&response, config.region_header.as_deref());
+  if let Some(region) = &metadata.region {
+    debug!(region = display(region), "connection routed");
+  }
+  if let Some(ConnectionTap(tap)) = &config.connection_tap {
+    tap(&metadata)
+  }
+  Ok(stream)
+}
+
+
+/// Convert a WebSocket stream on which the handshake has been
+/// performed into a stream of events.
+fn events<S>(
+  stream: S,
+  commands: Option<UnboundedReceiver<Command>>,
+  config: &StreamConfig,
+) -> impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>
+where
+  S: Stream<Item = Result<RawMessage, WebSocketError>>,
+  S: Sink<RawMessage, Error = WebSocketError> + Unpin,
+{
+  raw_events(stream, commands, config)
+    .map(|result| result.map(|result| result.map_err(|err| err.error)))
+}
+
+
+/// Convert a WebSocket stream on which the handshake has been
+/// performed into a stream of events, retaining the raw contents of
+/// frames that failed to parse.
+fn raw_events<S>(
+  stream: S,
+  commands: Option<UnboundedReceiver<Command>>,
+  config: &StreamConfig,
+) -> impl Stream<Item = Result<Result<Event, ParseError>, WebSocketError>>
+where
+  S: Stream<Item = Result<RawMessage, WebSocketError>>,
+  S: Sink<RawMessage, Error = WebSocketError> + Unpin,
+{
+  let frame_tap = config.frame_tap.clone();
+  if config.light_quotes {
+    Either::Left(decode_events::<_, LightQuote>(stream, commands, frame_tap))
+  } else {
+    Either::Right(decode_events::<_, Quote>(stream, commands, frame_tap))
+  }
+}
+
+
+/// Decode the frames of a WebSocket stream into events, with quotes
+/// decoded as `Q`.
+fn decode_events<S, Q>(
+  stream: S,
+  commands: Option<UnboundedReceiver<Command>>,
+  frame_tap: Option<FrameTap>,
+) -> impl Stream<Item = Result<Result<Event, ParseError>, WebSocketError>>
+where
+  S: Stream<Item = Result<RawMessage, WebSocketError>>,
+  S: Sink<RawMessage, Error = WebSocketError> + Unpin,
+  Q: DeserializeOwned + Into<Event>,
+{
+  // Note that we do not ask the wrapper to respond to pings: tungstenite
+  // already queues a pong for each received ping and sends it as part
+  // of reading the next message, which we do continuously.
+  let stream = Wrapper::builder().build(stream).inspect(move |result| {
+    if let (Ok(message), Some(FrameTap(tap))) = (result, &frame_tap) {
+      tap(message)
+    }
+  });
+  let stream = stream.map(|result| {
+    result.map(|message| match message {
+      WebSocketMessage::Text(string) => decode_messages::<Q>(string.as_bytes())
+        .map_err(|error| ParseError { error, raw: string }),
+      WebSocketMessage::Binary(data) => decode_messages::<Q>(&data).map_err(|error| {
+        let raw = String::from_utf8_lossy(&data).into_owned();
+        ParseError { error, raw }
+      }),
+    })
+  });
+  let stream = Box::pin(stream);
+  unfold(
+    (false, (stream, Vec::new(), commands, false)),
+    |(mut stop, (mut stream, mut messages, mut commands, mut paused))| async move {
+      let result = handle_msg(
+        &mut stop,
+        &mut stream,
+        &mut messages,
+        &mut commands,
+        &mut paused,
+      )
+      .await;
+      result.map(|result| (result, (stop, (stream, messages, commands, paused))))
+    },
+  )
+}
+
+
+/// Subscribe to and stream events from the Polygon service, using the
+/// provided configuration.
+pub async fn stream_with_config<S>(
+  api_info: ApiInfo,
+  subscriptions: S,
+  config: StreamConfig,
+) -> Result<impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>, Error>
+where
+  S: IntoIterator<Item = Subscription>,
+{
+  let stream = raw_stream_with_config(api_info, subscriptions, config).await?;
+  Ok(stream.map(|result| result.map(|result| result.map_err(|err| err.error))))
+}
+
+
+/// Subscribe to and stream events from the Polygon service, retaining
+/// the raw contents of frames that failed to parse.
+pub(crate) async fn raw_stream_with_config<S>(
+  api_info: ApiInfo,
+  subscriptions: S,
+  config: StreamConfig,
+) -> Result<impl Stream<Item = Result<Result<Event, ParseError>, WebSocketError>>, Error>
+where
+  S: IntoIterator<Item = Subscription>,
+{
+  let ApiInfo {
+    stream_url: url,
+    api_key,
+    ..
+  } = api_info;
+
+  let mut stream = connect(url, &config).await?;
+  let early = handshake(&mut stream, api_key, subscriptions, &config).await?;
+  debug!("subscription successful");
+
+  let early = iter(early).map(|event| Ok(Ok(event)));
+  Ok(early.chain(raw_events(stream, None, &config)))
+}
+
+
+/// Check that the given subscriptions can be streamed, without
+/// streaming any events.
+///
+/// A connection is established, authenticated, and subscribed to
+/// `subscriptions` just like `stream_with_config` does, but it is
+/// closed right after the subscriptions were confirmed. The confirmed
+/// subscriptions are reported back, sorted and with subscriptions made
+/// redundant by others removed. If only some subscriptions got
+/// confirmed before running into the configured timeouts, only those
+/// are reported, e.g., because a subscription limit was exceeded.
+/// Authentication failures, negative confirmations, and a lack of any
+/// confirmation are reported as errors, allowing for failing fast
+/// before committing to a long lived connection.
+pub async fn check_subscriptions<S>(
+  api_info: ApiInfo,
+  subscriptions: S,
+  config: StreamConfig,
+) -> Result<Vec<Subscription>, Error>
+where
+  S: IntoIterator<Item = Subscription>,
+{
+  let ApiInfo {
+    stream_url: url,
+    api_key,
+    ..
+  } = api_info;
+
+  let mut subscriptions = normalize(subscriptions);
+  let () = subscriptions.sort();
+
+  let mut stream = connect(url, &config).await?;
+  let () = setup(&mut stream, api_key, &config).await?;
+  let confirmed = subscribe_confirmed(&mut stream, &subscriptions, &config).await?;
+  debug!("subscription successful; closing connection");
+
+  let () = stream.close().await?;
+  Ok(confirmed)
+}
+
+
+/// Stream events from the Polygon service, additionally providing a
+/// handle for changing subscriptions while the stream is active.
+///
+/// In contrast to `stream_with_config`, `subscriptions` may be empty,
+/// in which case no events are received until subscriptions are made
+/// through the handle. Subscription changes requested through the
+/// handle are not confirmed.
+pub async fn stream_with_handle<S>(
+  api_info: ApiInfo,
+  subscriptions: S,
+  config: StreamConfig,
+) -> Result<
+  (
+    impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>,
+    SubscriptionHandle,
+  ),
+  Error,
+>
+where
+  S: IntoIterator<Item = Subscription>,
+{
+  let ApiInfo {
+    stream_url: url,
+    api_key,
+    ..
+  } = api_info;
+
+  let subscriptions = subscriptions.into_iter().collect::<Vec<_>>();
+  let registry = subscriptions.iter().cloned().collect::<BTreeSet<_>>();
+  let mut stream = connect(url, &config).await?;
+  setup(&mut stream, api_key, &config).await?;
+  let early = if !subscriptions.is_empty() {
+    let early = subscribe(&mut stream, subscriptions, &config).await?;
+    debug!("subscription successful");
+    early
+  } else {
+    Vec::new()
+  };
+
+  let (sender, receiver) = unbounded_channel();
+  let handle = SubscriptionHandle {
+    sender,
+    subscriptions: Arc::new(Mutex::new(registry)),
+  };
+  let early = iter(early).map(|event| Ok(Ok(event)));
+  let events = early.chain(events(stream, Some(receiver), &config));
+  Ok((events, handle))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::future::Future;
+  use std::sync::Mutex;
+  use std::time::Instant;
+
+  use futures::future::ready;
+  use futures::SinkExt;
+  use futures::StreamExt;
+  use futures::TryStreamExt;
+
+  use serde_json::from_str as from_json;
+  use serde_json::to_string as to_json;
+
+  use test_log::test;
+
+  use tokio::net::TcpListener;
+  use tokio::spawn;
+  use tokio::sync::oneshot::channel;
+  use tokio::time::timeout;
+
+  use tungstenite::accept_hdr_async;
+  use tungstenite::tungstenite::handshake::server::Request as UpgradeRequest;
+  use tungstenite::tungstenite::handshake::server::Response as UpgradeResponse;
+  use tungstenite::tungstenite::Message as WebSocketMessage;
+
+  use url::Url;
+
+  use websocket_util::test::mock_server;
+  use websocket_util::test::WebSocketStream;
+
+  use crate::conditions::is_odd_lot;
+  use crate::events::subscription::Stock;
+
+  #[cfg(not(target_arch = "wasm32"))]
+  use crate::Client;
+
+  const API_KEY: &str = "USER12345678";
+  const CONNECTED_MSG: &str =
+    r#"[{"ev":"status","status":"connected","message":"Connected Successfully"}]"#;
+  const DISCONNECTED_MSG: &str =
     r#"[{"ev":"status","status":"disconnected","message":"Reason: Max connections reached"}]"#;
   const AUTH_REQ: &str = r#"{"action":"auth","params":"USER12345678"}"#;
   const AUTH_RESP: &str = r#"[{"ev":"status","status":"auth_success","message":"authenticated"}]"#;
@@ -408,6 +1531,19 @@ mod tests {
     f: F,
     subscriptions: S,
   ) -> Result<impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>, Error>
+  where
+    F: FnOnce(WebSocketStream) -> R + Send + Sync + 'static,
+    R: Future<Output = Result<(), WebSocketError>> + Send + Sync + 'static,
+    S: IntoIterator<Item = Subscription>,
+  {
+    mock_stream_with_config(f, subscriptions, StreamConfig::default()).await
+  }
+
+  async fn mock_stream_with_config<F, R, S>(
+    f: F,
+    subscriptions: S,
+    config: StreamConfig,
+  ) -> Result<impl Stream<Item = Result<Result<Event, JsonError>, WebSocketError>>, Error>
   where
     F: FnOnce(WebSocketStream) -> R + Send + Sync + 'static,
     R: Future<Output = Result<(), WebSocketError>> + Send + Sync + 'static,
@@ -420,7 +1556,134 @@ mod tests {
       api_key: API_KEY.to_string(),
     };
 
-    stream(api_info, subscriptions).await
+    stream_with_config(api_info, subscriptions, config).await
+  }
+
+  /// Connect to a WebSocket server using the provided configuration
+  /// and report the user agent that the server saw in the upgrade
+  /// request.
+  async fn upgrade_user_agent(config: StreamConfig) -> Option<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, receiver) = channel();
+
+    spawn(async move {
+      let (stream, _addr) = listener.accept().await.unwrap();
+      let callback = |request: &UpgradeRequest, response: UpgradeResponse| {
+        let user_agent = request
+          .headers()
+          .get(USER_AGENT_HEADER)
+          .map(|value| value.to_str().unwrap().to_string());
+        let _ = sender.send(user_agent);
+        Ok(response)
+      };
+      // We are only interested in the upgrade request itself, so just
+      // drop the connection afterwards.
+      let _ = accept_hdr_async(stream, callback).await;
+    });
+
+    let api_info = ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: API_KEY.to_string(),
+    };
+    // The handshake fails, because the server never responds.
+    let _ = stream_with_config(api_info, Vec::new(), config).await;
+    receiver.await.unwrap()
+  }
+
+  /// Check that the region identified by the configured upgrade
+  /// response header is reported as part of the connection metadata.
+  #[test(tokio::test)]
+  async fn report_connection_region() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    spawn(async move {
+      let (stream, _addr) = listener.accept().await.unwrap();
+      let callback = |_request: &UpgradeRequest, mut response: UpgradeResponse| {
+        let value = HeaderValue::from_static("delayed-us-east-1");
+        let _ = response.headers_mut().insert("x-cluster", value);
+        Ok(response)
+      };
+      let mut stream = accept_hdr_async(stream, callback).await.unwrap();
+
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await
+        .unwrap();
+      assert_eq!(
+        stream.next().await.unwrap().unwrap(),
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await
+        .unwrap();
+      assert_eq!(
+        stream.next().await.unwrap().unwrap(),
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await
+        .unwrap();
+      stream.send(WebSocketMessage::Close(None)).await.unwrap();
+    });
+
+    let api_info = ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: API_KEY.to_string(),
+    };
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let metadata = Arc::new(Mutex::new(Vec::new()));
+    let config = StreamConfig {
+      connection_tap: Some(ConnectionTap::new({
+        let metadata = metadata.clone();
+        move |data| metadata.lock().unwrap().push(data.clone())
+      })),
+      region_header: Some("X-Cluster".into()),
+      ..Default::default()
+    };
+    let stream = stream_with_config(api_info, subscriptions, config)
+      .await
+      .unwrap();
+
+    let events = stream.collect::<Vec<_>>().await;
+    assert!(events.is_empty(), "{:?}", events);
+
+    let metadata = metadata.lock().unwrap();
+    assert_eq!(metadata.len(), 1);
+    assert_eq!(metadata[0].region.as_deref(), Some("delayed-us-east-1"));
+    assert_eq!(
+      metadata[0].headers.get("X-Cluster").unwrap(),
+      "delayed-us-east-1"
+    );
+  }
+
+  /// Check that the WebSocket upgrade request reports the default user
+  /// agent.
+  #[test(tokio::test)]
+  async fn stream_default_user_agent() {
+    let user_agent = upgrade_user_agent(StreamConfig::default()).await;
+    let expected = concat!("polyio/", env!("CARGO_PKG_VERSION"));
+    assert_eq!(user_agent.as_deref(), Some(expected));
+  }
+
+  /// Check that we can overwrite the user agent reported in the
+  /// WebSocket upgrade request.
+  #[test(tokio::test)]
+  async fn stream_custom_user_agent() {
+    let config = StreamConfig {
+      user_agent: "my-app/1.0".into(),
+      ..Default::default()
+    };
+    let user_agent = upgrade_user_agent(config).await;
+    assert_eq!(user_agent.as_deref(), Some("my-app/1.0"));
   }
 
   /// Check that we can deserialize a `Trade`.
@@ -439,58 +1702,273 @@ mod tests {
     }"#;
     let trade = from_json::<Trade>(response).unwrap();
     assert_eq!(trade.symbol, "SPY");
+    assert_eq!(trade.id.as_deref(), Some("436698869"));
     assert_eq!(trade.exchange, 19);
     assert_eq!(trade.price, Num::new(29367, 100));
     assert_eq!(trade.quantity, 100);
+    assert_eq!(trade.conditions, Vec::<u64>::new());
     assert_eq!(
       trade.timestamp,
       DateTime::parse_from_rfc3339("2020-03-06T15:43:22.638-05:00").unwrap()
     );
+    assert_eq!(trade.timestamp_millis(), 1583527402638);
   }
 
-  /// Check that we can deserialize a `Quote`.
+  /// Check that `Trade` timestamps around a US daylight saving time
+  /// transition map to the expected New York wall-clock times.
+  ///
+  /// Timestamps are kept in UTC and so do not depend on any time zone
+  /// database being present on the system.
   #[test]
-  fn deserialize_quote() {
-    let response = r#"{
-      "ev": "Q",
-      "sym": "SPY",
-      "c": 0,
-      "bx": 12,
-      "ax": 11,
-      "bp": 294.31,
-      "ap": 294.33,
-      "bs": 1,
-      "as": 2,
-      "t": 1583527004684,
-      "z": 2
-    }"#;
-    let quote = from_json::<Quote>(response).unwrap();
-    assert_eq!(quote.symbol, "SPY");
-    assert_eq!(quote.bid_exchange, 12);
-    assert_eq!(quote.bid_price, Num::new(29431, 100));
-    assert_eq!(quote.bid_quantity, 1);
-    assert_eq!(quote.ask_exchange, 11);
-    assert_eq!(quote.ask_price, Num::new(29433, 100));
-    assert_eq!(quote.ask_quantity, 2);
+  fn deserialize_trade_across_dst_transition() {
+    let trade = |millis: i64| {
+      let json = format!(
+        r#"{{"ev":"T","sym":"SPY","x":19,"p":420.07,"s":100,"t":{}}}"#,
+        millis
+      );
+      from_json::<Trade>(&json).unwrap()
+    };
+
+    // On March 13th, 2022, clocks in New York were advanced from 2:00
+    // EST to 3:00 EDT.
+    let before = trade(1647154799999);
     assert_eq!(
-      quote.timestamp,
-      DateTime::parse_from_rfc3339("2020-03-06T15:36:44.684-05:00").unwrap()
+      before.timestamp,
+      DateTime::parse_from_rfc3339("2022-03-13T01:59:59.999-05:00").unwrap()
+    );
+    let after = trade(1647154800000);
+    assert_eq!(
+      after.timestamp,
+      DateTime::parse_from_rfc3339("2022-03-13T03:00:00-04:00").unwrap()
+    );
+    assert_eq!((after.timestamp - before.timestamp).num_milliseconds(), 1);
+
+    // On November 6th, 2022, clocks were set back from 2:00 EDT to
+    // 1:00 EST, making wall-clock times between the two ambiguous.
+    let before = trade(1667714399999);
+    assert_eq!(
+      before.timestamp,
+      DateTime::parse_from_rfc3339("2022-11-06T01:59:59.999-04:00").unwrap()
+    );
+    let after = trade(1667714400000);
+    assert_eq!(
+      after.timestamp,
+      DateTime::parse_from_rfc3339("2022-11-06T01:00:00-05:00").unwrap()
     );
+    assert_eq!(after.timestamp_millis(), 1667714400000);
   }
 
-  /// Check that we can deserialize an `Aggregate`.
+  /// Check that we can deserialize a `Trade` with a price in
+  /// exponential notation.
   #[test]
-  fn deserialize_aggregate() {
-    let response = r#"{
-      "ev": "A",
-      "sym": "SPY",
-      "v": 2287,
-      "av": 163569633,
-      "op": 298.71,
-      "vw": 294.6301,
-      "o": 293.79,
-      "c": 293.68,
-      "h": 293.8,
+  fn deserialize_trade_exponential_price() {
+    let response = r#"{"ev":"T","sym":"MSFT","x":4,"p":1.569799e2,"s":3,"t":1577818283019}"#;
+    let trade = from_json::<Trade>(response).unwrap();
+    assert_eq!(trade.price, Num::new(1_569_799, 10000));
+
+    let response = r#"{"ev":"T","sym":"MSFT","x":4,"p":1.0e2,"s":3,"t":1577818283019}"#;
+    let trade = from_json::<Trade>(response).unwrap();
+    assert_eq!(trade.price, Num::from(100));
+  }
+
+  /// Check that a `Trade` without an ID serializes without the
+  /// corresponding key, just as Polygon reports it.
+  #[test]
+  fn serialize_trade_without_id() {
+    let response = r#"{"sym":"MSFT","x":4,"p":156.9799,"s":3,"t":1577818283019}"#;
+    let trade = from_json::<Trade>(response).unwrap();
+    assert_eq!(trade.id, None);
+
+    let json = to_json(&trade).unwrap();
+    assert!(!json.contains(r#""i""#), "{}", json);
+    assert_eq!(from_json::<Trade>(&json).unwrap(), trade);
+
+    let response = r#"{"sym":"MSFT","i":"52983525029461","x":4,"p":156.9799,"s":3,"t":1577818283019}"#;
+    let trade = from_json::<Trade>(response).unwrap();
+    let json = to_json(&trade).unwrap();
+    assert!(json.contains(r#""i":"52983525029461""#), "{}", json);
+  }
+
+  /// Check that an `Event` can be flattened into a user defined type
+  /// carrying additional data.
+  #[test]
+  fn deserialize_flattened_event() {
+    #[derive(Debug, Deserialize)]
+    struct Envelope {
+      strategy: String,
+      #[serde(flatten)]
+      event: Event,
+    }
+
+    let json = r#"{
+      "strategy": "momentum",
+      "ev": "T",
+      "sym": "MSFT",
+      "i": 8310,
+      "x": 4,
+      "p": 156.9799,
+      "s": 3,
+      "c": [37],
+      "t": 1577818283019,
+      "z": 3
+    }"#;
+    let envelope = from_json::<Envelope>(json).unwrap();
+    assert_eq!(envelope.strategy, "momentum");
+
+    let trade = envelope.event.to_trade().unwrap();
+    assert_eq!(trade.symbol, "MSFT");
+    assert_eq!(trade.id.as_deref(), Some("8310"));
+    assert_eq!(trade.price, Num::new(1569799, 10000));
+    assert_eq!(trade.conditions, vec![37]);
+    assert_eq!(trade.tape, Some(Tape::Nasdaq));
+    assert_eq!(trade.timestamp_millis(), 1577818283019);
+  }
+
+  /// Check that we can deserialize the tape a trade was reported on.
+  #[test]
+  fn deserialize_trade_tape() {
+    let response = r#"{"sym":"MSFT","x":4,"p":156.9799,"s":3,"t":1577818283019,"z":3}"#;
+    let trade = from_json::<Trade>(response).unwrap();
+    assert_eq!(trade.tape, Some(Tape::Nasdaq));
+    assert_eq!(trade.tape.unwrap().name(), "Nasdaq");
+    assert!(to_json(&trade).unwrap().contains(r#""z":3"#));
+
+    let response = r#"{"sym":"MSFT","x":4,"p":156.9799,"s":3,"t":1577818283019,"z":7}"#;
+    let trade = from_json::<Trade>(response).unwrap();
+    assert_eq!(trade.tape, Some(Tape::Unknown(7)));
+    assert_eq!(trade.tape.unwrap().name(), "unknown");
+    assert!(to_json(&trade).unwrap().contains(r#""z":7"#));
+
+    let response = r#"{"sym":"MSFT","x":4,"p":156.9799,"s":3,"t":1577818283019}"#;
+    let trade = from_json::<Trade>(response).unwrap();
+    assert_eq!(trade.tape, None);
+  }
+
+  /// Check that we can calculate the notional value of a `Trade`.
+  #[test]
+  fn trade_notional() {
+    let mut events = from_json::<Vec<Event>>(MSFT_TRADE_MSG).unwrap();
+    let trade = match events.remove(0) {
+      Event::Trade(trade) => trade,
+      event => panic!("unexpected event: {:?}", event),
+    };
+    assert_eq!(trade.notional(), Num::new(4_709_397, 10_000));
+  }
+
+  /// Check that we can deserialize a `Quote`.
+  #[test]
+  fn deserialize_quote() {
+    let response = r#"{
+      "ev": "Q",
+      "sym": "SPY",
+      "c": 0,
+      "bx": 12,
+      "ax": 11,
+      "bp": 294.31,
+      "ap": 294.33,
+      "bs": 1,
+      "as": 2,
+      "t": 1583527004684,
+      "z": 2
+    }"#;
+    let quote = from_json::<Quote>(response).unwrap();
+    assert_eq!(quote.symbol, "SPY");
+    assert_eq!(quote.bid_exchange, 12);
+    assert_eq!(quote.bid_price, Num::new(29431, 100));
+    assert_eq!(quote.bid_quantity, 1);
+    assert_eq!(quote.ask_exchange, 11);
+    assert_eq!(quote.ask_price, Num::new(29433, 100));
+    assert_eq!(quote.ask_quantity, 2);
+    assert_eq!(quote.tape, Some(Tape::AmexRegional));
+    assert_eq!(
+      quote.timestamp,
+      DateTime::parse_from_rfc3339("2020-03-06T15:36:44.684-05:00").unwrap()
+    );
+    assert_eq!(quote.timestamp_millis(), 1583527004684);
+  }
+
+  /// Check that we can serialize and deserialize a `Quote`.
+  #[test]
+  fn serialize_deserialize_quote() {
+    let response = r#"{"sym":"SPY","bx":12,"ax":11,"bp":294.31,"ap":294.33,"bs":1,"as":2,"t":1583527004684,"z":2}"#;
+    let quote = from_json::<Quote>(response).unwrap();
+    let json = to_json(&quote).unwrap();
+    assert!(json.contains(r#""z":2"#), "{}", json);
+    assert!(json.contains(r#""t":1583527004684"#), "{}", json);
+    assert_eq!(from_json::<Quote>(&json).unwrap(), quote);
+
+    let response = r#"{"sym":"SPY","bx":12,"ax":11,"bp":294.31,"ap":294.33,"bs":1,"as":2,"t":1583527004684}"#;
+    let quote = from_json::<Quote>(response).unwrap();
+    assert_eq!(quote.tape, None);
+    let json = to_json(&quote).unwrap();
+    assert!(!json.contains(r#""z""#), "{}", json);
+    assert_eq!(from_json::<Quote>(&json).unwrap(), quote);
+  }
+
+  /// Check that we can calculate the spread and mid price of a
+  /// `Quote`.
+  #[test]
+  fn quote_spread_mid_price() {
+    let response = r#"{
+      "ev": "Q",
+      "sym": "SPY",
+      "c": 0,
+      "bx": 12,
+      "ax": 11,
+      "bp": 294.31,
+      "ap": 294.33,
+      "bs": 1,
+      "as": 2,
+      "t": 1583527004684,
+      "z": 2
+    }"#;
+    let mut quote = from_json::<Quote>(response).unwrap();
+    assert_eq!(quote.spread(), Num::new(2, 100));
+    assert_eq!(quote.mid_price(), Num::new(29432, 100));
+
+    // A crossed market just yields a negative spread.
+    quote.ask_price = Num::new(29430, 100);
+    assert_eq!(quote.spread(), Num::new(-1, 100));
+    assert_eq!(quote.mid_price(), Num::new(588_610, 2000));
+  }
+
+  /// Check that we classify events into trading sessions based on
+  /// their timestamp.
+  #[test]
+  fn event_session() {
+    let trade = |millis: i64| {
+      let json = format!(
+        r#"{{"ev":"T","sym":"SPY","x":19,"p":420.07,"s":100,"t":{}}}"#,
+        millis
+      );
+      from_json::<Event>(&json).unwrap()
+    };
+
+    // 2022-03-07 08:00:00 EST
+    assert_eq!(trade(1646658000000).session(), MarketSession::PreMarket);
+    // 2022-03-07 10:00:00 EST
+    assert_eq!(trade(1646665200000).session(), MarketSession::Regular);
+    // 2022-03-07 17:00:00 EST
+    assert_eq!(trade(1646690400000).session(), MarketSession::AfterHours);
+    // 2022-03-14 10:00:00 EDT
+    assert_eq!(trade(1647266400000).session(), MarketSession::Regular);
+    // 2022-03-14 21:00:00 EDT
+    assert_eq!(trade(1647306000000).session(), MarketSession::Closed);
+  }
+
+  /// Check that we can deserialize an `Aggregate`.
+  #[test]
+  fn deserialize_aggregate() {
+    let response = r#"{
+      "ev": "A",
+      "sym": "SPY",
+      "v": 2287,
+      "av": 163569633,
+      "op": 298.71,
+      "vw": 294.6301,
+      "o": 293.79,
+      "c": 293.68,
+      "h": 293.8,
       "l": 293.68,
       "a": 293.7442,
       "s": 1583527401000,
@@ -500,10 +1978,10 @@ mod tests {
     let aggregate = from_json::<Aggregate>(response).unwrap();
     assert_eq!(aggregate.symbol, "SPY");
     assert_eq!(aggregate.volume, 2287);
-    assert_eq!(
-      aggregate.volume_weighted_average_price,
-      Num::new(2_946_301, 10000),
-    );
+    assert_eq!(aggregate.accumulated_volume, Some(163_569_633));
+    assert_eq!(aggregate.official_open_price, Some(Num::new(29871, 100)));
+    assert_eq!(aggregate.tick_vwap, Some(Num::new(2_946_301, 10000)));
+    assert_eq!(aggregate.day_vwap, Some(Num::new(2_937_442, 10000)));
     assert_eq!(aggregate.open_price, Num::new(29379, 100));
     assert_eq!(aggregate.close_price, Num::new(29368, 100));
     assert_eq!(aggregate.high_price, Num::new(2938, 10));
@@ -516,6 +1994,104 @@ mod tests {
       aggregate.end_timestamp,
       DateTime::parse_from_rfc3339("2020-03-06T15:43:22-05:00").unwrap()
     );
+    assert_eq!(aggregate.start_timestamp_millis(), 1583527401000);
+    assert_eq!(aggregate.end_timestamp_millis(), 1583527402000);
+  }
+
+  /// Check that an `Aggregate`'s symbol is picked up when keyed as
+  /// either `sym` or `T`, also when part of a batch of messages.
+  #[test]
+  fn deserialize_aggregate_symbol() {
+    let bar = r#""v":2287,"o":293.79,"c":293.68,"h":293.8,"l":293.68,"s":1583527401000,"e":1583527402000"#;
+    let with_sym = format!(r#"{{"sym":"SPY",{}}}"#, bar);
+    let with_t = format!(r#"{{"T":"SPY",{}}}"#, bar);
+
+    let first = from_json::<Aggregate>(&with_sym).unwrap();
+    let second = from_json::<Aggregate>(&with_t).unwrap();
+    assert_eq!(first.symbol, "SPY");
+    assert_eq!(first, second);
+
+    let response = format!(r#"[{{"ev":"A","T":"SPY",{}}}]"#, bar);
+    let mut messages = from_json::<Messages>(&response).unwrap();
+    match messages.remove(0) {
+      Message::SecondAggregate(aggregate) => assert_eq!(aggregate, first),
+      message => panic!("unexpected message: {:?}", message),
+    }
+  }
+
+  /// Check that we can retrieve an `Aggregate`'s volume as `Num`.
+  #[test]
+  fn aggregate_volume_num() {
+    let response = r#"{
+      "ev": "A",
+      "sym": "SPY",
+      "v": 2287,
+      "o": 293.79,
+      "c": 293.68,
+      "h": 293.8,
+      "l": 293.68,
+      "s": 1583527401000,
+      "e": 1583527402000
+    }"#;
+
+    let aggregate = from_json::<Aggregate>(response).unwrap();
+    assert_eq!(aggregate.volume, 2287);
+    assert_eq!(aggregate.volume_num(), Num::from(2287));
+  }
+
+  /// Check that we can deserialize a `FairMarketValue`.
+  #[test]
+  fn deserialize_fair_market_value() {
+    let response = r#"[{"ev":"FMV","fmv":189.22,"sym":"AAPL","t":1678220098130}]"#;
+
+    let mut messages = from_json::<Messages>(response).unwrap();
+    assert_eq!(messages.len(), 1);
+
+    let event = process_message(messages.remove(0)).unwrap().unwrap();
+    match event {
+      Event::FairMarketValue(fmv) => {
+        assert_eq!(fmv.symbol, "AAPL");
+        assert_eq!(fmv.price, Num::new(18922, 100));
+        assert_eq!(
+          fmv.timestamp,
+          DateTime::parse_from_rfc3339("2023-03-07T15:14:58.130-05:00").unwrap()
+        );
+        assert_eq!(fmv.timestamp_millis(), 1678220098130);
+      },
+      _ => panic!("unexpected event: {:?}", event),
+    }
+  }
+
+  /// Check that an `Aggregate` lacking the optional fields can still
+  /// be parsed as part of a batch of messages.
+  #[test]
+  fn deserialize_sparse_aggregate() {
+    let response = r#"[{
+      "ev": "AM",
+      "sym": "XYZ",
+      "v": 0,
+      "o": 12.5,
+      "c": 12.5,
+      "h": 12.5,
+      "l": 12.5,
+      "s": 1583527380000,
+      "e": 1583527440000
+    }]"#;
+
+    let mut messages = from_json::<Messages>(response).unwrap();
+    assert_eq!(messages.len(), 1);
+
+    match messages.remove(0) {
+      Message::MinuteAggregate(aggregate) => {
+        assert_eq!(aggregate.symbol, "XYZ");
+        assert_eq!(aggregate.accumulated_volume, None);
+        assert_eq!(aggregate.official_open_price, None);
+        assert_eq!(aggregate.tick_vwap, None);
+        assert_eq!(aggregate.day_vwap, None);
+        assert_eq!(aggregate.close_price, Num::new(125, 10));
+      },
+      message => panic!("unexpected message: {:?}", message),
+    }
   }
 
   #[test]
@@ -540,6 +2116,8 @@ mod tests {
     match event {
       Event::MinuteAggregate(aggregate) => {
         assert_eq!(aggregate.symbol, "MSFT");
+        assert_eq!(aggregate.tick_vwap, Some(Num::new(1144040, 10000)));
+        assert_eq!(aggregate.day_vwap, Some(Num::new(1141314, 10000)));
       },
       _ => panic!("unexpected event: {:?}", event),
     }
@@ -567,7 +2145,442 @@ mod tests {
   }
 
   #[test(tokio::test)]
-  async fn stream_msft() {
+  async fn stream_msft() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      // Authentication.
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      // Subscription.
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await?;
+
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
+      stream
+        .send(WebSocketMessage::Text(UFO_QUOTE_MSG.to_string()))
+        .await?;
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let mut stream = Box::pin(mock_stream(test, subscriptions).await.unwrap());
+
+    let trade = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(trade.to_trade().unwrap().symbol, "MSFT");
+    assert_eq!(trade.to_trade().unwrap().conditions, vec![37]);
+    assert!(is_odd_lot(&trade.to_trade().unwrap().conditions));
+
+    let quote = stream.next().await.unwrap().unwrap().unwrap();
+    let quote0 = quote.to_quote().unwrap();
+    assert_eq!(quote0.symbol, "UFO");
+    assert_eq!(quote0.ask_quantity, 11);
+
+    let quote = stream.next().await.unwrap().unwrap().unwrap();
+    let quote1 = quote.to_quote().unwrap();
+    assert_eq!(quote1.symbol, "UFO");
+    assert_eq!(quote1.ask_quantity, 3);
+
+    assert!(stream.next().await.is_none());
+  }
+
+  /// Check that `check_subscriptions` reports the confirmed
+  /// subscriptions and closes the connection afterwards.
+  #[test(tokio::test)]
+  async fn check_confirmed_subscriptions() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await?;
+
+      // The client closes the connection right away.
+      assert!(matches!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Close(..)
+      ));
+      Ok(())
+    }
+
+    let addr = mock_server(test).await;
+    let api_info = ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: API_KEY.to_string(),
+    };
+    let subscriptions = vec![
+      Subscription::Quotes(Stock::Symbol("AAPL".into())),
+      Subscription::Quotes(Stock::All),
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+    ];
+
+    let confirmed = check_subscriptions(api_info, subscriptions, StreamConfig::default())
+      .await
+      .unwrap();
+    let expected = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    assert_eq!(confirmed, expected);
+  }
+
+  /// Check that `check_subscriptions` reports only the subscriptions
+  /// that got confirmed in time.
+  #[test(tokio::test)]
+  async fn check_partially_confirmed_subscriptions() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(
+          r#"[{"ev":"status","status":"success","message":"subscribed to: Q.*"}]"#.to_string(),
+        ))
+        .await?;
+
+      // The client closes the connection once it gave up waiting.
+      assert!(matches!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Close(..)
+      ));
+      Ok(())
+    }
+
+    let addr = mock_server(test).await;
+    let api_info = ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: API_KEY.to_string(),
+    };
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let config = StreamConfig {
+      subscription_timeout: Some(Duration::from_millis(100)),
+      ..Default::default()
+    };
+
+    let confirmed = check_subscriptions(api_info, subscriptions, config)
+      .await
+      .unwrap();
+    assert_eq!(confirmed, vec![Subscription::Quotes(Stock::All)]);
+  }
+
+  /// Check that the event stream responds to pings while running.
+  #[test(tokio::test)]
+  async fn respond_to_ping() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await?;
+
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
+      stream
+        .send(WebSocketMessage::Ping(b"keepalive".to_vec()))
+        .await?;
+      // We only send more data once we got the pong, so the client
+      // would be stuck otherwise.
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Pong(b"keepalive".to_vec()),
+      );
+      stream
+        .send(WebSocketMessage::Text(UFO_QUOTE_MSG.to_string()))
+        .await?;
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let stream = mock_stream(test, subscriptions).await.unwrap();
+    let events = timeout(Duration::from_secs(10), stream.collect::<Vec<_>>())
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 3);
+  }
+
+  #[test(tokio::test)]
+  async fn interleaved_trade() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      // Authentication.
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      // Subscription.
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
+
+      // We have seen cases where the subscription response is actually
+      // preceded by an event we just subscribed to. Ugh. So simulate
+      // such a case to make sure we can deal with such races.
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await?;
+
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let _ = mock_stream(test, subscriptions)
+      .await
+      .unwrap()
+      .try_for_each(|_| ready(Ok(())))
+      .await
+      .unwrap();
+  }
+
+  /// Check that quotes are reported as `LightQuote` objects if
+  /// requested, including those received before the subscription got
+  /// confirmed.
+  #[test(tokio::test)]
+  async fn stream_light_quotes() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+      let _auth = stream.next().await.unwrap()?;
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+      let _subscribe = stream.next().await.unwrap()?;
+      stream
+        .send(WebSocketMessage::Text(UFO_QUOTE_MSG.to_string()))
+        .await?;
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await?;
+
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
+      stream
+        .send(WebSocketMessage::Text(UFO_QUOTE_MSG.to_string()))
+        .await?;
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let config = StreamConfig {
+      deliver_early_events: true,
+      light_quotes: true,
+      ..Default::default()
+    };
+    let stream = mock_stream_with_config(test, subscriptions, config)
+      .await
+      .unwrap();
+    let events = stream
+      .map(|result| result.unwrap().unwrap())
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(events.len(), 5);
+
+    // Trades are unaffected.
+    assert_eq!(events[2].to_trade().unwrap().symbol, "MSFT");
+
+    for event in events[..2].iter().chain(&events[3..]) {
+      match event {
+        Event::LightQuote(quote) => {
+          assert_eq!(quote.symbol, "UFO");
+          assert_eq!(quote.bid_price, 26.4);
+          assert_eq!(quote.ask_price, 26.47);
+          assert_eq!(quote.timestamp.timestamp_millis() / 1000, 1577818659);
+        },
+        event => panic!("unexpected event: {:?}", event),
+      }
+    }
+  }
+
+  /// Compare the decoding of a large frame of quotes with and without
+  /// light quotes, checking that both report the same prices.
+  #[test]
+  fn decode_light_quotes_throughput() {
+    let quotes = (0..10_000)
+      .map(|i| {
+        format!(
+          r#"{{"ev":"Q","sym":"UFO","c":1,"bx":8,"ax":12,"bp":26.{},"ap":27.{},"bs":1,"as":3,"t":1577818659363,"z":3}}"#,
+          i, i
+        )
+      })
+      .collect::<Vec<_>>();
+    let frame = format!("[{}]", quotes.join(","));
+
+    let start = Instant::now();
+    let full = decode_messages::<Quote>(frame.as_bytes()).unwrap();
+    let full_duration = start.elapsed();
+
+    let start = Instant::now();
+    let light = decode_messages::<LightQuote>(frame.as_bytes()).unwrap();
+    let light_duration = start.elapsed();
+
+    debug!(
+      full = debug(&full_duration),
+      light = debug(&light_duration),
+      "decoded {} quotes",
+      quotes.len()
+    );
+
+    assert_eq!(full.len(), light.len());
+    for (full, light) in full.iter().zip(light.iter()) {
+      match (full, light) {
+        (Message::Quote(full), Message::Quote(light)) => {
+          assert_eq!(light.symbol, full.symbol);
+          assert_eq!(Some(light.bid_price), full.bid_price.to_f64());
+          assert_eq!(Some(light.ask_price), full.ask_price.to_f64());
+          assert_eq!(light.timestamp, full.timestamp);
+        },
+        messages => panic!("unexpected messages: {:?}", messages),
+      }
+    }
+  }
+
+  /// Check that events received before the subscription got confirmed
+  /// are delivered if requested.
+  #[test(tokio::test)]
+  async fn deliver_early_events() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await?;
+      stream
+        .send(WebSocketMessage::Text(UFO_QUOTE_MSG.to_string()))
+        .await?;
+
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let config = StreamConfig {
+      deliver_early_events: true,
+      ..Default::default()
+    };
+    let events = mock_stream_with_config(test, subscriptions, config)
+      .await
+      .unwrap()
+      .map(|result| result.unwrap().unwrap())
+      .collect::<Vec<_>>()
+      .await;
+
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0].to_trade().unwrap().symbol, "MSFT");
+    assert_eq!(events[1].to_quote().unwrap().symbol, "UFO");
+  }
+
+  #[test(tokio::test)]
+  async fn disconnect() {
     async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
       stream
         .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
@@ -594,6 +2607,11 @@ mod tests {
       stream
         .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
         .await?;
+      stream
+        .send(WebSocketMessage::Text(DISCONNECTED_MSG.to_string()))
+        .await?;
+
+      // This message should never be seen.
       stream
         .send(WebSocketMessage::Text(UFO_QUOTE_MSG.to_string()))
         .await?;
@@ -605,26 +2623,80 @@ mod tests {
       Subscription::Trades(Stock::Symbol("MSFT".into())),
       Subscription::Quotes(Stock::All),
     ];
+
     let mut stream = Box::pin(mock_stream(test, subscriptions).await.unwrap());
 
-    let trade = stream.next().await.unwrap().unwrap().unwrap();
-    assert_eq!(trade.to_trade().unwrap().symbol, "MSFT");
+    assert!(stream.next().await.unwrap().is_ok());
+    let err = stream.next().await.unwrap().unwrap_err();
+    assert_eq!(
+      DisconnectReason::from_error(&err),
+      Some(&DisconnectReason::MaxConnections)
+    );
+    assert!(stream.next().await.is_none());
+  }
 
-    let quote = stream.next().await.unwrap().unwrap().unwrap();
-    let quote0 = quote.to_quote().unwrap();
-    assert_eq!(quote0.symbol, "UFO");
-    assert_eq!(quote0.ask_quantity, 11);
+  /// Check that the delay of a `StartupJitter` stays within the
+  /// configured bound.
+  #[test]
+  fn startup_jitter_bound() {
+    let max = Duration::from_millis(500);
+    let jitter = StartupJitter::new(max);
+    assert!((0..100).all(|_| jitter.delay() <= max));
 
-    let quote = stream.next().await.unwrap().unwrap().unwrap();
-    let quote1 = quote.to_quote().unwrap();
-    assert_eq!(quote1.symbol, "UFO");
-    assert_eq!(quote1.ask_quantity, 3);
+    let jitter = StartupJitter::with_rng(max, || 0.5);
+    assert_eq!(jitter.delay(), Duration::from_millis(250));
 
-    assert!(stream.next().await.is_none());
+    let jitter = StartupJitter::with_rng(max, || 7.0);
+    assert_eq!(jitter.delay(), max);
+
+    let jitter = StartupJitter::with_rng(max, || f64::NAN);
+    assert_eq!(jitter.delay(), Duration::from_secs(0));
   }
 
+  /// Check that a configured startup jitter delays the connection.
   #[test(tokio::test)]
-  async fn interleaved_trade() {
+  async fn delay_connection_by_jitter() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+      let _auth = stream.next().await.unwrap()?;
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+      let _sub = stream.next().await.unwrap()?;
+      stream
+        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .await?;
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let max = Duration::from_millis(400);
+    let config = StreamConfig {
+      startup_jitter: Some(StartupJitter::with_rng(max, || 0.5)),
+      ..Default::default()
+    };
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+
+    let start = Instant::now();
+    let _stream = mock_stream_with_config(test, subscriptions, config)
+      .await
+      .unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed >= max / 2, "{:?}", elapsed);
+    // The delay is half the maximum jitter and so the connection should
+    // be established well before the maximum elapsed.
+    assert!(elapsed < max, "{:?}", elapsed);
+  }
+
+  /// Check that a configured frame tap observes raw frames before they
+  /// are parsed.
+  #[test(tokio::test)]
+  async fn tap_raw_frames() {
     async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
       stream
         .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
@@ -644,35 +2716,46 @@ mod tests {
         stream.next().await.unwrap()?,
         WebSocketMessage::Text(SUB_REQ.to_string()),
       );
-
-      // We have seen cases where the subscription response is actually
-      // preceded by an event we just subscribed to. Ugh. So simulate
-      // such a case to make sure we can deal with such races.
-      stream
-        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
-        .await?;
       stream
         .send(WebSocketMessage::Text(SUB_RESP.to_string()))
         .await?;
 
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
       stream.send(WebSocketMessage::Close(None)).await?;
       Ok(())
     }
 
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let config = StreamConfig {
+      frame_tap: Some(FrameTap::new({
+        let frames = frames.clone();
+        move |message| match message {
+          websocket_util::wrap::Message::Text(text) => frames.lock().unwrap().push(text.clone()),
+          websocket_util::wrap::Message::Binary(..) => panic!("unexpected binary frame"),
+        }
+      })),
+      ..Default::default()
+    };
+
     let subscriptions = vec![
       Subscription::Trades(Stock::Symbol("MSFT".into())),
       Subscription::Quotes(Stock::All),
     ];
-    let _ = mock_stream(test, subscriptions)
-      .await
-      .unwrap()
-      .try_for_each(|_| ready(Ok(())))
+    let stream = mock_stream_with_config(test, subscriptions, config)
       .await
       .unwrap();
+    let events = stream.try_collect::<Vec<_>>().await.unwrap();
+    assert_eq!(events.len(), 1);
+
+    assert_eq!(*frames.lock().unwrap(), vec![MSFT_TRADE_MSG.to_string()]);
   }
 
+  /// Check that we report unconfirmed subscriptions if Polygon does not
+  /// confirm all of them.
   #[test(tokio::test)]
-  async fn disconnect() {
+  async fn incomplete_subscription_confirmations() {
     async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
       stream
         .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
@@ -692,22 +2775,71 @@ mod tests {
         stream.next().await.unwrap()?,
         WebSocketMessage::Text(SUB_REQ.to_string()),
       );
+      // Only confirm the second subscription and then go quiet.
       stream
-        .send(WebSocketMessage::Text(SUB_RESP.to_string()))
+        .send(WebSocketMessage::Text(
+          r#"[{"ev":"status","status":"success","message":"subscribed to: Q.*"}]"#.to_string(),
+        ))
         .await?;
 
+      let _ = stream.next().await;
+      Ok(())
+    }
+
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let config = StreamConfig {
+      confirmation_timeout: Some(Duration::from_millis(100)),
+      ..Default::default()
+    };
+
+    let result = mock_stream_with_config(test, subscriptions, config).await;
+    match result {
+      Err(Error::Str(ref err))
+        if err.contains("subscription limit likely exceeded")
+          && err.ends_with("unconfirmed: T.MSFT") => {},
+      Err(err) => panic!("unexpected error: {}", err),
+      Ok(_) => panic!("stream creation succeeded unexpectedly"),
+    }
+  }
+
+  /// Check that the subscription timeout bounds the wait for
+  /// confirmations even while events keep arriving.
+  #[test(tokio::test)]
+  async fn subscription_timeout() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
       stream
-        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
         .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
       stream
-        .send(WebSocketMessage::Text(DISCONNECTED_MSG.to_string()))
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
         .await?;
 
-      // This message should never be seen.
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(SUB_REQ.to_string()),
+      );
       stream
-        .send(WebSocketMessage::Text(UFO_QUOTE_MSG.to_string()))
+        .send(WebSocketMessage::Text(
+          r#"[{"ev":"status","status":"success","message":"subscribed to: Q.*"}]"#.to_string(),
+        ))
         .await?;
-      stream.send(WebSocketMessage::Close(None)).await?;
+
+      // Keep the connection busy, so that the idle timeout never
+      // fires.
+      for _ in 0..100 {
+        stream
+          .send(WebSocketMessage::Text(UFO_QUOTE_MSG.to_string()))
+          .await?;
+        let () = sleep(Duration::from_millis(20)).await;
+      }
       Ok(())
     }
 
@@ -715,12 +2847,215 @@ mod tests {
       Subscription::Trades(Stock::Symbol("MSFT".into())),
       Subscription::Quotes(Stock::All),
     ];
+    let config = StreamConfig {
+      confirmation_timeout: Some(Duration::from_secs(1)),
+      subscription_timeout: Some(Duration::from_millis(200)),
+      ..Default::default()
+    };
 
-    let mut stream = Box::pin(mock_stream(test, subscriptions).await.unwrap());
+    let start = Instant::now();
+    let result = mock_stream_with_config(test, subscriptions, config).await;
+    match result {
+      Err(Error::Str(ref err))
+        if err.contains("only partially confirmed (1 of 2)")
+          && err.ends_with("unconfirmed: T.MSFT") => {},
+      Err(err) => panic!("unexpected error: {}", err),
+      Ok(_) => panic!("stream creation succeeded unexpectedly"),
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+  }
 
-    assert!(stream.next().await.unwrap().is_ok());
-    assert!(stream.next().await.unwrap().is_err());
+  /// Check that we can change subscriptions of an active stream.
+  #[test(tokio::test)]
+  async fn change_subscriptions() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(r#"{"action":"subscribe","params":"T.MSFT"}"#.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(r#"{"action":"unsubscribe","params":"T.MSFT"}"#.to_string()),
+      );
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let addr = mock_server(test).await;
+    let api_info = ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: API_KEY.to_string(),
+    };
+
+    let (stream, handle) = stream_with_handle(api_info, Vec::new(), StreamConfig::default())
+      .await
+      .unwrap();
+    let mut stream = Box::pin(stream);
+
+    let subscriptions = vec![Subscription::Trades(Stock::Symbol("MSFT".into()))];
+    handle.subscribe(subscriptions.clone()).unwrap();
+
+    let trade = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(trade.to_trade().unwrap().symbol, "MSFT");
+
+    handle.unsubscribe(subscriptions).unwrap();
+    assert!(stream.next().await.is_none());
+
+    // Once the stream is gone, subscription changes are reported as
+    // failed.
+    drop(stream);
+    assert!(handle.subscribe(Vec::new()).is_err());
+  }
+
+  /// Check that we can unsubscribe from all events subscribed to with
+  /// a single request.
+  #[test(tokio::test)]
+  async fn unsubscribe_from_all() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(r#"{"action":"subscribe","params":"T.MSFT"}"#.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(
+          r#"[{"ev":"status","status":"success","message":"subscribed to: T.MSFT"}]"#.to_string(),
+        ))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(r#"{"action":"subscribe","params":"T.AAPL,Q.SPY"}"#.to_string()),
+      );
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(r#"{"action":"unsubscribe","params":"Q.SPY"}"#.to_string()),
+      );
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(r#"{"action":"subscribe","params":"A.UFO"}"#.to_string()),
+      );
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(
+          r#"{"action":"unsubscribe","params":"A.UFO,T.AAPL,T.MSFT"}"#.to_string()
+        ),
+      );
+      stream.send(WebSocketMessage::Close(None)).await?;
+      Ok(())
+    }
+
+    let addr = mock_server(test).await;
+    let api_info = ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: API_KEY.to_string(),
+    };
+
+    let subscriptions = vec![Subscription::Trades(Stock::Symbol("MSFT".into()))];
+    let (stream, handle) = stream_with_handle(api_info, subscriptions, StreamConfig::default())
+      .await
+      .unwrap();
+    let mut stream = Box::pin(stream);
+
+    handle
+      .subscribe(vec![
+        Subscription::Trades(Stock::Symbol("AAPL".into())),
+        Subscription::Quotes(Stock::Symbol("SPY".into())),
+      ])
+      .unwrap();
+    handle
+      .unsubscribe(vec![Subscription::Quotes(Stock::Symbol("SPY".into()))])
+      .unwrap();
+    // Subscriptions made through clones are covered as well.
+    let ufo = Subscription::SecondAggregates(Stock::Symbol("UFO".into()));
+    handle.clone().subscribe(vec![ufo]).unwrap();
+    handle.unsubscribe_all().unwrap();
     assert!(stream.next().await.is_none());
+    assert!(handle.subscriptions.lock().unwrap().is_empty());
+  }
+
+  /// Check that a paused stream does not report events until it is
+  /// resumed, without the connection being closed.
+  #[test(tokio::test)]
+  async fn pause_and_resume() {
+    async fn test(mut stream: WebSocketStream) -> Result<(), WebSocketError> {
+      stream
+        .send(WebSocketMessage::Text(CONNECTED_MSG.to_string()))
+        .await?;
+
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(AUTH_REQ.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(AUTH_RESP.to_string()))
+        .await?;
+
+      // The subscription request is sent while the stream is paused.
+      assert_eq!(
+        stream.next().await.unwrap()?,
+        WebSocketMessage::Text(r#"{"action":"subscribe","params":"T.MSFT"}"#.to_string()),
+      );
+      stream
+        .send(WebSocketMessage::Text(MSFT_TRADE_MSG.to_string()))
+        .await?;
+
+      // Wait for the client to close the connection.
+      let _ = stream.next().await;
+      Ok(())
+    }
+
+    let addr = mock_server(test).await;
+    let api_info = ApiInfo {
+      api_url: Url::parse("http://example.com").unwrap(),
+      stream_url: Url::parse(&format!("ws://{}", addr)).unwrap(),
+      api_key: API_KEY.to_string(),
+    };
+
+    let (stream, handle) = stream_with_handle(api_info, Vec::new(), StreamConfig::default())
+      .await
+      .unwrap();
+    let mut stream = Box::pin(stream);
+
+    handle.pause().unwrap();
+    let subscriptions = vec![Subscription::Trades(Stock::Symbol("MSFT".into()))];
+    handle.subscribe(subscriptions).unwrap();
+
+    let result = timeout(Duration::from_millis(200), stream.next()).await;
+    assert!(result.is_err(), "{:?}", result);
+
+    handle.resume().unwrap();
+    let trade = stream.next().await.unwrap().unwrap().unwrap();
+    assert_eq!(trade.to_trade().unwrap().symbol, "MSFT");
   }
 
   /// Check that we can stream realtime market data quotes.
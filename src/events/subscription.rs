@@ -1,15 +1,26 @@
 // Copyright (C) 2019-2022 Daniel Mueller <deso@posteo.net>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::collections::BTreeSet;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashSet;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fmt::Result as FmtResult;
+use std::str::FromStr;
 
+use serde::de::Error as _;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+use crate::Error;
 use crate::Str;
 
 
 /// Possible subscriptions for a stock.
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Stock {
   /// Subscribe to the stock with the given symbol.
   Symbol(Str),
@@ -26,9 +37,44 @@ impl Display for Stock {
   }
 }
 
+impl FromStr for Stock {
+  type Err = Error;
+
+  /// Parse a `Stock` from its string representation, i.e., `*` or a
+  /// symbol.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "" => Err(Error::Str("invalid stock: empty symbol".into())),
+      "*" => Ok(Stock::All),
+      symbol => Ok(Stock::Symbol(symbol.to_string().into())),
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Stock {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let stock = String::deserialize(deserializer)?;
+    Stock::from_str(&stock).map_err(D::Error::custom)
+  }
+}
+
+impl Serialize for Stock {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.collect_str(self)
+  }
+}
+
 
 /// An enum describing a subscription.
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+///
+/// Subscriptions are ordered by event type first and by stock second.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Subscription {
   /// A type representing second aggregates for the given stock.
   SecondAggregates(Stock),
@@ -38,6 +84,9 @@ pub enum Subscription {
   Trades(Stock),
   /// A type representing quotes for the given stock.
   Quotes(Stock),
+  /// A type representing fair market value indications for the given
+  /// stock.
+  Fmv(Stock),
 }
 
 impl Subscription {
@@ -48,18 +97,285 @@ impl Subscription {
       Subscription::SecondAggregates(stock)
       | Subscription::MinuteAggregates(stock)
       | Subscription::Trades(stock)
-      | Subscription::Quotes(stock) => stock,
+      | Subscription::Quotes(stock)
+      | Subscription::Fmv(stock) => stock,
+    }
+  }
+
+  /// Create trade and quote subscriptions for each of the given
+  /// stocks.
+  ///
+  /// The subscriptions are reported pairwise per stock, trades first.
+  pub fn trades_and_quotes<I>(stocks: I) -> Vec<Subscription>
+  where
+    I: IntoIterator<Item = Stock>,
+  {
+    stocks
+      .into_iter()
+      .flat_map(|stock| [Subscription::Trades(stock.clone()), Subscription::Quotes(stock)])
+      .collect()
+  }
+
+  /// Retrieve the prefix identifying the subscription's event type.
+  fn prefix(&self) -> &'static str {
+    match self {
+      Subscription::SecondAggregates(..) => "A",
+      Subscription::MinuteAggregates(..) => "AM",
+      Subscription::Trades(..) => "T",
+      Subscription::Quotes(..) => "Q",
+      Subscription::Fmv(..) => "FMV",
+    }
+  }
+
+  /// Render the given subscriptions compactly, grouped by event type.
+  ///
+  /// Subscriptions are sorted and duplicates are removed. The stocks of
+  /// an event type with multiple subscriptions are enclosed in braces,
+  /// e.g., `T.{AAPL,MSFT},Q.*`. Please note that Polygon expects each
+  /// subscription to be provided individually; this representation is
+  /// meant for human consumption, such as logging.
+  pub fn grouped<I>(subscriptions: I) -> String
+  where
+    I: IntoIterator<Item = Subscription>,
+  {
+    let subscriptions = subscriptions.into_iter().collect::<BTreeSet<_>>();
+    let mut groups = Vec::<(&str, Vec<String>)>::new();
+
+    for subscription in &subscriptions {
+      let prefix = subscription.prefix();
+      let stock = subscription.stock().to_string();
+      match groups.last_mut() {
+        Some((last, stocks)) if *last == prefix => stocks.push(stock),
+        _ => groups.push((prefix, vec![stock])),
+      }
+    }
+
+    groups
+      .into_iter()
+      .map(|(prefix, stocks)| {
+        if stocks.len() == 1 {
+          format!("{}.{}", prefix, stocks[0])
+        } else {
+          format!("{}.{{{}}}", prefix, stocks.join(","))
+        }
+      })
+      .collect::<Vec<_>>()
+      .join(",")
+  }
+
+  /// Create a subscription of the same type but applying to all
+  /// stocks.
+  #[cfg(not(target_arch = "wasm32"))]
+  fn to_all(&self) -> Self {
+    match self {
+      Subscription::SecondAggregates(..) => Subscription::SecondAggregates(Stock::All),
+      Subscription::MinuteAggregates(..) => Subscription::MinuteAggregates(Stock::All),
+      Subscription::Trades(..) => Subscription::Trades(Stock::All),
+      Subscription::Quotes(..) => Subscription::Quotes(Stock::All),
+      Subscription::Fmv(..) => Subscription::Fmv(Stock::All),
     }
   }
 }
 
 impl Display for Subscription {
   fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
-    match self {
-      Subscription::SecondAggregates(stock) => write!(fmt, "A.{}", stock),
-      Subscription::MinuteAggregates(stock) => write!(fmt, "AM.{}", stock),
-      Subscription::Trades(stock) => write!(fmt, "T.{}", stock),
-      Subscription::Quotes(stock) => write!(fmt, "Q.{}", stock),
-    }
+    write!(fmt, "{}.{}", self.prefix(), self.stock())
+  }
+}
+
+impl FromStr for Subscription {
+  type Err = Error;
+
+  /// Parse a `Subscription` from its string representation, e.g.,
+  /// `T.MSFT` or `Q.*`.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (prefix, stock) = s
+      .split_once('.')
+      .ok_or_else(|| Error::Str(format!("invalid subscription: {}", s).into()))?;
+    let stock = Stock::from_str(stock)?;
+    let subscription = match prefix {
+      "A" => Subscription::SecondAggregates(stock),
+      "AM" => Subscription::MinuteAggregates(stock),
+      "T" => Subscription::Trades(stock),
+      "Q" => Subscription::Quotes(stock),
+      "FMV" => Subscription::Fmv(stock),
+      _ => return Err(Error::Str(format!("invalid subscription: {}", s).into())),
+    };
+    Ok(subscription)
+  }
+}
+
+impl<'de> Deserialize<'de> for Subscription {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let subscription = String::deserialize(deserializer)?;
+    Subscription::from_str(&subscription).map_err(D::Error::custom)
+  }
+}
+
+impl Serialize for Subscription {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.collect_str(self)
+  }
+}
+
+
+/// Normalize a list of subscriptions, removing duplicates and overlaps.
+///
+/// If a subscription applies to all stocks of a certain type (e.g.,
+/// `Subscription::Trades(Stock::All)`) then more specific subscriptions
+/// are removed (e.g., `Subscription::Trades(Stock::Symbol("SPY"))`).
+/// The order of the remaining subscriptions is preserved.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn normalize<S>(subscriptions: S) -> Vec<Subscription>
+where
+  S: IntoIterator<Item = Subscription>,
+{
+  let subs = subscriptions.into_iter().collect::<Vec<_>>();
+  let all = subs
+    .iter()
+    .filter(|sub| *sub.stock() == Stock::All)
+    .cloned()
+    .collect::<HashSet<_>>();
+
+  let mut seen = HashSet::with_capacity(subs.len());
+  subs
+    .into_iter()
+    .filter(|sub| *sub.stock() == Stock::All || !all.contains(&sub.to_all()))
+    .filter(|sub| seen.insert(sub.clone()))
+    .collect()
+}
+
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use serde_json::from_str as from_json;
+  use serde_json::to_string as to_json;
+
+
+  #[test]
+  fn normalize_subscriptions() {
+    let subscriptions = vec![
+      Subscription::Quotes(Stock::Symbol("SPY".into())),
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    let expected = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::All),
+    ];
+    assert_eq!(normalize(subscriptions), expected);
+
+    let subscriptions = vec![
+      Subscription::SecondAggregates(Stock::All),
+      Subscription::SecondAggregates(Stock::Symbol("SPY".into())),
+      Subscription::MinuteAggregates(Stock::Symbol("AAPL".into())),
+      Subscription::MinuteAggregates(Stock::Symbol("VMW".into())),
+      Subscription::MinuteAggregates(Stock::All),
+    ];
+    let expected = vec![
+      Subscription::SecondAggregates(Stock::All),
+      Subscription::MinuteAggregates(Stock::All),
+    ];
+    assert_eq!(normalize(subscriptions), expected);
+
+    let subscriptions = vec![
+      Subscription::Trades(Stock::All),
+      Subscription::Trades(Stock::Symbol("VMW".into())),
+      Subscription::Trades(Stock::All),
+    ];
+    let expected = vec![Subscription::Trades(Stock::All)];
+    assert_eq!(normalize(subscriptions), expected);
+
+    let subscriptions = vec![
+      Subscription::Fmv(Stock::Symbol("AAPL".into())),
+      Subscription::Fmv(Stock::All),
+      Subscription::Quotes(Stock::Symbol("AAPL".into())),
+    ];
+    let expected = vec![
+      Subscription::Fmv(Stock::All),
+      Subscription::Quotes(Stock::Symbol("AAPL".into())),
+    ];
+    assert_eq!(normalize(subscriptions), expected);
+  }
+
+  /// Check that we create trade and quote subscriptions for each
+  /// stock.
+  #[test]
+  fn create_trades_and_quotes() {
+    let stocks = vec![Stock::Symbol("X".into()), Stock::Symbol("Y".into())];
+    let subscriptions = Subscription::trades_and_quotes(stocks)
+      .iter()
+      .map(ToString::to_string)
+      .collect::<BTreeSet<_>>();
+    let expected = ["T.X", "Q.X", "T.Y", "Q.Y"]
+      .iter()
+      .map(ToString::to_string)
+      .collect::<BTreeSet<_>>();
+    assert_eq!(subscriptions, expected);
+  }
+
+  /// Check that subscriptions survive a serialization round trip in
+  /// their string form.
+  #[test]
+  fn serialize_deserialize_subscriptions() {
+    let subscriptions = vec![
+      Subscription::SecondAggregates(Stock::Symbol("SPY".into())),
+      Subscription::MinuteAggregates(Stock::All),
+      Subscription::Trades(Stock::Symbol("BRK.A".into())),
+      Subscription::Quotes(Stock::All),
+      Subscription::Fmv(Stock::Symbol("AAPL".into())),
+    ];
+
+    let json = to_json(&subscriptions).unwrap();
+    assert_eq!(json, r#"["A.SPY","AM.*","T.BRK.A","Q.*","FMV.AAPL"]"#);
+    assert_eq!(from_json::<Vec<Subscription>>(&json).unwrap(), subscriptions);
+
+    assert!(from_json::<Subscription>(r#""X.SPY""#).is_err());
+    assert!(from_json::<Subscription>(r#""T.""#).is_err());
+    assert!(from_json::<Subscription>(r#""SPY""#).is_err());
+  }
+
+  /// Check that we render subscriptions grouped by event type.
+  #[test]
+  fn group_subscriptions() {
+    let subscriptions = vec![
+      Subscription::Quotes(Stock::All),
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::SecondAggregates(Stock::Symbol("SPY".into())),
+      Subscription::Trades(Stock::Symbol("AAPL".into())),
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+    ];
+    assert_eq!(
+      Subscription::grouped(subscriptions),
+      "A.SPY,T.{AAPL,MSFT},Q.*"
+    );
+    assert_eq!(Subscription::grouped(Vec::new()), "");
+  }
+
+  /// Check that exact duplicates are removed while the order of
+  /// subscriptions is preserved.
+  #[test]
+  fn normalize_duplicates() {
+    let subscriptions = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::Symbol("MSFT".into())),
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Trades(Stock::Symbol("AAPL".into())),
+    ];
+    let expected = vec![
+      Subscription::Trades(Stock::Symbol("MSFT".into())),
+      Subscription::Quotes(Stock::Symbol("MSFT".into())),
+      Subscription::Trades(Stock::Symbol("AAPL".into())),
+    ];
+    assert_eq!(normalize(subscriptions), expected);
   }
 }
@@ -59,19 +59,43 @@ mod endpoint;
 /// A module comprising the functionality backing interactions with the
 /// API.
 pub mod api;
+/// A module comprising predicates for interpreting Polygon's equity
+/// trade condition codes, as reported by, e.g.,
+/// `events::Trade::conditions`.
+pub mod conditions;
 /// A module comprising functionality for interacting with Polygon's
 /// market data streaming service.
 pub mod events;
+/// A module comprising helpers for working with prices as reported by
+/// Polygon.
+pub mod price;
 
 mod api_info;
 mod client;
+mod de;
 mod error;
+#[cfg(not(target_arch = "wasm32"))]
+mod metrics;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod mock;
+#[cfg(not(target_arch = "wasm32"))]
+mod rate_limit;
+mod tz;
 
 use std::borrow::Cow;
 
 pub use api_info::ApiInfo;
 pub use client::Client;
+pub use client::ClientBuilder;
 pub use error::Error;
 pub use error::RequestError;
+#[cfg(not(target_arch = "wasm32"))]
+pub use metrics::MetricsSink;
+#[cfg(not(target_arch = "wasm32"))]
+pub use metrics::NoopMetrics;
 
 type Str = Cow<'static, str>;
+
+/// The user agent reported to Polygon unless overwritten.
+#[cfg(not(target_arch = "wasm32"))]
+const USER_AGENT: &str = concat!("polyio/", env!("CARGO_PKG_VERSION"));
@@ -0,0 +1,37 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Debug;
+use std::time::Duration;
+
+use http::StatusCode;
+
+
+/// A sink for metrics about the requests issued by a `Client`.
+///
+/// A sink can be installed by means of `ClientBuilder::metrics`, e.g.,
+/// to feed request counts and latencies into a metrics system of
+/// choice.
+pub trait MetricsSink: Debug + Send + Sync {
+  /// Observe the completion of a request.
+  ///
+  /// `endpoint` is the name of the endpoint the request was issued
+  /// to, i.e., its type name as reported by `std::any::type_name`,
+  /// e.g., `polyio::api::exchanges::Get`. `status` is the HTTP status
+  /// code of the final response, or `None` if no response was
+  /// received, e.g., because the request timed out. `duration` is the
+  /// total time the request took, including any retries.
+  fn observe(&self, endpoint: &str, status: Option<StatusCode>, duration: Duration);
+}
+
+
+/// A `MetricsSink` discarding all observations.
+///
+/// This sink is used unless a different one is configured.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl MetricsSink for NoopMetrics {
+  #[inline]
+  fn observe(&self, _endpoint: &str, _status: Option<StatusCode>, _duration: Duration) {}
+}
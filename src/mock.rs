@@ -0,0 +1,241 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A module providing a minimal HTTP server for testing purposes.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::str::from_utf8;
+use std::sync::Arc;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt as _;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt as _;
+use tokio::net::TcpListener;
+use tokio::spawn;
+
+use tokio_native_tls::native_tls::Identity;
+use tokio_native_tls::native_tls::TlsAcceptor as NativeTlsAcceptor;
+use tokio_native_tls::TlsAcceptor;
+
+use url::Url;
+
+use crate::ApiInfo;
+
+
+/// An HTTP request as received by the mock server.
+#[derive(Clone, Debug)]
+pub struct Request {
+  /// The requested path, including any query.
+  pub path: String,
+  /// The request headers, with lower case names.
+  pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+  /// Retrieve the value of the header with the given (lower case)
+  /// name.
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(key, _)| key == name)
+      .map(|(_, value)| value.as_str())
+  }
+}
+
+
+/// An HTTP response to be sent by the mock server.
+#[derive(Clone, Debug)]
+pub struct Response {
+  /// The response's status code.
+  pub status: u16,
+  /// Additional headers to include.
+  pub headers: Vec<(String, String)>,
+  /// The response body.
+  pub body: Vec<u8>,
+}
+
+impl Response {
+  /// Create a response with the given status and a JSON body.
+  pub fn json<B>(status: u16, body: B) -> Self
+  where
+    B: Into<Vec<u8>>,
+  {
+    Self {
+      status,
+      headers: vec![("content-type".into(), "application/json".into())],
+      body: body.into(),
+    }
+  }
+}
+
+
+/// Read and parse a single request from the given stream.
+async fn read_request<S>(stream: &mut S) -> Option<Request>
+where
+  S: AsyncRead + Unpin,
+{
+  let mut data = Vec::new();
+  let mut buffer = [0; 4096];
+
+  let end = loop {
+    let count = stream.read(&mut buffer).await.ok()?;
+    if count == 0 {
+      return None
+    }
+    data.extend_from_slice(&buffer[..count]);
+
+    if let Some(idx) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+      break idx
+    }
+  };
+
+  let head = from_utf8(&data[..end]).ok()?;
+  let mut lines = head.split("\r\n");
+  let mut request_line = lines.next()?.split(' ');
+  let _method = request_line.next()?;
+  let path = request_line.next()?.to_string();
+  let headers = lines
+    .filter_map(|line| {
+      let (key, value) = line.split_once(':')?;
+      Some((key.trim().to_ascii_lowercase(), value.trim().to_string()))
+    })
+    .collect::<Vec<_>>();
+
+  let request = Request {
+    path,
+    headers,
+  };
+
+  let length = request
+    .header("content-length")
+    .and_then(|length| length.parse::<usize>().ok())
+    .unwrap_or(0);
+  let mut remaining = length.saturating_sub(data.len() - end - 4);
+  while remaining > 0 {
+    let count = stream.read(&mut buffer).await.ok()?;
+    if count == 0 {
+      return None
+    }
+    remaining = remaining.saturating_sub(count);
+  }
+
+  Some(request)
+}
+
+
+/// Write the given response to the provided stream.
+async fn write_response<S>(stream: &mut S, response: Response)
+where
+  S: AsyncWrite + Unpin,
+{
+  let mut data = format!("HTTP/1.1 {} Mock\r\n", response.status);
+  for (key, value) in &response.headers {
+    data += &format!("{}: {}\r\n", key, value);
+  }
+  data += &format!(
+    "content-length: {}\r\nconnection: close\r\n\r\n",
+    response.body.len()
+  );
+
+  let mut data = data.into_bytes();
+  data.extend_from_slice(&response.body);
+
+  let _ = stream.write_all(&data).await;
+  let _ = stream.shutdown().await;
+}
+
+
+/// Create an HTTP server that answers each request using the provided
+/// function.
+///
+/// Each connection is used for exactly one request.
+pub async fn mock_server<F, R>(f: F) -> SocketAddr
+where
+  F: Fn(Request) -> R + Send + Sync + 'static,
+  R: Future<Output = Response> + Send + 'static,
+{
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let f = Arc::new(f);
+
+  let future = async move {
+    while let Ok((mut stream, _addr)) = listener.accept().await {
+      let f = f.clone();
+      spawn(async move {
+        if let Some(request) = read_request(&mut stream).await {
+          let response = f(request).await;
+          write_response(&mut stream, response).await;
+        }
+      });
+    }
+  };
+
+  spawn(future);
+  addr
+}
+
+
+/// Create a TLS identity using a self-signed certificate for
+/// `localhost`.
+///
+/// The certificate was created via:
+/// ```sh
+/// $ openssl req -x509 -newkey rsa:2048 -nodes -keyout key.pem \
+///     -out localhost.crt -days 36500 -subj "/CN=localhost"
+/// $ openssl pkcs8 -topk8 -nocrypt -in key.pem -out localhost.key
+/// ```
+fn self_signed_identity() -> Identity {
+  let cert = include_bytes!("data/localhost.crt");
+  let key = include_bytes!("data/localhost.key");
+  Identity::from_pkcs8(cert, key).unwrap()
+}
+
+
+/// Create an HTTPS server using a self-signed certificate that answers
+/// each request using the provided function.
+///
+/// Each connection is used for exactly one request.
+pub async fn mock_tls_server<F, R>(f: F) -> SocketAddr
+where
+  F: Fn(Request) -> R + Send + Sync + 'static,
+  R: Future<Output = Response> + Send + 'static,
+{
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let acceptor = NativeTlsAcceptor::new(self_signed_identity()).unwrap();
+  let acceptor = Arc::new(TlsAcceptor::from(acceptor));
+  let f = Arc::new(f);
+
+  let future = async move {
+    while let Ok((stream, _addr)) = listener.accept().await {
+      let acceptor = acceptor.clone();
+      let f = f.clone();
+      spawn(async move {
+        // Clients rejecting the certificate fail the handshake.
+        if let Ok(mut stream) = acceptor.accept(stream).await {
+          if let Some(request) = read_request(&mut stream).await {
+            let response = f(request).await;
+            write_response(&mut stream, response).await;
+          }
+        }
+      });
+    }
+  };
+
+  spawn(future);
+  addr
+}
+
+
+/// Create an `ApiInfo` object pointing to a mock server at the given
+/// address.
+pub fn api_info(addr: SocketAddr) -> ApiInfo {
+  ApiInfo {
+    api_url: Url::parse(&format!("http://{}", addr)).unwrap(),
+    stream_url: Url::parse("ws://127.0.0.1:1").unwrap(),
+    api_key: "USER12345678".to_string(),
+  }
+}
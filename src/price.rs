@@ -0,0 +1,51 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use num_decimal::Num;
+
+
+/// Check whether two prices are equal within the given tolerance.
+///
+/// The comparison is performed exactly, on rational numbers, and
+/// succeeds if the absolute difference between `a` and `b` is at most
+/// `tolerance`. It is meant for comparing prices that were derived
+/// through different computation paths, e.g., when one of them made a
+/// round trip through `f64`.
+pub fn approx_eq(a: &Num, b: &Num, tolerance: &Num) -> bool {
+  let diff = a - b;
+  let diff = if diff.is_negative() { -diff } else { diff };
+  &diff <= tolerance
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that prices differing by less than the tolerance are
+  /// considered approximately equal.
+  #[test]
+  fn approx_eq_within_tolerance() {
+    let a = Num::new(1_569_799, 10_000);
+    let b = Num::new(15_698, 100);
+    let tolerance = Num::new(1, 100);
+
+    assert_ne!(a, b);
+    assert!(approx_eq(&a, &b, &tolerance));
+    assert!(approx_eq(&b, &a, &tolerance));
+  }
+
+  /// Check that prices differing by more than the tolerance are not
+  /// considered approximately equal.
+  #[test]
+  fn approx_eq_outside_tolerance() {
+    let a = Num::new(1_569_799, 10_000);
+    let b = Num::new(15_698, 100);
+    let tolerance = Num::new(1, 100_000);
+
+    assert!(!approx_eq(&a, &b, &tolerance));
+    assert!(!approx_eq(&b, &a, &tolerance));
+    assert!(approx_eq(&a, &a, &Num::from(0)));
+  }
+}
@@ -0,0 +1,97 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::sleep;
+use tokio::time::Instant;
+
+use tracing::debug;
+
+
+/// A rate limiter allowing for at most a certain number of requests in
+/// a sliding window of time.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+  /// The maximum number of requests allowed per `period`.
+  count: usize,
+  /// The length of the sliding window.
+  period: Duration,
+  /// The points in time at which the requests inside the current
+  /// window were issued.
+  issued: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+  /// Create a new `RateLimiter` allowing for `count` requests per
+  /// `period`.
+  pub fn new(count: usize, period: Duration) -> Self {
+    Self {
+      count,
+      period,
+      issued: Mutex::new(VecDeque::with_capacity(count)),
+    }
+  }
+
+  /// Wait until another request can be issued without exceeding the
+  /// rate limit and account for it.
+  pub async fn acquire(&self) {
+    loop {
+      let wait = {
+        let mut issued = self.issued.lock().unwrap();
+        let now = Instant::now();
+
+        while let Some(first) = issued.front() {
+          if now.duration_since(*first) >= self.period {
+            let _ = issued.pop_front();
+          } else {
+            break
+          }
+        }
+
+        match issued.front() {
+          Some(first) if issued.len() >= self.count => self.period - now.duration_since(*first),
+          _ => {
+            issued.push_back(now);
+            return
+          },
+        }
+      };
+
+      debug!(wait = debug(&wait), "rate limit reached");
+      sleep(wait).await;
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use test_log::test;
+
+
+  /// Check that the `RateLimiter` delays requests exceeding the limit
+  /// until the window has moved on.
+  #[test(tokio::test(start_paused = true))]
+  async fn limit_requests() {
+    let limiter = RateLimiter::new(2, Duration::from_secs(60));
+    let start = Instant::now();
+
+    limiter.acquire().await;
+    limiter.acquire().await;
+    assert_eq!(start.elapsed(), Duration::from_secs(0));
+
+    limiter.acquire().await;
+    assert_eq!(start.elapsed(), Duration::from_secs(60));
+
+    limiter.acquire().await;
+    assert_eq!(start.elapsed(), Duration::from_secs(60));
+
+    limiter.acquire().await;
+    assert_eq!(start.elapsed(), Duration::from_secs(120));
+  }
+}
@@ -0,0 +1,87 @@
+// Copyright (C) 2022 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use chrono::DateTime;
+use chrono::Datelike as _;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone as _;
+use chrono::Utc;
+use chrono::Weekday;
+
+
+/// Create the point in time `hour` full hours into the given day.
+fn at_hour(date: NaiveDate, hour: u32) -> NaiveDateTime {
+  date.and_hms_opt(hour, 0, 0).unwrap()
+}
+
+
+/// Determine how far New York wall-clock time lags behind UTC at the
+/// provided point in time, given in UTC.
+///
+/// Daylight saving time is in effect from the second Sunday in March,
+/// 2:00 EST, until the first Sunday in November, 2:00 EDT, as has been
+/// the case since 2007.
+fn new_york_offset(utc: &NaiveDateTime) -> Duration {
+  let year = utc.year();
+  let dst = NaiveDate::from_weekday_of_month_opt(year, 3, Weekday::Sun, 2)
+    .zip(NaiveDate::from_weekday_of_month_opt(year, 11, Weekday::Sun, 1))
+    .map(|(start, end)| {
+      // 2:00 EST is 7:00 UTC, 2:00 EDT is 6:00 UTC.
+      at_hour(start, 7) <= *utc && *utc < at_hour(end, 6)
+    })
+    .unwrap_or(false);
+
+  Duration::hours(if dst { 4 } else { 5 })
+}
+
+
+/// Convert the provided point in time into New York wall-clock time.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn new_york_time(time: &DateTime<Utc>) -> NaiveDateTime {
+  let utc = time.naive_utc();
+  utc - new_york_offset(&utc)
+}
+
+
+/// Retrieve the point in time at which the given day starts in New
+/// York.
+///
+/// Transitions between standard and daylight saving time happen at
+/// 2:00, so that midnight itself is never skipped or repeated.
+pub(crate) fn new_york_midnight(date: NaiveDate) -> DateTime<Utc> {
+  let midnight = at_hour(date, 0);
+  // Midnight is at 4:00 or 5:00 UTC. At 4:00 UTC the previous day may
+  // still be in standard time, but at 5:00 UTC the day's offset is
+  // already in effect.
+  let offset = new_york_offset(&(midnight + Duration::hours(5)));
+  Utc.from_utc_datetime(&(midnight + offset))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we find the start of days in New York, including on
+  /// the days of the transitions.
+  #[test]
+  fn midnight() {
+    let midnights = [
+      ((2022, 1, 4), "2022-01-04T00:00:00-05:00"),
+      ((2022, 3, 13), "2022-03-13T00:00:00-05:00"),
+      ((2022, 3, 14), "2022-03-14T00:00:00-04:00"),
+      ((2022, 7, 12), "2022-07-12T00:00:00-04:00"),
+      ((2022, 11, 6), "2022-11-06T00:00:00-04:00"),
+      ((2022, 11, 7), "2022-11-07T00:00:00-05:00"),
+    ];
+
+    for ((year, month, day), expected) in midnights {
+      let date = NaiveDate::from_ymd_opt(year, month, day).unwrap();
+      let expected = DateTime::<Utc>::from(DateTime::parse_from_rfc3339(expected).unwrap());
+      assert_eq!(new_york_midnight(date), expected, "{}", date);
+    }
+  }
+}